@@ -0,0 +1,99 @@
+//! Connects by name compared to connects by the resolved token, to a
+//! service with many providers.
+//!
+//! Run with 'cargo bench --bench connect'.
+
+extern crate kobzar_ccs;
+
+use std::future::{self, Future};
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Waker};
+use std::time::Instant;
+
+use kobzar_ccs::{OpenNetwork, RegistrationForm, Service, Time, TokenConnect};
+use kobzar_ccs::local::{finish, LocalNetwork, LocalService, LocalSocket};
+use kobzar_ccs::rt::Runtime;
+
+const CONNECTS: u32 = 100_000;
+const PROVIDERS: u32 = 64;
+
+/// Connects made before the accepted channels are served.
+const ROUND: u32 = 256;
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Runtime that polls the tasks on the bench thread when asked, so that
+/// the channels are served without starting threads.
+#[derive(Clone, Default)]
+struct Inline(Arc<Mutex<Vec<Task>>>);
+
+impl Inline {
+
+    /// Poll the tasks, and the ones they spawn, once each.
+    fn run(&self) {
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut pending = Vec::new();
+        loop {
+            let tasks = mem::take(&mut *self.0.lock().unwrap());
+            if tasks.is_empty() {
+                break;
+            }
+            for mut task in tasks {
+                if task.as_mut().poll(&mut cx).is_pending() {
+                    pending.push(task);
+                }
+            }
+        }
+        *self.0.lock().unwrap() = pending;
+    }
+}
+
+impl Runtime for Inline {
+
+    type Sleep = future::Ready<()>;
+
+    fn spawn<F>(&self, task: F)
+        where F: Future<Output = ()> + Send + 'static
+    {
+        self.0.lock().unwrap().push(Box::pin(task));
+    }
+
+    fn sleep<T: Time>(&self, _time: T) -> future::Ready<()> {
+        future::ready(())
+    }
+
+    fn block_on<F: Future>(&self, _future: F) -> F::Output {
+        unimplemented!()
+    }
+}
+
+fn unused(_socket: LocalSocket) -> ! {
+    finish()
+}
+
+fn bench<F: Fn()>(name: &str, rt: &Inline, connect: F) {
+    let start = Instant::now();
+    for i in 0..CONNECTS {
+        connect();
+        if i % ROUND == 0 {
+            rt.run();
+        }
+    }
+    rt.run();
+    println!("{}: {:?}", name, start.elapsed());
+}
+
+fn main() {
+    let network = LocalNetwork::new();
+    let rt = Inline::default();
+    for _ in 0..PROVIDERS {
+        let form = RegistrationForm::new(unused, "target".to_string());
+        network.serve(&rt, form, |_| future::ready(())).unwrap();
+    }
+    let service = LocalService::by_id("target".to_string());
+    bench("by name ", &rt, || drop(network.connect(service.clone()).unwrap()));
+    let token = network.resolve(&service).unwrap();
+    bench("by token", &rt, || drop(network.connect_with_token(&token).unwrap()));
+}
@@ -0,0 +1,86 @@
+//! Registration churn and lookups on a large registry, alone and in the
+//! local network, where each object registers and resolves services of
+//! its own.
+//!
+//! Run with 'cargo bench --bench registry'.
+
+extern crate kobzar_ccs;
+
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Instant;
+
+use kobzar_ccs::{OpenNetwork, OwnedService, RegistrationForm, Service, TokenConnect};
+use kobzar_ccs::local::{finish, LocalNetwork, LocalSocket};
+use kobzar_ccs::registry::ShardedRegistry;
+
+const SERVICES: u32 = 50_000;
+const THREADS: u32 = 8;
+
+/// Services each object registers in the local network.
+const PER_OBJECT: u32 = 5_000;
+
+fn run(shards: usize) {
+    let registry = Arc::new(ShardedRegistry::new(shards));
+    let start = Instant::now();
+    let workers: Vec<_> = (0..THREADS).map(|t| {
+        let registry = registry.clone();
+        thread::spawn(move || {
+            for i in (t..SERVICES).step_by(THREADS as usize) {
+                registry.register(i, t, false).unwrap();
+            }
+            for i in 0..SERVICES {
+                registry.providers(&i);
+            }
+            for i in (t..SERVICES).step_by(THREADS as usize) {
+                registry.unregister(&i, &t);
+            }
+        })
+    }).collect();
+    for w in workers {
+        w.join().unwrap();
+    }
+    println!("{:>4} shards: {:?}", shards, start.elapsed());
+}
+
+fn idle(_socket: LocalSocket) -> ! {
+    finish()
+}
+
+/// Each object registers, resolves and discontinues services of its
+/// own. The work per object is the same, so the time stays flat as long
+/// as the objects don't contend.
+fn network(objects: u32) {
+    let network = LocalNetwork::new();
+    let (tx, rx) = mpsc::channel();
+    let start = Instant::now();
+    for o in 0..objects {
+        let (network, tx) = (network.clone(), tx.clone());
+        network.clone().spawn(move || {
+            let ids: Vec<_> = (0..PER_OBJECT).map(|i| format!("{}.{}", o, i)).collect();
+            let owned: Vec<_> = ids.iter()
+                .map(|id| network.register(RegistrationForm::new(idle, id.clone())).unwrap())
+                .collect();
+            for id in &ids {
+                network.resolve(&Service::by_id(id.clone())).unwrap();
+            }
+            for service in owned {
+                service.discontinue();
+            }
+            tx.send(()).unwrap();
+        });
+    }
+    for _ in 0..objects {
+        rx.recv().unwrap();
+    }
+    println!("{:>4} objects on the network: {:?}", objects, start.elapsed());
+}
+
+fn main() {
+    for &shards in &[1, 16, 64] {
+        run(shards);
+    }
+    for &objects in &[1, 2, 4, THREADS] {
+        network(objects);
+    }
+}
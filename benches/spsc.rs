@@ -0,0 +1,112 @@
+//! Throughput of the lock-free ring compared to a mutex-protected
+//! queue for a single producer and a single consumer, alone and as the
+//! buffer of the local channel.
+//!
+//! Run with 'cargo bench --bench spsc'.
+
+extern crate kobzar_ccs;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use kobzar_ccs::{Data, OpenNetwork, RegistrationForm, Service, Socket};
+use kobzar_ccs::local::{finish, LocalNetwork, LocalSocket};
+use kobzar_ccs::spsc;
+
+const MESSAGES: u64 = 2_000_000;
+const CAPACITY: usize = 1024;
+
+/// Buffers up to this size are kept in the rings by the local network.
+const RING_LIMIT: usize = 4096;
+
+fn ring() {
+    let (mut tx, mut rx) = spsc::ring(CAPACITY);
+    let start = Instant::now();
+    let producer = thread::spawn(move || {
+        for i in 0..MESSAGES {
+            let mut item = i;
+            while let Err(back) = tx.push(item) {
+                item = back;
+                thread::yield_now();
+            }
+        }
+    });
+    let mut received = 0;
+    while received < MESSAGES {
+        match rx.pop() {
+            Some(_) => received += 1,
+            None    => thread::yield_now(),
+        }
+    }
+    producer.join().unwrap();
+    println!("spsc ring:      {:?}", start.elapsed());
+}
+
+fn mutex() {
+    let queue = Arc::new(Mutex::new(VecDeque::with_capacity(CAPACITY)));
+    let start = Instant::now();
+    let tx = queue.clone();
+    let producer = thread::spawn(move || {
+        let mut i = 0;
+        while i < MESSAGES {
+            let mut q = tx.lock().unwrap();
+            if q.len() < CAPACITY {
+                q.push_back(i);
+                i += 1;
+            } else {
+                drop(q);
+                thread::yield_now();
+            }
+        }
+    });
+    let mut received = 0;
+    while received < MESSAGES {
+        let item = queue.lock().unwrap().pop_front();
+        match item {
+            Some(_) => received += 1,
+            None    => thread::yield_now(),
+        }
+    }
+    producer.join().unwrap();
+    println!("mutex queue:    {:?}", start.elapsed());
+}
+
+struct Number(u64);
+
+impl Data for Number {
+}
+
+fn sink(socket: LocalSocket) -> ! {
+    for _ in 0..MESSAGES {
+        socket.receive::<Number>().unwrap();
+    }
+    socket.send(Number(MESSAGES)).unwrap();
+    // Queued messages are dropped with the channel, so keep it open.
+    let _ = socket.receive::<Number>();
+    finish()
+}
+
+/// Channel with the buffer of given size, the largest one kept in the
+/// ring and the next one behind the mutex.
+fn channel(capacity: usize) {
+    let network = LocalNetwork::new();
+    network.register(RegistrationForm::new(sink, "sink".to_string()).capacity(capacity))
+        .unwrap();
+    let socket = network.connect(Service::by_id("sink".to_string())).unwrap();
+    let start = Instant::now();
+    for i in 0..MESSAGES {
+        socket.send(Number(i)).unwrap();
+    }
+    assert_eq!(socket.receive::<Number>().unwrap().0, MESSAGES);
+    let path = if capacity <= RING_LIMIT { "ring" } else { "mutex" };
+    println!("{:>5} channel:  {:?}", path, start.elapsed());
+}
+
+fn main() {
+    ring();
+    mutex();
+    channel(RING_LIMIT);
+    channel(RING_LIMIT + 1);
+}
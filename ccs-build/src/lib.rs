@@ -0,0 +1,501 @@
+//! Code generation for CCS services. Build script of the crate that
+//! provides or uses a service reads the service descriptor and gets a
+//! module with the message types, typed client and server skeleton, so
+//! nobody writes the envelopes and codecs by hand:
+//!
+//! ```ignore
+//! // build.rs
+//! extern crate ccs_build;
+//!
+//! fn main() {
+//!     ccs_build::compile("memory.ccs").unwrap();
+//! }
+//!
+//! // lib.rs
+//! include!(concat!(env!("OUT_DIR"), "/memory.rs"));
+//! ```
+//!
+//! Descriptor is written one item per line:
+//!
+//! ```text
+//! # Comment.
+//! service Memory "kobzar.memory"
+//!
+//! struct Block {
+//!     addr: u64
+//!     size: u64
+//! }
+//!
+//! method alloc(u64) -> Block
+//! method free(Block) -> bool
+//! ```
+//!
+//! Field and argument types are 'u8' to 'u64', 'i8' to 'i64', 'bool',
+//! 'string', 'bytes', 'list<T>', 'option<T>' and the structs of the
+//! descriptor. Messages use the binary encoding of
+//! 'kobzar_ccs::message'.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Error of the descriptor parsing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseErr {
+
+    /// Line of the descriptor, starting from 1. Zero for the errors
+    /// of the whole descriptor.
+    pub line    : usize,
+    pub message : String,
+}
+
+/// Error of the build step.
+#[derive(Debug)]
+pub enum BuildErr {
+
+    /// Descriptor could not be read or the module written.
+    Io(io::Error),
+
+    /// Descriptor is not valid.
+    Parse(ParseErr),
+
+    /// Not run by Cargo, so there is no 'OUT_DIR'.
+    NoOutDir,
+}
+
+impl From<io::Error> for BuildErr {
+
+    fn from(e: io::Error) -> Self {
+        BuildErr::Io(e)
+    }
+}
+
+/// Type of the field or the argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Type {
+    Primitive(&'static str),
+    String,
+    Bytes,
+    List(Box<Type>),
+    Option(Box<Type>),
+    Struct(String),
+}
+
+impl Type {
+
+    fn parse(text: &str) -> Option<Type> {
+        const PRIMITIVES: [&str; 9] = ["u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64",
+                "bool"];
+        let inner = |prefix: &str| text.strip_prefix(prefix)
+            .and_then(|t| t.strip_suffix('>'))
+            .and_then(|t| Type::parse(t.trim()))
+            .map(Box::new);
+        if let Some(p) = PRIMITIVES.iter().find(|&&p| p == text) {
+            Some(Type::Primitive(p))
+        } else if text == "string" {
+            Some(Type::String)
+        } else if text == "bytes" {
+            Some(Type::Bytes)
+        } else if text.starts_with("list<") {
+            inner("list<").map(Type::List)
+        } else if text.starts_with("option<") {
+            inner("option<").map(Type::Option)
+        } else if is_ident(text) {
+            Some(Type::Struct(text.to_string()))
+        } else {
+            None
+        }
+    }
+
+    fn rust(&self) -> String {
+        match *self {
+            Type::Primitive(p)      => p.to_string(),
+            Type::String            => "String".to_string(),
+            Type::Bytes             => "Vec<u8>".to_string(),
+            Type::List(ref t)       => format!("Vec<{}>", t.rust()),
+            Type::Option(ref t)     => format!("Option<{}>", t.rust()),
+            Type::Struct(ref name)  => name.clone(),
+        }
+    }
+
+    /// Struct names the type refers to.
+    fn structs(&self) -> Option<&str> {
+        match *self {
+            Type::List(ref t) | Type::Option(ref t) => t.structs(),
+            Type::Struct(ref name)                  => Some(name),
+            _                                       => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Struct {
+    name    : String,
+    fields  : Vec<(String, Type)>,
+    line    : usize,
+}
+
+#[derive(Debug)]
+struct Method {
+    name    : String,
+    request : Type,
+    reply   : Type,
+    line    : usize,
+}
+
+#[derive(Debug)]
+struct Descriptor {
+    name    : String,
+    id      : String,
+    structs : Vec<Struct>,
+    methods : Vec<Method>,
+}
+
+fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn err<T>(line: usize, message: &str) -> Result<T, ParseErr> {
+    Err(ParseErr { line, message: message.to_string() })
+}
+
+fn parse(text: &str) -> Result<Descriptor, ParseErr> {
+    let mut service = None;
+    let mut structs: Vec<Struct> = Vec::new();
+    let mut methods = Vec::new();
+    let mut open: Option<Struct> = None;
+    for (i, line) in text.lines().enumerate() {
+        let n = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(mut s) = open.take() {
+            if line == "}" {
+                structs.push(s);
+                continue;
+            }
+            let (name, ty) = match line.split_once(':') {
+                Some((name, ty))    => (name.trim(), ty.trim()),
+                None                => return err(n, "expected 'name: type' or '}'"),
+            };
+            if !is_ident(name) {
+                return err(n, "bad field name");
+            }
+            let ty = Type::parse(ty).ok_or(ParseErr { line: n, message: "bad type".into() })?;
+            s.fields.push((name.to_string(), ty));
+            open = Some(s);
+            continue;
+        }
+
+        let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        match word {
+            "service"   => {
+                let (name, id) = rest.split_once(' ').unwrap_or((rest, ""));
+                let id = id.trim();
+                if !is_ident(name) || id.len() < 2 || !id.starts_with('"') || !id.ends_with('"') {
+                    return err(n, "expected 'service Name \"id\"'");
+                }
+                if service.is_some() {
+                    return err(n, "second service");
+                }
+                service = Some((name.to_string(), id[1..id.len() - 1].to_string()));
+            },
+            "struct"    => {
+                let name = match rest.strip_suffix('{') {
+                    Some(name) if is_ident(name.trim()) => name.trim(),
+                    _                                   => {
+                        return err(n, "expected 'struct Name {'");
+                    },
+                };
+                if structs.iter().any(|s| s.name == name) {
+                    return err(n, "struct is already defined");
+                }
+                open = Some(Struct { name: name.to_string(), fields: Vec::new(), line: n });
+            },
+            "method"    => {
+                let bad = || ParseErr {
+                    line    : n,
+                    message : "expected 'method name(Type) -> Type'".to_string(),
+                };
+                let (name, rest) = rest.split_once('(').ok_or_else(bad)?;
+                let (request, reply) = rest.split_once(')').ok_or_else(bad)?;
+                let reply = reply.trim().strip_prefix("->").ok_or_else(bad)?;
+                let name = name.trim();
+                if !is_ident(name) {
+                    return Err(bad());
+                }
+                methods.push(Method {
+                    name    : name.to_string(),
+                    request : Type::parse(request.trim()).ok_or_else(bad)?,
+                    reply   : Type::parse(reply.trim()).ok_or_else(bad)?,
+                    line    : n,
+                });
+            },
+            _           => return err(n, "unknown item"),
+        }
+    }
+
+    if let Some(s) = open {
+        return err(s.line, "struct is not closed");
+    }
+    let (name, id) = match service {
+        Some(service)   => service,
+        None            => return err(0, "no service"),
+    };
+    if methods.is_empty() {
+        return err(0, "no methods");
+    }
+    let known = |t: &Type, line| match t.structs() {
+        Some(name) if !structs.iter().any(|s| s.name == name) => err(line, "unknown struct"),
+        _                                                       => Ok(()),
+    };
+    for s in &structs {
+        for (_, ty) in &s.fields {
+            known(ty, s.line)?;
+        }
+    }
+    for m in &methods {
+        known(&m.request, m.line)?;
+        known(&m.reply, m.line)?;
+    }
+    Ok(Descriptor { name, id, structs, methods })
+}
+
+fn camel(name: &str) -> String {
+    name.split('_').map(|part| {
+        let mut chars = part.chars();
+        match chars.next() {
+            Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+            None        => String::new(),
+        }
+    }).collect()
+}
+
+fn snake(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+const MESSAGE: &str = "::kobzar_ccs::message::Message";
+const DECODE_ERR: &str = "::kobzar_ccs::message::DecodeErr";
+const ENCODE_ERR: &str = "::kobzar_ccs::message::EncodeErr";
+const TYPED_ERR: &str = "::kobzar_ccs::message::TypedErr";
+const SOCKET: &str = "<SC as ::kobzar_ccs::Socket<O, S>>";
+const SOCKET_ERR: &str = "::kobzar_ccs::SocketErr";
+
+/// Enum of the requests or replies of all methods.
+fn envelope(out: &mut String, name: &str, methods: &[(String, String)]) {
+    let _ = writeln!(out, "#[derive(Debug, Clone, PartialEq)]");
+    let _ = writeln!(out, "pub enum {} {{", name);
+    for (variant, ty) in methods {
+        let _ = writeln!(out, "    {}({}),", variant, ty);
+    }
+    let _ = writeln!(out, "}}\n");
+    let _ = writeln!(out, "impl {} for {} {{\n", MESSAGE, name);
+    let _ = writeln!(out, "    fn encode(&self, out: &mut Vec<u8>) -> Result<(), {}> {{",
+            ENCODE_ERR);
+    let _ = writeln!(out, "        match *self {{");
+    for (tag, (variant, _)) in methods.iter().enumerate() {
+        let _ = writeln!(out, "            {}::{}(ref body) => {{", name, variant);
+        let _ = writeln!(out, "                out.push({});", tag);
+        let _ = writeln!(out, "                {}::encode(body, out)", MESSAGE);
+        let _ = writeln!(out, "            }},");
+    }
+    let _ = writeln!(out, "        }}");
+    let _ = writeln!(out, "    }}\n");
+    let _ = writeln!(out, "    fn decode(input: &mut &[u8]) -> Result<Self, {}> {{", DECODE_ERR);
+    let _ = writeln!(out, "        match <u8 as {}>::decode(input)? {{", MESSAGE);
+    for (tag, (variant, _)) in methods.iter().enumerate() {
+        let _ = writeln!(out, "            {} => Ok({}::{}({}::decode(input)?)),",
+                tag, name, variant, MESSAGE);
+    }
+    let _ = writeln!(out, "            _ => Err({}::Invalid(\"{}\")),", DECODE_ERR, name);
+    let _ = writeln!(out, "        }}");
+    let _ = writeln!(out, "    }}");
+    let _ = writeln!(out, "}}\n");
+}
+
+fn render(d: &Descriptor) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "// Generated by ccs-build from the descriptor of '{}'. Do not edit.\n",
+            d.id);
+    let _ = writeln!(out, "/// Identifier the service is registered under.");
+    let _ = writeln!(out, "pub const SERVICE_ID: &str = \"{}\";\n", d.id);
+
+    for s in &d.structs {
+        let _ = writeln!(out, "#[derive(Debug, Clone, PartialEq)]");
+        let _ = writeln!(out, "pub struct {} {{", s.name);
+        for (name, ty) in &s.fields {
+            let _ = writeln!(out, "    pub {}: {},", name, ty.rust());
+        }
+        let _ = writeln!(out, "}}\n");
+        let _ = writeln!(out, "impl {} for {} {{\n", MESSAGE, s.name);
+        let _ = writeln!(out, "    fn encode(&self, out: &mut Vec<u8>) -> Result<(), {}> {{",
+                ENCODE_ERR);
+        for (name, _) in &s.fields {
+            let _ = writeln!(out, "        {}::encode(&self.{}, out)?;", MESSAGE, name);
+        }
+        if s.fields.is_empty() {
+            let _ = writeln!(out, "        let _ = out;");
+        }
+        let _ = writeln!(out, "        Ok(())");
+        let _ = writeln!(out, "    }}\n");
+        let _ = writeln!(out, "    fn decode(input: &mut &[u8]) -> Result<Self, {}> {{",
+                DECODE_ERR);
+        if s.fields.is_empty() {
+            let _ = writeln!(out, "        let _ = input;");
+        }
+        let _ = writeln!(out, "        Ok({} {{", s.name);
+        for (name, _) in &s.fields {
+            let _ = writeln!(out, "            {}: {}::decode(input)?,", name, MESSAGE);
+        }
+        let _ = writeln!(out, "        }})");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}\n");
+    }
+
+    let request = format!("{}Request", d.name);
+    let reply = format!("{}Reply", d.name);
+    let variants = |ty: fn(&Method) -> &Type| -> Vec<(String, String)> {
+        d.methods.iter().map(|m| (camel(&m.name), ty(m).rust())).collect()
+    };
+    envelope(&mut out, &request, &variants(|m| &m.request));
+    envelope(&mut out, &reply, &variants(|m| &m.reply));
+
+    let client = format!("{}Client", d.name);
+    let bounds = "    where   O   : ::kobzar_ccs::Object<S>,\n\
+                  \x20           S   : ::kobzar_ccs::Service,\n\
+                  \x20           SC  : ::kobzar_ccs::Socket<O, S>,\n";
+    let _ = writeln!(out, "/// Requester side of the '{}' service.", d.id);
+    let _ = writeln!(out, "pub struct {}<O, S, SC> {{", client);
+    let _ = writeln!(out, "    socket: SC,");
+    let _ = writeln!(out, "    _m: ::std::marker::PhantomData<(O, S)>,");
+    let _ = writeln!(out, "}}\n");
+    let _ = writeln!(out, "impl<O, S, SC> {}<O, S, SC>\n{}{{\n", client, bounds);
+    let _ = writeln!(out, "    /// Make calls over the channel to the service.");
+    let _ = writeln!(out, "    pub fn new(socket: SC) -> Self {{");
+    let _ = writeln!(out, "        {} {{ socket, _m: ::std::marker::PhantomData }}", client);
+    let _ = writeln!(out, "    }}\n");
+    let _ = writeln!(out, "    /// Unwrap the socket.");
+    let _ = writeln!(out, "    pub fn into_inner(self) -> SC {{");
+    let _ = writeln!(out, "        self.socket");
+    let _ = writeln!(out, "    }}\n");
+    let _ = writeln!(out, "    fn call(&self, request: {}) -> Result<{}, {}> {{", request, reply,
+            TYPED_ERR);
+    let _ = writeln!(out, "        let socket = &self.socket;");
+    let _ = writeln!(out, "        let bytes = {}::to_bytes(&request)", MESSAGE);
+    let _ = writeln!(out, "            .map_err({}::Encode)?;", TYPED_ERR);
+    let _ = writeln!(out, "        {}::send(socket, bytes)", SOCKET);
+    let _ = writeln!(out, "            .map_err({}::Socket)?;", TYPED_ERR);
+    let _ = writeln!(out, "        let bytes = {}::receive::<Vec<u8>>(socket)", SOCKET);
+    let _ = writeln!(out, "            .map_err({}::Socket)?;", TYPED_ERR);
+    let _ = writeln!(out, "        <{} as {}>::from_bytes(&bytes).map_err({}::Decode)", reply,
+            MESSAGE, TYPED_ERR);
+    let _ = writeln!(out, "    }}");
+    for m in &d.methods {
+        let variant = camel(&m.name);
+        let _ = writeln!(out);
+        let _ = writeln!(out, "    #[allow(unreachable_patterns)]");
+        let _ = writeln!(out, "    pub fn {}(&self, request: {}) -> Result<{}, {}> {{", m.name,
+                m.request.rust(), m.reply.rust(), TYPED_ERR);
+        let _ = writeln!(out, "        match self.call({}::{}(request))? {{", request, variant);
+        let _ = writeln!(out, "            {}::{}(reply) => Ok(reply),", reply, variant);
+        let _ = writeln!(out, "            _ => Err({}::Decode({}::Invalid(\"{}\"))),",
+                TYPED_ERR, DECODE_ERR, reply);
+        let _ = writeln!(out, "        }}");
+        let _ = writeln!(out, "    }}");
+    }
+    let _ = writeln!(out, "}}\n");
+
+    let server = format!("{}Server", d.name);
+    let _ = writeln!(out, "/// Provider side of the '{}' service.", d.id);
+    let _ = writeln!(out, "pub trait {} {{", server);
+    for m in &d.methods {
+        let _ = writeln!(out, "    fn {}(&mut self, request: {}) -> {};", m.name, m.request.rust(),
+                m.reply.rust());
+    }
+    let _ = writeln!(out, "}}\n");
+    let _ = writeln!(out, "/// Serve the requests on the channel until the requester closes it.");
+    let _ = writeln!(out, "pub fn serve_{}<O, S, SC, H>(socket: &SC, server: &mut H)",
+            snake(&d.name));
+    let _ = writeln!(out, "    -> Result<(), {}>", TYPED_ERR);
+    let _ = writeln!(out, "{}            H   : {}\n{{", bounds, server);
+    let _ = writeln!(out, "    loop {{");
+    let _ = writeln!(out, "        let bytes = match {}::receive::<Vec<u8>>(socket) {{", SOCKET);
+    let _ = writeln!(out, "            Ok(bytes) => bytes,");
+    let _ = writeln!(out, "            Err({}::ChannelClosed) => return Ok(()),", SOCKET_ERR);
+    let _ = writeln!(out, "            Err(e) => return Err({}::Socket(e)),", TYPED_ERR);
+    let _ = writeln!(out, "        }};");
+    let _ = writeln!(out, "        let request = <{} as {}>::from_bytes(&bytes)", request, MESSAGE);
+    let _ = writeln!(out, "            .map_err({}::Decode)?;", TYPED_ERR);
+    let _ = writeln!(out, "        let reply = match request {{");
+    for m in &d.methods {
+        let variant = camel(&m.name);
+        let _ = writeln!(out, "            {}::{}(request) => {}::{}(server.{}(request)),",
+                request, variant, reply, variant, m.name);
+    }
+    let _ = writeln!(out, "        }};");
+    let _ = writeln!(out, "        let bytes = {}::to_bytes(&reply)", MESSAGE);
+    let _ = writeln!(out, "            .map_err({}::Encode)?;", TYPED_ERR);
+    let _ = writeln!(out, "        match {}::send(socket, bytes) {{", SOCKET);
+    let _ = writeln!(out, "            Ok(()) => (),");
+    let _ = writeln!(out, "            Err({}::ChannelClosed) => return Ok(()),", SOCKET_ERR);
+    let _ = writeln!(out, "            Err(e) => return Err({}::Socket(e)),", TYPED_ERR);
+    let _ = writeln!(out, "        }}");
+    let _ = writeln!(out, "    }}");
+    let _ = writeln!(out, "}}");
+    out
+}
+
+/// Generate the module for the descriptor text.
+pub fn generate(descriptor: &str) -> Result<String, ParseErr> {
+    parse(descriptor).map(|d| render(&d))
+}
+
+/// Generate the module for the descriptor file into 'OUT_DIR' of the
+/// build script, named after the file, e.g. 'memory.rs' for
+/// 'memory.ccs'. Returns path of the module.
+pub fn compile<P: AsRef<Path>>(descriptor: P) -> Result<PathBuf, BuildErr> {
+    let descriptor = descriptor.as_ref();
+    let out_dir = env::var_os("OUT_DIR").ok_or(BuildErr::NoOutDir)?;
+    println!("cargo:rerun-if-changed={}", descriptor.display());
+    let text = fs::read_to_string(descriptor)?;
+    let module = generate(&text).map_err(BuildErr::Parse)?;
+    let stem = descriptor.file_stem().unwrap_or_else(|| "service".as_ref());
+    let path = Path::new(&out_dir).join(stem).with_extension("rs");
+    fs::write(&path, module)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_errors() {
+        let line = |text: &str| generate(text).unwrap_err().line;
+        assert_eq!(line("service Memory \"m\"\nmethod alloc(u64) Block"), 2);
+        assert_eq!(line("service Memory \"m\"\nstruct Block {\n    addr u64\n}"), 3);
+        assert_eq!(line("service Memory \"m\"\nmethod free(Block) -> bool"), 2);
+        assert_eq!(line("method free(u64) -> bool"), 0);
+        assert_eq!(snake("NameServer"), "name_server");
+        assert_eq!(camel("get_all"), "GetAll");
+    }
+}
@@ -0,0 +1,66 @@
+extern crate ccs_build;
+extern crate kobzar_ccs;
+
+use kobzar_ccs::{OpenNetwork, RegistrationForm, Service};
+use kobzar_ccs::local::{finish, LocalNetwork, LocalService, LocalSocket};
+
+#[allow(dead_code)]
+mod memory {
+    include!("generated/memory.rs");
+}
+
+use memory::{Block, MemoryClient, MemoryServer, Usage};
+
+/// Generated module checked in above must stay what the generator
+/// makes of the descriptor.
+#[test]
+fn generated_is_current() {
+    let module = ccs_build::generate(include_str!("memory.ccs")).unwrap();
+    assert_eq!(module, include_str!("generated/memory.rs"));
+}
+
+#[derive(Default)]
+struct Allocator {
+    next    : u64,
+    blocks  : Vec<Block>,
+}
+
+impl MemoryServer for Allocator {
+
+    fn alloc(&mut self, size: u64) -> Block {
+        let block = Block { addr: self.next, size };
+        self.next += size;
+        self.blocks.push(block.clone());
+        block
+    }
+
+    fn free(&mut self, block: Block) -> bool {
+        let before = self.blocks.len();
+        self.blocks.retain(|b| *b != block);
+        self.blocks.len() != before
+    }
+
+    fn usage(&mut self, owner: String) -> Usage {
+        Usage { blocks: self.blocks.clone(), owner: Some(owner) }
+    }
+}
+
+fn allocator(socket: LocalSocket) -> ! {
+    let _ = memory::serve_memory(&socket, &mut Allocator::default());
+    finish()
+}
+
+#[test]
+fn client_and_server() {
+    let network = LocalNetwork::new();
+    network.register(RegistrationForm::new(allocator, memory::SERVICE_ID.to_string())).unwrap();
+    let socket = network.connect(LocalService::by_id(memory::SERVICE_ID.to_string())).unwrap();
+    let client = MemoryClient::new(socket);
+
+    let first = client.alloc(16).unwrap();
+    let second = client.alloc(8).unwrap();
+    assert_eq!(second, Block { addr: 16, size: 8 });
+    assert!(client.free(first).unwrap());
+    let usage = client.usage("test".to_string()).unwrap();
+    assert_eq!(usage, Usage { blocks: vec![second], owner: Some("test".to_string()) });
+}
@@ -0,0 +1,216 @@
+// Generated by ccs-build from the descriptor of 'kobzar.memory'. Do not edit.
+
+/// Identifier the service is registered under.
+pub const SERVICE_ID: &str = "kobzar.memory";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub addr: u64,
+    pub size: u64,
+}
+
+impl ::kobzar_ccs::message::Message for Block {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), ::kobzar_ccs::message::EncodeErr> {
+        ::kobzar_ccs::message::Message::encode(&self.addr, out)?;
+        ::kobzar_ccs::message::Message::encode(&self.size, out)?;
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, ::kobzar_ccs::message::DecodeErr> {
+        Ok(Block {
+            addr: ::kobzar_ccs::message::Message::decode(input)?,
+            size: ::kobzar_ccs::message::Message::decode(input)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub blocks: Vec<Block>,
+    pub owner: Option<String>,
+}
+
+impl ::kobzar_ccs::message::Message for Usage {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), ::kobzar_ccs::message::EncodeErr> {
+        ::kobzar_ccs::message::Message::encode(&self.blocks, out)?;
+        ::kobzar_ccs::message::Message::encode(&self.owner, out)?;
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, ::kobzar_ccs::message::DecodeErr> {
+        Ok(Usage {
+            blocks: ::kobzar_ccs::message::Message::decode(input)?,
+            owner: ::kobzar_ccs::message::Message::decode(input)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryRequest {
+    Alloc(u64),
+    Free(Block),
+    Usage(String),
+}
+
+impl ::kobzar_ccs::message::Message for MemoryRequest {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), ::kobzar_ccs::message::EncodeErr> {
+        match *self {
+            MemoryRequest::Alloc(ref body) => {
+                out.push(0);
+                ::kobzar_ccs::message::Message::encode(body, out)
+            },
+            MemoryRequest::Free(ref body) => {
+                out.push(1);
+                ::kobzar_ccs::message::Message::encode(body, out)
+            },
+            MemoryRequest::Usage(ref body) => {
+                out.push(2);
+                ::kobzar_ccs::message::Message::encode(body, out)
+            },
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, ::kobzar_ccs::message::DecodeErr> {
+        match <u8 as ::kobzar_ccs::message::Message>::decode(input)? {
+            0 => Ok(MemoryRequest::Alloc(::kobzar_ccs::message::Message::decode(input)?)),
+            1 => Ok(MemoryRequest::Free(::kobzar_ccs::message::Message::decode(input)?)),
+            2 => Ok(MemoryRequest::Usage(::kobzar_ccs::message::Message::decode(input)?)),
+            _ => Err(::kobzar_ccs::message::DecodeErr::Invalid("MemoryRequest")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryReply {
+    Alloc(Block),
+    Free(bool),
+    Usage(Usage),
+}
+
+impl ::kobzar_ccs::message::Message for MemoryReply {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), ::kobzar_ccs::message::EncodeErr> {
+        match *self {
+            MemoryReply::Alloc(ref body) => {
+                out.push(0);
+                ::kobzar_ccs::message::Message::encode(body, out)
+            },
+            MemoryReply::Free(ref body) => {
+                out.push(1);
+                ::kobzar_ccs::message::Message::encode(body, out)
+            },
+            MemoryReply::Usage(ref body) => {
+                out.push(2);
+                ::kobzar_ccs::message::Message::encode(body, out)
+            },
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, ::kobzar_ccs::message::DecodeErr> {
+        match <u8 as ::kobzar_ccs::message::Message>::decode(input)? {
+            0 => Ok(MemoryReply::Alloc(::kobzar_ccs::message::Message::decode(input)?)),
+            1 => Ok(MemoryReply::Free(::kobzar_ccs::message::Message::decode(input)?)),
+            2 => Ok(MemoryReply::Usage(::kobzar_ccs::message::Message::decode(input)?)),
+            _ => Err(::kobzar_ccs::message::DecodeErr::Invalid("MemoryReply")),
+        }
+    }
+}
+
+/// Requester side of the 'kobzar.memory' service.
+pub struct MemoryClient<O, S, SC> {
+    socket: SC,
+    _m: ::std::marker::PhantomData<(O, S)>,
+}
+
+impl<O, S, SC> MemoryClient<O, S, SC>
+    where   O   : ::kobzar_ccs::Object<S>,
+            S   : ::kobzar_ccs::Service,
+            SC  : ::kobzar_ccs::Socket<O, S>,
+{
+
+    /// Make calls over the channel to the service.
+    pub fn new(socket: SC) -> Self {
+        MemoryClient { socket, _m: ::std::marker::PhantomData }
+    }
+
+    /// Unwrap the socket.
+    pub fn into_inner(self) -> SC {
+        self.socket
+    }
+
+    fn call(&self, request: MemoryRequest) -> Result<MemoryReply, ::kobzar_ccs::message::TypedErr> {
+        let socket = &self.socket;
+        let bytes = ::kobzar_ccs::message::Message::to_bytes(&request)
+            .map_err(::kobzar_ccs::message::TypedErr::Encode)?;
+        <SC as ::kobzar_ccs::Socket<O, S>>::send(socket, bytes)
+            .map_err(::kobzar_ccs::message::TypedErr::Socket)?;
+        let bytes = <SC as ::kobzar_ccs::Socket<O, S>>::receive::<Vec<u8>>(socket)
+            .map_err(::kobzar_ccs::message::TypedErr::Socket)?;
+        <MemoryReply as ::kobzar_ccs::message::Message>::from_bytes(&bytes).map_err(::kobzar_ccs::message::TypedErr::Decode)
+    }
+
+    #[allow(unreachable_patterns)]
+    pub fn alloc(&self, request: u64) -> Result<Block, ::kobzar_ccs::message::TypedErr> {
+        match self.call(MemoryRequest::Alloc(request))? {
+            MemoryReply::Alloc(reply) => Ok(reply),
+            _ => Err(::kobzar_ccs::message::TypedErr::Decode(::kobzar_ccs::message::DecodeErr::Invalid("MemoryReply"))),
+        }
+    }
+
+    #[allow(unreachable_patterns)]
+    pub fn free(&self, request: Block) -> Result<bool, ::kobzar_ccs::message::TypedErr> {
+        match self.call(MemoryRequest::Free(request))? {
+            MemoryReply::Free(reply) => Ok(reply),
+            _ => Err(::kobzar_ccs::message::TypedErr::Decode(::kobzar_ccs::message::DecodeErr::Invalid("MemoryReply"))),
+        }
+    }
+
+    #[allow(unreachable_patterns)]
+    pub fn usage(&self, request: String) -> Result<Usage, ::kobzar_ccs::message::TypedErr> {
+        match self.call(MemoryRequest::Usage(request))? {
+            MemoryReply::Usage(reply) => Ok(reply),
+            _ => Err(::kobzar_ccs::message::TypedErr::Decode(::kobzar_ccs::message::DecodeErr::Invalid("MemoryReply"))),
+        }
+    }
+}
+
+/// Provider side of the 'kobzar.memory' service.
+pub trait MemoryServer {
+    fn alloc(&mut self, request: u64) -> Block;
+    fn free(&mut self, request: Block) -> bool;
+    fn usage(&mut self, request: String) -> Usage;
+}
+
+/// Serve the requests on the channel until the requester closes it.
+pub fn serve_memory<O, S, SC, H>(socket: &SC, server: &mut H)
+    -> Result<(), ::kobzar_ccs::message::TypedErr>
+    where   O   : ::kobzar_ccs::Object<S>,
+            S   : ::kobzar_ccs::Service,
+            SC  : ::kobzar_ccs::Socket<O, S>,
+            H   : MemoryServer
+{
+    loop {
+        let bytes = match <SC as ::kobzar_ccs::Socket<O, S>>::receive::<Vec<u8>>(socket) {
+            Ok(bytes) => bytes,
+            Err(::kobzar_ccs::SocketErr::ChannelClosed) => return Ok(()),
+            Err(e) => return Err(::kobzar_ccs::message::TypedErr::Socket(e)),
+        };
+        let request = <MemoryRequest as ::kobzar_ccs::message::Message>::from_bytes(&bytes)
+            .map_err(::kobzar_ccs::message::TypedErr::Decode)?;
+        let reply = match request {
+            MemoryRequest::Alloc(request) => MemoryReply::Alloc(server.alloc(request)),
+            MemoryRequest::Free(request) => MemoryReply::Free(server.free(request)),
+            MemoryRequest::Usage(request) => MemoryReply::Usage(server.usage(request)),
+        };
+        let bytes = ::kobzar_ccs::message::Message::to_bytes(&reply)
+            .map_err(::kobzar_ccs::message::TypedErr::Encode)?;
+        match <SC as ::kobzar_ccs::Socket<O, S>>::send(socket, bytes) {
+            Ok(()) => (),
+            Err(::kobzar_ccs::SocketErr::ChannelClosed) => return Ok(()),
+            Err(e) => return Err(::kobzar_ccs::message::TypedErr::Socket(e)),
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Adaptive batching and compression of a channel. 'Tuner' observes the
+//! sizes and the rate of the messages and how well they compress, and
+//! decides how many messages to pack into one batch and whether to
+//! compress them. When the messages wait longer than the latency budget
+//! allows, it backs off: batches get smaller and compression is turned
+//! off until the latency is back within the budget. 'AdaptiveSender'
+//! and 'AdaptiveReceiver' apply the decisions on the channel, so
+//! operators don't hand-tune every channel. The crate has no codec of
+//! its own, so compression is given as a 'Compressor'.
+
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use super::{Data, Object, Service, Socket, SocketErr, Time};
+use rt::duration;
+
+/// Codec of the message bytes.
+pub trait Compressor {
+
+    /// Compress the bytes.
+    fn compress(&self, bytes: &[u8]) -> Vec<u8>;
+
+    /// Restore the bytes. None if they are not valid.
+    fn decompress(&self, bytes: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Limits the tuner works within.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptivePolicy {
+
+    /// How long the message may wait in the batch and in the codec.
+    pub latency_budget      : Duration,
+
+    /// Largest batch.
+    pub max_batch           : usize,
+
+    /// Messages smaller than this on average are never compressed.
+    pub min_compress_bytes  : usize,
+
+    /// Compression is used only if it makes messages at most this
+    /// part of their size, e.g. '0.8'.
+    pub max_ratio           : f32,
+}
+
+impl AdaptivePolicy {
+
+    /// Policy with given latency budget, batches of up to 64 messages
+    /// and compression of messages from 256 bytes that shrink at least
+    /// by a fifth.
+    pub fn new<T: Time>(latency_budget: T) -> Self {
+        AdaptivePolicy {
+            latency_budget      : duration(&latency_budget),
+            max_batch           : 64,
+            min_compress_bytes  : 256,
+            max_ratio           : 0.8,
+        }
+    }
+}
+
+/// Current decision of the tuner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tuning {
+
+    /// Messages to pack into one batch. One means no batching.
+    pub batch       : usize,
+    pub compress    : bool,
+}
+
+/// Weight of the newest observation in the moving averages.
+const WEIGHT: f64 = 0.25;
+
+/// Every this many messages the compression is tried even if it is
+/// off, to see whether it became worth it.
+const PROBE_EVERY: u64 = 16;
+
+fn average(old: Option<f64>, new: f64) -> f64 {
+    match old {
+        Some(old)   => old + (new - old) * WEIGHT,
+        None        => new,
+    }
+}
+
+/// Observer of the channel traffic that makes the decisions.
+#[derive(Debug, Clone)]
+pub struct Tuner {
+    policy      : AdaptivePolicy,
+
+    /// Averages of the message size in bytes, of the time between the
+    /// messages in seconds and of the compression ratio.
+    size        : Option<f64>,
+    interval    : Option<f64>,
+    ratio       : Option<f64>,
+
+    last        : Option<Instant>,
+    messages    : u64,
+
+    /// How many times the latency was over the budget recently. Each
+    /// step halves the batch and compression is off while above zero.
+    backoff     : u32,
+}
+
+impl Tuner {
+
+    /// Create the tuner that starts without batching and compression.
+    pub fn new(policy: AdaptivePolicy) -> Self {
+        Tuner {
+            policy,
+            size        : None,
+            interval    : None,
+            ratio       : None,
+            last        : None,
+            messages    : 0,
+            backoff     : 0,
+        }
+    }
+
+    /// Observe the message of given size sent at given instant.
+    pub fn observe_message(&mut self, bytes: usize, at: Instant) {
+        self.size = Some(average(self.size, bytes as f64));
+        if let Some(last) = self.last {
+            let interval = at.saturating_duration_since(last).as_secs_f64();
+            self.interval = Some(average(self.interval, interval));
+        }
+        self.last = Some(at);
+        self.messages += 1;
+    }
+
+    /// Observe how well the message compressed.
+    pub fn observe_compression(&mut self, raw: usize, compressed: usize) {
+        if raw > 0 {
+            self.ratio = Some(average(self.ratio, compressed as f64 / raw as f64));
+        }
+    }
+
+    /// Observe how long the message waited before it was sent. Latency
+    /// over the budget backs off, latency within half of it recovers.
+    pub fn observe_latency<T: Time>(&mut self, latency: T) {
+        let latency = duration(&latency);
+        if latency > self.policy.latency_budget {
+            self.backoff = (self.backoff + 1).min(16);
+        } else if latency <= self.policy.latency_budget / 2 {
+            self.backoff = self.backoff.saturating_sub(1);
+        }
+    }
+
+    /// Whether the compression should be tried on the next message to
+    /// measure it, though it is off now.
+    pub fn should_probe(&self) -> bool {
+        self.messages % PROBE_EVERY == 1
+            && self.size.is_some_and(|s| s >= self.policy.min_compress_bytes as f64)
+    }
+
+    /// Current decision.
+    pub fn tuning(&self) -> Tuning {
+        // Messages that arrive within half of the budget wait together.
+        let window = self.policy.latency_budget.as_secs_f64() / 2.0;
+        let batch = match self.interval {
+            Some(interval) if interval > 0.0    => (window / interval) as usize,
+            Some(_)                             => self.policy.max_batch,
+            None                                => 1,
+        };
+        let batch = (batch >> self.backoff.min(31)).clamp(1, self.policy.max_batch.max(1));
+        let compress = self.backoff == 0
+            && self.size.is_some_and(|s| s >= self.policy.min_compress_bytes as f64)
+            && self.ratio.is_some_and(|r| r <= self.policy.max_ratio as f64);
+        Tuning { batch, compress }
+    }
+}
+
+/// Messages sent together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packed {
+
+    /// Whether each message is compressed.
+    pub compressed  : bool,
+    pub messages    : Vec<Vec<u8>>,
+}
+
+impl Data for Packed {
+}
+
+/// Sending end of the adaptive channel.
+pub struct AdaptiveSender<'a, O, S, SC: 'a, C> {
+    socket      : &'a SC,
+    compressor  : C,
+    tuner       : RefCell<Tuner>,
+
+    /// Messages of the unfinished batch and when the first one came.
+    pending     : RefCell<Vec<Vec<u8>>>,
+    since       : Cell<Option<Instant>>,
+    _os         : PhantomData<(O, S)>,
+}
+
+impl<'a, O, S, SC, C> AdaptiveSender<'a, O, S, SC, C>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                C   : Compressor
+{
+
+    /// Wrap the socket.
+    pub fn new(socket: &'a SC, policy: AdaptivePolicy, compressor: C) -> Self {
+        AdaptiveSender {
+            socket,
+            compressor,
+            tuner       : RefCell::new(Tuner::new(policy)),
+            pending     : RefCell::new(Vec::new()),
+            since       : Cell::new(None),
+            _os         : PhantomData,
+        }
+    }
+
+    /// Current decision of the tuner.
+    pub fn tuning(&self) -> Tuning {
+        self.tuner.borrow().tuning()
+    }
+
+    /// Send the bytes. They may wait in the batch until it fills up,
+    /// until half of the latency budget passes or until 'flush'.
+    pub fn send(&self, bytes: Vec<u8>) -> Result<(), SocketErr> {
+        let now = Instant::now();
+        {
+            let mut tuner = self.tuner.borrow_mut();
+            tuner.observe_message(bytes.len(), now);
+            if !tuner.tuning().compress && tuner.should_probe() {
+                let compressed = self.compressor.compress(&bytes).len();
+                tuner.observe_compression(bytes.len(), compressed);
+            }
+        }
+        if self.since.get().is_none() {
+            self.since.set(Some(now));
+        }
+        self.pending.borrow_mut().push(bytes);
+
+        let tuning = self.tuning();
+        let window = self.tuner.borrow().policy.latency_budget / 2;
+        let waited = self.since.get().map_or(Duration::ZERO, |s| now - s);
+        if self.pending.borrow().len() >= tuning.batch || waited >= window {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Send the unfinished batch now.
+    pub fn flush(&self) -> Result<(), SocketErr> {
+        let messages = ::std::mem::take(&mut *self.pending.borrow_mut());
+        let since = match self.since.take() {
+            Some(since) if !messages.is_empty() => since,
+            _                                   => return Ok(()),
+        };
+        let compressed = self.tuning().compress;
+        let messages = if compressed {
+            let mut tuner = self.tuner.borrow_mut();
+            messages.iter().map(|m| {
+                let c = self.compressor.compress(m);
+                tuner.observe_compression(m.len(), c.len());
+                c
+            }).collect()
+        } else {
+            messages
+        };
+        self.tuner.borrow_mut().observe_latency(since.elapsed());
+        self.socket.send(Packed { compressed, messages })
+    }
+}
+
+/// Receiving end of the adaptive channel.
+pub struct AdaptiveReceiver<'a, O, S, SC: 'a, C> {
+    socket      : &'a SC,
+    compressor  : C,
+
+    /// Messages of the received batch not yet taken.
+    ready       : RefCell<Vec<Vec<u8>>>,
+    _os         : PhantomData<(O, S)>,
+}
+
+impl<'a, O, S, SC, C> AdaptiveReceiver<'a, O, S, SC, C>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                C   : Compressor
+{
+
+    /// Wrap the socket.
+    pub fn new(socket: &'a SC, compressor: C) -> Self {
+        AdaptiveReceiver {
+            socket,
+            compressor,
+            ready       : RefCell::new(Vec::new()),
+            _os         : PhantomData,
+        }
+    }
+
+    /// Receive the next message. Fails with 'SocketErr::UnexpectedData'
+    /// if the compressed message can't be restored.
+    pub fn receive(&self) -> Result<Vec<u8>, SocketErr> {
+        if self.ready.borrow().is_empty() {
+            let packed = self.socket.receive::<Packed>()?;
+            let mut messages = if packed.compressed {
+                packed.messages.iter()
+                    .map(|m| self.compressor.decompress(m).ok_or(SocketErr::UnexpectedData))
+                    .collect::<Result<Vec<_>, _>>()?
+            } else {
+                packed.messages
+            };
+            messages.reverse();
+            *self.ready.borrow_mut() = messages;
+        }
+        self.ready.borrow_mut().pop().ok_or(SocketErr::UnexpectedData)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use {OpenNetwork, RegistrationForm};
+
+    /// Run-length codec: pairs of the count and the byte.
+    struct Rle;
+
+    impl Compressor for Rle {
+
+        fn compress(&self, bytes: &[u8]) -> Vec<u8> {
+            let mut out = Vec::new();
+            for &b in bytes {
+                match out.len() {
+                    n if n >= 2 && out[n - 1] == b && out[n - 2] < 255 => out[n - 2] += 1,
+                    _ => out.extend_from_slice(&[1, b]),
+                }
+            }
+            out
+        }
+
+        fn decompress(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+            if !bytes.len().is_multiple_of(2) {
+                return None;
+            }
+            Some(bytes.chunks(2).flat_map(|p| vec![p[1]; p[0] as usize]).collect())
+        }
+    }
+
+    #[test]
+    fn tuner_backs_off() {
+        let mut tuner = Tuner::new(AdaptivePolicy::new(Duration::from_millis(10)));
+        assert_eq!(tuner.tuning(), Tuning { batch: 1, compress: false });
+
+        let start = Instant::now();
+        for i in 0..8 {
+            tuner.observe_message(1024, start + Duration::from_micros(100 * i));
+        }
+        tuner.observe_compression(1024, 100);
+        assert_eq!(tuner.tuning(), Tuning { batch: 50, compress: true });
+
+        tuner.observe_latency(Duration::from_millis(20));
+        assert_eq!(tuner.tuning(), Tuning { batch: 25, compress: false });
+        tuner.observe_latency(Duration::from_millis(1));
+        assert_eq!(tuner.tuning(), Tuning { batch: 50, compress: true });
+    }
+
+    /// Takes twenty messages and sends them back.
+    fn echo(socket: LocalSocket) -> ! {
+        let receiver = AdaptiveReceiver::new(&socket, Rle);
+        let messages: Result<Vec<_>, _> = (0..20).map(|_| receiver.receive()).collect();
+        if let Ok(messages) = messages {
+            let policy = AdaptivePolicy::new(Duration::from_secs(60));
+            let sender = AdaptiveSender::new(&socket, policy, Rle);
+            for bytes in messages {
+                let _ = sender.send(bytes);
+            }
+            let _ = sender.flush();
+        }
+        finish()
+    }
+
+    #[test]
+    fn batches_round_trip() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let socket = network.connect(LocalService::by_id("echo".to_string())).unwrap();
+        let mut policy = AdaptivePolicy::new(Duration::from_secs(60));
+        policy.min_compress_bytes = 4;
+        let sender = AdaptiveSender::new(&socket, policy, Rle);
+        let receiver = AdaptiveReceiver::new(&socket, Rle);
+
+        for i in 0..20u8 {
+            sender.send(vec![i; 8]).unwrap();
+        }
+        sender.flush().unwrap();
+        assert!(sender.tuning().batch > 1);
+        assert!(sender.tuning().compress);
+        for i in 0..20u8 {
+            assert_eq!(receiver.receive().unwrap(), vec![i; 8]);
+        }
+    }
+}
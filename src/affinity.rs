@@ -0,0 +1,160 @@
+//! Pinning of the channels to scheduling domains. High-rate real-time
+//! channels suffer from the cache and interrupt noise of bulk traffic
+//! when both are delivered on the same cores. Kernel backends group
+//! cores into scheduling domains, some of them isolated, and let the
+//! channel ask to have its delivery processed in given domain. Pinning
+//! is a hint: backends without domains accept it and ignore it.
+
+use std::collections::HashMap;
+
+use super::{Network, Object, Service, Socket};
+
+/// Identifier of the scheduling domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DomainId(pub u32);
+
+/// Where delivery of the channel is processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Affinity {
+
+    /// Anywhere the backend sees fit.
+    #[default]
+    Any,
+
+    /// On any core of the domain.
+    Domain(DomainId),
+
+    /// On single core.
+    Core(u32),
+}
+
+/// Scheduling domain of the backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Domain {
+    pub id          : DomainId,
+
+    /// Cores of the domain.
+    pub cores       : Vec<u32>,
+
+    /// Whether the domain is kept free of bulk traffic. Channels that
+    /// are not pinned are never delivered here.
+    pub isolated    : bool,
+}
+
+/// Error of the pinning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AffinityErr {
+
+    /// There is no such domain or core.
+    NotFound,
+
+    /// Object is not allowed to use the isolated domain.
+    Denied,
+}
+
+/// Network with scheduling domains.
+pub trait AffinityNetwork<S: Service>: Network<S> {
+
+    /// All scheduling domains of the network.
+    fn domains(&self) -> Vec<Domain>;
+}
+
+/// Socket which delivery can be pinned.
+pub trait AffinitySocket<O, S>: Socket<O, S>
+        where O: Object<S>, S: Service {
+
+    /// Process delivery of this channel with given affinity from now on.
+    /// Messages that are already queued may still be delivered with the
+    /// old one.
+    fn pin(&self, affinity: Affinity) -> Result<(), AffinityErr>;
+
+    /// Current affinity of the channel.
+    fn affinity(&self) -> Affinity;
+}
+
+/// Kind of the channel traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Traffic {
+
+    /// Latency-sensitive traffic that needs an isolated domain.
+    RealTime,
+
+    /// Everything else.
+    Bulk,
+}
+
+/// Chooser of the domains for new channels. Real-time channels are
+/// spread over the isolated domains and bulk ones over the rest, each
+/// going to the domain with the fewest pinned channels.
+#[derive(Debug)]
+pub struct DomainPlanner {
+    domains : Vec<Domain>,
+    pinned  : HashMap<DomainId, usize>,
+}
+
+impl DomainPlanner {
+
+    /// Create planner for the domains of the network.
+    pub fn new(domains: Vec<Domain>) -> Self {
+        DomainPlanner {
+            domains,
+            pinned  : HashMap::new(),
+        }
+    }
+
+    /// Affinity for the new channel with given traffic. 'Affinity::Any'
+    /// if the network has no domain for such traffic.
+    pub fn assign(&mut self, traffic: Traffic) -> Affinity {
+        let isolated = traffic == Traffic::RealTime;
+        let pinned = &self.pinned;
+        let id = self.domains.iter()
+            .filter(|d| d.isolated == isolated)
+            .min_by_key(|d| (pinned.get(&d.id).cloned().unwrap_or(0), d.id))
+            .map(|d| d.id);
+        match id {
+            Some(id) => {
+                *self.pinned.entry(id).or_insert(0) += 1;
+                Affinity::Domain(id)
+            },
+            None => Affinity::Any,
+        }
+    }
+
+    /// Forget the channel that was pinned with given affinity when it is
+    /// closed.
+    pub fn release(&mut self, affinity: Affinity) {
+        if let Affinity::Domain(id) = affinity {
+            if let Some(count) = self.pinned.get_mut(&id) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domain(id: u32, isolated: bool) -> Domain {
+        Domain {
+            id      : DomainId(id),
+            cores   : vec![id * 2, id * 2 + 1],
+            isolated,
+        }
+    }
+
+    #[test]
+    fn real_time_goes_to_isolated_domains() {
+        let mut planner = DomainPlanner::new(vec![
+            domain(0, false), domain(1, true), domain(2, true),
+        ]);
+        assert_eq!(planner.assign(Traffic::RealTime), Affinity::Domain(DomainId(1)));
+        assert_eq!(planner.assign(Traffic::RealTime), Affinity::Domain(DomainId(2)));
+        assert_eq!(planner.assign(Traffic::Bulk), Affinity::Domain(DomainId(0)));
+        planner.release(Affinity::Domain(DomainId(1)));
+        assert_eq!(planner.assign(Traffic::RealTime), Affinity::Domain(DomainId(1)));
+
+        let mut flat = DomainPlanner::new(vec![domain(0, false)]);
+        assert_eq!(flat.assign(Traffic::RealTime), Affinity::Any);
+    }
+}
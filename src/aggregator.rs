@@ -0,0 +1,241 @@
+//! Fan-in aggregator. Many producers, e.g. channels of objects that
+//! send logs or telemetry, are merged into a single stream. Each
+//! producer has its own bounded queue so that a fast producer can only
+//! fill its own queue and gets backpressure instead of starving the
+//! others. The consumer takes items from the queues in turn.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+use super::{Data, Object, Service, Socket, SocketErr};
+
+/// Aggregator that merges items of many producers.
+pub struct Aggregator<T> {
+    shared  : Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    state       : Mutex<State<T>>,
+
+    /// Notified when some item is pushed or a producer is closed.
+    pushed      : Condvar,
+
+    /// Notified when some item is taken.
+    taken       : Condvar,
+
+    /// Maximal count of items queued for single producer.
+    capacity    : usize,
+}
+
+struct State<T> {
+    queues      : Vec<Queue<T>>,
+
+    /// Index of the queue to take next item from.
+    next        : usize,
+
+    /// Identifier to assign to the next producer.
+    next_id     : usize,
+}
+
+struct Queue<T> {
+    id          : usize,
+    items       : VecDeque<T>,
+
+    /// Producer is dropped and no new items will come.
+    closed      : bool,
+}
+
+/// Handle of a single producer. The producer is closed when the
+/// handle is dropped. Items it already pushed are still delivered.
+pub struct Producer<T> {
+    shared  : Arc<Shared<T>>,
+    id      : usize,
+}
+
+impl<T> Aggregator<T> {
+
+    /// Create aggregator that keeps at most 'capacity' items queued for
+    /// each producer.
+    pub fn new(capacity: usize) -> Self {
+        Aggregator {
+            shared  : Arc::new(Shared {
+                state       : Mutex::new(State {
+                    queues      : Vec::new(),
+                    next        : 0,
+                    next_id     : 0,
+                }),
+                pushed      : Condvar::new(),
+                taken       : Condvar::new(),
+                capacity,
+            }),
+        }
+    }
+
+    /// Add new producer.
+    pub fn producer(&self) -> Producer<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.queues.push(Queue {
+            id,
+            items   : VecDeque::new(),
+            closed  : false,
+        });
+        Producer {
+            shared  : self.shared.clone(),
+            id,
+        }
+    }
+
+    /// Count of items queued for all producers.
+    pub fn len(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state.queues.iter().map(|q| q.items.len()).sum()
+    }
+
+    /// Check if no items are queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take next item waiting until some producer pushes it. Returns
+    /// None when all producers are closed and all their items are
+    /// taken.
+    pub fn next_item(&self) -> Option<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.take() {
+                self.shared.taken.notify_all();
+                return Some(item);
+            }
+            if state.queues.is_empty() {
+                return None;
+            }
+            state = self.shared.pushed.wait(state).unwrap();
+        }
+    }
+
+    /// Take next item if there is one.
+    pub fn try_next(&self) -> Option<T> {
+        let item = self.shared.state.lock().unwrap().take();
+        if item.is_some() {
+            self.shared.taken.notify_all();
+        }
+        item
+    }
+
+    /// Send all items to the socket until all producers are closed or
+    /// the socket fails.
+    pub fn drain_to<O, S, SC>(&self, socket: &SC) -> Result<(), SocketErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                T   : Data
+    {
+        while let Some(item) = self.next_item() {
+            socket.send(item)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> State<T> {
+
+    /// Take item from the next queue in turn that has one. Queues that
+    /// are closed and empty are removed.
+    fn take(&mut self) -> Option<T> {
+        self.queues.retain(|q| !q.closed || !q.items.is_empty());
+        let count = self.queues.len();
+        for i in 0..count {
+            let index = (self.next + i) % count;
+            if let Some(item) = self.queues[index].items.pop_front() {
+                self.next = (index + 1) % count;
+                return Some(item);
+            }
+        }
+        None
+    }
+}
+
+impl<T> Producer<T> {
+
+    /// Push the item waiting while the queue of this producer is full.
+    pub fn push(&self, item: T) {
+        let shared = &self.shared;
+        let mut state = shared.state.lock().unwrap();
+        loop {
+            let queue = state.queues.iter_mut().find(|q| q.id == self.id)
+                .expect("producer queue is removed only when closed");
+            if queue.items.len() < shared.capacity {
+                queue.items.push_back(item);
+                shared.pushed.notify_one();
+                return;
+            }
+            state = shared.taken.wait(state).unwrap();
+        }
+    }
+
+    /// Push the item if the queue of this producer is not full.
+    /// Otherwise the item is returned back.
+    pub fn try_push(&self, item: T) -> Result<(), T> {
+        let mut state = self.shared.state.lock().unwrap();
+        let queue = state.queues.iter_mut().find(|q| q.id == self.id)
+            .expect("producer queue is removed only when closed");
+        if queue.items.len() >= self.shared.capacity {
+            return Err(item);
+        }
+        queue.items.push_back(item);
+        self.shared.pushed.notify_one();
+        Ok(())
+    }
+
+    /// Push all items received from the socket until the channel gets
+    /// closed. The sender on the other side of the channel gets
+    /// backpressure when the queue of this producer is full.
+    pub fn feed_from<O, S, SC>(&self, socket: &SC) -> Result<(), SocketErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                T   : Data
+    {
+        loop {
+            match socket.receive::<T>() {
+                Ok(item)                        => self.push(item),
+                Err(SocketErr::ChannelClosed)   => return Ok(()),
+                Err(e)                          => return Err(e),
+            }
+        }
+    }
+}
+
+impl<T> Drop for Producer<T> {
+
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(queue) = state.queues.iter_mut().find(|q| q.id == self.id) {
+            queue.closed = true;
+        }
+        self.shared.pushed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robin() {
+        let aggregator = Aggregator::new(4);
+        let a = aggregator.producer();
+        let b = aggregator.producer();
+        a.push(1);
+        a.push(2);
+        b.push(10);
+        assert_eq!(b.try_push(11), Ok(()));
+        drop(a);
+        drop(b);
+
+        let items: Vec<_> = ::std::iter::from_fn(|| aggregator.next_item()).collect();
+        assert_eq!(items, vec![1, 10, 2, 11]);
+    }
+}
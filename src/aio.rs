@@ -0,0 +1,84 @@
+//! Asynchronous counterparts of the CCS traits. Operations return
+//! futures instead of blocking current thread so that a single thread
+//! can drive many of them on some runtime (see 'rt' module).
+
+use std::future::Future;
+
+use super::{ConnectErr, Data, Form, Network, Object, ObjectId, OpenNetwork,
+        RegistrationErr, Service, Socket, SocketErr, Time};
+use cancel::CancelToken;
+
+/// Error of the asynchronous operation.
+#[derive(Debug)]
+pub enum AsyncErr<E> {
+
+    /// Operation itself has failed with given error.
+    Failed(E),
+
+    /// Operation was stopped because its cancel token was triggered.
+    Cancelled,
+}
+
+/// Network which events can be awaited.
+pub trait AsyncNetwork<S>: Network<S> where S: Service {
+
+    /// Resolves when some object in the network provides the service
+    /// with given identifier. Same as 'wait_for_service' but without
+    /// blocking.
+    fn wait_for_service_async(&self, id: &S::Id) -> impl Future<Output = ()>;
+
+    /// Resolves when the object with given identifier dies. Same as
+    /// 'wait_for_death' but without blocking.
+    fn wait_for_death_async(&self, id: &ObjectId<S, Self>)
+        -> impl Future<Output = ()>;
+}
+
+/// Open network which lifecycle operations can be awaited.
+/// Each operation accepts a cancel token. When token gets cancelled
+/// while operation is still pending, the future resolves with
+/// 'AsyncErr::Cancelled' and nothing is connected or registered.
+pub trait AsyncOpenNetwork<S>: OpenNetwork<S> + AsyncNetwork<S>
+        where S: Service {
+
+    /// Connect to a service provider. Same as 'connect' but without
+    /// blocking.
+    fn connect_async(&self, service: S, cancel: &CancelToken)
+        -> impl Future<Output = Result<Self::Socket, AsyncErr<ConnectErr<S>>>>;
+
+    /// Register new service that current object is ready to provide.
+    /// Same as 'register' but without blocking.
+    fn register_async(&self, reg_form: Form<S, Self>, cancel: &CancelToken)
+        -> impl Future<Output = Result<Self::OwnedService, AsyncErr<RegistrationErr>>>;
+
+    /// Uniquely register new service. Same as 'register_unique' but
+    /// without blocking.
+    fn register_unique_async(&self, reg_form: Form<S, Self>,
+            cancel: &CancelToken)
+        -> impl Future<Output = Result<Self::OwnedService, AsyncErr<RegistrationErr>>>;
+}
+
+/// Socket which operations can be awaited, so that a single thread can
+/// serve many channels. When the cancel token gets cancelled while the
+/// operation is still pending, the future resolves with
+/// 'AsyncErr::Cancelled' and nothing is received, and the data that the
+/// peer has not taken yet is not sent.
+pub trait AsyncSocket<O, S>: Socket<O, S> where O: Object<S>, S: Service {
+
+    /// Resolves when some data is received. Same as 'receive' but
+    /// without blocking.
+    fn receive_async<D: Data>(&self, cancel: &CancelToken)
+        -> impl Future<Output = Result<D, AsyncErr<SocketErr>>>;
+
+    /// Resolves when the peer receives the data. Same as 'send' but
+    /// without blocking.
+    fn send_async<D: Data>(&self, data: D, cancel: &CancelToken)
+        -> impl Future<Output = Result<(), AsyncErr<SocketErr>>>;
+
+    /// Same as 'receive_async' but resolves with None after timeout.
+    fn wait_to_receive_async<D: Data, T: Time>(&self, time: T, cancel: &CancelToken)
+        -> impl Future<Output = Option<Result<D, AsyncErr<SocketErr>>>>;
+
+    /// Same as 'wait_to_send' but without blocking.
+    fn wait_to_send_async<T: Time>(&self, time: T, cancel: &CancelToken)
+        -> impl Future<Output = Option<Result<(), AsyncErr<SocketErr>>>>;
+}
@@ -0,0 +1,170 @@
+//! Attestation of unique services. The network records which object
+//! claimed each unique service and with which credentials, so objects
+//! started later can verify that they talk to the genuine provider,
+//! e.g. the Memory Server started at boot, and not to an impostor that
+//! managed to register the service before it.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use super::{Network, Service};
+
+/// Record of the unique registration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attestation<Id, OId> {
+
+    /// Identifier of the service.
+    pub service     : Id,
+
+    /// Object that registered the service.
+    pub object      : OId,
+
+    /// Name of the program the object runs.
+    pub program     : String,
+
+    /// Names of the capabilities the object held at registration.
+    pub credentials : Vec<String>,
+
+    /// Order of the registration since the network started, from zero.
+    pub order       : u64,
+}
+
+/// What the client expects from the provider of the unique service.
+#[derive(Debug, Clone, Default)]
+pub struct Expectation {
+
+    /// Name of the program that must provide the service.
+    pub program     : Option<String>,
+
+    /// Capabilities the provider must have held.
+    pub credentials : Vec<String>,
+
+    /// Service must have been registered among the first 'n'.
+    pub within_first: Option<u64>,
+}
+
+/// Attestation does not meet the expectation.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AttestErr {
+
+    /// Service has no attestation, e.g. it is not uniquely registered.
+    Unknown,
+
+    /// Service is provided by some other program.
+    Program,
+
+    /// Provider lacked the capability with given name.
+    Credential(String),
+
+    /// Service was registered too late.
+    Late,
+}
+
+impl<Id, OId> Attestation<Id, OId> {
+
+    /// Check the record against the expectation.
+    pub fn verify(&self, expected: &Expectation) -> Result<(), AttestErr> {
+        if expected.program.as_ref().is_some_and(|p| *p != self.program) {
+            return Err(AttestErr::Program);
+        }
+        if let Some(c) = expected.credentials.iter()
+                .find(|c| !self.credentials.contains(c)) {
+            return Err(AttestErr::Credential(c.clone()));
+        }
+        if expected.within_first.is_some_and(|n| self.order >= n) {
+            return Err(AttestErr::Late);
+        }
+        Ok(())
+    }
+}
+
+/// Network that attests its unique services.
+pub trait AttestingNetwork<S: Service>: Network<S> {
+
+    /// Identifier of the object in this network.
+    type ObjectId;
+
+    /// Get attestation of the uniquely registered service.
+    fn attestation(&self, service: &S::Id)
+        -> Option<Attestation<S::Id, Self::ObjectId>>;
+
+    /// Get attestation and verify it. Shortcut for the clients that
+    /// connect to well-known services.
+    fn verify(&self, service: &S::Id, expected: &Expectation)
+        -> Result<(), AttestErr>
+    {
+        self.attestation(service).ok_or(AttestErr::Unknown)?.verify(expected)
+    }
+}
+
+/// Log of the unique registrations for backends to keep.
+pub struct AttestationLog<Id, OId> {
+    state   : Mutex<LogState<Id, OId>>,
+}
+
+/// Count of registrations so far and the current records.
+type LogState<Id, OId> = (u64, HashMap<Id, Attestation<Id, OId>>);
+
+impl<Id, OId> Default for AttestationLog<Id, OId> {
+
+    fn default() -> Self {
+        AttestationLog {
+            state   : Mutex::new((0, HashMap::new())),
+        }
+    }
+}
+
+impl<Id, OId> AttestationLog<Id, OId>
+        where Id: Hash + Eq + Clone, OId: Clone {
+
+    /// Record the unique registration and return the attestation.
+    /// Record of the previous provider is replaced, but the order keeps
+    /// growing, so the late provider can be told from the one at boot.
+    pub fn record(&self, service: Id, object: OId, program: &str,
+            credentials: Vec<String>) -> Attestation<Id, OId> {
+        let mut state = self.state.lock().unwrap();
+        let attestation = Attestation {
+            service     : service.clone(),
+            object,
+            program     : program.to_string(),
+            credentials,
+            order       : state.0,
+        };
+        state.0 += 1;
+        state.1.insert(service, attestation.clone());
+        attestation
+    }
+
+    /// Remove the record when the service is discontinued.
+    pub fn remove(&self, service: &Id) {
+        self.state.lock().unwrap().1.remove(service);
+    }
+
+    /// Get the record of the service.
+    pub fn get(&self, service: &Id) -> Option<Attestation<Id, OId>> {
+        self.state.lock().unwrap().1.get(service).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impostor_is_late() {
+        let log = AttestationLog::default();
+        log.record("memory", 1, "memory-server", vec!["boot".to_string()]);
+        let expected = Expectation {
+            program     : Some("memory-server".to_string()),
+            credentials : vec!["boot".to_string()],
+            within_first: Some(1),
+        };
+        assert_eq!(log.get(&"memory").unwrap().verify(&expected), Ok(()));
+
+        log.remove(&"memory");
+        log.record("memory", 2, "memory-server", vec!["boot".to_string()]);
+        assert_eq!(log.get(&"memory").unwrap().verify(&expected),
+                Err(AttestErr::Late));
+    }
+}
@@ -0,0 +1,194 @@
+//! Cache of the authorization decisions. Evaluating the policy walks
+//! its rules and matches their patterns, which is too slow to repeat on
+//! every connect in hot paths. Decisions are remembered per subject,
+//! service and action instead, and the policy engine pushes the
+//! invalidation whenever the rules or the capabilities of some subject
+//! change, so a cached decision never outlives what it was made from.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+
+use policy::{Action, Decision, Policy, Request};
+
+/// What the cached decision was made about. Capabilities of the subject
+/// are not part of the key: their change must be pushed with
+/// 'Invalidation::Subject'.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DecisionKey {
+    pub action  : Action,
+    pub subject : String,
+    pub service : String,
+}
+
+impl<'a> From<&'a Request<'a>> for DecisionKey {
+
+    fn from(request: &'a Request<'a>) -> Self {
+        DecisionKey {
+            action  : request.action,
+            subject : request.subject.to_string(),
+            service : request.service.to_string(),
+        }
+    }
+}
+
+/// Which cached decisions are no longer valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation {
+
+    /// Rules changed, so are all the decisions.
+    All,
+
+    /// Capabilities of the subject with given name changed.
+    Subject(String),
+}
+
+/// Storage of the decisions.
+pub trait DecisionCache: Send + Sync {
+
+    /// Decision made earlier for the key.
+    fn get(&self, key: &DecisionKey) -> Option<Decision>;
+
+    /// Remember the decision.
+    fn put(&self, key: DecisionKey, decision: Decision);
+
+    /// Forget the decisions that are no longer valid.
+    fn invalidate(&self, what: &Invalidation);
+}
+
+/// Cache that keeps up to given count of decisions in memory. When it
+/// is full, everything is forgotten and the cache fills anew.
+pub struct MemoryDecisionCache {
+    decisions   : Mutex<HashMap<DecisionKey, Decision>>,
+    capacity    : usize,
+}
+
+impl MemoryDecisionCache {
+
+    /// Create cache that keeps at most 'capacity' decisions.
+    pub fn new(capacity: usize) -> Self {
+        MemoryDecisionCache {
+            decisions   : Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    /// Count of the cached decisions.
+    pub fn len(&self) -> usize {
+        self.decisions.lock().unwrap().len()
+    }
+
+    /// Check if no decision is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl DecisionCache for MemoryDecisionCache {
+
+    fn get(&self, key: &DecisionKey) -> Option<Decision> {
+        self.decisions.lock().unwrap().get(key).cloned()
+    }
+
+    fn put(&self, key: DecisionKey, decision: Decision) {
+        let mut decisions = self.decisions.lock().unwrap();
+        if decisions.len() >= self.capacity {
+            decisions.clear();
+        }
+        decisions.insert(key, decision);
+    }
+
+    fn invalidate(&self, what: &Invalidation) {
+        let mut decisions = self.decisions.lock().unwrap();
+        match *what {
+            Invalidation::All               => decisions.clear(),
+            Invalidation::Subject(ref name) => decisions.retain(|k, _| k.subject != *name),
+        }
+    }
+}
+
+/// Policy engine that answers from the cache when it can. The policy
+/// is replaced and the invalidations are pushed under the write lock,
+/// so no decision made by the old rules gets cached after the change.
+pub struct CachedPolicy<C: DecisionCache> {
+    policy  : RwLock<Policy>,
+    cache   : C,
+}
+
+impl<C: DecisionCache> CachedPolicy<C> {
+
+    /// Create engine of the policy that caches its decisions in 'cache'.
+    pub fn new(policy: Policy, cache: C) -> Self {
+        CachedPolicy {
+            policy  : RwLock::new(policy),
+            cache,
+        }
+    }
+
+    /// Decide on the request, evaluating the policy only on the cache
+    /// miss.
+    pub fn evaluate(&self, request: &Request) -> Decision {
+        let policy = self.policy.read().unwrap();
+        let key = DecisionKey::from(request);
+        if let Some(decision) = self.cache.get(&key) {
+            return decision;
+        }
+        let decision = policy.evaluate(request);
+        self.cache.put(key, decision.clone());
+        decision
+    }
+
+    /// Replace the rules and drop all the cached decisions.
+    pub fn set_policy(&self, policy: Policy) {
+        let mut current = self.policy.write().unwrap();
+        *current = policy;
+        self.cache.invalidate(&Invalidation::All);
+    }
+
+    /// Drop the decisions about the subject which capabilities were
+    /// granted or revoked.
+    pub fn capabilities_changed(&self, subject: &str) {
+        let _policy = self.policy.write().unwrap();
+        self.cache.invalidate(&Invalidation::Subject(subject.to_string()));
+    }
+
+    /// Current rules.
+    pub fn policy(&self) -> Policy {
+        self.policy.read().unwrap().clone()
+    }
+
+    /// Cache of the decisions.
+    pub fn cache(&self) -> &C {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalidation_pushed() {
+        let policy = Policy::parse("allow connect service=driver.* require=drivers").unwrap();
+        let engine = CachedPolicy::new(policy, MemoryDecisionCache::new(16));
+        let drivers = ["drivers".to_string()];
+        let request = |capabilities| Request {
+            action  : Action::Connect,
+            subject : "app",
+            service : "driver.disk",
+            capabilities,
+        };
+
+        let missing = Decision::MissingCapability(0, "drivers".to_string());
+        assert_eq!(engine.evaluate(&request(&[])), missing);
+        assert_eq!(engine.cache().len(), 1);
+
+        // Stale until the grant is pushed.
+        assert_eq!(engine.evaluate(&request(&drivers)), missing);
+        engine.capabilities_changed("app");
+        assert_eq!(engine.evaluate(&request(&drivers)), Decision::Allow);
+
+        engine.set_policy(Policy::parse("default deny").unwrap());
+        assert!(engine.cache().is_empty());
+        assert_eq!(engine.evaluate(&request(&drivers)), Decision::Deny(None));
+    }
+}
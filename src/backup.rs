@@ -0,0 +1,409 @@
+//! Backup and restore of stateful services. Coordinator asks each
+//! service for its state, which the service streams back in chunks, and
+//! the chunks are put to the backup sink. On restore the chunks are read
+//! back from the sink and streamed to the services in dependency order:
+//! a service is restored only after all services it depends on, so when
+//! it starts to use them they already hold the restored state. The
+//! dependencies are those of the shutdown plan of the system.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use super::{Data, Object, Service, Socket, SocketErr};
+use shutdown::{ShutdownErr, ShutdownPlan};
+
+/// Message of the backup protocol. Each carries the identifier of the
+/// backup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackupMsg {
+
+    /// Coordinator asks for the state. Service replies with chunks
+    /// followed by 'End', or with 'Failed'.
+    Backup(u64),
+
+    /// Part of the state.
+    Chunk(u64, Vec<u8>),
+
+    /// End of the chunks.
+    End(u64),
+
+    /// Coordinator is going to replace the state. Chunks follow, then
+    /// 'End'. Service replies with 'Restored' or 'Failed'.
+    Restore(u64),
+
+    /// Service replaced its state with the restored one.
+    Restored(u64),
+
+    /// Service could not back up or restore the state.
+    Failed(u64, String),
+}
+
+impl Data for BackupMsg {
+}
+
+/// Error of the backup or restore.
+#[derive(Debug)]
+pub enum BackupErr<Id> {
+
+    /// Channel to the service failed.
+    Socket(Id, SocketErr),
+
+    /// Service replied out of protocol.
+    Unexpected(Id, BackupMsg),
+
+    /// Service reported the failure.
+    Failed(Id, String),
+
+    /// Sink could not store the chunk.
+    Sink(Id, String),
+
+    /// Sink has no backup of the service.
+    Missing(Id),
+
+    /// Service could not be reached for restore.
+    Unreachable(Id),
+
+    /// Services depend on each other and there is no order to restore
+    /// them.
+    Cycle(Vec<Id>),
+}
+
+impl<Id> From<ShutdownErr<Id>> for BackupErr<Id> {
+
+    fn from(e: ShutdownErr<Id>) -> Self {
+        match e {
+            ShutdownErr::Cycle(ids) => BackupErr::Cycle(ids),
+        }
+    }
+}
+
+/// Storage of the backups, e.g. a file system or a remote archive.
+pub trait BackupSink<Id> {
+
+    /// Drop what is stored for the service under given backup. Called
+    /// before the chunks are streamed and when the stream fails, so
+    /// that the sink never holds a partial state.
+    fn discard(&self, backup: u64, service: &Id);
+
+    /// Append the chunk to the state of the service.
+    fn append(&self, backup: u64, service: &Id, chunk: Vec<u8>) -> Result<(), String>;
+
+    /// Chunks of the service state in the order they were appended.
+    fn chunks(&self, backup: u64, service: &Id) -> Option<Vec<Vec<u8>>>;
+}
+
+type Chunks<Id> = HashMap<(u64, Id), Vec<Vec<u8>>>;
+
+/// Sink that keeps the backups in memory.
+pub struct MemoryBackupSink<Id: Eq + Hash> {
+    chunks  : Mutex<Chunks<Id>>,
+}
+
+impl<Id: Eq + Hash> MemoryBackupSink<Id> {
+
+    /// Create empty sink.
+    pub fn new() -> Self {
+        MemoryBackupSink {
+            chunks  : Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<Id: Eq + Hash> Default for MemoryBackupSink<Id> {
+
+    fn default() -> Self {
+        MemoryBackupSink::new()
+    }
+}
+
+impl<Id: Eq + Hash + Clone> BackupSink<Id> for MemoryBackupSink<Id> {
+
+    fn discard(&self, backup: u64, service: &Id) {
+        self.chunks.lock().unwrap().remove(&(backup, service.clone()));
+    }
+
+    fn append(&self, backup: u64, service: &Id, chunk: Vec<u8>) -> Result<(), String> {
+        self.chunks.lock().unwrap()
+            .entry((backup, service.clone()))
+            .or_default()
+            .push(chunk);
+        Ok(())
+    }
+
+    fn chunks(&self, backup: u64, service: &Id) -> Option<Vec<Vec<u8>>> {
+        self.chunks.lock().unwrap().get(&(backup, service.clone())).cloned()
+    }
+}
+
+/// Service side of the backup.
+pub trait Stateful {
+
+    /// Write the state as a sequence of chunks.
+    fn backup(&mut self, chunk: &mut dyn FnMut(Vec<u8>)) -> Result<(), String>;
+
+    /// Replace the state with the one made of given chunks.
+    fn restore(&mut self, chunks: Vec<Vec<u8>>) -> Result<(), String>;
+}
+
+fn receive<O, S, SC, Id>(service: &Id, socket: &SC) -> Result<BackupMsg, BackupErr<Id>>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            Id  : Clone
+{
+    socket.receive::<BackupMsg>().map_err(|e| BackupErr::Socket(service.clone(), e))
+}
+
+/// Back up the services over the channels to them. Each state is put to
+/// the sink under given backup identifier. Stops at the first service
+/// that fails, leaving no partial state of it in the sink.
+pub fn backup<O, S, SC, Id, B>(id: u64, services: &[(Id, SC)], sink: &B)
+    -> Result<(), BackupErr<Id>>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            Id  : Clone,
+            B   : BackupSink<Id>
+{
+    for (service, socket) in services {
+        sink.discard(id, service);
+        let result = stream_to_sink(id, service, socket, sink);
+        if result.is_err() {
+            sink.discard(id, service);
+        }
+        result?;
+    }
+    Ok(())
+}
+
+fn stream_to_sink<O, S, SC, Id, B>(id: u64, service: &Id, socket: &SC, sink: &B)
+    -> Result<(), BackupErr<Id>>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            Id  : Clone,
+            B   : BackupSink<Id>
+{
+    socket.send(BackupMsg::Backup(id)).map_err(|e| BackupErr::Socket(service.clone(), e))?;
+    loop {
+        match receive(service, socket)? {
+            BackupMsg::Chunk(_, chunk)  => sink.append(id, service, chunk)
+                .map_err(|e| BackupErr::Sink(service.clone(), e))?,
+            BackupMsg::End(_)           => return Ok(()),
+            BackupMsg::Failed(_, e)     => return Err(BackupErr::Failed(service.clone(), e)),
+            msg                         => return Err(BackupErr::Unexpected(service.clone(), msg)),
+        }
+    }
+}
+
+/// Restore the services of the plan from the backup with given
+/// identifier. The channel to each service is opened by 'connect' right
+/// before its restore. Returns the services in the order they were
+/// restored.
+pub fn restore<O, S, SC, Id, B, F>(id: u64, plan: &ShutdownPlan<Id>, sink: &B, mut connect: F)
+    -> Result<Vec<Id>, BackupErr<Id>>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            Id  : PartialEq + Clone,
+            B   : BackupSink<Id>,
+            F   : FnMut(&Id) -> Option<SC>
+{
+    let mut restored = Vec::new();
+    for stage in plan.stages()?.into_iter().rev() {
+        for service in stage {
+            let chunks = sink.chunks(id, &service)
+                .ok_or_else(|| BackupErr::Missing(service.clone()))?;
+            let socket = connect(&service)
+                .ok_or_else(|| BackupErr::Unreachable(service.clone()))?;
+            restore_one(id, &service, &socket, chunks)?;
+            restored.push(service);
+        }
+    }
+    Ok(restored)
+}
+
+fn restore_one<O, S, SC, Id>(id: u64, service: &Id, socket: &SC, chunks: Vec<Vec<u8>>)
+    -> Result<(), BackupErr<Id>>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            Id  : Clone
+{
+    let send = |msg| socket.send(msg).map_err(|e| BackupErr::Socket(service.clone(), e));
+    send(BackupMsg::Restore(id))?;
+    for chunk in chunks {
+        send(BackupMsg::Chunk(id, chunk))?;
+    }
+    send(BackupMsg::End(id))?;
+    match receive(service, socket)? {
+        BackupMsg::Restored(_)  => Ok(()),
+        BackupMsg::Failed(_, e) => Err(BackupErr::Failed(service.clone(), e)),
+        msg                     => Err(BackupErr::Unexpected(service.clone(), msg)),
+    }
+}
+
+/// Serve backup and restore requests on the channel from the
+/// coordinator until the channel fails.
+pub fn serve<O, S, SC, T>(socket: &SC, state: &mut T) -> Result<(), SocketErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            T   : Stateful
+{
+    loop {
+        match socket.receive::<BackupMsg>()? {
+            BackupMsg::Backup(id)   => serve_backup(socket, state, id)?,
+            BackupMsg::Restore(id)  => serve_restore(socket, state, id)?,
+            _                       => (),
+        }
+    }
+}
+
+fn serve_backup<O, S, SC, T>(socket: &SC, state: &mut T, id: u64) -> Result<(), SocketErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            T   : Stateful
+{
+    let mut failed = None;
+    let result = state.backup(&mut |chunk| {
+        if failed.is_none() {
+            failed = socket.send(BackupMsg::Chunk(id, chunk)).err();
+        }
+    });
+    if let Some(e) = failed {
+        return Err(e);
+    }
+    match result {
+        Ok(())  => socket.send(BackupMsg::End(id)),
+        Err(e)  => socket.send(BackupMsg::Failed(id, e)),
+    }
+}
+
+fn serve_restore<O, S, SC, T>(socket: &SC, state: &mut T, id: u64) -> Result<(), SocketErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            T   : Stateful
+{
+    let mut chunks = Vec::new();
+    loop {
+        match socket.receive::<BackupMsg>()? {
+            BackupMsg::Chunk(_, chunk)  => chunks.push(chunk),
+            BackupMsg::End(_)           => break,
+            _                           => (),
+        }
+    }
+    match state.restore(chunks) {
+        Ok(())  => socket.send(BackupMsg::Restored(id)),
+        Err(e)  => socket.send(BackupMsg::Failed(id, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use {OpenNetwork, RegistrationForm, Time};
+
+    struct Secs(u32);
+
+    impl Time for Secs {
+
+        fn nanos(&self) -> u32 {
+            0
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Secs(seconds.saturating_add(nanos / 1_000_000_000))
+        }
+    }
+
+    static STATES: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+    static RESTORED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// State of the service kept in 'STATES' under its name.
+    struct Store(&'static str);
+
+    impl Store {
+
+        fn get(&self) -> String {
+            STATES.lock().unwrap().iter()
+                .find(|(name, _)| name == self.0)
+                .map(|(_, state)| state.clone())
+                .unwrap_or_default()
+        }
+
+        fn set(&self, state: String) {
+            let mut states = STATES.lock().unwrap();
+            states.retain(|(name, _)| name != self.0);
+            states.push((self.0.to_string(), state));
+        }
+    }
+
+    impl Stateful for Store {
+
+        fn backup(&mut self, chunk: &mut dyn FnMut(Vec<u8>)) -> Result<(), String> {
+            for word in self.get().split(' ') {
+                chunk(word.as_bytes().to_vec());
+            }
+            Ok(())
+        }
+
+        fn restore(&mut self, chunks: Vec<Vec<u8>>) -> Result<(), String> {
+            let words: Vec<String> = chunks.into_iter()
+                .map(|c| String::from_utf8(c).map_err(|e| e.to_string()))
+                .collect::<Result<_, _>>()?;
+            self.set(words.join(" "));
+            RESTORED.lock().unwrap().push(self.0.to_string());
+            Ok(())
+        }
+    }
+
+    fn users(socket: LocalSocket) -> ! {
+        let _ = serve(&socket, &mut Store("users"));
+        finish()
+    }
+
+    fn sessions(socket: LocalSocket) -> ! {
+        let _ = serve(&socket, &mut Store("sessions"));
+        finish()
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(users, "users".to_string())).unwrap();
+        network.register(RegistrationForm::new(sessions, "sessions".to_string())).unwrap();
+        let connect = |id: &String| network.connect(LocalService::by_id(id.clone())).ok();
+        Store("users").set("ann bob".to_string());
+        Store("sessions").set("ann:1".to_string());
+
+        let sink = MemoryBackupSink::new();
+        let services: Vec<_> = ["sessions", "users"].iter()
+            .map(|id| (id.to_string(), connect(&id.to_string()).unwrap()))
+            .collect();
+        backup(7, &services, &sink).unwrap();
+        assert_eq!(sink.chunks(7, &"users".to_string()),
+                Some(vec![b"ann".to_vec(), b"bob".to_vec()]));
+
+        Store("users").set(String::new());
+        Store("sessions").set(String::new());
+        let plan = ShutdownPlan::new(Secs(1))
+            .depends("sessions".to_string(), "users".to_string());
+        let order = restore(7, &plan, &sink, connect).unwrap();
+        assert_eq!(order, vec!["users", "sessions"]);
+        assert_eq!(*RESTORED.lock().unwrap(), vec!["users", "sessions"]);
+        assert_eq!(Store("users").get(), "ann bob");
+        assert_eq!(Store("sessions").get(), "ann:1");
+
+        let missing = restore(8, &plan, &sink, connect);
+        assert!(matches!(missing, Err(BackupErr::Missing(ref s)) if s == "users"));
+    }
+}
@@ -0,0 +1,31 @@
+//! Console on a local network with an 'echo' service, for trying the
+//! console commands by hand. Reads commands from the standard input.
+
+extern crate kobzar_ccs;
+
+use std::io;
+
+use kobzar_ccs::{OpenNetwork, RegistrationForm, Socket};
+use kobzar_ccs::console::{Console, TextCodec};
+use kobzar_ccs::local::{finish, LocalNetwork, LocalService, LocalSocket};
+
+/// Replies to each payload with the same payload.
+fn echo(socket: LocalSocket) -> ! {
+    while let Ok(bytes) = socket.receive::<Vec<u8>>() {
+        if socket.send(bytes).is_err() {
+            break;
+        }
+    }
+    finish()
+}
+
+fn main() {
+    let network = LocalNetwork::new();
+    network.register(RegistrationForm::new(echo, "echo".to_string()))
+        .expect("fresh network accepts the service");
+    let console = Console::new(&network, TextCodec);
+    let stdin = io::stdin();
+    if let Err(e) = console.run::<LocalService, _, _>(stdin.lock(), io::stdout()) {
+        eprintln!("{}", e);
+    }
+}
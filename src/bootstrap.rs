@@ -0,0 +1,194 @@
+//! Bootstrap barrier. Init-like objects start many other objects and
+//! then need to wait until the services those objects provide become
+//! available before going further.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::{Service, Time};
+use aio::AsyncNetwork;
+use rt::Runtime;
+
+/// Set of services that are required to be available before the
+/// system can continue to boot.
+pub struct Bootstrap<S: Service> {
+    required    : Vec<S::Id>,
+
+    /// Called each time some required service becomes available.
+    /// Receives the service identifier, count of available services
+    /// and total count of required services.
+    progress    : Option<Box<ProgressFn<S::Id>>>,
+}
+
+/// Function that reports bootstrap progress.
+type ProgressFn<Id> = dyn Fn(&Id, usize, usize);
+
+/// Future that waits for a single service.
+type ServiceWait<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+/// Error returned when not all required services became available
+/// in time.
+#[derive(Debug)]
+pub struct MissingServices<Id> {
+
+    /// Identifiers of the services that are still not available.
+    pub missing : Vec<Id>,
+}
+
+impl<S: Service> Bootstrap<S> {
+
+    /// Create a barrier for the services with given identifiers.
+    pub fn new(required: Vec<S::Id>) -> Self {
+        Bootstrap {
+            required,
+            progress    : None,
+        }
+    }
+
+    /// Set a function that is called each time some required service
+    /// becomes available.
+    pub fn on_progress<F>(mut self, f: F) -> Self
+        where F: Fn(&S::Id, usize, usize) + 'static
+    {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Identifiers of the required services.
+    pub fn required(&self) -> &[S::Id] {
+        &self.required
+    }
+
+    /// Block until all required services are available or until
+    /// timeout. On timeout, identifiers of the missing services are
+    /// returned.
+    pub fn wait<N, R, T>(&self, network: &N, runtime: &R, timeout: T)
+        -> Result<(), MissingServices<S::Id>>
+        where   N   : AsyncNetwork<S>,
+                R   : Runtime,
+                T   : Time,
+                S::Id: Clone
+    {
+        runtime.block_on(self.wait_async(network, runtime, timeout))
+    }
+
+    /// Same as 'wait' but resolves a future instead of blocking.
+    pub fn wait_async<'a, N, R, T>(&'a self, network: &'a N, runtime: &R,
+            timeout: T) -> BootstrapWait<'a, S, R::Sleep>
+        where   N   : AsyncNetwork<S>,
+                R   : Runtime,
+                T   : Time,
+                S::Id: Clone
+    {
+        let pending = self.required.iter()
+            .map(|id| {
+                let f: ServiceWait<'a>
+                    = Box::pin(network.wait_for_service_async(id));
+                (id, f)
+            })
+            .collect();
+        BootstrapWait {
+            bootstrap   : self,
+            pending,
+            timer       : Box::pin(runtime.sleep(timeout)),
+        }
+    }
+}
+
+/// Future that resolves when all required services are available.
+pub struct BootstrapWait<'a, S: Service + 'a, Sl> {
+    bootstrap   : &'a Bootstrap<S>,
+
+    /// Services that are not yet available with futures that wait
+    /// for them.
+    pending     : Vec<(&'a S::Id, ServiceWait<'a>)>,
+
+    timer       : Pin<Box<Sl>>,
+}
+
+impl<'a, S, Sl> Future for BootstrapWait<'a, S, Sl>
+        where   S   : Service,
+                S::Id: Clone,
+                Sl  : Future<Output = ()>
+{
+
+    type Output = Result<(), MissingServices<S::Id>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let total = this.bootstrap.required.len();
+        let mut i = 0;
+        while i < this.pending.len() {
+            if this.pending[i].1.as_mut().poll(cx).is_pending() {
+                i += 1;
+                continue;
+            }
+            let (id, _) = this.pending.swap_remove(i);
+            if let Some(ref progress) = this.bootstrap.progress {
+                progress(id, total - this.pending.len(), total);
+            }
+        }
+
+        if this.pending.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        if this.timer.as_mut().poll(cx).is_ready() {
+            let missing = this.pending.iter().map(|p| p.0.clone()).collect();
+            return Poll::Ready(Err(MissingServices { missing }));
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use rt::ThreadRuntime;
+    use {OpenNetwork, RegistrationForm};
+
+    fn idle(_: LocalSocket) -> ! {
+        finish()
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn late_service() {
+        let network = LocalNetwork::new();
+        let _a = network.register(RegistrationForm::new(idle, "a".to_string())).unwrap();
+        let registering = network.clone();
+        let late = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            registering.register(RegistrationForm::new(idle, "b".to_string())).unwrap()
+        });
+
+        let progress = Rc::new(RefCell::new(Vec::new()));
+        let reported = progress.clone();
+        let bootstrap = Bootstrap::<LocalService>::new(ids(&["a", "b"]))
+            .on_progress(move |id, done, total| {
+                reported.borrow_mut().push((id.clone(), done, total))
+            });
+        bootstrap.wait(&network, &ThreadRuntime, Duration::from_secs(5)).unwrap();
+        assert_eq!(*progress.borrow(), vec![("a".to_string(), 1, 2), ("b".to_string(), 2, 2)]);
+        late.join().unwrap();
+    }
+
+    #[test]
+    fn missing_services() {
+        let network = LocalNetwork::new();
+        let _b = network.register(RegistrationForm::new(idle, "b".to_string())).unwrap();
+        let bootstrap = Bootstrap::<LocalService>::new(ids(&["a", "b", "c"]));
+        let wait = bootstrap.wait_async(&network, &ThreadRuntime, Duration::from_millis(30));
+        let mut missing = ThreadRuntime.block_on(wait).unwrap_err().missing;
+        missing.sort();
+        assert_eq!(missing, ids(&["a", "c"]));
+    }
+}
@@ -0,0 +1,207 @@
+//! Caching of the call results. Read-heavy services such as name
+//! lookups or configuration get the same requests over and over. Their
+//! requests declare what can be cached by giving a cache key, and
+//! 'CachingClient' keeps the replies for a while instead of calling
+//! the provider each time. Provider that changes the data publishes
+//! 'Invalidation' messages to a topic, and the clients subscribed to it
+//! drop the stale replies before their time runs out.
+
+use std::hash::Hash;
+
+use super::{Data, Object, Service, Socket, Time};
+use idempotency::DedupCache;
+use pubsub::{PubSubErr, Subscription};
+use rpc::{RpcClient, RpcErr};
+
+/// Request that may be answered from the cache.
+pub trait Cacheable<K> {
+
+    /// Key of the request. Requests with the same key get the same
+    /// reply. Requests without key are never cached.
+    fn cache_key(&self) -> Option<&K>;
+}
+
+/// Message of the provider that cached replies are stale.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation<K> {
+
+    /// Reply for the key is stale.
+    Key(K),
+
+    /// All replies are stale.
+    All,
+}
+
+impl<K: Send + 'static> Data for Invalidation<K> {
+}
+
+/// Subscription of the client that gets no invalidations and relies on
+/// the time-to-live alone.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoInvalidations;
+
+impl Subscription for NoInvalidations {
+
+    fn receive<D: Data>(&self) -> Result<D, PubSubErr> {
+        Err(PubSubErr::Closed)
+    }
+
+    fn try_receive<D: Data>(&self) -> Result<Option<D>, PubSubErr> {
+        Ok(None)
+    }
+
+    fn dropped(&self) -> u64 {
+        0
+    }
+}
+
+/// Call-style client that caches the replies to cacheable requests.
+pub struct CachingClient<O, S, SC, Q, R, K, SB> {
+    client          : RpcClient<O, S, SC, Q, R>,
+    cache           : DedupCache<K, R>,
+    invalidations   : SB,
+}
+
+impl<O, S, SC, Q, R, K> CachingClient<O, S, SC, Q, R, K, NoInvalidations>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                Q   : Data + Cacheable<K>,
+                R   : Data + Clone,
+                K   : Hash + Eq + Clone + Send + 'static
+{
+
+    /// Cache at most 'capacity' replies, each for 'ttl' time.
+    pub fn new<T: Time>(socket: SC, capacity: usize, ttl: T) -> Self {
+        CachingClient::with_invalidations(socket, NoInvalidations, capacity, ttl)
+    }
+}
+
+impl<O, S, SC, Q, R, K, SB> CachingClient<O, S, SC, Q, R, K, SB>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                Q   : Data + Cacheable<K>,
+                R   : Data + Clone,
+                K   : Hash + Eq + Clone + Send + 'static,
+                SB  : Subscription
+{
+
+    /// Cache the replies and drop them on the invalidations that come
+    /// from the subscription.
+    pub fn with_invalidations<T: Time>(socket: SC, invalidations: SB, capacity: usize, ttl: T)
+        -> Self
+    {
+        CachingClient {
+            client          : RpcClient::new(socket),
+            cache           : DedupCache::new(capacity, ttl),
+            invalidations,
+        }
+    }
+
+    /// Apply the invalidations that came so far. Lost ones make the
+    /// whole cache suspect, so it is cleared.
+    fn refresh(&self) {
+        loop {
+            match self.invalidations.try_receive::<Invalidation<K>>() {
+                Ok(Some(Invalidation::Key(key)))    => self.cache.remove(&key),
+                Ok(Some(Invalidation::All))         => self.cache.clear(),
+                Err(PubSubErr::Overrun)             => self.cache.clear(),
+                Ok(None) | Err(_)                   => return,
+            }
+        }
+    }
+
+    /// Get the cached reply to the request or call the provider.
+    pub fn call<T: Time>(&self, request: Q, timeout: T) -> Result<R, RpcErr> {
+        self.refresh();
+        let key = match request.cache_key() {
+            Some(key)   => key.clone(),
+            None        => return self.client.call(request, timeout),
+        };
+        if let Some(reply) = self.cache.get(&key) {
+            return Ok(reply);
+        }
+        let reply = self.client.call(request, timeout)?;
+        self.cache.insert(key, reply.clone());
+        Ok(reply)
+    }
+
+    /// Drop the cached reply for the key.
+    pub fn invalidate(&self, key: &K) {
+        self.cache.remove(key);
+    }
+
+    /// Drop all cached replies.
+    pub fn clear(&self) {
+        self.cache.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use pubsub::{Broadcast, BufferPolicy, Overflow, PubSubNetwork};
+    use rpc::serve_loop;
+    use {OpenNetwork, RegistrationForm};
+
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    struct Get(String);
+
+    impl Data for Get {
+    }
+
+    impl Cacheable<String> for Get {
+
+        fn cache_key(&self) -> Option<&String> {
+            Some(&self.0)
+        }
+    }
+
+    static LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Replies with the key and the number of the lookup.
+    fn config(socket: LocalSocket) -> ! {
+        let _ = serve_loop(&socket, |Get(key)| {
+            format!("{}={}", key, LOOKUPS.fetch_add(1, Ordering::Relaxed))
+        });
+        finish()
+    }
+
+    #[test]
+    fn invalidation_drops_reply() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(config, "config".to_string())).unwrap();
+        let socket = network.connect(LocalService::by_id("config".to_string())).unwrap();
+        let updates = network.broadcast("config.invalidate").unwrap();
+        let buffer = BufferPolicy::new(8, Overflow::DropOldest);
+        let subscription = network.subscribe("config.invalidate", buffer);
+        let client = CachingClient::with_invalidations(socket, subscription, 8, Millis(60_000));
+
+        let get = |key: &str| -> String {
+            client.call(Get(key.to_string()), Millis(1000)).unwrap()
+        };
+        let first = get("a");
+        assert_eq!(get("a"), first);
+        updates.publish(Invalidation::Key("a".to_string())).unwrap();
+        assert_ne!(get("a"), first);
+    }
+}
@@ -0,0 +1,99 @@
+//! Canary rollouts. Providers of the same service may register under
+//! different releases, e.g. the stable one and a new candidate, and the
+//! network splits the connects among the releases by the traffic split
+//! of the service. Rules that pin some requesters to a release come
+//! first; the rest of the traffic is split by percentage. System
+//! services are then rolled out through the CCS layer itself: the
+//! candidate gets a small share, then all of it, and the old release is
+//! discontinued.
+
+use super::{Form, OpenNetwork, RegistrationErr, Service};
+use policy::Pattern;
+
+/// How the connects to the service are split among its releases.
+#[derive(Debug, Clone, Default)]
+pub struct TrafficSplit {
+
+    /// Requesters which names match the pattern always go to the
+    /// release. The first matching rule is taken.
+    pub rules       : Vec<(Pattern, String)>,
+
+    /// Releases with their shares of the other connects in percent.
+    /// Shares that add up to less than a hundred leave the rest of the
+    /// connects to any provider.
+    pub weights     : Vec<(String, u32)>,
+}
+
+impl TrafficSplit {
+
+    /// Create split without rules and weights.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Send requesters with matching names to the release.
+    pub fn pin(mut self, requesters: &str, release: &str) -> Self {
+        self.rules.push((Pattern(requesters.to_string()), release.to_string()));
+        self
+    }
+
+    /// Send given percent of the other connects to the release.
+    pub fn weight(mut self, release: &str, percent: u32) -> Self {
+        self.weights.push((release.to_string(), percent));
+        self
+    }
+
+    /// Choose the release for the connect of the requester. 'roll' is
+    /// a number in 0..100 that the network draws for each connect. None
+    /// means any provider.
+    pub fn choose(&self, requester: &str, roll: u32) -> Option<&str> {
+        if let Some((_, release)) = self.rules.iter().find(|(p, _)| p.matches(requester)) {
+            return Some(release);
+        }
+        let mut bound = 0;
+        for (release, percent) in &self.weights {
+            bound += percent;
+            if roll < bound {
+                return Some(release);
+            }
+        }
+        None
+    }
+}
+
+/// Network with several releases of the same service.
+pub trait SplitNetwork<S: Service>: OpenNetwork<S> {
+
+    /// Register the service as provided by given release. Providers
+    /// registered with plain 'register' belong to no release and get
+    /// the connects that the split leaves to any provider.
+    fn register_release(&self, reg_form: Form<S, Self>, release: &str)
+        -> Result<Self::OwnedService, RegistrationErr>;
+
+    /// Set the traffic split of the service. Release chosen by the
+    /// split that has no providers is ignored and the connect goes to
+    /// any provider.
+    fn set_split(&self, id: &S::Id, split: TrafficSplit);
+
+    /// Remove the traffic split of the service.
+    fn clear_split(&self, id: &S::Id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_then_weights() {
+        let split = TrafficSplit::new()
+            .pin("test-*", "2.0")
+            .weight("2.0", 10)
+            .weight("1.0", 90);
+        assert_eq!(split.choose("test-runner", 99), Some("2.0"));
+        assert_eq!(split.choose("app", 5), Some("2.0"));
+        assert_eq!(split.choose("app", 10), Some("1.0"));
+
+        let partial = TrafficSplit::new().weight("2.0", 5);
+        assert_eq!(partial.choose("app", 50), None);
+    }
+}
@@ -0,0 +1,275 @@
+//! Cancellation of pending CCS operations. The same token stops both
+//! futures and blocking calls, so a supervisor or any other thread that
+//! holds its clone can interrupt a receive, send or connect that would
+//! otherwise wait forever.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use super::{ConnectErr, Data, Object, OpenNetwork, Service, Socket, SocketErr};
+
+/// Token that is passed to some pending operation and can be
+/// triggered from any other place that holds a clone of it. When
+/// token is cancelled, all operations that use it stop as soon as
+/// possible.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner   : Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled   : AtomicBool,
+
+    /// Wakers of the operations that wait on this token.
+    wakers      : Mutex<Wakers>,
+}
+
+#[derive(Default)]
+struct Wakers {
+    next        : u64,
+    list        : Vec<(WakerKey, Waker)>,
+}
+
+/// Key of one registration with the token. Two operations of the same
+/// task register the same waker, and the key tells their registrations
+/// apart, so that one of them ending does not remove the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WakerKey(u64);
+
+impl CancelToken {
+
+    /// Create new token that is not yet cancelled.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Cancel all operations that use this token. Cancelling token
+    /// twice has no additional effect.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut self.inner.wakers.lock().unwrap().list);
+        for (_, waker) in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Check if token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Register waker that will be woken when token gets cancelled.
+    /// Returns None without registering if token is already cancelled.
+    pub fn register(&self, waker: &Waker) -> Option<WakerKey> {
+        let mut wakers = self.inner.wakers.lock().unwrap();
+        if self.is_cancelled() {
+            return None;
+        }
+        let key = WakerKey(wakers.next);
+        wakers.next += 1;
+        wakers.list.push((key, waker.clone()));
+        Some(key)
+    }
+
+    /// Replace the waker of the registration, e.g. when the future is
+    /// polled with another one. Returns false if token is cancelled.
+    pub fn update(&self, key: WakerKey, waker: &Waker) -> bool {
+        let mut wakers = self.inner.wakers.lock().unwrap();
+        if self.is_cancelled() {
+            return false;
+        }
+        match wakers.list.iter_mut().find(|&&mut (k, _)| k == key) {
+            Some(&mut (_, ref mut w))   => {
+                if !w.will_wake(waker) {
+                    *w = waker.clone();
+                }
+            },
+            None                        => wakers.list.push((key, waker.clone())),
+        }
+        true
+    }
+
+    /// Remove the registration, once the operation that waited on the
+    /// token is over. Other registrations of the same waker stay.
+    pub fn unregister(&self, key: WakerKey) {
+        self.inner.wakers.lock().unwrap().list.retain(|&(k, _)| k != key);
+    }
+
+    /// Count of the registrations waiting on the token.
+    pub fn registered(&self) -> usize {
+        self.inner.wakers.lock().unwrap().list.len()
+    }
+}
+
+impl std::fmt::Debug for CancelToken {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Registration of one pending future with the token. Each poll
+/// updates the waker under the same key, and the registration is
+/// removed when the future completes or is dropped.
+pub struct CancelWaker {
+    token   : CancelToken,
+    key     : Option<WakerKey>,
+}
+
+impl CancelWaker {
+
+    /// Registration with given token, made by the first 'register'.
+    pub fn new(token: CancelToken) -> Self {
+        CancelWaker {
+            token,
+            key     : None,
+        }
+    }
+
+    /// Token of the registration.
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+
+    /// Register the waker of this poll. Returns false if the token is
+    /// cancelled.
+    pub fn register(&mut self, waker: &Waker) -> bool {
+        match self.key {
+            Some(key)   => self.token.update(key, waker),
+            None        => {
+                self.key = self.token.register(waker);
+                self.key.is_some()
+            },
+        }
+    }
+
+    /// Remove the registration from the token.
+    pub fn unregister(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.token.unregister(key);
+        }
+    }
+}
+
+/// Dropped future won't be polled again, so its waker must not stay
+/// with the token that may outlive it.
+impl Drop for CancelWaker {
+
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+/// Future that completes either with the output of inner future or
+/// with None when the token gets cancelled first.
+pub struct Cancellable<F> {
+    future  : Pin<Box<F>>,
+    waker   : CancelWaker,
+}
+
+impl<F: Future> Cancellable<F> {
+
+    /// Wrap the future so that it can be cancelled with given token.
+    pub fn new(future: F, token: CancelToken) -> Self {
+        Cancellable {
+            future  : Box::pin(future),
+            waker   : CancelWaker::new(token),
+        }
+    }
+}
+
+impl<F: Future> Future for Cancellable<F> {
+
+    type Output = Option<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if !self.waker.register(cx.waker()) {
+            return Poll::Ready(None);
+        }
+        let output = self.future.as_mut().poll(cx);
+        if output.is_ready() {
+            self.waker.unregister();
+        }
+        output.map(Some)
+    }
+}
+
+/// Socket which blocking operations can be cancelled by the token.
+pub trait CancelSocket<O, S>: Socket<O, S> where O: Object<S>, S: Service {
+
+    /// Same as 'receive' but fails with 'SocketErr::Cancelled' when the
+    /// token is triggered first.
+    fn receive_cancellable<D: Data>(&self, cancel: &CancelToken) -> Result<D, SocketErr>;
+
+    /// Same as 'send' but fails with 'SocketErr::Cancelled' when the
+    /// token is triggered before the requester receives the data. The
+    /// data is then not delivered.
+    fn send_cancellable<D: Data>(&self, data: D, cancel: &CancelToken)
+        -> Result<(), SocketErr>;
+}
+
+/// Network which connects can be cancelled by the token.
+pub trait CancelNetwork<S>: OpenNetwork<S> where S: Service {
+
+    /// Wait until some object provides the service and connect to it.
+    /// Fails with 'ConnectErr::Cancelled' when the token is triggered
+    /// first.
+    fn connect_cancellable(&self, service: S, cancel: &CancelToken)
+        -> Result<Self::Socket, ConnectErr<S>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rt::{Runtime, ThreadRuntime};
+
+    #[test]
+    fn cancel_stops_pending_future() {
+        let token = CancelToken::new();
+        let future = Cancellable::new(std::future::pending::<()>(), token.clone());
+        let canceller = std::thread::spawn(move || token.cancel());
+        assert!(ThreadRuntime.block_on(future).is_none());
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn waker_unregistered() {
+        let token = CancelToken::new();
+        let registered = || token.registered();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut pending = Cancellable::new(std::future::pending::<()>(), token.clone());
+        assert!(Pin::new(&mut pending).poll(&mut cx).is_pending());
+        assert_eq!(registered(), 1);
+        drop(pending);
+        assert_eq!(registered(), 0);
+
+        let ready = Cancellable::new(std::future::ready(1), token.clone());
+        assert_eq!(ThreadRuntime.block_on(ready), Some(1));
+        assert_eq!(registered(), 0);
+    }
+
+    #[test]
+    fn same_task_registrations_are_separate() {
+        let token = CancelToken::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut first = Cancellable::new(std::future::pending::<()>(), token.clone());
+        let mut second = Cancellable::new(std::future::pending::<()>(), token.clone());
+        assert!(Pin::new(&mut first).poll(&mut cx).is_pending());
+        assert!(Pin::new(&mut second).poll(&mut cx).is_pending());
+        assert!(Pin::new(&mut second).poll(&mut cx).is_pending());
+        assert_eq!(token.registered(), 2);
+
+        // The waker of the second future is still registered, so it
+        // learns about the cancel.
+        drop(first);
+        assert_eq!(token.registered(), 1);
+        token.cancel();
+        assert_eq!(Pin::new(&mut second).poll(&mut cx), Poll::Ready(None));
+    }
+}
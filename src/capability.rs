@@ -0,0 +1,539 @@
+//! Capabilities. A capability is a token that grants its holder access
+//! to some services. Holder can derive attenuated capabilities from it,
+//! e.g. for narrower set of services, with earlier expiry or usable
+//! only once, and pass them over channels to helpers, so each object
+//! gets exactly the access it needs and no more.
+//!
+//! Issuer may revoke the capability at any time. Revocation applies to
+//! all capabilities derived from it too: channels they authorized are
+//! closed with 'SocketErr::CapabilityRevoked' and new connects fail.
+//!
+//! Providers may also restrict who connects at all: the registration
+//! form names the capabilities the requester must hold, and connects of
+//! the objects that lack them fail with 'ConnectErr::PermissionDenied'.
+//!
+//! Capabilities can't be made up. Each network has an 'Issuer' that
+//! only its owner gets, and accepts only the capabilities minted by it
+//! or derived from those. Objects get them granted by the owner, or
+//! delegated by other objects that hold them.
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use super::{Data, OpenNetwork, Service};
+use policy::Pattern;
+
+/// Source of unique capability identifiers.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Token granting access to services.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capability {
+
+    /// Unique identifier of this token.
+    id          : u64,
+
+    /// Issuer that minted the root of this capability.
+    issuer      : u64,
+
+    /// Name of the capability, e.g. 'drivers'. Derived capabilities
+    /// keep the name of their parent.
+    name        : String,
+
+    /// Patterns of the services the capability grants access to.
+    services    : Vec<Pattern>,
+
+    /// Time after which the capability is no longer valid.
+    expires     : Option<SystemTime>,
+
+    /// Whether the capability can be used only once.
+    single_use  : bool,
+
+    /// Identifiers of the capabilities this one was derived from,
+    /// starting from the root. Revoking any of them revokes this one.
+    lineage     : Vec<u64>,
+}
+
+/// Mints root capabilities of one network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issuer {
+    id      : u64,
+}
+
+impl Issuer {
+
+    /// Create the issuer of a new network. Backends create one per
+    /// network and give it only to the owner of the network. Issuers
+    /// created elsewhere mint capabilities no network accepts.
+    pub fn new() -> Self {
+        Issuer {
+            id      : NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Issue new root capability.
+    pub fn issue(&self, name: &str, services: Vec<Pattern>) -> Capability {
+        Capability {
+            id          : NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            issuer      : self.id,
+            name        : name.to_string(),
+            services,
+            expires     : None,
+            single_use  : false,
+            lineage     : Vec::new(),
+        }
+    }
+
+    /// Check if the capability was minted by this issuer or derived
+    /// from the one that was.
+    pub fn minted(&self, capability: &Capability) -> bool {
+        capability.issuer == self.id
+    }
+}
+
+impl Default for Issuer {
+
+    fn default() -> Self {
+        Issuer::new()
+    }
+}
+
+/// Error of giving the capability to the object.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GrantErr {
+
+    /// Capability was not minted by the issuer.
+    NotIssuer,
+
+    /// Delegating object holds neither the capability nor any it was
+    /// derived from.
+    NotHeld,
+}
+
+impl Data for Capability {
+}
+
+/// Restrictions to apply when deriving new capability.
+#[derive(Debug, Clone, Default)]
+pub struct Attenuation {
+
+    /// Narrower service patterns. Parent patterns are kept if None.
+    pub services    : Option<Vec<Pattern>>,
+
+    /// Earlier expiry.
+    pub expires     : Option<SystemTime>,
+
+    /// Make the derived capability single-use.
+    pub single_use  : bool,
+}
+
+/// Error of deriving the capability.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AttenuateErr {
+
+    /// Requested service pattern is not covered by the parent.
+    WiderServices(Pattern),
+
+    /// Requested expiry is later than the parent's.
+    LaterExpiry,
+
+    /// Parent is single-use, so derived capability must be too.
+    NotSingleUse,
+}
+
+impl Capability {
+
+    /// Unique identifier of this token.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Name of the capability, e.g. 'drivers'.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Patterns of the services the capability grants access to.
+    pub fn services(&self) -> &[Pattern] {
+        &self.services
+    }
+
+    /// Time after which the capability is no longer valid.
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    /// Whether the capability can be used only once.
+    pub fn is_single_use(&self) -> bool {
+        self.single_use
+    }
+
+    /// Identifiers of the capabilities this one was derived from,
+    /// starting from the root.
+    pub fn lineage(&self) -> &[u64] {
+        &self.lineage
+    }
+
+    /// Check if this capability is the one with given identifier or
+    /// was derived from it.
+    pub fn descends_from(&self, id: u64) -> bool {
+        self.id == id || self.lineage.contains(&id)
+    }
+
+    /// Check if the capability grants access to the service at given
+    /// time.
+    pub fn permits(&self, service: &str, now: SystemTime) -> bool {
+        !self.is_expired(now) && self.services.iter().any(|p| p.matches(service))
+    }
+
+    /// Check if the capability has expired at given time.
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|e| now >= e)
+    }
+
+    /// Derive new capability that grants no more than this one.
+    pub fn attenuate(&self, a: Attenuation) -> Result<Capability, AttenuateErr> {
+        let services = match a.services {
+            Some(services) => {
+                if let Some(p) = services.iter().find(|p| !self.covers(p)) {
+                    return Err(AttenuateErr::WiderServices(p.clone()));
+                }
+                services
+            },
+            None => self.services.clone(),
+        };
+        let expires = match (self.expires, a.expires) {
+            (Some(p), Some(c)) if c > p => return Err(AttenuateErr::LaterExpiry),
+            (p, None)                   => p,
+            (_, c)                      => c,
+        };
+        if self.single_use && !a.single_use {
+            return Err(AttenuateErr::NotSingleUse);
+        }
+        let mut lineage = self.lineage.clone();
+        lineage.push(self.id);
+        Ok(Capability {
+            id          : NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            issuer      : self.issuer,
+            name        : self.name.clone(),
+            services,
+            expires,
+            single_use  : a.single_use,
+            lineage,
+        })
+    }
+
+    /// Check if the pattern grants nothing beyond this capability. The
+    /// check is conservative: some narrower patterns may be refused.
+    fn covers(&self, pattern: &Pattern) -> bool {
+        self.services.iter().any(|p| {
+            if p == pattern {
+                return true;
+            }
+            if !pattern.0.contains('*') {
+                return p.matches(&pattern.0);
+            }
+
+            // Prefix pattern covers any pattern that starts with the
+            // same prefix.
+            match p.0.strip_suffix('*') {
+                Some(prefix) if !prefix.contains('*') => pattern.0.starts_with(prefix),
+                _ => false,
+            }
+        })
+    }
+}
+
+/// Error of using the capability.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CapabilityErr {
+
+    /// Capability or one it was derived from was revoked.
+    Revoked,
+
+    /// Capability has expired.
+    Expired,
+
+    /// Single-use capability was already used.
+    Used,
+
+    /// Capability does not grant access to the service.
+    NotPermitted,
+}
+
+/// Error of the revocation.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RevokeErr {
+
+    /// Only the issuer of the root capability may revoke it or any
+    /// capability derived from it.
+    NotIssuer,
+}
+
+/// Channels of type 'C' authorized by capabilities. Backends keep one
+/// per network to find the channels to close on revocation.
+pub struct Revocations<C> {
+    state   : Mutex<State<C>>,
+}
+
+struct State<C> {
+
+    /// Identifiers of the revoked capabilities.
+    revoked : HashSet<u64>,
+
+    /// Identifiers of the used single-use capabilities.
+    used    : HashSet<u64>,
+
+    /// Channels with the lineage of the capability that authorized
+    /// them, including its own identifier.
+    bound   : Vec<(Vec<u64>, C)>,
+}
+
+impl<C> Default for Revocations<C> {
+
+    fn default() -> Self {
+        Revocations {
+            state   : Mutex::new(State {
+                revoked : HashSet::new(),
+                used    : HashSet::new(),
+                bound   : Vec::new(),
+            }),
+        }
+    }
+}
+
+impl<C> Revocations<C> {
+
+    /// Check if the capability or any it was derived from is revoked.
+    pub fn is_revoked(&self, capability: &Capability) -> bool {
+        let state = self.state.lock().unwrap();
+        Self::revoked_in(&state, capability)
+    }
+
+    fn revoked_in(state: &State<C>, capability: &Capability) -> bool {
+        state.revoked.contains(&capability.id)
+            || capability.lineage.iter().any(|id| state.revoked.contains(id))
+    }
+
+    /// Check that the capability may be used for the service now,
+    /// without spending it.
+    pub fn check(&self, capability: &Capability, service: &str, now: SystemTime)
+        -> Result<(), CapabilityErr>
+    {
+        Self::check_in(&self.state.lock().unwrap(), capability, service, now)
+    }
+
+    fn check_in(state: &State<C>, capability: &Capability, service: &str, now: SystemTime)
+        -> Result<(), CapabilityErr>
+    {
+        if Self::revoked_in(state, capability) {
+            return Err(CapabilityErr::Revoked);
+        }
+        if capability.is_expired(now) {
+            return Err(CapabilityErr::Expired);
+        }
+        if !capability.permits(service, now) {
+            return Err(CapabilityErr::NotPermitted);
+        }
+        if capability.single_use && state.used.contains(&capability.id) {
+            return Err(CapabilityErr::Used);
+        }
+        Ok(())
+    }
+
+    /// Choose for each required name a held capability, minted by the
+    /// issuer of the network, that may be used for the service now.
+    /// None if some name has no such capability.
+    pub fn authorizing(&self, issuer: &Issuer, held: &[Capability], requires: &[String],
+            service: &str, now: SystemTime)
+        -> Option<Vec<Capability>>
+    {
+        let state = self.state.lock().unwrap();
+        requires.iter()
+            .map(|name| held.iter().find(|c| {
+                issuer.minted(c) && c.name == *name
+                    && Self::check_in(&state, c, service, now).is_ok()
+            }).cloned())
+            .collect()
+    }
+
+    /// Check all the capabilities and spend the single-use ones. None is
+    /// spent unless all may be used.
+    fn spend_in(state: &mut State<C>, capabilities: &[Capability], service: &str,
+            now: SystemTime)
+        -> Result<(), CapabilityErr>
+    {
+        for capability in capabilities {
+            Self::check_in(state, capability, service, now)?;
+        }
+        for capability in capabilities.iter().filter(|c| c.single_use) {
+            if !state.used.insert(capability.id) {
+                return Err(CapabilityErr::Used);
+            }
+        }
+        Ok(())
+    }
+
+    /// Spend the capabilities on the use that opens no channel, e.g. a
+    /// direct call.
+    pub fn spend(&self, capabilities: &[Capability], service: &str, now: SystemTime)
+        -> Result<(), CapabilityErr>
+    {
+        Self::spend_in(&mut self.state.lock().unwrap(), capabilities, service, now)
+    }
+
+    /// Check that the capability allows connecting to the service and
+    /// remember the channel it authorized. Single-use capability is
+    /// spent by this call.
+    pub fn bind(&self, capability: &Capability, service: &str, now: SystemTime,
+            channel: C) -> Result<(), CapabilityErr> {
+        self.bind_all(::std::slice::from_ref(capability), service, now, channel)
+    }
+
+    /// Same as 'bind' for all the capabilities that authorized the
+    /// channel together. Revoking any of them closes it.
+    pub fn bind_all(&self, capabilities: &[Capability], service: &str, now: SystemTime,
+            channel: C) -> Result<(), CapabilityErr> {
+        let mut state = self.state.lock().unwrap();
+        Self::spend_in(&mut state, capabilities, service, now)?;
+        let mut lineage = Vec::new();
+        for capability in capabilities {
+            lineage.extend_from_slice(&capability.lineage);
+            lineage.push(capability.id);
+        }
+        state.bound.push((lineage, channel));
+        Ok(())
+    }
+
+    /// Forget the channels that were closed on their own.
+    pub fn unbind<F>(&self, mut closed: F) where F: FnMut(&C) -> bool {
+        self.state.lock().unwrap().bound.retain(|(_, c)| !closed(c));
+    }
+
+    /// Revoke the capability with given identifier. Returns channels
+    /// authorized by it or by capabilities derived from it, which the
+    /// backend must close with 'SocketErr::CapabilityRevoked'.
+    pub fn revoke(&self, id: u64) -> Vec<C> {
+        let mut state = self.state.lock().unwrap();
+        state.revoked.insert(id);
+        let (revoked, kept) = ::std::mem::take(&mut state.bound)
+            .into_iter()
+            .partition(|(lineage, _)| lineage.contains(&id));
+        state.bound = kept;
+        revoked.into_iter().map(|(_, c)| c).collect()
+    }
+}
+
+/// Object that holds capabilities. Networks check them on connect
+/// against the capabilities the provider requires.
+pub trait CapabilityHolder: Sized {
+
+    /// Give the capability minted by the issuer to the object.
+    fn grant(&self, issuer: &Issuer, capability: Capability) -> Result<(), GrantErr>;
+
+    /// Give the other object a capability this one holds or one derived
+    /// from it.
+    fn delegate(&self, to: &Self, capability: Capability) -> Result<(), GrantErr>;
+
+    /// Take the capability with given identifier away from the object.
+    fn take_away(&self, id: u64);
+
+    /// Capabilities the object holds.
+    fn capabilities(&self) -> Vec<Capability>;
+}
+
+/// Open network that authorizes connects by capabilities.
+pub trait CapabilityNetwork<S: Service>: OpenNetwork<S> {
+
+    /// Connect to the service using the capability. The channel is
+    /// closed if the capability gets revoked later.
+    fn connect_with_capability(&self, capability: &Capability, service: S)
+        -> Result<Self::Socket, CapabilityErr>;
+
+    /// Revoke the capability and all derived from it. Returns count of
+    /// channels that were closed.
+    fn revoke(&self, capability: &Capability) -> Result<usize, RevokeErr>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn attenuation_narrows() {
+        let issuer = Issuer::new();
+        let root = issuer.issue("fs", vec![Pattern("fs.*".to_string())]);
+        let read = root.attenuate(Attenuation {
+            services    : Some(vec![Pattern("fs.read.*".to_string())]),
+            single_use  : true,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(read.lineage, vec![root.id]);
+        assert!(read.permits("fs.read.file", SystemTime::now()));
+        assert!(!read.permits("fs.write.file", SystemTime::now()));
+
+        assert_eq!(read.attenuate(Default::default()),
+                Err(AttenuateErr::NotSingleUse));
+        assert!(root.attenuate(Attenuation {
+            services    : Some(vec![Pattern("*".to_string())]),
+            ..Default::default()
+        }).is_err());
+
+        let soon = SystemTime::now() + Duration::from_secs(1);
+        let expiring = root.attenuate(Attenuation {
+            expires     : Some(soon),
+            ..Default::default()
+        }).unwrap();
+        assert!(expiring.is_expired(soon));
+    }
+
+    #[test]
+    fn foreign_capabilities_refused() {
+        let (ours, theirs) = (Issuer::new(), Issuer::new());
+        let now = SystemTime::now();
+        let requires = ["drivers".to_string()];
+        let revocations = Revocations::<u32>::default();
+        let forged = theirs.issue("drivers", vec![Pattern("*".to_string())]);
+        let derived = forged.attenuate(Default::default()).unwrap();
+        assert_eq!(revocations.authorizing(&ours, &[forged, derived], &requires, "disk", now),
+                None);
+        let granted = ours.issue("drivers", vec![Pattern("disk".to_string())]);
+        let held = [granted];
+        assert_eq!(revocations.authorizing(&ours, &held, &requires, "disk", now),
+                Some(held.to_vec()));
+    }
+
+    #[test]
+    fn revocation_closes_derived() {
+        let now = SystemTime::now();
+        let issuer = Issuer::new();
+        let root = issuer.issue("fs", vec![Pattern("fs.*".to_string())]);
+        let child = root.attenuate(Default::default()).unwrap();
+        let once = root.attenuate(Attenuation {
+            single_use  : true,
+            ..Default::default()
+        }).unwrap();
+
+        let revocations = Revocations::default();
+        revocations.bind(&child, "fs.read", now, 1).unwrap();
+        revocations.bind(&once, "fs.read", now, 2).unwrap();
+        assert_eq!(revocations.bind(&once, "fs.read", now, 3),
+                Err(CapabilityErr::Used));
+        assert_eq!(revocations.bind(&child, "net", now, 3),
+                Err(CapabilityErr::NotPermitted));
+
+        let once = [once];
+        assert_eq!(revocations.spend(&once, "fs.read", now), Err(CapabilityErr::Used));
+        assert_eq!(revocations.authorizing(&issuer, &once, &["fs".to_string()], "fs.read", now),
+                None);
+
+        revocations.unbind(|&c| c == 2);
+        assert_eq!(revocations.revoke(root.id), vec![1]);
+        assert!(revocations.is_revoked(&child));
+        assert_eq!(revocations.bind(&child, "fs.read", now, 4),
+                Err(CapabilityErr::Revoked));
+    }
+}
@@ -0,0 +1,186 @@
+//! Causal ordering of events. Producers stamp each published event with
+//! a vector clock, and consumers put received events through
+//! 'CausalBuffer', which holds back each event until all events it may
+//! depend on were delivered. Events of many producers are then consumed
+//! in an order consistent with causality, whatever way they travelled.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use super::Data;
+
+/// Vector clock: count of events of each producer that are known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VectorClock<P: Ord>(pub BTreeMap<P, u64>);
+
+impl<P: Ord> Default for VectorClock<P> {
+
+    fn default() -> Self {
+        VectorClock(BTreeMap::new())
+    }
+}
+
+impl<P: Ord + Clone> VectorClock<P> {
+
+    /// Count of known events of the producer.
+    pub fn get(&self, producer: &P) -> u64 {
+        self.0.get(producer).cloned().unwrap_or(0)
+    }
+
+    /// Count new event of the producer.
+    pub fn tick(&mut self, producer: &P) {
+        *self.0.entry(producer.clone()).or_insert(0) += 1;
+    }
+
+    /// Learn all events known to the other clock.
+    pub fn merge(&mut self, other: &VectorClock<P>) {
+        for (p, &n) in &other.0 {
+            let known = self.0.entry(p.clone()).or_insert(0);
+            *known = (*known).max(n);
+        }
+    }
+
+    /// Causal order of the clocks. None if the events are concurrent.
+    pub fn compare(&self, other: &VectorClock<P>) -> Option<Ordering> {
+        let mut less = false;
+        let mut greater = false;
+        for p in self.0.keys().chain(other.0.keys()) {
+            match self.get(p).cmp(&other.get(p)) {
+                Ordering::Less      => less = true,
+                Ordering::Greater   => greater = true,
+                Ordering::Equal     => (),
+            }
+        }
+        match (less, greater) {
+            (false, false)  => Some(Ordering::Equal),
+            (true, false)   => Some(Ordering::Less),
+            (false, true)   => Some(Ordering::Greater),
+            (true, true)    => None,
+        }
+    }
+}
+
+/// Event with its causal metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stamped<P: Ord, T> {
+
+    /// Producer of the event.
+    pub producer    : P,
+
+    /// Clock of the producer right after the event.
+    pub clock       : VectorClock<P>,
+
+    pub payload     : T,
+}
+
+impl<P: Ord + Send + 'static, T: Data> Data for Stamped<P, T> {
+}
+
+/// Clock of the producer.
+pub struct CausalProducer<P: Ord> {
+    id      : P,
+    clock   : VectorClock<P>,
+}
+
+impl<P: Ord + Clone> CausalProducer<P> {
+
+    /// Create producer with given identifier.
+    pub fn new(id: P) -> Self {
+        CausalProducer {
+            id,
+            clock   : VectorClock::default(),
+        }
+    }
+
+    /// Learn events the producer has seen before publishing, so that
+    /// its next events are ordered after them.
+    pub fn observe(&mut self, clock: &VectorClock<P>) {
+        self.clock.merge(clock);
+    }
+
+    /// Stamp the event that is going to be published.
+    pub fn stamp<T>(&mut self, payload: T) -> Stamped<P, T> {
+        self.clock.tick(&self.id);
+        Stamped {
+            producer    : self.id.clone(),
+            clock       : self.clock.clone(),
+            payload,
+        }
+    }
+}
+
+/// Consumer-side reordering buffer.
+pub struct CausalBuffer<P: Ord, T> {
+
+    /// Events that were delivered.
+    delivered   : VectorClock<P>,
+
+    /// Events that wait for the events they depend on.
+    pending     : Vec<Stamped<P, T>>,
+}
+
+impl<P: Ord + Clone, T> Default for CausalBuffer<P, T> {
+
+    fn default() -> Self {
+        CausalBuffer {
+            delivered   : VectorClock::default(),
+            pending     : Vec::new(),
+        }
+    }
+}
+
+impl<P: Ord + Clone, T> CausalBuffer<P, T> {
+
+    /// Events that were delivered so far.
+    pub fn delivered(&self) -> &VectorClock<P> {
+        &self.delivered
+    }
+
+    /// Count of events that wait.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn is_deliverable(&self, e: &Stamped<P, T>) -> bool {
+        e.clock.get(&e.producer) == self.delivered.get(&e.producer) + 1
+            && e.clock.0.iter().all(|(p, &n)| {
+                *p == e.producer || n <= self.delivered.get(p)
+            })
+    }
+
+    /// Add received event. Returns events that can be consumed now, in
+    /// causal order.
+    pub fn push(&mut self, event: Stamped<P, T>) -> Vec<Stamped<P, T>> {
+        self.pending.push(event);
+        let mut ready = Vec::new();
+        while let Some(i) = self.pending.iter().position(|e| self.is_deliverable(e)) {
+            let e = self.pending.remove(i);
+            self.delivered.tick(&e.producer);
+            ready.push(e);
+        }
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_waits_for_question() {
+        let mut a = CausalProducer::new("a");
+        let mut b = CausalProducer::new("b");
+        let question = a.stamp("question");
+        b.observe(&question.clock);
+        let answer = b.stamp("answer");
+        assert_eq!(question.clock.compare(&answer.clock), Some(Ordering::Less));
+
+        let mut buffer = CausalBuffer::default();
+        assert!(buffer.push(answer).is_empty());
+        assert_eq!(buffer.pending(), 1);
+        let ready: Vec<_> = buffer.push(question).into_iter()
+            .map(|e| e.payload)
+            .collect();
+        assert_eq!(ready, ["question", "answer"]);
+    }
+}
@@ -0,0 +1,117 @@
+//! Object checkpoints. An object may periodically save its state as
+//! an opaque blob with the supervisor or some checkpoint service. When
+//! the object is restarted, the new instance receives the last saved
+//! checkpoint and can continue from it instead of starting from
+//! scratch.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use super::{Object, Service};
+
+/// Saved state of the object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+
+    /// Sequence number of the checkpoint. Each next checkpoint saved
+    /// by the object gets greater number.
+    pub seq     : u64,
+
+    /// Opaque state of the object.
+    pub state   : Vec<u8>,
+}
+
+/// Error that can appear when saving the checkpoint.
+#[derive(Debug)]
+pub enum CheckpointErr {
+
+    /// Object has neither supervisor nor checkpoint service that
+    /// could store the checkpoint.
+    NoStore,
+
+    /// Checkpoint is bigger than the store accepts.
+    TooLarge {
+        /// Maximal size of the state in bytes.
+        limit   : usize,
+    },
+
+    /// Checkpoint has sequence number that is not greater than the
+    /// one of the already stored checkpoint.
+    Stale,
+}
+
+/// Object that can save checkpoints of its state and that can be
+/// restored from them when restarted.
+pub trait Checkpointed<S: Service>: Object<S> {
+
+    /// Save the state of the object. Returns sequence number that was
+    /// assigned to the checkpoint.
+    fn save_checkpoint(&self, state: &[u8]) -> Result<u64, CheckpointErr>;
+
+    /// Checkpoint that this object instance received at spawn. None if
+    /// the object was started for the first time or no checkpoint was
+    /// saved by its previous instances.
+    fn restored_checkpoint(&self) -> Option<&Checkpoint>;
+}
+
+/// Storage of checkpoints. It is used by supervisors and checkpoint
+/// services. Key identifies the object in a way that survives
+/// restarts, e.g. the name under which supervisor started it.
+pub trait CheckpointStore<K> {
+
+    /// Store the checkpoint replacing the previous one.
+    fn store(&self, key: &K, checkpoint: Checkpoint)
+        -> Result<(), CheckpointErr>;
+
+    /// Get last stored checkpoint.
+    fn last(&self, key: &K) -> Option<Checkpoint>;
+
+    /// Remove stored checkpoint. Used when the object exits normally
+    /// and so should start from scratch next time.
+    fn clear(&self, key: &K);
+}
+
+/// Checkpoint store that keeps everything in memory.
+pub struct MemoryCheckpointStore<K> {
+    limit   : usize,
+    map     : Mutex<HashMap<K, Checkpoint>>,
+}
+
+impl<K: Hash + Eq> MemoryCheckpointStore<K> {
+
+    /// Create new store that accepts states of at most 'limit' bytes.
+    pub fn new(limit: usize) -> Self {
+        MemoryCheckpointStore {
+            limit,
+            map     : Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> CheckpointStore<K> for MemoryCheckpointStore<K> {
+
+    fn store(&self, key: &K, checkpoint: Checkpoint)
+        -> Result<(), CheckpointErr>
+    {
+        if checkpoint.state.len() > self.limit {
+            return Err(CheckpointErr::TooLarge { limit: self.limit });
+        }
+        let mut map = self.map.lock().unwrap();
+        if let Some(last) = map.get(key) {
+            if last.seq >= checkpoint.seq {
+                return Err(CheckpointErr::Stale);
+            }
+        }
+        map.insert(key.clone(), checkpoint);
+        Ok(())
+    }
+
+    fn last(&self, key: &K) -> Option<Checkpoint> {
+        self.map.lock().unwrap().get(key).cloned()
+    }
+
+    fn clear(&self, key: &K) {
+        self.map.lock().unwrap().remove(key);
+    }
+}
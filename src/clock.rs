@@ -0,0 +1,152 @@
+//! Clock synchronization of bridged networks. Deadlines and TTLs carried
+//! in message metadata are absolute times of the sender's clock, which
+//! on another machine may be off by a lot. The requester estimates the
+//! offset of the provider's clock by exchanging timestamped probes over
+//! a channel, the same way NTP does, and networks expose the offsets of
+//! the networks they are bridged to.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{Data, Network, Object, Service, Socket, SocketErr};
+
+/// Probe sent by the requester. Holds its send time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Probe {
+    pub sent        : i64,
+}
+
+impl Data for Probe {
+}
+
+/// Reply of the provider with its receive and send times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeReply {
+    pub probe_sent  : i64,
+    pub received    : i64,
+    pub sent        : i64,
+}
+
+impl Data for ProbeReply {
+}
+
+/// Estimated offset of the remote clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Offset {
+
+    /// Nanoseconds to add to the local time to get the remote time.
+    pub nanos       : i64,
+
+    /// Round-trip delay of the probe the estimate comes from, in
+    /// nanoseconds. The estimate is off by at most half of it.
+    pub delay       : i64,
+}
+
+impl Offset {
+
+    /// Estimate from the four timestamps of one probe: 'a' and 'd' are
+    /// the local send and receive times, 'b' and 'c' are the remote
+    /// receive and send times.
+    pub fn from_probe(a: i64, b: i64, c: i64, d: i64) -> Self {
+        Offset {
+            nanos   : ((b - a) + (c - d)) / 2,
+            delay   : (d - a) - (c - b),
+        }
+    }
+
+    /// Convert the time of the remote clock to the local clock.
+    pub fn to_local(&self, remote: SystemTime) -> SystemTime {
+        shift(remote, -self.nanos)
+    }
+
+    /// Convert the time of the local clock to the remote clock.
+    pub fn to_remote(&self, local: SystemTime) -> SystemTime {
+        shift(local, self.nanos)
+    }
+}
+
+fn shift(time: SystemTime, nanos: i64) -> SystemTime {
+    if nanos >= 0 {
+        time + Duration::from_nanos(nanos as u64)
+    } else {
+        time - Duration::from_nanos(nanos.unsigned_abs())
+    }
+}
+
+/// Current time in nanoseconds since the Unix epoch.
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d)   => d.as_nanos() as i64,
+        Err(e)  => -(e.duration().as_nanos() as i64),
+    }
+}
+
+/// Estimate the offset of the provider's clock with given count of
+/// probes. The probe with the least delay wins, as it is the least
+/// affected by queueing on the way.
+pub fn measure<O, S, SC>(socket: &SC, probes: usize) -> Result<Offset, SocketErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>
+{
+    let mut best: Option<Offset> = None;
+    for _ in 0..probes.max(1) {
+        socket.send(Probe { sent: now() })?;
+        let reply = socket.receive::<ProbeReply>()?;
+        let offset = Offset::from_probe(reply.probe_sent, reply.received,
+                reply.sent, now());
+        if best.is_none_or(|b| offset.delay < b.delay) {
+            best = Some(offset);
+        }
+    }
+    Ok(best.unwrap())
+}
+
+/// Reply to the probes until the channel gets closed.
+pub fn serve<O, S, SC>(socket: &SC) -> Result<(), SocketErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>
+{
+    loop {
+        let probe = match socket.receive::<Probe>() {
+            Ok(probe)                       => probe,
+            Err(SocketErr::ChannelClosed)   => return Ok(()),
+            Err(e)                          => return Err(e),
+        };
+        let received = now();
+        socket.send(ProbeReply {
+            probe_sent  : probe.sent,
+            received,
+            sent        : now(),
+        })?;
+    }
+}
+
+/// Network bridged to other networks, possibly on other machines.
+pub trait ClockedNetwork<S: Service>: Network<S> {
+
+    /// Identifier of the bridged network.
+    type Peer;
+
+    /// Last estimated offset of the clock of the bridged network. None
+    /// if it was not measured yet.
+    fn clock_offset(&self, peer: &Self::Peer) -> Option<Offset>;
+
+    /// Offsets of all bridged networks.
+    fn clock_offsets(&self) -> Vec<(Self::Peer, Offset)>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_from_probe() {
+        // Remote clock is 100 ahead, each way takes 10, remote spends 5.
+        let offset = Offset::from_probe(0, 110, 115, 25);
+        assert_eq!(offset, Offset { nanos: 100, delay: 20 });
+
+        let local = UNIX_EPOCH + Duration::from_secs(1);
+        assert_eq!(offset.to_local(offset.to_remote(local)), local);
+    }
+}
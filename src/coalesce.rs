@@ -0,0 +1,201 @@
+//! Coalescing of delivery notifications. When a sender produces a burst
+//! of messages, waking the receiver for each of them costs a context
+//! switch per message. 'Batcher' wakes the receiver once the batch is
+//! full or once the oldest pending message has waited for the
+//! configured latency. The local network batches the channels of the
+//! services registered with 'RegistrationForm::coalesce' through it, and
+//! applications that hand messages between their own threads can signal
+//! through it as well.
+
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::Time;
+use rt::duration;
+
+/// Limits of the batch, set on the registration form of the service
+/// whose channels are batched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coalescing {
+    /// Count of messages that wake the receiver at once.
+    pub max_batch   : usize,
+
+    /// Longest time the first message of the batch waits for the rest.
+    pub max_latency : Duration,
+}
+
+impl Coalescing {
+
+    /// Wake the receiver after 'max_batch' messages or once the first of
+    /// them has waited for 'max_latency'.
+    pub fn new<T: Time>(max_batch: usize, max_latency: T) -> Self {
+        Coalescing {
+            max_batch,
+            max_latency : duration(&max_latency),
+        }
+    }
+
+    /// Create batcher with these limits.
+    pub fn batcher(&self) -> Batcher {
+        Batcher::with(*self)
+    }
+}
+
+/// Notification batcher shared by the senders and the receiver of a
+/// channel.
+pub struct Batcher {
+    max_batch   : usize,
+    max_latency : Duration,
+    state       : Mutex<State>,
+    cond        : Condvar,
+}
+
+struct State {
+
+    /// Count of messages the receiver was not yet told about.
+    pending     : usize,
+
+    /// When the oldest pending message arrived.
+    since       : Option<Instant>,
+
+    /// Receiver sleeps without a deadline and must be woken by the
+    /// first message of the batch.
+    idle        : bool,
+
+    /// Count of times the receiver was actually woken by senders.
+    wakeups     : u64,
+
+    /// Receiver was asked to return from the wait right away.
+    interrupted : bool,
+}
+
+impl Batcher {
+
+    /// Create batcher that wakes the receiver after 'max_batch' messages
+    /// or after 'max_latency' time since the first pending message.
+    pub fn new<T: Time>(max_batch: usize, max_latency: T) -> Self {
+        Batcher::with(Coalescing::new(max_batch, max_latency))
+    }
+
+    fn with(limits: Coalescing) -> Self {
+        Batcher {
+            max_batch   : limits.max_batch.max(1),
+            max_latency : limits.max_latency,
+            state       : Mutex::new(State {
+                pending     : 0,
+                since       : None,
+                idle        : false,
+                wakeups     : 0,
+                interrupted : false,
+            }),
+            cond        : Condvar::new(),
+        }
+    }
+
+    /// Tell that one more message was delivered. The receiver is woken
+    /// only if this message fills the batch or if the receiver sleeps
+    /// without a deadline and needs to start counting the latency.
+    pub fn notify(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending += 1;
+        if state.since.is_none() {
+            state.since = Some(Instant::now());
+        }
+        if state.pending == self.max_batch || state.idle {
+            state.idle = false;
+            state.wakeups += 1;
+            self.cond.notify_one();
+        }
+    }
+
+    /// Wait for the next batch. Returns count of messages delivered
+    /// since the previous call.
+    pub fn wait(&self) -> usize {
+        self.wait_until(None)
+    }
+
+    /// Wait for the next batch, but not past the deadline and not after
+    /// 'interrupt'. Returns count of messages delivered since the
+    /// previous call, which may be less than the batch or none.
+    pub fn wait_until(&self, deadline: Option<Instant>) -> usize {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.pending >= self.max_batch || state.interrupted {
+                break;
+            }
+            let now = Instant::now();
+            if deadline.is_some_and(|d| now >= d) {
+                break;
+            }
+            let latency = match state.since {
+                None        => None,
+                Some(since) => {
+                    let elapsed = now.duration_since(since);
+                    if elapsed >= self.max_latency {
+                        break;
+                    }
+                    Some(self.max_latency - elapsed)
+                },
+            };
+            state.idle = latency.is_none();
+            let timeout = match (latency, deadline) {
+                (Some(l), Some(d))  => Some(l.min(d - now)),
+                (l, d)              => l.or(d.map(|d| d - now)),
+            };
+            state = match timeout {
+                Some(t) => self.cond.wait_timeout(state, t).unwrap().0,
+                None    => self.cond.wait(state).unwrap(),
+            };
+        }
+        state.since = None;
+        state.idle = false;
+        state.interrupted = false;
+        ::std::mem::replace(&mut state.pending, 0)
+    }
+
+    /// Make the receiver return from the wait right away, e.g. because
+    /// the channel was closed. If it is not waiting, its next wait
+    /// returns at once.
+    pub fn interrupt(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.interrupted = true;
+        self.cond.notify_one();
+    }
+
+    /// Count of times senders woke the receiver.
+    pub fn wakeups(&self) -> u64 {
+        self.state.lock().unwrap().wakeups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    #[test]
+    fn burst_is_one_batch() {
+        let batcher = Batcher::new(8, Millis(1000));
+        for _ in 0..8 {
+            batcher.notify();
+        }
+        assert_eq!(batcher.wait(), 8);
+        assert_eq!(batcher.wakeups(), 1);
+    }
+}
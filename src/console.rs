@@ -0,0 +1,261 @@
+//! Console for exercising a network by hand. It lists registered
+//! services, calls them with hand-written payloads and watches control
+//! events. Useful during bring-up of new services when there is no
+//! client for them yet. The 'ccs-console' binary, built with the
+//! 'console' feature, runs it on a local network.
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+
+use super::{OpenNetwork, Service, Socket, SocketErr};
+use events::{ControlEvent, EventFilter, EventLog};
+use fixture::{Fixture, Interaction};
+
+/// Converts payloads between text typed by the user and bytes that are
+/// sent over channels.
+pub trait Codec {
+
+    /// Convert text into the payload.
+    fn encode(&self, text: &str) -> Result<Vec<u8>, String>;
+
+    /// Convert received payload into text.
+    fn decode(&self, bytes: &[u8]) -> String;
+}
+
+/// Codec that accepts either hexadecimal bytes prefixed with '0x' or
+/// plain text. Received payloads are shown as text when they are valid
+/// UTF-8 and as hexadecimal bytes otherwise.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TextCodec;
+
+impl Codec for TextCodec {
+
+    fn encode(&self, text: &str) -> Result<Vec<u8>, String> {
+        let hex = match text.strip_prefix("0x") {
+            Some(hex)   => hex,
+            None        => return Ok(text.as_bytes().to_vec()),
+        };
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err("not a hexadecimal digit".to_string());
+        }
+        if !hex.len().is_multiple_of(2) {
+            return Err("odd count of hexadecimal digits".to_string());
+        }
+        // All digits are ASCII, so each byte is a char boundary.
+        (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        match ::std::str::from_utf8(bytes) {
+            Ok(s)   => s.to_string(),
+            Err(_)  => {
+                let mut out = "0x".to_string();
+                for b in bytes {
+                    let _ = write!(out, "{:02x}", b);
+                }
+                out
+            },
+        }
+    }
+}
+
+/// Error of the console command.
+#[derive(Debug)]
+pub enum ConsoleErr {
+
+    /// Command is not known or has wrong arguments.
+    Usage(String),
+
+    /// Payload could not be encoded.
+    Payload(String),
+
+    /// No object provides the service.
+    NoProvider,
+
+    /// Channel failed during the call.
+    Socket(SocketErr),
+}
+
+/// Console attached to some open network.
+pub struct Console<'a, N: 'a, C> {
+    network     : &'a N,
+    codec       : C,
+
+    /// Calls made by 'execute' while recording is on.
+    recording   : RefCell<Option<Fixture>>,
+}
+
+impl<'a, N, C: Codec> Console<'a, N, C> {
+
+    /// Attach console to the network.
+    pub fn new(network: &'a N, codec: C) -> Self {
+        Console {
+            network,
+            codec,
+            recording   : RefCell::new(None),
+        }
+    }
+
+    /// Start recording calls made by 'execute'. Recording that is
+    /// already going on is discarded.
+    pub fn start_recording(&self) {
+        *self.recording.borrow_mut() = Some(Fixture::new());
+    }
+
+    /// Stop recording and get the recorded calls as a test fixture.
+    pub fn stop_recording(&self) -> Option<Fixture> {
+        self.recording.borrow_mut().take()
+    }
+
+    /// List identifiers of all registered services.
+    pub fn services<S>(&self) -> Vec<S::Id>
+        where   S   : Service,
+                N   : OpenNetwork<S>
+    {
+        self.network.snapshot().entries.into_iter().map(|e| e.id).collect()
+    }
+
+    /// Connect to the service, send the payload and wait for a single
+    /// reply.
+    pub fn call<S>(&self, service: S, payload: &str)
+        -> Result<String, ConsoleErr>
+        where   S   : Service,
+                N   : OpenNetwork<S>
+    {
+        let payload = self.codec.encode(payload).map_err(ConsoleErr::Payload)?;
+        let reply = self.call_raw(service, payload)?;
+        Ok(self.codec.decode(&reply))
+    }
+
+    fn call_raw<S>(&self, service: S, payload: Vec<u8>)
+        -> Result<Vec<u8>, ConsoleErr>
+        where   S   : Service,
+                N   : OpenNetwork<S>
+    {
+        let socket = self.network.connect(service)
+            .map_err(|_| ConsoleErr::NoProvider)?;
+        socket.send(payload).map_err(ConsoleErr::Socket)?;
+        let reply = socket.receive::<Vec<u8>>().map_err(ConsoleErr::Socket)?;
+        socket.close();
+        Ok(reply)
+    }
+
+    /// Watch control events that pass the filter.
+    pub fn watch<OId, SId>(&self, log: &EventLog<OId, SId>,
+            filter: EventFilter<OId, SId>) -> Receiver<ControlEvent<OId, SId>>
+        where   OId : PartialEq + Clone,
+                SId : PartialEq + Clone
+    {
+        log.subscribe(filter)
+    }
+
+    /// Execute single command line and return its output. Known
+    /// commands are:
+    ///
+    /// * 'list' - list registered services;
+    /// * 'call <service> <payload>' - call the service.
+    pub fn execute<S>(&self, line: &str) -> Result<String, ConsoleErr>
+        where   S   : Service,
+                S::Id: FromStr + ToString,
+                N   : OpenNetwork<S>
+    {
+        let mut words = line.trim().splitn(3, ' ');
+        match words.next() {
+            Some("list") => {
+                let ids: Vec<_> = self.services::<S>().iter()
+                    .map(|id| id.to_string())
+                    .collect();
+                Ok(ids.join("\n"))
+            },
+            Some("call") => {
+                let text = words.next().unwrap_or("");
+                let id = text.parse()
+                    .map_err(|_| ConsoleErr::Usage("call <service> <payload>".to_string()))?;
+                let payload = self.codec.encode(words.next().unwrap_or(""))
+                    .map_err(ConsoleErr::Payload)?;
+                let reply = self.call_raw(S::by_id(id), payload.clone())?;
+                if let Some(ref mut fixture) = *self.recording.borrow_mut() {
+                    fixture.push(Interaction {
+                        service : text.to_string(),
+                        request : payload,
+                        reply   : reply.clone(),
+                    });
+                }
+                Ok(self.codec.decode(&reply))
+            },
+            _ => Err(ConsoleErr::Usage("list | call <service> <payload>".to_string())),
+        }
+    }
+
+    /// Execute the command lines read from the input, writing the
+    /// output of each to the output, until the input ends or a line
+    /// says 'quit'.
+    pub fn run<S, R, W>(&self, input: R, mut output: W) -> io::Result<()>
+        where   S   : Service,
+                S::Id: FromStr + ToString,
+                N   : OpenNetwork<S>,
+                R   : BufRead,
+                W   : io::Write
+    {
+        for line in input.lines() {
+            let line = line?;
+            match line.trim() {
+                ""      => continue,
+                "quit"  => break,
+                _       => (),
+            }
+            match self.execute::<S>(&line) {
+                Ok(out) => writeln!(output, "{}", out)?,
+                Err(e)  => writeln!(output, "error: {:?}", e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use RegistrationForm;
+
+    #[test]
+    fn text_codec() {
+        assert_eq!(TextCodec.encode("0x00ff").unwrap(), vec![0, 255]);
+        assert_eq!(TextCodec.encode("hi").unwrap(), b"hi".to_vec());
+        assert_eq!(TextCodec.decode(&[0xff]), "0xff");
+        assert!(TextCodec.encode("0x0").is_err());
+        assert!(TextCodec.encode("0xzz").is_err());
+        assert!(TextCodec.encode("0x\u{e9}0").is_err());
+        assert!(TextCodec.encode("0x\u{e9}").is_err());
+    }
+
+    fn echo(socket: LocalSocket) -> ! {
+        if let Ok(bytes) = socket.receive::<Vec<u8>>() {
+            let _ = socket.send(bytes);
+        }
+        finish()
+    }
+
+    #[test]
+    fn run_lines() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let console = Console::new(&network, TextCodec);
+        let input = "list\ncall echo 0x6869\ncall echo 0x6\nquit\nlist\n";
+        let mut output = Vec::new();
+        console.run::<LocalService, _, _>(input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[..2], ["echo", "hi"]);
+        assert!(lines[2].starts_with("error: Payload"));
+        assert_eq!(lines.len(), 3);
+    }
+}
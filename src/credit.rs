@@ -0,0 +1,149 @@
+//! Flow-control credits. Each channel of the object may have only as
+//! many messages in flight as it has credits, and all credits of the
+//! object come from one budget. Object may move credits from a bulk
+//! channel to an urgent one at runtime and back, trading throughput of
+//! one for latency of the other without reconfiguring the channels.
+//!
+//! This module is a utility for the backends that bound the messages in
+//! flight per object. The local network bounds them per channel with the
+//! capacity given at connect and does not implement 'CreditSocket'.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use super::{Object, Service, Socket};
+
+/// Credits of one channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Credits {
+
+    /// Count of messages the channel may have in flight.
+    pub limit       : u32,
+
+    /// Count of messages currently in flight.
+    pub in_flight   : u32,
+}
+
+/// Error of the credit transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreditErr {
+
+    /// Channel is not known to the budget.
+    UnknownChannel,
+
+    /// Channel has fewer credits than requested to move.
+    Insufficient {
+        available   : u32,
+    },
+}
+
+/// Credits of all channels of one object for backends to keep.
+pub struct CreditBudget<C> {
+    channels    : Mutex<HashMap<C, Credits>>,
+}
+
+impl<C> Default for CreditBudget<C> {
+
+    fn default() -> Self {
+        CreditBudget {
+            channels    : Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<C: Hash + Eq> CreditBudget<C> {
+
+    /// Add the channel with given limit.
+    pub fn open(&self, channel: C, limit: u32) {
+        self.channels.lock().unwrap().insert(channel, Credits {
+            limit,
+            in_flight   : 0,
+        });
+    }
+
+    /// Remove the channel. Its credits are returned.
+    pub fn close(&self, channel: &C) -> Option<Credits> {
+        self.channels.lock().unwrap().remove(channel)
+    }
+
+    /// Credits of the channel.
+    pub fn credits(&self, channel: &C) -> Option<Credits> {
+        self.channels.lock().unwrap().get(channel).cloned()
+    }
+
+    /// Take a credit to send a message. False if all credits of the
+    /// channel are in flight and sender must wait.
+    pub fn acquire(&self, channel: &C) -> bool {
+        match self.channels.lock().unwrap().get_mut(channel) {
+            Some(c) if c.in_flight < c.limit => {
+                c.in_flight += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Return the credit when the message was received.
+    pub fn release(&self, channel: &C) {
+        if let Some(c) = self.channels.lock().unwrap().get_mut(channel) {
+            c.in_flight = c.in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Move credits from one channel to another. Credits that are in
+    /// flight can be moved too: the donor then can't send until enough
+    /// of its messages are received.
+    pub fn donate(&self, from: &C, to: &C, amount: u32) -> Result<(), CreditErr> {
+        let mut channels = self.channels.lock().unwrap();
+        if !channels.contains_key(to) {
+            return Err(CreditErr::UnknownChannel);
+        }
+        let donor = channels.get_mut(from).ok_or(CreditErr::UnknownChannel)?;
+        if donor.limit < amount {
+            return Err(CreditErr::Insufficient {
+                available   : donor.limit,
+            });
+        }
+        donor.limit -= amount;
+        channels.get_mut(to).unwrap().limit += amount;
+        Ok(())
+    }
+}
+
+/// Socket which channel is flow-controlled by credits.
+pub trait CreditSocket<O, S>: Socket<O, S>
+        where O: Object<S>, S: Service {
+
+    /// Credits of this channel.
+    fn credits(&self) -> Credits;
+
+    /// Move credits of this channel to the other channel of the same
+    /// object.
+    fn donate(&self, to: &Self, amount: u32) -> Result<(), CreditErr>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bulk_donates_to_urgent() {
+        let budget = CreditBudget::default();
+        budget.open("bulk", 2);
+        budget.open("urgent", 1);
+        assert!(budget.acquire(&"bulk"));
+        assert!(budget.acquire(&"bulk"));
+        assert!(budget.acquire(&"urgent"));
+        assert!(!budget.acquire(&"urgent"));
+
+        budget.donate(&"bulk", &"urgent", 2).unwrap();
+        assert!(budget.acquire(&"urgent"));
+        assert!(!budget.acquire(&"bulk"));
+        budget.release(&"bulk");
+        budget.release(&"bulk");
+        assert!(!budget.acquire(&"bulk"));
+        assert_eq!(budget.donate(&"bulk", &"urgent", 1),
+                Err(CreditErr::Insufficient { available: 0 }));
+    }
+}
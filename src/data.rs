@@ -0,0 +1,160 @@
+//! Containers for message payloads.
+
+use std::fmt;
+use std::ops::Deref;
+
+use super::Data;
+
+/// Default count of bytes that 'SmallData' keeps inline.
+pub const INLINE_LEN: usize = 32;
+
+/// Bytes of the payload that are kept inline when there are at most N
+/// of them and on the heap otherwise. Most control messages are tiny,
+/// so this avoids an allocation per message in the common case.
+#[derive(Clone)]
+pub struct SmallData<const N: usize = INLINE_LEN> {
+    repr    : Repr<N>,
+}
+
+#[derive(Clone)]
+enum Repr<const N: usize> {
+    Inline {
+        len : usize,
+        buf : [u8; N],
+    },
+    Heap(Vec<u8>),
+}
+
+impl<const N: usize> SmallData<N> {
+
+    /// Create empty payload.
+    pub fn new() -> Self {
+        SmallData {
+            repr    : Repr::Inline { len: 0, buf: [0; N] },
+        }
+    }
+
+    /// Copy the bytes into new payload.
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        if bytes.len() > N {
+            return SmallData { repr: Repr::Heap(bytes.to_vec()) };
+        }
+        let mut buf = [0; N];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        SmallData {
+            repr    : Repr::Inline { len: bytes.len(), buf },
+        }
+    }
+
+    /// Bytes of the payload.
+    pub fn as_slice(&self) -> &[u8] {
+        match self.repr {
+            Repr::Inline { len, ref buf }   => &buf[..len],
+            Repr::Heap(ref v)               => v,
+        }
+    }
+
+    /// Check if the bytes are kept inline.
+    pub fn is_inline(&self) -> bool {
+        match self.repr {
+            Repr::Inline { .. } => true,
+            Repr::Heap(_)       => false,
+        }
+    }
+
+    /// Append the bytes, moving the payload to the heap if it no longer
+    /// fits inline.
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        match self.repr {
+            Repr::Inline { ref mut len, ref mut buf } if *len + bytes.len() <= N => {
+                buf[*len..*len + bytes.len()].copy_from_slice(bytes);
+                *len += bytes.len();
+            },
+            Repr::Heap(ref mut v) => v.extend_from_slice(bytes),
+            Repr::Inline { len, ref buf } => {
+                let mut v = Vec::with_capacity(len + bytes.len());
+                v.extend_from_slice(&buf[..len]);
+                v.extend_from_slice(bytes);
+                self.repr = Repr::Heap(v);
+            },
+        }
+    }
+
+    /// Convert into vector of bytes.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.repr {
+            Repr::Inline { len, buf }   => buf[..len].to_vec(),
+            Repr::Heap(v)               => v,
+        }
+    }
+}
+
+impl<const N: usize> Default for SmallData<N> {
+
+    fn default() -> Self {
+        SmallData::new()
+    }
+}
+
+impl<const N: usize> Deref for SmallData<N> {
+
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl<const N: usize> From<Vec<u8>> for SmallData<N> {
+
+    /// Vector that fits inline is copied, otherwise it is moved
+    /// without copying.
+    fn from(v: Vec<u8>) -> Self {
+        if v.len() <= N {
+            SmallData::from_slice(&v)
+        } else {
+            SmallData { repr: Repr::Heap(v) }
+        }
+    }
+}
+
+impl<'a, const N: usize> From<&'a [u8]> for SmallData<N> {
+
+    fn from(bytes: &'a [u8]) -> Self {
+        SmallData::from_slice(bytes)
+    }
+}
+
+impl<const N: usize> PartialEq for SmallData<N> {
+
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<const N: usize> Eq for SmallData<N> {
+}
+
+impl<const N: usize> fmt::Debug for SmallData<N> {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+impl<const N: usize> Data for SmallData<N> {
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spills_to_heap() {
+        let mut data = SmallData::<4>::from_slice(&[1, 2, 3]);
+        assert!(data.is_inline());
+        data.extend_from_slice(&[4, 5]);
+        assert!(!data.is_inline());
+        assert_eq!(&*data, &[1, 2, 3, 4, 5]);
+    }
+}
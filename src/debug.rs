@@ -0,0 +1,125 @@
+//! Interactive debugging of live channels. A debugging tool attaches to
+//! some object and then can list its open sockets, peek the messages
+//! queued in them, inject test messages and force-close channels. It
+//! is the CCS counterpart of attaching a debugger to a process.
+
+use super::{Data, Network, Service};
+
+/// Name of the capability that permits debugging. Its service patterns
+/// are matched against the identifier of the debugged object.
+pub const DEBUG_CAPABILITY: &str = "debug";
+
+/// Error of the debugging operation.
+#[derive(Debug)]
+pub enum DebugErr {
+
+    /// Caller lacks the capability to debug given object.
+    Denied,
+
+    /// Object does not exist or is not alive.
+    NoObject,
+
+    /// Channel does not belong to the object of the session.
+    NoChannel,
+
+    /// Channel is already closed.
+    ChannelClosed,
+
+    /// Buffer of the channel has no room for the injected message.
+    Full,
+}
+
+/// Side of the channel the object is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+
+    /// Object requested the service.
+    Requester,
+
+    /// Object provides the service.
+    Provider,
+}
+
+/// Direction of the message in the channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+
+    /// From requester to provider.
+    ToProvider,
+
+    /// From provider to requester.
+    ToRequester,
+}
+
+/// Open socket of the debugged object.
+#[derive(Debug, Clone)]
+pub struct SocketInfo<OId, SId, CId> {
+
+    /// Channel of the socket.
+    pub channel     : CId,
+
+    /// Service the channel was established for.
+    pub service     : SId,
+
+    /// Object on the other side of the channel.
+    pub peer        : OId,
+
+    /// Side of the channel the debugged object is on.
+    pub role        : Role,
+
+    /// Count of the messages that are sent but not yet received.
+    pub queued      : usize,
+}
+
+/// Message that is sent but not yet received.
+#[derive(Debug, Clone)]
+pub struct QueuedMessage {
+
+    /// Where the message goes.
+    pub direction   : Direction,
+
+    /// Size of the message in bytes.
+    pub size        : usize,
+
+    /// Raw content of the message if the backend can show it.
+    pub bytes       : Option<Vec<u8>>,
+}
+
+/// Network that allows debugging of its objects.
+pub trait DebugNetwork<S: Service>: Network<S> {
+
+    /// Identifier of the object in this network.
+    type ObjectId;
+
+    /// Identifier of the channel in this network.
+    type ChannelId;
+
+    /// Debugging session attached to a single object.
+    type Session;
+
+    /// Attach to the object. Fails with 'DebugErr::Denied' unless the
+    /// caller holds the capability to debug this object, see
+    /// 'DEBUG_CAPABILITY'.
+    fn attach(&self, object: &Self::ObjectId)
+        -> Result<Self::Session, DebugErr>;
+
+    /// List open sockets of the object. The list is empty if the object
+    /// has died since the session was attached.
+    fn sockets(&self, session: &Self::Session)
+        -> Vec<SocketInfo<Self::ObjectId, S::Id, Self::ChannelId>>;
+
+    /// Get at most 'max' messages queued in the channel without
+    /// removing them from the queue.
+    fn peek(&self, session: &Self::Session, channel: &Self::ChannelId,
+            max: usize) -> Result<Vec<QueuedMessage>, DebugErr>;
+
+    /// Put the message into the channel as if it was sent by the side
+    /// opposite to given direction.
+    fn inject<D: Data>(&self, session: &Self::Session,
+            channel: &Self::ChannelId, direction: Direction, data: D)
+            -> Result<(), DebugErr>;
+
+    /// Close the channel. Both sides get 'SocketErr::ChannelClosed'.
+    fn force_close(&self, session: &Self::Session, channel: &Self::ChannelId)
+        -> Result<(), DebugErr>;
+}
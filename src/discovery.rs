@@ -0,0 +1,72 @@
+//! Batch discovery of the services registered in the network.
+//! Management tools and caches take one snapshot of the registry and
+//! then keep it up to date by requesting only the changes made since
+//! the snapshot.
+
+/// Position in the history of registry changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cursor(pub u64);
+
+/// Registered service as seen in the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryEntry<Id> {
+
+    /// Identifier of the service.
+    pub id          : Id,
+
+    /// Count of the objects that provide the service.
+    pub providers   : usize,
+
+    /// Whether the service is uniquely registered.
+    pub unique      : bool,
+}
+
+/// State of the whole registry at some point.
+#[derive(Debug, Clone)]
+pub struct Snapshot<Id> {
+
+    /// All registered services.
+    pub entries     : Vec<RegistryEntry<Id>>,
+
+    /// Cursor to request changes made after this snapshot.
+    pub cursor      : Cursor,
+}
+
+/// Single change of the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryChange<Id> {
+
+    /// New provider registered the service.
+    Registered {
+        id      : Id,
+        unique  : bool,
+    },
+
+    /// Provider discontinued the service.
+    Discontinued {
+        id      : Id,
+    },
+}
+
+/// Changes of the registry since some cursor.
+#[derive(Debug, Clone)]
+pub struct Delta<Id> {
+
+    /// Changes in the order they were made.
+    pub changes     : Vec<RegistryChange<Id>>,
+
+    /// Cursor to request next changes.
+    pub cursor      : Cursor,
+}
+
+/// Error that appears when requesting changes.
+#[derive(Debug)]
+pub enum CursorErr {
+
+    /// Network no longer keeps history that far back. New snapshot
+    /// must be taken.
+    Expired,
+
+    /// Cursor was not issued by this network.
+    Invalid,
+}
@@ -0,0 +1,284 @@
+//! Control-plane events of the network: registrations, connects,
+//! kills, denials and penalized slow consumers. Networks publish them
+//! to a single event log and loggers or security monitors subscribe to
+//! the log with a filter, locally or over a CCS channel.
+
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use super::{Data, ExitReason, Object, Service, Socket, SocketErr};
+use reaper::AuditSink;
+use pubsub::SlowConsumer;
+use slo::Objective;
+
+/// Event of the network control plane.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlEvent<OId, SId> {
+
+    /// Object registered the service.
+    Registered {
+        service     : SId,
+        owner       : OId,
+        unique      : bool,
+    },
+
+    /// Object discontinued the service.
+    Discontinued {
+        service     : SId,
+        owner       : OId,
+    },
+
+    /// Channel was established.
+    Connected {
+        service     : SId,
+        requester   : OId,
+        provider    : OId,
+    },
+
+    /// Object was killed or deceased.
+    Killed {
+        object      : OId,
+        reason      : ExitReason,
+    },
+
+    /// Network denied the object to connect to or register the service.
+    Denied {
+        service     : SId,
+        object      : OId,
+    },
+
+    /// Service stopped meeting its objective. Observed value is in the
+    /// units of the objective.
+    SloViolated {
+        service     : SId,
+        objective   : Objective,
+        observed    : u32,
+    },
+
+    /// Pub/sub subscriber fell behind and was penalized.
+    SlowConsumer(SlowConsumer),
+}
+
+/// Kinds of the control events, used to filter them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Registered,
+    Discontinued,
+    Connected,
+    Killed,
+    Denied,
+    SloViolated,
+    SlowConsumer,
+}
+
+impl<OId, SId> ControlEvent<OId, SId> {
+
+    /// Kind of this event.
+    pub fn kind(&self) -> EventKind {
+        match *self {
+            ControlEvent::Registered    { .. } => EventKind::Registered,
+            ControlEvent::Discontinued  { .. } => EventKind::Discontinued,
+            ControlEvent::Connected     { .. } => EventKind::Connected,
+            ControlEvent::Killed        { .. } => EventKind::Killed,
+            ControlEvent::Denied        { .. } => EventKind::Denied,
+            ControlEvent::SloViolated   { .. } => EventKind::SloViolated,
+            ControlEvent::SlowConsumer  (..)   => EventKind::SlowConsumer,
+        }
+    }
+
+    /// Service this event is about, if any.
+    pub fn service(&self) -> Option<&SId> {
+        match *self {
+            ControlEvent::Registered    { ref service, .. } |
+            ControlEvent::Discontinued  { ref service, .. } |
+            ControlEvent::Connected     { ref service, .. } |
+            ControlEvent::Denied        { ref service, .. } |
+            ControlEvent::SloViolated   { ref service, .. } => Some(service),
+            ControlEvent::Killed        { .. }              |
+            ControlEvent::SlowConsumer  (..)                => None,
+        }
+    }
+
+    /// Check if given object takes part in this event.
+    pub fn involves(&self, id: &OId) -> bool where OId: PartialEq {
+        match *self {
+            ControlEvent::Registered    { ref owner, .. } |
+            ControlEvent::Discontinued  { ref owner, .. } => owner == id,
+            ControlEvent::Connected { ref requester, ref provider, .. }
+                => requester == id || provider == id,
+            ControlEvent::Killed    { ref object, .. } |
+            ControlEvent::Denied    { ref object, .. } => object == id,
+            ControlEvent::SloViolated { .. } |
+            ControlEvent::SlowConsumer(..) => false,
+        }
+    }
+}
+
+impl<OId, SId> Data for ControlEvent<OId, SId>
+        where OId: Send + 'static, SId: Send + 'static {
+}
+
+/// Filter of the control events. Empty filter matches all events.
+#[derive(Debug, Clone)]
+pub struct EventFilter<OId, SId> {
+
+    /// Kinds of the events to pass. All kinds pass if empty.
+    pub kinds       : Vec<EventKind>,
+
+    /// Pass only events that involve given object.
+    pub object      : Option<OId>,
+
+    /// Pass only events about given service.
+    pub service     : Option<SId>,
+}
+
+impl<OId, SId> Default for EventFilter<OId, SId> {
+
+    fn default() -> Self {
+        EventFilter {
+            kinds       : Vec::new(),
+            object      : None,
+            service     : None,
+        }
+    }
+}
+
+impl<OId: PartialEq, SId: PartialEq> EventFilter<OId, SId> {
+
+    /// Check if the event passes the filter.
+    pub fn matches(&self, event: &ControlEvent<OId, SId>) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&event.kind()) {
+            return false;
+        }
+        if let Some(ref object) = self.object {
+            if !event.involves(object) {
+                return false;
+            }
+        }
+        if let Some(ref service) = self.service {
+            if event.service() != Some(service) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl<OId, SId> Data for EventFilter<OId, SId>
+        where OId: Send + 'static, SId: Send + 'static {
+}
+
+type Subscriber<OId, SId> = (EventFilter<OId, SId>, Sender<ControlEvent<OId, SId>>);
+
+/// Log that receives control events from networks and delivers them to
+/// the subscribers.
+pub struct EventLog<OId, SId> {
+    subscribers : Mutex<Vec<Subscriber<OId, SId>>>,
+}
+
+impl<OId, SId> Default for EventLog<OId, SId> {
+
+    fn default() -> Self {
+        EventLog {
+            subscribers : Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<OId, SId> EventLog<OId, SId>
+        where   OId : PartialEq + Clone,
+                SId : PartialEq + Clone
+{
+
+    /// Create log without subscribers.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Subscribe to the events that pass given filter. Subscription
+    /// ends when the receiver is dropped.
+    pub fn subscribe(&self, filter: EventFilter<OId, SId>)
+        -> Receiver<ControlEvent<OId, SId>>
+    {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push((filter, tx));
+        rx
+    }
+
+    /// Deliver the event to all interested subscribers.
+    pub fn publish(&self, event: ControlEvent<OId, SId>) {
+        self.subscribers.lock().unwrap().retain(|(filter, tx)| {
+            !filter.matches(&event) || tx.send(event.clone()).is_ok()
+        });
+    }
+
+    /// Serve one subscriber connected over the channel. Subscriber
+    /// first sends the filter and then receives matching events until
+    /// the channel gets closed.
+    pub fn serve<O, S, SC>(&self, socket: &SC) -> Result<(), SocketErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                OId : Send + 'static,
+                SId : Send + 'static
+    {
+        let filter = socket.receive::<EventFilter<OId, SId>>()?;
+        let events = self.subscribe(filter);
+        for event in events {
+            socket.send(event)?;
+        }
+        Ok(())
+    }
+}
+
+impl<OId, SId> AuditSink<ControlEvent<OId, SId>> for EventLog<OId, SId>
+        where   OId : PartialEq + Clone,
+                SId : PartialEq + Clone
+{
+
+    fn record(&self, event: ControlEvent<OId, SId>) {
+        self.publish(event)
+    }
+}
+
+impl<OId, SId> AuditSink<SlowConsumer> for EventLog<OId, SId>
+        where   OId : PartialEq + Clone,
+                SId : PartialEq + Clone
+{
+
+    fn record(&self, report: SlowConsumer) {
+        self.publish(ControlEvent::SlowConsumer(report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filtered_subscription() {
+        let log = EventLog::<u32, &str>::new();
+        let killed = log.subscribe(EventFilter {
+            kinds   : vec![EventKind::Killed],
+            ..Default::default()
+        });
+        let memory = log.subscribe(EventFilter {
+            service : Some("memory"),
+            ..Default::default()
+        });
+
+        log.publish(ControlEvent::Killed {
+            object  : 1,
+            reason  : ExitReason::Normal,
+        });
+        log.publish(ControlEvent::Registered {
+            service : "memory",
+            owner   : 2,
+            unique  : true,
+        });
+
+        assert_eq!(killed.try_iter().count(), 1);
+        assert_eq!(memory.try_iter().next().map(|e| e.kind()),
+                Some(EventKind::Registered));
+    }
+}
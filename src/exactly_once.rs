@@ -0,0 +1,293 @@
+//! Exactly-once delivery for critical control messages. Each message
+//! gets a sequence number and the receiver acknowledges it. The sender
+//! reserves the number in a persistent store before the first attempt,
+//! so no number is ever given to two messages, and resends the message
+//! until it is acknowledged. The receiver keeps the number of the last
+//! delivered message in a persistent store, so resent duplicates are
+//! acknowledged again but not delivered twice, even across restarts of
+//! either end.
+//!
+//! 'Sender' and 'Receiver' are a layer over any socket, the local ones
+//! included. Networks don't number the messages for them: the stores
+//! outlive the channels, and with them the restarts of the objects.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::{Data, Object, Service, Socket, SocketErr, Time};
+use idempotency::Idempotent;
+
+/// Message with its sequence number.
+#[derive(Debug, Clone)]
+pub struct Sequenced<D> {
+
+    /// Sequence number of the message in its stream.
+    pub seq     : u64,
+
+    /// The message itself.
+    pub payload : D,
+}
+
+impl<D: Data> Data for Sequenced<D> {
+}
+
+/// Sequence number also serves as the idempotency key, so plain
+/// deduplicating providers can accept sequenced messages too.
+impl<D> Idempotent<u64> for Sequenced<D> {
+
+    fn idempotency_key(&self) -> Option<&u64> {
+        Some(&self.seq)
+    }
+}
+
+/// Acknowledgement of the message with given sequence number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ack {
+
+    /// Sequence number of the acknowledged message.
+    pub seq     : u64,
+}
+
+impl Data for Ack {
+}
+
+/// Error of the exactly-once delivery.
+#[derive(Debug)]
+pub enum ExactlyOnceErr {
+
+    /// Channel failed.
+    Socket(SocketErr),
+
+    /// Message was resent given count of times and still was not
+    /// acknowledged. It may have been delivered all the same, so it
+    /// must be resent with the same sequence number, see
+    /// 'Sender::resend'.
+    NotAcknowledged {
+        seq     : u64,
+        retries : u32,
+    },
+}
+
+/// Persistent storage of sequence numbers. Stream key identifies the
+/// channel in a way that survives restarts of its endpoints.
+pub trait SequenceStore {
+
+    /// Get last sequence number saved for the stream.
+    fn last_seq(&self, stream: &str) -> Option<u64>;
+
+    /// Save last sequence number for the stream.
+    fn set_last_seq(&self, stream: &str, seq: u64);
+
+    /// Reserve the next sequence number of the sending stream and save
+    /// the reservation before returning it. Numbers start at zero and
+    /// are never given out twice.
+    fn reserve_seq(&self, stream: &str) -> u64;
+}
+
+/// Sequence store that keeps numbers in memory. Suitable for tests and
+/// for receivers that do not outlive their senders.
+#[derive(Debug, Default)]
+pub struct MemorySequenceStore {
+    map     : Mutex<HashMap<String, u64>>,
+
+    /// Next numbers of the sending streams.
+    next    : Mutex<HashMap<String, u64>>,
+}
+
+impl SequenceStore for MemorySequenceStore {
+
+    fn last_seq(&self, stream: &str) -> Option<u64> {
+        self.map.lock().unwrap().get(stream).cloned()
+    }
+
+    fn set_last_seq(&self, stream: &str, seq: u64) {
+        self.map.lock().unwrap().insert(stream.to_string(), seq);
+    }
+
+    fn reserve_seq(&self, stream: &str) -> u64 {
+        let mut next = self.next.lock().unwrap();
+        let next = next.entry(stream.to_string()).or_insert(0);
+        *next += 1;
+        *next - 1
+    }
+}
+
+/// Sending side of the exactly-once stream.
+pub struct Sender<'a, St: 'a> {
+    store   : &'a St,
+    stream  : String,
+}
+
+impl<'a, St: SequenceStore> Sender<'a, St> {
+
+    /// Create sender of the stream with given key.
+    pub fn new(store: &'a St, stream: &str) -> Self {
+        Sender {
+            store,
+            stream  : stream.to_string(),
+        }
+    }
+
+    /// Send the message under the newly reserved sequence number,
+    /// waiting 'timeout' for acknowledgement and resending it at most
+    /// 'retries' times.
+    pub fn send<O, S, SC, D, T>(&self, socket: &SC, payload: D, timeout: T,
+            retries: u32) -> Result<(), ExactlyOnceErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                D   : Data + Clone,
+                T   : Time + Clone
+    {
+        let seq = self.store.reserve_seq(&self.stream);
+        self.resend(socket, seq, payload, timeout, retries)
+    }
+
+    /// Send again the message that failed with
+    /// 'ExactlyOnceErr::NotAcknowledged'. If the receiver got it
+    /// before, it only acknowledges it again.
+    pub fn resend<O, S, SC, D, T>(&self, socket: &SC, seq: u64, payload: D, timeout: T,
+            retries: u32) -> Result<(), ExactlyOnceErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                D   : Data + Clone,
+                T   : Time + Clone
+    {
+        for _ in 0..retries + 1 {
+            socket.send(Sequenced { seq, payload: payload.clone() })
+                .map_err(ExactlyOnceErr::Socket)?;
+            loop {
+                match socket.wait_to_receive::<Ack, T>(timeout.clone()) {
+                    Some(Ok(ack)) if ack.seq == seq => return Ok(()),
+
+                    // Late acknowledgement of some earlier resend.
+                    Some(Ok(_))     => continue,
+                    Some(Err(e))    => return Err(ExactlyOnceErr::Socket(e)),
+                    None            => break,
+                }
+            }
+        }
+        Err(ExactlyOnceErr::NotAcknowledged { seq, retries })
+    }
+}
+
+/// Receiving side of the exactly-once stream.
+pub struct Receiver<'a, St: 'a> {
+    store   : &'a St,
+    stream  : String,
+}
+
+impl<'a, St: SequenceStore> Receiver<'a, St> {
+
+    /// Create receiver of the stream with given key.
+    pub fn new(store: &'a St, stream: &str) -> Self {
+        Receiver {
+            store,
+            stream  : stream.to_string(),
+        }
+    }
+
+    /// Receive the next message that was not delivered before.
+    /// Duplicates are acknowledged and skipped. The message is
+    /// acknowledged only after the handler returns, so a message is
+    /// never lost if the receiver dies while handling it.
+    pub fn receive<O, S, SC, D, F>(&self, socket: &SC, handler: F)
+        -> Result<(), SocketErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                D   : Data,
+                F   : FnOnce(D)
+    {
+        let message = loop {
+            let message = socket.receive::<Sequenced<D>>()?;
+            let duplicate = self.store.last_seq(&self.stream)
+                .is_some_and(|last| message.seq <= last);
+            if !duplicate {
+                break message;
+            }
+            socket.send(Ack { seq: message.seq })?;
+        };
+        let seq = message.seq;
+        handler(message.payload);
+        self.store.set_last_seq(&self.stream, seq);
+        socket.send(Ack { seq })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use {OpenNetwork, RegistrationForm};
+
+    /// Payloads delivered by 'ledger' handlers.
+    static LEDGER: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Delivers the messages exactly once. Messages starting with
+    /// 'slow' are acknowledged late.
+    fn ledger(socket: LocalSocket) -> ! {
+        let store = MemorySequenceStore::default();
+        let receiver = Receiver::new(&store, "ledger");
+        while receiver.receive(&socket, |payload: String| {
+            if payload.starts_with("slow") {
+                thread::sleep(Duration::from_millis(60));
+            }
+            LEDGER.lock().unwrap().push(payload);
+        }).is_ok() {
+        }
+        finish()
+    }
+
+    fn delivered(suffix: &str) -> Vec<String> {
+        LEDGER.lock().unwrap().iter().filter(|p| p.ends_with(suffix)).cloned().collect()
+    }
+
+    fn connect() -> (LocalNetwork, LocalSocket) {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(ledger, "ledger".to_string())).unwrap();
+        let socket = network.connect_bounded(LocalService::by_id("ledger".to_string()), 8)
+            .unwrap();
+        (network, socket)
+    }
+
+    #[test]
+    fn resend_after_lost_ack() {
+        let (_network, socket) = connect();
+        let store = MemorySequenceStore::default();
+        let sender = Sender::new(&store, "ledger");
+        let short = Duration::from_millis(10);
+        let long = Duration::from_secs(5);
+
+        // Receiver gets the message but the ack comes too late.
+        let seq = match sender.send(&socket, "slow-a.lost".to_string(), short, 0) {
+            Err(ExactlyOnceErr::NotAcknowledged { seq, .. })    => seq,
+            other => panic!("unexpected {:?}", other),
+        };
+        // Next message gets its own number and skips the late ack.
+        sender.send(&socket, "b.lost".to_string(), long, 0).unwrap();
+        sender.resend(&socket, seq, "slow-a.lost".to_string(), long, 0).unwrap();
+        assert_eq!(delivered(".lost"), vec!["slow-a.lost", "b.lost"]);
+    }
+
+    #[test]
+    fn late_ack_and_restart() {
+        let (_network, socket) = connect();
+        let store = MemorySequenceStore::default();
+        let sender = Sender::new(&store, "ledger");
+
+        // Resends while the receiver is slow, then takes its late ack.
+        sender.send(&socket, "slow-x.late".to_string(), Duration::from_millis(20), 20)
+            .unwrap();
+        drop(sender);
+
+        // Restarted sender goes on with the next number.
+        let sender = Sender::new(&store, "ledger");
+        sender.send(&socket, "y.late".to_string(), Duration::from_secs(5), 0).unwrap();
+        assert_eq!(delivered(".late"), vec!["slow-x.late", "y.late"]);
+        assert_eq!(store.reserve_seq("ledger"), 2);
+    }
+}
@@ -0,0 +1,127 @@
+//! Feature detection. Backends differ in maturity: the Kobzar kernel
+//! implements most optional traits, while simpler backends implement
+//! only the core ones or implement some traits partially. Portable
+//! libraries ask the network for its features and adapt to what is
+//! there instead of failing at runtime on unsupported calls.
+
+/// Version of the CCS API this crate defines. Increased with each
+/// incompatible change of the core traits.
+pub const API_VERSION: u32 = 1;
+
+/// Optional feature of the network backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+
+    /// Asynchronous operations of 'aio'.
+    Async,
+
+    /// Messages can be placed in memory shared by the peers instead of
+    /// being copied.
+    SharedMemory,
+
+    /// Unique registration is enforced, so no other object can take
+    /// over the uniquely registered service.
+    UniqueRegistration,
+
+    /// Connects to named endpoints with 'EndpointConnect'.
+    Endpoints,
+
+    /// Connects with tokens of 'TokenConnect'.
+    ConnectTokens,
+
+    /// Publish/subscribe topics of 'pubsub'.
+    PubSub,
+
+    /// Pinning of the channels to scheduling domains.
+    Affinity,
+
+    /// Security policy enforcement.
+    Policy,
+
+    /// Capability checks on connect.
+    Capabilities,
+
+    /// Export and import of topology images.
+    Images,
+
+    /// Live migration of the objects.
+    Migration,
+
+    /// Preemption of contended services.
+    Preemption,
+
+    /// Resource usage reports.
+    Usage,
+
+    /// Timed waits of the sockets are native. Without it, backends may
+    /// implement them with coarse polling or not honour the time.
+    Timeouts,
+
+    /// Releases of the services with traffic splits of 'canary'.
+    TrafficSplit,
+
+    /// Connects by the partition key of 'partition'.
+    Partitioning,
+
+    /// Creation of the objects with 'Spawner'.
+    Spawning,
+}
+
+/// Features of the network backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+
+    /// Version of the CCS API the backend implements.
+    pub version : u32,
+    flags       : u64,
+}
+
+impl Default for Features {
+
+    /// No optional features at the API version of this crate.
+    fn default() -> Self {
+        Features {
+            version : API_VERSION,
+            flags   : 0,
+        }
+    }
+}
+
+impl Features {
+
+    /// Create empty set at the API version of this crate.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the feature to the set.
+    pub fn with(mut self, feature: Feature) -> Self {
+        self.flags |= 1 << feature as u32;
+        self
+    }
+
+    /// Check if the backend has the feature.
+    pub fn has(&self, feature: Feature) -> bool {
+        self.flags & (1 << feature as u32) != 0
+    }
+
+    /// Check if the backend implements the API of this crate. Backends
+    /// of other versions may differ in the semantics of the core traits.
+    pub fn is_current(&self) -> bool {
+        self.version == API_VERSION
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        let features = Features::new().with(Feature::Async).with(Feature::PubSub);
+        assert!(features.has(Feature::Async));
+        assert!(features.has(Feature::PubSub));
+        assert!(!features.has(Feature::SharedMemory));
+        assert!(features.is_current());
+    }
+}
@@ -0,0 +1,169 @@
+//! Test fixtures recorded from the console. Calls made by hand during
+//! exploratory testing are saved as a fixture and later replayed
+//! against the service to check that it still replies the same way.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use super::{OpenNetwork, Service, Socket, SocketErr};
+
+/// Single call of the service: the request and the reply to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interaction {
+
+    /// Identifier of the called service in text form.
+    pub service     : String,
+
+    /// Payload sent to the service.
+    pub request     : Vec<u8>,
+
+    /// Payload received from the service.
+    pub reply       : Vec<u8>,
+}
+
+/// Sequence of recorded interactions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fixture {
+
+    /// Interactions in the order they were made.
+    pub interactions    : Vec<Interaction>,
+}
+
+/// Error of the fixture replay or parsing.
+#[derive(Debug)]
+pub enum FixtureErr {
+
+    /// Data file has wrong format at given line.
+    Format(usize),
+
+    /// Service identifier could not be parsed.
+    BadService(String),
+
+    /// No object provides the service.
+    NoProvider(String),
+
+    /// Channel failed during the replay.
+    Socket(SocketErr),
+
+    /// Service replied differently than recorded.
+    Mismatch {
+        /// Index of the interaction.
+        index   : usize,
+
+        /// Reply that was actually received.
+        actual  : Vec<u8>,
+    },
+}
+
+impl Fixture {
+
+    /// Create empty fixture.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add interaction to the end of the fixture.
+    pub fn push(&mut self, interaction: Interaction) {
+        self.interactions.push(interaction);
+    }
+
+    /// Serialize the fixture into data file. Each line holds service,
+    /// request and reply separated by tabs, payloads are hexadecimal.
+    pub fn to_data(&self) -> String {
+        let mut out = String::new();
+        for i in &self.interactions {
+            let _ = writeln!(out, "{}\t{}\t{}", i.service, hex(&i.request),
+                    hex(&i.reply));
+        }
+        out
+    }
+
+    /// Parse the fixture from data file made by 'to_data'.
+    pub fn from_data(data: &str) -> Result<Self, FixtureErr> {
+        let mut fixture = Fixture::new();
+        for (n, line) in data.lines().enumerate().filter(|l| !l.1.is_empty()) {
+            let fields: Vec<_> = line.split('\t').collect();
+            if fields.len() != 3 {
+                return Err(FixtureErr::Format(n + 1));
+            }
+            fixture.push(Interaction {
+                service : fields[0].to_string(),
+                request : unhex(fields[1]).ok_or(FixtureErr::Format(n + 1))?,
+                reply   : unhex(fields[2]).ok_or(FixtureErr::Format(n + 1))?,
+            });
+        }
+        Ok(fixture)
+    }
+
+    /// Generate Rust source of the test function that replays this
+    /// fixture. The test expects a function 'network()' that returns
+    /// the network to run against and type alias 'TestService' to be
+    /// in scope.
+    pub fn to_rust_source(&self, test_name: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "#[test]");
+        let _ = writeln!(out, "fn {}() {{", test_name);
+        let _ = writeln!(out, "    let fixture = ::kobzar_ccs::fixture::Fixture::from_data(");
+        let _ = writeln!(out, "        {:?}).unwrap();", self.to_data());
+        let _ = writeln!(out, "    fixture.replay::<TestService, _>(&network())");
+        let _ = writeln!(out, "        .unwrap();");
+        let _ = writeln!(out, "}}");
+        out
+    }
+
+    /// Replay the fixture against the network. Each interaction opens
+    /// new channel to the service, sends the request and checks that
+    /// the reply is the same as recorded.
+    pub fn replay<S, N>(&self, network: &N) -> Result<(), FixtureErr>
+        where   S   : Service,
+                S::Id: FromStr,
+                N   : OpenNetwork<S>
+    {
+        for (index, i) in self.interactions.iter().enumerate() {
+            let id = i.service.parse()
+                .map_err(|_| FixtureErr::BadService(i.service.clone()))?;
+            let socket = network.connect(S::by_id(id))
+                .map_err(|_| FixtureErr::NoProvider(i.service.clone()))?;
+            socket.send(i.request.clone()).map_err(FixtureErr::Socket)?;
+            let actual = socket.receive::<Vec<u8>>().map_err(FixtureErr::Socket)?;
+            socket.close();
+            if actual != i.reply {
+                return Err(FixtureErr::Mismatch { index, actual });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_roundtrip() {
+        let mut fixture = Fixture::new();
+        fixture.push(Interaction {
+            service : "memory.alloc".to_string(),
+            request : vec![0, 16],
+            reply   : vec![],
+        });
+        assert_eq!(Fixture::from_data(&fixture.to_data()).unwrap(), fixture);
+    }
+}
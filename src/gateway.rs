@@ -0,0 +1,740 @@
+//! HTTP/JSON gateway for the development on hosted targets. Web
+//! dashboards and external tools talk to a Kobzar node over HTTP while
+//! the gateway translates their JSON into the binary messages of the
+//! services and the replies back, using the descriptions of 'reflection'
+//! to know the types. Routes are:
+//!
+//! * 'GET /services' - identifiers of the registered services;
+//! * 'GET /services/<service>' - description of the service;
+//! * 'POST /services/<service>/<method>' - call the method of the
+//!   service with the request in the body;
+//! * 'POST /services/<service>/<endpoint>/<method>' - call the method
+//!   of the named endpoint.
+//!
+//! Services are expected to speak the encoding of 'ccs-build' clients:
+//! number of the method in its endpoint as a byte, then the request, and
+//! the reply likewise. Bytes are JSON arrays of numbers, options are
+//! null or the value and structs are objects.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read as IoRead, Write as IoWrite};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+
+use super::{EndpointConnect, Service, Socket, SocketErr};
+use message::{self, DecodeErr, EncodeErr, Message, TypedErr};
+use reflection::{self, ReflectionErr, ServiceDescription, TypeDesc};
+
+/// Largest body of the HTTP request the gateway reads, in bytes.
+pub const MAX_BODY: usize = 1 << 20;
+
+/// JSON value. Numbers keep their text so that 64-bit integers are not
+/// rounded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Error of the gateway request.
+#[derive(Debug)]
+pub enum GatewayErr {
+
+    /// Body is not valid JSON. Holds the offset where parsing failed.
+    Syntax(usize),
+
+    /// HTTP request itself is malformed, e.g. its body is shorter than
+    /// its 'Content-Length'.
+    Malformed,
+
+    /// Body of the HTTP request is longer than 'MAX_BODY'.
+    TooLarge,
+
+    /// JSON value does not fit the type. Holds the expected type.
+    Mismatch(String),
+
+    /// There is no such route, service, endpoint or method.
+    NotFound,
+
+    /// Reply of the service does not fit its description.
+    Decode(DecodeErr),
+
+    /// Channel to the service failed.
+    Socket(SocketErr),
+}
+
+impl GatewayErr {
+
+    /// HTTP status of the error.
+    pub fn status(&self) -> u16 {
+        match *self {
+            GatewayErr::Syntax(_) | GatewayErr::Mismatch(_) => 400,
+            GatewayErr::Malformed                           => 400,
+            GatewayErr::NotFound                            => 404,
+            GatewayErr::TooLarge                            => 413,
+            GatewayErr::Decode(_) | GatewayErr::Socket(_)   => 502,
+        }
+    }
+}
+
+impl From<DecodeErr> for GatewayErr {
+
+    fn from(e: DecodeErr) -> Self {
+        GatewayErr::Decode(e)
+    }
+}
+
+impl From<EncodeErr> for GatewayErr {
+
+    // Only the sequences fail to encode, when they are too long.
+    fn from(_: EncodeErr) -> Self {
+        GatewayErr::TooLarge
+    }
+}
+
+impl From<SocketErr> for GatewayErr {
+
+    fn from(e: SocketErr) -> Self {
+        GatewayErr::Socket(e)
+    }
+}
+
+struct Parser<'a> {
+    text    : &'a [u8],
+    at      : usize,
+}
+
+impl<'a> Parser<'a> {
+
+    fn skip_space(&mut self) {
+        while self.text.get(self.at).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.at += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_space();
+        self.text.get(self.at).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), GatewayErr> {
+        if self.peek() == Some(byte) {
+            self.at += 1;
+            Ok(())
+        } else {
+            Err(GatewayErr::Syntax(self.at))
+        }
+    }
+
+    fn word(&mut self, word: &str, value: Json) -> Result<Json, GatewayErr> {
+        if self.text[self.at..].starts_with(word.as_bytes()) {
+            self.at += word.len();
+            Ok(value)
+        } else {
+            Err(GatewayErr::Syntax(self.at))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, GatewayErr> {
+        match self.peek() {
+            Some(b'n')  => self.word("null", Json::Null),
+            Some(b't')  => self.word("true", Json::Bool(true)),
+            Some(b'f')  => self.word("false", Json::Bool(false)),
+            Some(b'"')  => self.string().map(Json::String),
+            Some(b'[')  => {
+                self.at += 1;
+                let mut items = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.at += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    match self.peek() {
+                        Some(b',')  => self.at += 1,
+                        _           => break,
+                    }
+                }
+                self.expect(b']')?;
+                Ok(Json::Array(items))
+            },
+            Some(b'{')  => {
+                self.at += 1;
+                let mut fields = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.at += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_space();
+                    let name = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((name, self.value()?));
+                    match self.peek() {
+                        Some(b',')  => self.at += 1,
+                        _           => break,
+                    }
+                }
+                self.expect(b'}')?;
+                Ok(Json::Object(fields))
+            },
+            Some(b) if b == b'-' || b.is_ascii_digit() => {
+                let start = self.at;
+                while self.text.get(self.at)
+                        .is_some_and(|b| b"+-.eE".contains(b) || b.is_ascii_digit()) {
+                    self.at += 1;
+                }
+                let number = String::from_utf8_lossy(&self.text[start..self.at]).into_owned();
+                match number.parse::<f64>() {
+                    Ok(_)   => Ok(Json::Number(number)),
+                    Err(_)  => Err(GatewayErr::Syntax(start)),
+                }
+            },
+            _           => Err(GatewayErr::Syntax(self.at)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, GatewayErr> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            let byte = *self.text.get(self.at).ok_or(GatewayErr::Syntax(self.at))?;
+            self.at += 1;
+            match byte {
+                b'"'    => break,
+                b'\\'   => {
+                    let escaped = *self.text.get(self.at).ok_or(GatewayErr::Syntax(self.at))?;
+                    self.at += 1;
+                    let c = match escaped {
+                        b'n'    => '\n',
+                        b't'    => '\t',
+                        b'r'    => '\r',
+                        b'b'    => '\u{8}',
+                        b'f'    => '\u{c}',
+                        b'u'    => {
+                            let hex = self.text.get(self.at..self.at + 4)
+                                .and_then(|h| ::std::str::from_utf8(h).ok())
+                                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                                .ok_or(GatewayErr::Syntax(self.at))?;
+                            self.at += 4;
+                            ::std::char::from_u32(hex).unwrap_or('\u{fffd}')
+                        },
+                        other   => other as char,
+                    };
+                    let mut buf = [0; 4];
+                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                },
+                other   => out.push(other),
+            }
+        }
+        String::from_utf8(out).map_err(|_| GatewayErr::Syntax(self.at))
+    }
+}
+
+impl Json {
+
+    /// Parse the JSON text.
+    pub fn parse(text: &str) -> Result<Json, GatewayErr> {
+        let mut parser = Parser { text: text.as_bytes(), at: 0 };
+        let value = parser.value()?;
+        match parser.peek() {
+            None    => Ok(value),
+            Some(_) => Err(GatewayErr::Syntax(parser.at)),
+        }
+    }
+
+    /// Field of the object. Null if there is no such field.
+    pub fn field(&self, name: &str) -> &Json {
+        match *self {
+            Json::Object(ref fields) => fields.iter()
+                .find(|&(field, _)| field == name)
+                .map_or(&Json::Null, |(_, value)| value),
+            _                       => &Json::Null,
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"'                 => f.write_str("\\\"")?,
+            '\\'                => f.write_str("\\\\")?,
+            '\n'                => f.write_str("\\n")?,
+            c if c < ' '        => write!(f, "\\u{:04x}", c as u32)?,
+            c                   => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Json {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null                  => f.write_str("null"),
+            Json::Bool(b)               => write!(f, "{}", b),
+            Json::Number(ref n)         => f.write_str(n),
+            Json::String(ref s)         => write_string(f, s),
+            Json::Array(ref items)      => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            },
+            Json::Object(ref fields)    => {
+                f.write_str("{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            },
+        }
+    }
+}
+
+fn mismatch(ty: &TypeDesc) -> GatewayErr {
+    GatewayErr::Mismatch(ty.to_string())
+}
+
+fn number<T: FromStr>(ty: &TypeDesc, value: &Json) -> Result<T, GatewayErr> {
+    match *value {
+        Json::Number(ref n) => n.parse().map_err(|_| mismatch(ty)),
+        _                   => Err(mismatch(ty)),
+    }
+}
+
+/// Encode the JSON value as the message of given type.
+pub fn to_message(desc: &ServiceDescription, ty: &TypeDesc, value: &Json, out: &mut Vec<u8>)
+    -> Result<(), GatewayErr>
+{
+    match (ty, value) {
+        (TypeDesc::U8, _)                       => number::<u8>(ty, value)?.encode(out)?,
+        (TypeDesc::U16, _)                      => number::<u16>(ty, value)?.encode(out)?,
+        (TypeDesc::U32, _)                      => number::<u32>(ty, value)?.encode(out)?,
+        (TypeDesc::U64, _)                      => number::<u64>(ty, value)?.encode(out)?,
+        (TypeDesc::I8, _)                       => number::<i8>(ty, value)?.encode(out)?,
+        (TypeDesc::I16, _)                      => number::<i16>(ty, value)?.encode(out)?,
+        (TypeDesc::I32, _)                      => number::<i32>(ty, value)?.encode(out)?,
+        (TypeDesc::I64, _)                      => number::<i64>(ty, value)?.encode(out)?,
+        (TypeDesc::Bool, Json::Bool(b))         => b.encode(out)?,
+        (TypeDesc::String, Json::String(s))     => s.encode(out)?,
+        (TypeDesc::Bytes, Json::Array(items))   => {
+            let bytes = items.iter()
+                .map(|item| number::<u8>(ty, item))
+                .collect::<Result<Vec<u8>, _>>()?;
+            bytes.encode(out)?;
+        },
+        (TypeDesc::List(t), Json::Array(items)) => {
+            message::encode_len(items.len(), out)?;
+            for item in items {
+                to_message(desc, t, item, out)?;
+            }
+        },
+        (TypeDesc::Option(_), Json::Null)       => out.push(0),
+        (TypeDesc::Option(t), _)                => {
+            out.push(1);
+            to_message(desc, t, value, out)?;
+        },
+        (TypeDesc::Struct(name), Json::Object(_)) => {
+            let s = desc.find_struct(name).ok_or_else(|| mismatch(ty))?;
+            for (field, t) in &s.fields {
+                to_message(desc, t, value.field(field), out)?;
+            }
+        },
+        _                                       => return Err(mismatch(ty)),
+    }
+    Ok(())
+}
+
+/// Decode the message of given type into JSON.
+pub fn from_message(desc: &ServiceDescription, ty: &TypeDesc, input: &mut &[u8])
+    -> Result<Json, GatewayErr>
+{
+    let number = |n: String| Json::Number(n);
+    Ok(match *ty {
+        TypeDesc::U8            => number(u8::decode(input)?.to_string()),
+        TypeDesc::U16           => number(u16::decode(input)?.to_string()),
+        TypeDesc::U32           => number(u32::decode(input)?.to_string()),
+        TypeDesc::U64           => number(u64::decode(input)?.to_string()),
+        TypeDesc::I8            => number(i8::decode(input)?.to_string()),
+        TypeDesc::I16           => number(i16::decode(input)?.to_string()),
+        TypeDesc::I32           => number(i32::decode(input)?.to_string()),
+        TypeDesc::I64           => number(i64::decode(input)?.to_string()),
+        TypeDesc::Bool          => Json::Bool(bool::decode(input)?),
+        TypeDesc::String        => Json::String(String::decode(input)?),
+        TypeDesc::Bytes         => Json::Array(Vec::<u8>::decode(input)?.into_iter()
+            .map(|b| number(b.to_string()))
+            .collect()),
+        TypeDesc::List(ref t)   => {
+            let len = u32::decode(input)?;
+            let items = (0..len).map(|_| from_message(desc, t, input));
+            Json::Array(items.collect::<Result<_, _>>()?)
+        },
+        TypeDesc::Option(ref t) => match bool::decode(input)? {
+            false   => Json::Null,
+            true    => from_message(desc, t, input)?,
+        },
+        TypeDesc::Struct(ref name) => {
+            let s = desc.find_struct(name).ok_or(GatewayErr::Decode(DecodeErr::Invalid("struct")))?;
+            let mut fields = Vec::with_capacity(s.fields.len());
+            for (field, t) in &s.fields {
+                fields.push((field.clone(), from_message(desc, t, input)?));
+            }
+            Json::Object(fields)
+        },
+    })
+}
+
+fn describe_json(desc: &ServiceDescription) -> Json {
+    let string = |s: &str| Json::String(s.to_string());
+    let number = |n: u32| Json::Number(n.to_string());
+    let endpoints = desc.endpoints.iter().map(|e| Json::Object(vec![
+        ("name".to_string(), e.name.as_deref().map_or(Json::Null, string)),
+        ("methods".to_string(), Json::Array(e.methods.iter().map(|m| Json::Object(vec![
+            ("name".to_string(), string(&m.name)),
+            ("request".to_string(), string(&m.request.to_string())),
+            ("reply".to_string(), string(&m.reply.to_string())),
+        ])).collect())),
+    ])).collect();
+    let structs = desc.structs.iter().map(|s| Json::Object(vec![
+        ("name".to_string(), string(&s.name)),
+        ("fields".to_string(), Json::Object(s.fields.iter()
+            .map(|(name, t)| (name.clone(), string(&t.to_string())))
+            .collect())),
+    ])).collect();
+    Json::Object(vec![
+        ("service".to_string(), string(&desc.service)),
+        ("versions".to_string(), Json::Array(vec![number(desc.versions.min),
+            number(desc.versions.max)])),
+        ("endpoints".to_string(), Json::Array(endpoints)),
+        ("structs".to_string(), Json::Array(structs)),
+    ])
+}
+
+/// Response of the gateway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status  : u16,
+
+    /// JSON body. Errors have an object with the 'error' field.
+    pub body    : String,
+}
+
+impl From<GatewayErr> for Response {
+
+    fn from(e: GatewayErr) -> Self {
+        let error = Json::Object(vec![
+            ("error".to_string(), Json::String(format!("{:?}", e))),
+        ]);
+        Response {
+            status  : e.status(),
+            body    : error.to_string(),
+        }
+    }
+}
+
+/// Gateway attached to some network.
+pub struct Gateway<'a, N: 'a> {
+    network     : &'a N,
+}
+
+impl<'a, N> Gateway<'a, N> {
+
+    /// Attach gateway to the network.
+    pub fn new(network: &'a N) -> Self {
+        Gateway {
+            network,
+        }
+    }
+
+    fn describe<S>(&self, id: &str) -> Result<ServiceDescription, GatewayErr>
+        where   S       : Service,
+                S::Id   : FromStr,
+                N       : EndpointConnect<S>
+    {
+        let id = id.parse().map_err(|_| GatewayErr::NotFound)?;
+        match reflection::describe(self.network, S::by_id(id)) {
+            Ok(desc)                        => Ok(desc),
+            Err(ReflectionErr::Connect(_))  => Err(GatewayErr::NotFound),
+            Err(ReflectionErr::Typed(e))    => Err(match e {
+                TypedErr::Socket(e)    => GatewayErr::Socket(e),
+                TypedErr::Encode(e)    => GatewayErr::from(e),
+                TypedErr::Decode(e)    => GatewayErr::Decode(e),
+            }),
+        }
+    }
+
+    fn call<S>(&self, id: &str, endpoint: Option<&str>, method: &str, body: &str)
+        -> Result<Json, GatewayErr>
+        where   S       : Service,
+                S::Id   : FromStr,
+                N       : EndpointConnect<S>
+    {
+        let desc = self.describe::<S>(id)?;
+        let methods = &desc.endpoints.iter()
+            .find(|e| e.name.as_deref() == endpoint)
+            .ok_or(GatewayErr::NotFound)?
+            .methods;
+        let tag = methods.iter().position(|m| m.name == method).ok_or(GatewayErr::NotFound)?;
+        let request = Json::parse(if body.trim().is_empty() { "null" } else { body })?;
+        let mut payload = vec![tag as u8];
+        to_message(&desc, &methods[tag].request, &request, &mut payload)?;
+
+        let service = S::by_id(id.parse().map_err(|_| GatewayErr::NotFound)?);
+        let socket = match endpoint {
+            Some(name)  => self.network.connect_endpoint(service, name),
+            None        => self.network.connect(service),
+        }.map_err(|_| GatewayErr::NotFound)?;
+        socket.send(payload)?;
+        let reply = socket.receive::<Vec<u8>>()?;
+        socket.close();
+
+        let mut input = &reply[..];
+        if u8::decode(&mut input)? as usize != tag {
+            return Err(GatewayErr::Decode(DecodeErr::Invalid("reply")));
+        }
+        let value = from_message(&desc, &methods[tag].reply, &mut input)?;
+        match input.len() {
+            0       => Ok(value),
+            rest    => Err(GatewayErr::Decode(DecodeErr::Trailing(rest))),
+        }
+    }
+
+    /// Handle the HTTP request with given method, path and body.
+    pub fn handle<S>(&self, method: &str, path: &str, body: &str) -> Response
+        where   S       : Service,
+                S::Id   : FromStr + ToString,
+                N       : EndpointConnect<S>
+    {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let result = match (method, &segments[..]) {
+            ("GET", &["services"])                      => {
+                let ids = self.network.snapshot().entries.into_iter()
+                    .map(|e| Json::String(e.id.to_string()))
+                    .collect();
+                Ok(Json::Array(ids))
+            },
+            ("GET", &["services", id])                  => {
+                self.describe::<S>(id).map(|desc| describe_json(&desc))
+            },
+            ("POST", &["services", id, method])         => {
+                self.call::<S>(id, None, method, body)
+            },
+            ("POST", &["services", id, endpoint, method]) => {
+                self.call::<S>(id, Some(endpoint), method, body)
+            },
+            _                                           => Err(GatewayErr::NotFound),
+        };
+        match result {
+            Ok(value)   => Response { status: 200, body: value.to_string() },
+            Err(e)      => e.into(),
+        }
+    }
+
+    /// Serve the gateway over HTTP on given address, one request at a
+    /// time. Blocks current thread forever unless the address can't be
+    /// bound.
+    pub fn serve_http<S, A>(&self, addr: A) -> io::Result<()>
+        where   S       : Service,
+                S::Id   : FromStr + ToString,
+                N       : EndpointConnect<S>,
+                A       : ToSocketAddrs
+    {
+        self.serve::<S>(TcpListener::bind(addr)?)
+    }
+
+    /// Serve the gateway over HTTP on the bound listener, one request at
+    /// a time. Failed connection or a client that went away affects only
+    /// its own request. Blocks current thread forever.
+    pub fn serve<S>(&self, listener: TcpListener) -> !
+        where   S       : Service,
+                S::Id   : FromStr + ToString,
+                N       : EndpointConnect<S>
+    {
+        loop {
+            if let Ok((stream, _)) = listener.accept() {
+                let _ = self.exchange::<S>(stream);
+            }
+        }
+    }
+
+    /// Read one HTTP request from the stream and write the response.
+    fn exchange<S>(&self, stream: TcpStream) -> io::Result<()>
+        where   S       : Service,
+                S::Id   : FromStr + ToString,
+                N       : EndpointConnect<S>
+    {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut words = line.split_whitespace();
+        let (method, path) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+
+        let mut length = Ok(0);
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    length = match value.trim().parse() {
+                        Ok(n) if n > MAX_BODY   => Err(GatewayErr::TooLarge),
+                        Ok(n)                   => Ok(n),
+                        Err(_)                  => Err(GatewayErr::Malformed),
+                    };
+                }
+            }
+        }
+        let response = length.and_then(|length| {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).map_err(|_| GatewayErr::Malformed)?;
+            Ok(body)
+        });
+        let response = match response {
+            Ok(body)    => self.handle::<S>(method, path, &String::from_utf8_lossy(&body)),
+            Err(e)      => e.into(),
+        };
+        write!(reader.get_mut(),
+            "HTTP/1.0 {} {}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            response.status, reason(response.status), response.body.len(), response.body)
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        _   => "Bad Gateway",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+    use std::thread;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use reflection::{EndpointDesc, StructDesc};
+    use {OpenNetwork, RegistrationForm, Versions};
+
+    fn description() -> ServiceDescription {
+        ServiceDescription::new("memory", Versions::only(1))
+            .endpoint(EndpointDesc::service()
+                .method("alloc", "u64", "Block")
+                .method("owner", "option<string>", "list<u8>"))
+            .structure(StructDesc::new("Block")
+                .field("addr", "u64")
+                .field("size", "u64"))
+    }
+
+    /// Allocator that gives out blocks from a fixed address.
+    fn memory(socket: LocalSocket) -> ! {
+        while let Ok(bytes) = socket.receive::<Vec<u8>>() {
+            let mut input = &bytes[1..];
+            let mut reply = vec![bytes[0]];
+            if bytes[0] == 0 {
+                (0x1000u64, u64::decode(&mut input).unwrap()).encode(&mut reply).unwrap();
+            } else {
+                let owner = Option::<String>::decode(&mut input).unwrap();
+                owner.unwrap_or_default().into_bytes().encode(&mut reply).unwrap();
+            }
+            if socket.send(reply).is_err() {
+                break;
+            }
+        }
+        finish()
+    }
+
+    fn reflect(socket: LocalSocket) -> ! {
+        let _ = reflection::serve(&socket, &description());
+        finish()
+    }
+
+    #[test]
+    fn json_text() {
+        let text = r#"{"a":[1,-2.5e3,true,null],"b":"q\"\nA"}"#;
+        let value = Json::parse(text).unwrap();
+        assert_eq!(value.field("b"), &Json::String("q\"\nA".to_string()));
+        assert_eq!(value.to_string(), r#"{"a":[1,-2.5e3,true,null],"b":"q\"\nA"}"#);
+        assert!(matches!(Json::parse("[1,]"), Err(GatewayErr::Syntax(3))));
+        assert!(matches!(Json::parse("{} x"), Err(GatewayErr::Syntax(3))));
+    }
+
+    #[test]
+    fn calls_through_reflection() {
+        let network = LocalNetwork::new();
+        let form = RegistrationForm::new(memory, "memory".to_string())
+            .endpoint(reflection::ENDPOINT, reflect);
+        network.register(form).unwrap();
+        let gateway = Gateway::new(&network);
+        let handle = |method, path, body| gateway.handle::<LocalService>(method, path, body);
+
+        assert_eq!(handle("GET", "/services", "").body, r#"["memory"]"#);
+        let desc = Json::parse(&handle("GET", "/services/memory", "").body).unwrap();
+        assert_eq!(desc.field("structs"), &Json::Array(vec![Json::parse(
+            r#"{"name":"Block","fields":{"addr":"u64","size":"u64"}}"#).unwrap()]));
+
+        let alloc = handle("POST", "/services/memory/alloc", "18446744073709551615");
+        assert_eq!(alloc, Response {
+            status  : 200,
+            body    : r#"{"addr":4096,"size":18446744073709551615}"#.to_string(),
+        });
+        assert_eq!(handle("POST", "/services/memory/owner", r#""ab""#).body, "[97,98]");
+        assert_eq!(handle("POST", "/services/memory/owner", "").body, "[]");
+
+        assert_eq!(handle("POST", "/services/memory/alloc", "-1").status, 400);
+        assert_eq!(handle("POST", "/services/memory/free", "1").status, 404);
+        assert_eq!(handle("GET", "/services/disk", "").status, 404);
+    }
+
+    #[test]
+    fn bad_requests_do_not_stop_serving() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let network = LocalNetwork::new();
+            network.register(RegistrationForm::new(memory, "memory".to_string())).unwrap();
+            Gateway::new(&network).serve::<LocalService>(listener)
+        });
+        let request = |text: &str, truncate: bool| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(text.as_bytes()).unwrap();
+            if truncate {
+                stream.shutdown(Shutdown::Write).unwrap();
+            }
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            response
+        };
+
+        let huge = request("POST /services/memory/alloc HTTP/1.0\r\n\
+            Content-Length: 18446744073709551615\r\n\r\n", false);
+        assert!(huge.starts_with("HTTP/1.0 413 "));
+        let short = request("POST /services/memory/alloc HTTP/1.0\r\n\
+            Content-Length: 10\r\n\r\n12", true);
+        assert!(short.starts_with("HTTP/1.0 400 "));
+        let _ = request("GET /serv", true);
+
+        let ok = request("GET /services HTTP/1.0\r\n\r\n", false);
+        assert!(ok.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(ok.ends_with(r#"["memory"]"#));
+    }
+}
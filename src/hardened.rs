@@ -0,0 +1,102 @@
+//! Hardened internal networks. By default sub-objects of an object may
+//! connect to the services of its external network, and the object may
+//! make internal services visible outside by granting their visibility.
+//! Security-sensitive composite objects, like credential managers,
+//! instead start with both disabled and grant each external connect and
+//! each visible service one by one.
+
+use super::{OwnedObject, Service};
+use policy::Pattern;
+
+/// What crosses the boundary of the internal network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grants {
+
+    /// Patterns of the external services the sub-objects may connect
+    /// to.
+    pub connect     : Vec<Pattern>,
+
+    /// Patterns of the internal services that are visible in the
+    /// external network.
+    pub visible     : Vec<Pattern>,
+}
+
+impl Grants {
+
+    /// Nothing crosses the boundary. This is how the hardened internal
+    /// network starts.
+    pub fn deny_all() -> Self {
+        Grants {
+            connect     : Vec::new(),
+            visible     : Vec::new(),
+        }
+    }
+
+    /// Everything crosses the boundary.
+    pub fn allow_all() -> Self {
+        Grants {
+            connect     : vec![Pattern("*".to_string())],
+            visible     : vec![Pattern("*".to_string())],
+        }
+    }
+
+    /// Sub-objects may connect out, but nothing inside is visible. This
+    /// is how the ordinary internal network starts.
+    pub fn ordinary() -> Self {
+        Grants {
+            connect     : vec![Pattern("*".to_string())],
+            visible     : Vec::new(),
+        }
+    }
+
+    /// Check if the sub-objects may connect to the external service
+    /// with given identifier in text form.
+    pub fn may_connect(&self, service: &str) -> bool {
+        self.connect.iter().any(|p| p.matches(service))
+    }
+
+    /// Check if the internal service with given identifier in text
+    /// form is visible in the external network.
+    pub fn is_visible(&self, service: &str) -> bool {
+        self.visible.iter().any(|p| p.matches(service))
+    }
+}
+
+/// Object which internal network may be hardened.
+pub trait HardenedObject<S: Service>: OwnedObject<S> {
+
+    /// Whether the internal network started with 'Grants::deny_all'.
+    fn is_hardened(&self) -> bool;
+
+    /// Current grants of the internal network.
+    fn grants(&self) -> Grants;
+
+    /// Allow the sub-objects to connect to the external services that
+    /// match the pattern.
+    fn grant_connect(&self, pattern: &str);
+
+    /// Make the internal services that match the pattern visible in
+    /// the external network.
+    fn grant_visible(&self, pattern: &str);
+
+    /// Withdraw all grants. Open channels stay open, but no new
+    /// crossing is allowed.
+    fn reset_grants(&self);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hardened_denies_until_granted() {
+        let mut grants = Grants::deny_all();
+        assert!(!grants.may_connect("kobzar.memory.alloc"));
+        assert!(!grants.is_visible("vault.get"));
+
+        grants.connect.push(Pattern("kobzar.memory.*".to_string()));
+        assert!(grants.may_connect("kobzar.memory.alloc"));
+        assert!(!grants.may_connect("net.socket"));
+        assert!(Grants::allow_all().is_visible("vault.get"));
+    }
+}
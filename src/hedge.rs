@@ -0,0 +1,189 @@
+//! Hedged requests. Tail latency of a replicated service comes from the
+//! occasional slow provider. Hedged call sends the request to one
+//! provider and, if no reply comes within a short delay, sends the same
+//! request to another one too and takes whichever reply comes first,
+//! closing the other channel. Provider may get to run both copies, so
+//! only requests with an idempotency key are hedged.
+
+use std::future::{self, Future};
+use std::task::Poll;
+
+use super::{ConnectErr, Data, Object, OpenNetwork, Service, SocketErr, Time};
+use aio::{AsyncErr, AsyncSocket};
+use cancel::CancelToken;
+use idempotency::Idempotent;
+use rt::Runtime;
+
+/// Error of the hedged call.
+#[derive(Debug)]
+pub enum HedgeErr<S> {
+
+    /// Service could not be connected.
+    Connect(ConnectErr<S>),
+
+    /// Channel failed. If both channels failed, this is the error of
+    /// the first one.
+    Socket(SocketErr),
+
+    /// Call was cancelled.
+    Cancelled,
+}
+
+impl<S> From<AsyncErr<SocketErr>> for HedgeErr<S> {
+
+    fn from(e: AsyncErr<SocketErr>) -> Self {
+        match e {
+            AsyncErr::Failed(e)     => HedgeErr::Socket(e),
+            AsyncErr::Cancelled     => HedgeErr::Cancelled,
+        }
+    }
+}
+
+/// Send the request and receive the reply.
+fn exchange<'a, O, S, SC, Q, R>(socket: &'a SC, request: Q, cancel: &'a CancelToken)
+    -> impl Future<Output = Result<R, AsyncErr<SocketErr>>> + 'a
+    where   O   : Object<S> + 'a,
+            S   : Service + 'a,
+            SC  : AsyncSocket<O, S>,
+            Q   : Data,
+            R   : Data
+{
+    let mut sending = Some(Box::pin(socket.send_async(request, cancel)));
+    let mut receiving = None;
+    future::poll_fn(move |cx| {
+        if let Some(ref mut send) = sending {
+            match send.as_mut().poll(cx) {
+                Poll::Pending           => return Poll::Pending,
+                Poll::Ready(Err(e))     => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(()))     => sending = None,
+            }
+        }
+        receiving.get_or_insert_with(|| Box::pin(socket.receive_async::<R>(cancel)))
+            .as_mut().poll(cx)
+    })
+}
+
+/// Call the service hedging the request after given delay. Channels are
+/// opened with plain 'connect', so networks that spread connects among
+/// the providers usually send the hedge to another provider. Requests
+/// without idempotency key are sent to the first provider only.
+pub fn call<N, S, Q, K, R, RT, T>(network: &N, runtime: &RT, service: S,
+        request: Q, delay: T, cancel: &CancelToken) -> Result<R, HedgeErr<S>>
+    where   N   : OpenNetwork<S>,
+            N::Socket: AsyncSocket<N::Object, S>,
+            S   : Service + Clone,
+            Q   : Data + Clone + Idempotent<K>,
+            R   : Data,
+            RT  : Runtime,
+            T   : Time
+{
+    let hedge = request.idempotency_key().map(|_| service.clone());
+    let primary = network.connect(service).map_err(HedgeErr::Connect)?;
+    let secondary;
+    let mut first = Box::pin(exchange::<_, _, _, _, R>(&primary, request.clone(), cancel));
+    let service = match hedge {
+        Some(service)   => service,
+        None            => return runtime.block_on(first).map_err(From::from),
+    };
+
+    let mut timer = Box::pin(runtime.sleep(delay));
+    let early = runtime.block_on(future::poll_fn(|cx| match first.as_mut().poll(cx) {
+        Poll::Ready(result) => Poll::Ready(Some(result)),
+        Poll::Pending       => timer.as_mut().poll(cx).map(|()| None),
+    }));
+    if let Some(result) = early {
+        return result.map_err(From::from);
+    }
+
+    secondary = match network.connect(service) {
+        Ok(socket)  => socket,
+        Err(_)      => return runtime.block_on(first).map_err(From::from),
+    };
+    let second = Box::pin(exchange::<_, _, _, _, R>(&secondary, request, cancel));
+    let mut exchanges = [first, second];
+    let mut errors = [None, None];
+    runtime.block_on(future::poll_fn(|cx| {
+        for (exchange, error) in exchanges.iter_mut().zip(errors.iter_mut()) {
+            if error.is_some() {
+                continue;
+            }
+            if let Poll::Ready(result) = exchange.as_mut().poll(cx) {
+                match result {
+                    Ok(reply)   => return Poll::Ready(Ok(reply)),
+                    Err(e)      => *error = Some(e),
+                }
+            }
+        }
+        match errors {
+            [Some(_), Some(_)]  => Poll::Ready(Err(errors[0].take().unwrap().into())),
+            _                   => Poll::Pending,
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use rt::ThreadRuntime;
+    use {RegistrationForm, Socket};
+
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    #[derive(Clone)]
+    struct Lookup(u32);
+
+    impl Data for Lookup {
+    }
+
+    impl Idempotent<u32> for Lookup {
+
+        fn idempotency_key(&self) -> Option<&u32> {
+            Some(&self.0)
+        }
+    }
+
+    fn reply(socket: LocalSocket, name: &str, after: u64) -> ! {
+        if socket.receive::<Lookup>().is_ok() {
+            thread::sleep(Duration::from_millis(after));
+            let _ = socket.send(name.to_string());
+        }
+        finish()
+    }
+
+    fn slow(socket: LocalSocket) -> ! {
+        reply(socket, "slow", 1000)
+    }
+
+    fn fast(socket: LocalSocket) -> ! {
+        reply(socket, "fast", 0)
+    }
+
+    #[test]
+    fn first_reply_wins() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(slow, "lookup".to_string())).unwrap();
+        network.register(RegistrationForm::new(fast, "lookup".to_string())).unwrap();
+        let reply: String = call(&network, &ThreadRuntime,
+                LocalService::by_id("lookup".to_string()), Lookup(7), Millis(10),
+                &CancelToken::new()).unwrap();
+        assert_eq!(reply, "fast");
+    }
+}
@@ -0,0 +1,258 @@
+//! Request deduplication for providers. Clients attach an idempotency
+//! key to requests that change state. When a request is retried after
+//! a timeout, the provider finds the key in the cache and replies with
+//! the saved response instead of doing the work again, so e.g. memory
+//! is not allocated twice. A retry that comes while the first request
+//! is still being handled waits for its response.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{Data, Object, Service, Socket, SocketErr, Time};
+use rt::duration;
+
+/// Request that may carry an idempotency key.
+pub trait Idempotent<K> {
+
+    /// Key of the request. Requests without key are never deduplicated.
+    fn idempotency_key(&self) -> Option<&K>;
+}
+
+/// Bounded cache of responses with time-to-live.
+pub struct DedupCache<K, R> {
+    capacity    : usize,
+    ttl         : Duration,
+    inner       : Mutex<Inner<K, R>>,
+
+    /// Signalled when some key stops being handled.
+    done        : Condvar,
+}
+
+struct Inner<K, R> {
+    entries     : HashMap<K, (Instant, R)>,
+
+    /// Keys in the order of insertion, to evict the oldest first.
+    order       : VecDeque<K>,
+
+    /// Keys of the requests being handled now.
+    in_flight   : HashSet<K>,
+}
+
+/// Key being handled. Dropped after the response is saved, or if the
+/// handler panics, so waiting retries go on.
+struct InFlight<'a, K: 'a + Hash + Eq, R: 'a> {
+    cache   : &'a DedupCache<K, R>,
+    key     : &'a K,
+}
+
+impl<'a, K: Hash + Eq, R> Drop for InFlight<'a, K, R> {
+
+    fn drop(&mut self) {
+        self.cache.inner.lock().unwrap().in_flight.remove(self.key);
+        self.cache.done.notify_all();
+    }
+}
+
+impl<K, R> DedupCache<K, R>
+        where   K   : Hash + Eq + Clone,
+                R   : Clone
+{
+
+    /// Create cache that keeps at most 'capacity' responses, each for
+    /// 'ttl' time. Cache of zero capacity keeps nothing: only the
+    /// retries that come while the request is being handled are
+    /// deduplicated.
+    pub fn new<T: Time>(capacity: usize, ttl: T) -> Self {
+        DedupCache {
+            capacity,
+            ttl         : duration(&ttl),
+            inner       : Mutex::new(Inner {
+                entries     : HashMap::new(),
+                order       : VecDeque::new(),
+                in_flight   : HashSet::new(),
+            }),
+            done        : Condvar::new(),
+        }
+    }
+
+    /// Get saved response for the key if it has not yet expired.
+    pub fn get(&self, key: &K) -> Option<R> {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.get(key)
+            .filter(|e| e.0.elapsed() < self.ttl)
+            .map(|e| e.1.clone())
+    }
+
+    /// Save the response for the key.
+    pub fn insert(&self, key: K, response: R) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+
+        // Drop expired entries from the front, then the oldest ones
+        // if still over capacity.
+        while let Some(oldest) = inner.order.front().cloned() {
+            let expired = inner.entries.get(&oldest)
+                .is_none_or(|e| now.duration_since(e.0) >= self.ttl);
+            if !expired && inner.order.len() < self.capacity {
+                break;
+            }
+            inner.order.pop_front();
+            inner.entries.remove(&oldest);
+        }
+
+        if inner.entries.insert(key.clone(), (now, response)).is_none() {
+            inner.order.push_back(key);
+        }
+    }
+
+    /// Forget the response for the key.
+    pub fn remove(&self, key: &K) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.remove(key).is_some() {
+            inner.order.retain(|k| k != key);
+        }
+    }
+
+    /// Forget all responses.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.order.clear();
+    }
+
+    /// Handle the request. If the request has a key with saved response,
+    /// the response is returned without calling the handler. If the
+    /// request with the same key is being handled, its response is
+    /// waited for. Otherwise handler is called and its response is
+    /// saved.
+    pub fn handle<Q, F>(&self, request: Q, handler: F) -> R
+        where   Q   : Idempotent<K>,
+                F   : FnOnce(Q) -> R
+    {
+        let key = match request.idempotency_key() {
+            Some(key)   => key.clone(),
+            None        => return handler(request),
+        };
+        {
+            let mut inner = self.inner.lock().unwrap();
+            loop {
+                let saved = inner.entries.get(&key)
+                    .filter(|e| e.0.elapsed() < self.ttl)
+                    .map(|e| e.1.clone());
+                if let Some(response) = saved {
+                    return response;
+                }
+                if inner.in_flight.insert(key.clone()) {
+                    break;
+                }
+                inner = self.done.wait(inner).unwrap();
+            }
+        }
+        let _in_flight = InFlight { cache: self, key: &key };
+        let response = handler(request);
+        self.insert(key.clone(), response.clone());
+        response
+    }
+
+    /// Serve requests received from the socket until the channel gets
+    /// closed.
+    pub fn serve<O, S, SC, Q, F>(&self, socket: &SC, mut handler: F)
+        -> Result<(), SocketErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                Q   : Data + Idempotent<K>,
+                R   : Data,
+                F   : FnMut(Q) -> R
+    {
+        loop {
+            let request = match socket.receive::<Q>() {
+                Ok(request)                     => request,
+                Err(SocketErr::ChannelClosed)   => return Ok(()),
+                Err(e)                          => return Err(e),
+            };
+            let response = self.handle(request, &mut handler);
+            socket.send(response)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::thread;
+
+    struct Secs(u32);
+
+    impl Time for Secs {
+
+        fn nanos(&self) -> u32 {
+            0
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Secs(seconds.saturating_add(nanos / 1_000_000_000))
+        }
+    }
+
+    struct Alloc(Option<u32>);
+
+    impl Idempotent<u32> for Alloc {
+
+        fn idempotency_key(&self) -> Option<&u32> {
+            self.0.as_ref()
+        }
+    }
+
+    #[test]
+    fn concurrent_retry_waits() {
+        let cache = Arc::new(DedupCache::new(2, Secs(60)));
+        let calls = Arc::new(AtomicU32::new(0));
+        let threads: Vec<_> = (0..2).map(|_| {
+            let (cache, calls) = (cache.clone(), calls.clone());
+            thread::spawn(move || cache.handle(Alloc(Some(7)), |_| {
+                thread::sleep(Duration::from_millis(50));
+                calls.fetch_add(1, Ordering::SeqCst) + 1
+            }))
+        }).collect();
+        let responses: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(responses, vec![1, 1]);
+    }
+
+    #[test]
+    fn retry_is_deduplicated() {
+        let cache = DedupCache::new(2, Secs(60));
+        let mut calls = 0;
+        for _ in 0..2 {
+            cache.handle(Alloc(Some(7)), |_| { calls += 1; calls });
+        }
+        assert_eq!(calls, 1);
+        cache.handle(Alloc(None), |_| { calls += 1; calls });
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn capacity_bounds() {
+        let cache = DedupCache::new(0, Secs(60));
+        cache.insert(1, "a");
+        assert_eq!(cache.get(&1), None);
+
+        let cache = DedupCache::new(1, Secs(60));
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some("b"));
+    }
+}
@@ -0,0 +1,232 @@
+//! Bulk export and import of the network topology. System images and
+//! staging environments need the same registrations and the same
+//! policy as the network they were taken from. The network exports its
+//! whole registration and policy state into a portable 'Image', which
+//! another network instance imports by starting the same providers.
+//!
+//! Image text holds one entry per line:
+//!
+//! ```text
+//! register <service> provider=<name>
+//! register-unique <service> provider=<name>
+//! policy <policy line>
+//! ```
+//!
+//! where policy lines are in the form read by 'Policy::parse'. Lines
+//! starting with '#' are comments.
+//!
+//! Stored images reveal the names of all services and the policy. To
+//! keep them unreadable from raw storage, the image is sealed with an
+//! 'ImageCipher' whose key comes from the keystore service of the
+//! kernel, and opened with it on import.
+
+use std::fmt::Write;
+
+use super::Service;
+use policy::{Policy, PolicyNetwork};
+
+/// Registration of the service in the image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageEntry {
+
+    /// Identifier of the service in text form.
+    pub service     : String,
+
+    /// Name of the program that provides the service. Network that
+    /// imports the image starts the provider by this name.
+    pub provider    : String,
+
+    /// Whether the service is uniquely registered.
+    pub unique      : bool,
+}
+
+/// Portable description of the network topology.
+#[derive(Debug, Clone, Default)]
+pub struct Image {
+
+    /// Registrations in the order they were made.
+    pub entries     : Vec<ImageEntry>,
+
+    /// Policy of the network.
+    pub policy      : Policy,
+}
+
+/// Error of the image import or parsing.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ImageErr {
+
+    /// Image text has wrong format at given line.
+    Format(usize),
+
+    /// Network does not know the program with given name.
+    UnknownProvider(String),
+
+    /// Service is already registered in the network in a way that
+    /// conflicts with the image.
+    Conflict(String),
+
+    /// Provider could not register the service.
+    Registration(String),
+
+    /// Sealed image could not be decrypted: the key is wrong or the
+    /// bytes were changed.
+    Sealed,
+}
+
+/// Encryption of the image at rest. Implementations hold the key given
+/// by the keystore service and never store it beside the image.
+pub trait ImageCipher {
+
+    /// Encrypt the image text.
+    fn encrypt(&self, plain: &[u8]) -> Vec<u8>;
+
+    /// Decrypt the image text. Fails if the key does not fit or the
+    /// bytes were changed.
+    fn decrypt(&self, sealed: &[u8]) -> Option<Vec<u8>>;
+}
+
+impl Image {
+
+    /// Write the image in text form.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for entry in &self.entries {
+            let action = if entry.unique { "register-unique" } else { "register" };
+            let _ = writeln!(out, "{} {} provider={}", action, entry.service,
+                    entry.provider);
+        }
+        for line in self.policy.to_text().lines() {
+            let _ = writeln!(out, "policy {}", line);
+        }
+        out
+    }
+
+    /// Parse the image from the text made by 'to_text'.
+    pub fn parse(text: &str) -> Result<Self, ImageErr> {
+        let mut entries = Vec::new();
+
+        // Policy lines stay at their positions so that policy errors
+        // point to the lines of the image.
+        let mut policy = String::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some(rule) = line.strip_prefix("policy ") {
+                policy.push_str(rule);
+            }
+            policy.push('\n');
+            if line.is_empty() || line.starts_with('#') || line.starts_with("policy ") {
+                continue;
+            }
+            let words: Vec<_> = line.split_whitespace().collect();
+            let unique = match words[0] {
+                "register"          => false,
+                "register-unique"   => true,
+                _                   => return Err(ImageErr::Format(n + 1)),
+            };
+            let provider = match words.get(2).and_then(|w| w.strip_prefix("provider=")) {
+                Some(provider) if words.len() == 3  => provider,
+                _                                   => return Err(ImageErr::Format(n + 1)),
+            };
+            entries.push(ImageEntry {
+                service     : words[1].to_string(),
+                provider    : provider.to_string(),
+                unique,
+            });
+        }
+        let policy = Policy::parse(&policy).map_err(|e| ImageErr::Format(e.line))?;
+        Ok(Image { entries, policy })
+    }
+
+    /// Write the image in text form encrypted with the cipher.
+    pub fn seal<C: ImageCipher>(&self, cipher: &C) -> Vec<u8> {
+        cipher.encrypt(self.to_text().as_bytes())
+    }
+
+    /// Decrypt and parse the image made by 'seal'.
+    pub fn open<C: ImageCipher>(sealed: &[u8], cipher: &C) -> Result<Self, ImageErr> {
+        let plain = cipher.decrypt(sealed).ok_or(ImageErr::Sealed)?;
+        let text = String::from_utf8(plain).map_err(|_| ImageErr::Sealed)?;
+        Image::parse(&text)
+    }
+}
+
+/// Network that can export and import its topology.
+pub trait ImageNetwork<S: Service>: PolicyNetwork<S> {
+
+    /// Export all registrations and the policy of the network.
+    fn export(&self) -> Image;
+
+    /// Set the policy from the image and start the providers of all its
+    /// registrations. Only the owner of the network may do that. Import
+    /// stops at the first error; providers started before it are left
+    /// running.
+    fn import(&self, image: &Image) -> Result<(), ImageErr>;
+
+    /// Export the image sealed with the cipher.
+    fn export_sealed<C: ImageCipher>(&self, cipher: &C) -> Vec<u8> {
+        self.export().seal(cipher)
+    }
+
+    /// Open the sealed image with the cipher and import it.
+    fn import_sealed<C: ImageCipher>(&self, sealed: &[u8], cipher: &C)
+        -> Result<(), ImageErr>
+    {
+        self.import(&Image::open(sealed, cipher)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cipher that only hides the text from a glance, with the sum of
+    /// the bytes to detect the wrong key.
+    struct Xor(u8);
+
+    impl ImageCipher for Xor {
+
+        fn encrypt(&self, plain: &[u8]) -> Vec<u8> {
+            let sum = plain.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+            plain.iter().chain(Some(&sum)).map(|b| b ^ self.0).collect()
+        }
+
+        fn decrypt(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+            let mut plain: Vec<u8> = sealed.iter().map(|b| b ^ self.0).collect();
+            let sum = plain.pop()?;
+            match plain.iter().fold(0u8, |s, b| s.wrapping_add(*b)) == sum {
+                true    => Some(plain),
+                false   => None,
+            }
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let text = "\
+            # Staging topology.\n\
+            register-unique kobzar.memory.alloc provider=memory-server\n\
+            register driver.disk provider=disk\n\
+            policy deny connect subject=* service=kobzar.memory.*\n\
+            policy default allow\n";
+        let image = Image::parse(text).unwrap();
+        assert_eq!(image.entries[0], ImageEntry {
+            service     : "kobzar.memory.alloc".to_string(),
+            provider    : "memory-server".to_string(),
+            unique      : true,
+        });
+        assert_eq!(image.policy.rules.len(), 1);
+        assert_eq!(image.to_text(), text.split_once('\n').unwrap().1);
+
+        assert_eq!(Image::parse("register disk").unwrap_err(), ImageErr::Format(1));
+        assert_eq!(Image::parse("\npolicy permit all").unwrap_err(), ImageErr::Format(2));
+    }
+
+    #[test]
+    fn sealed_image() {
+        let image = Image::parse("register-unique kobzar.memory.alloc provider=memory\n").unwrap();
+        let sealed = image.seal(&Xor(0x5a));
+        assert!(!sealed.windows(6).any(|w| w == b"memory"));
+        assert_eq!(Image::open(&sealed, &Xor(0x5a)).unwrap().entries, image.entries);
+        assert_eq!(Image::open(&sealed, &Xor(0x5b)).unwrap_err(), ImageErr::Sealed);
+    }
+}
@@ -0,0 +1,97 @@
+//! Information about the services. Providers describe their service in
+//! the registration form: its human-readable name, what it does, which
+//! message types it accepts and its limits. Requesters and system
+//! tooling read the description together with what the network knows
+//! about the service, so they can see what a provider offers before
+//! connecting to it.
+
+use std::collections::BTreeMap;
+
+use super::{Data, Versions};
+
+/// Description of the service given by its provider. Everything is
+/// optional.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Metadata {
+
+    /// Human-readable name, e.g. 'Memory Server'.
+    pub name            : Option<String>,
+
+    /// What the service does.
+    pub description     : Option<String>,
+
+    /// Names of the message types the service accepts.
+    pub message_types   : Vec<String>,
+
+    /// Limits of the service by their names, e.g. 'max-request-bytes'.
+    pub limits          : BTreeMap<String, u64>,
+
+    /// Any other properties.
+    pub properties      : BTreeMap<String, String>,
+}
+
+impl Metadata {
+
+    /// Create empty metadata.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the human-readable name.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Set the description.
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// Add the message type the service accepts.
+    pub fn message_type(mut self, name: &str) -> Self {
+        self.message_types.push(name.to_string());
+        self
+    }
+
+    /// Set the limit.
+    pub fn limit(mut self, name: &str, value: u64) -> Self {
+        self.limits.insert(name.to_string(), value);
+        self
+    }
+
+    /// Set the property.
+    pub fn property(mut self, name: &str, value: &str) -> Self {
+        self.properties.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+/// What the network knows about the service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInfo<Id> {
+    pub id          : Id,
+
+    /// Count of the objects that provide the service.
+    pub providers   : usize,
+
+    /// Whether the service is registered uniquely.
+    pub unique      : bool,
+
+    /// Names of the endpoints besides the main entry.
+    pub endpoints   : Vec<String>,
+
+    /// Protocol versions the provider supports.
+    pub version     : Versions,
+
+    /// Messages the channels to the service buffer in each direction.
+    pub capacity    : usize,
+
+    /// Description given by the provider. When there are many
+    /// providers, that of the first one.
+    pub metadata    : Metadata,
+}
+
+impl<Id: Send + 'static> Data for ServiceInfo<Id> {
+}
@@ -0,0 +1,108 @@
+//! Integrity verification of the messages. Backends that move messages
+//! over DMA buffers or external links may deliver corrupted bytes.
+//! Channels that need protection from that wrap their socket so that
+//! each message is sent with a checksum and verified on receive.
+//!
+//! 'SocketErr::CorruptData' comes only from 'IntegritySocket'. The local
+//! network passes the messages between the threads of one process and
+//! never damages them, so it has nothing to verify itself.
+
+use std::marker::PhantomData;
+
+use super::{Data, Object, Service, Socket, SocketErr};
+
+/// Integrity mode of the channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrity {
+
+    /// Messages are sent as they are.
+    Off,
+
+    /// Each message carries CRC-32 of its bytes.
+    Crc32,
+}
+
+/// Message with the checksum of its bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksummed {
+
+    /// Checksum of the bytes. Zero when integrity is off.
+    pub checksum    : u32,
+
+    /// The message itself.
+    pub bytes       : Vec<u8>,
+}
+
+impl Data for Checksummed {
+}
+
+/// Socket wrapper that checksums each message.
+pub struct IntegritySocket<'a, O, S, SC: 'a> {
+    socket  : &'a SC,
+    mode    : Integrity,
+    _os     : PhantomData<(O, S)>,
+}
+
+impl<'a, O, S, SC> IntegritySocket<'a, O, S, SC>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+{
+
+    /// Wrap the socket. Both sides of the channel must use the same
+    /// mode.
+    pub fn new(socket: &'a SC, mode: Integrity) -> Self {
+        IntegritySocket {
+            socket,
+            mode,
+            _os     : PhantomData,
+        }
+    }
+
+    /// Integrity mode of this channel.
+    pub fn mode(&self) -> Integrity {
+        self.mode
+    }
+
+    /// Send the bytes with their checksum.
+    pub fn send(&self, bytes: Vec<u8>) -> Result<(), SocketErr> {
+        let checksum = match self.mode {
+            Integrity::Off      => 0,
+            Integrity::Crc32    => crc32(&bytes),
+        };
+        self.socket.send(Checksummed { checksum, bytes })
+    }
+
+    /// Receive the bytes and verify their checksum. Fails with
+    /// 'SocketErr::CorruptData' if the bytes were damaged.
+    pub fn receive(&self) -> Result<Vec<u8>, SocketErr> {
+        let message = self.socket.receive::<Checksummed>()?;
+        if self.mode == Integrity::Crc32 && crc32(&message.bytes) != message.checksum {
+            return Err(SocketErr::CorruptData);
+        }
+        Ok(message.bytes)
+    }
+}
+
+/// CRC-32 (IEEE) of the bytes.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -1,3 +1,89 @@
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "rt-smol")]
+extern crate smol;
+#[cfg(feature = "rt-tokio")]
+extern crate tokio;
+
+pub mod adaptive;
+pub mod affinity;
+pub mod aggregator;
+pub mod aio;
+pub mod attestation;
+pub mod authz;
+pub mod backup;
+pub mod bootstrap;
+pub mod cache;
+pub mod cancel;
+pub mod canary;
+pub mod capability;
+pub mod causal;
+pub mod checkpoint;
+pub mod clock;
+pub mod coalesce;
+pub mod console;
+pub mod credit;
+pub mod data;
+pub mod debug;
+pub mod discovery;
+pub mod events;
+pub mod exactly_once;
+pub mod features;
+pub mod fixture;
+#[cfg(feature = "gateway")]
+pub mod gateway;
+pub mod hardened;
+pub mod hedge;
+pub mod idempotency;
+pub mod image;
+pub mod info;
+pub mod integrity;
+pub mod local;
+pub mod manifest;
+pub mod memory;
+pub mod message;
+pub mod message_auth;
+pub mod metrics;
+pub mod migration;
+pub mod mock;
+pub mod panic;
+pub mod partition;
+pub mod path;
+pub mod pipeline;
+pub mod policy;
+pub mod pool;
+pub mod preemption;
+pub mod protocol;
+pub mod pubsub;
+pub mod reaper;
+pub mod reflection;
+pub mod registry;
+pub mod router;
+pub mod rpc;
+pub mod rt;
+pub mod sandbox;
+pub mod sched;
+pub mod schema;
+pub mod select;
+#[cfg(feature = "serde")]
+pub mod serde_message;
+pub mod shadow;
+pub mod shared;
+pub mod shed;
+pub mod shim;
+pub mod shutdown;
+pub mod slo;
+pub mod snapshot;
+pub mod spawn;
+pub mod spsc;
+pub mod stepper;
+pub mod supervision;
+pub mod tenant;
+pub mod throttle;
+pub mod time;
+pub mod trace;
+pub mod usage;
+
 /// Object is sort of process in Kobzar. It is an instanse of some
 /// program that is currently running on the system, or residing in
 /// the RAM. The CCS object may request services of other objects which
@@ -9,6 +95,12 @@ pub trait Object<S: Service>: Sized {
     /// among all others.
     type Id;
 
+    /// Owned handle of the object that 'myself' returns.
+    type Owned: OwnedObject<S, Id = Self::Id>;
+
+    /// Network the object lives in.
+    type Network: Network<S>;
+
     /// Get object identifier. Each object in one CCS network has
     /// unique identifier.
     fn id(&self) -> Self::Id;
@@ -16,23 +108,27 @@ pub trait Object<S: Service>: Sized {
     /// Get information about the service by given identifier.
     /// If object has no service with given identifier, None will
     /// be returned.
-    fn service_by_id(&self, id: &S::Id) -> Option<&S>;
+    fn service_by_id(&self, id: &S::Id) -> Option<S>;
 
     /// Get object of current running application. When this application
     /// calls this function, it gets a self object.
-    fn myself<OO: OwnedObject<S, Id = Self::Id>>() -> OO;
+    fn myself() -> Self::Owned;
 
     /// The object that called this function quits.
     /// All allocated resources are freed. All services registered
     /// by the object are removed. All sub-objects are killed.
-    fn decease() -> !;
+    /// Monitors of the object are told the reason.
+    fn decease(reason: ExitReason) -> !;
 
     /// Get a CCS Network reference for this object.
-    fn network<N: Network<S>>(&self) -> &N;
+    fn network(&self) -> &Self::Network;
 }
 
 pub trait OwnedObject<S>: Object<S> where S: Service {
 
+    /// Network where the object can register and request services.
+    type OpenNetwork: OpenNetwork<S>;
+
     /// Kill given owned object. All resources local for this object
     /// are released. All services provided by this object are discarded.
     /// Fail when object is not alive. The function consumes the
@@ -48,10 +144,48 @@ pub trait OwnedObject<S>: Object<S> where S: Service {
     /// That is, all sub-objects and their services are created in
     /// master-object's internal network. It is not visible from the
     /// outside of that object in its external network.
-    fn internal_network<ON: OpenNetwork<S>>(&self) -> &ON;
+    fn internal_network(&self) -> &Self::OpenNetwork;
 
     /// Get an external CCS Network reference for this object.
-    fn network<ON: OpenNetwork<S>>(&self) -> &ON;
+    fn network(&self) -> &Self::OpenNetwork;
+
+    /// Check if the object and all of its sub-objects are quiescent.
+    /// That is, none of them has open channels or messages that are
+    /// sent but not yet received.
+    fn is_quiescent(&self) -> bool;
+
+    /// Wait until the object and all of its sub-objects become
+    /// quiescent. New channels are still accepted while waiting, so
+    /// the caller must stop the sources of new requests first if it
+    /// needs the subtree to stay quiescent, e.g. to take a snapshot.
+    fn await_quiescent<T: Time>(&self, deadline: T)
+        -> Result<(), QuiescenceErr>;
+
+    /// Suspend execution of the object and its sub-objects without
+    /// killing them. Channels of the frozen object stay open, but
+    /// nothing is received from or sent to them, so peers wait as if
+    /// the channel was full rather than get errors. Handlers of the
+    /// channels opened to the frozen object start on thaw.
+    fn freeze(&self) -> Result<(), FreezeErr>;
+
+    /// Resume execution of the frozen object.
+    fn thaw(&self) -> Result<(), FreezeErr>;
+
+    /// Check if the object is frozen.
+    fn is_frozen(&self) -> bool;
+
+    /// Ask the object to stop and wait for it to finish on its own.
+    /// Once the object has received the messages that are already
+    /// queued, its receives fail with 'SocketErr::ShuttingDown', so
+    /// the handlers end their channels and the object discontinues its
+    /// services and returns from the main thread. Object that is still
+    /// alive at the deadline is killed.
+    fn request_shutdown<T: Time>(self, deadline: T)
+        -> Result<Termination, ObjectKillErr>;
+
+    /// Check if the object was asked to stop. Objects check it on the
+    /// handle from 'myself'.
+    fn is_shutting_down(&self) -> bool;
 }
 
 /// Errors that appear on failed attempt to kill an object.
@@ -61,28 +195,178 @@ pub enum ObjectKillErr {
     NotAlive,
 }
 
+/// How the object stopped on the shutdown request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+
+    /// Object finished on its own before the deadline.
+    Graceful,
+
+    /// Object was killed at the deadline.
+    Forced,
+}
+
+/// Why the object stopped running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExitReason {
+
+    /// Object finished its work.
+    Normal,
+
+    /// Object was killed by its owner.
+    Killed,
+
+    /// Some thread of the object panicked. Holds the panic message.
+    Panicked(String),
+}
+
+/// Errors that appear on attempt to connect to a service.
+#[derive(Debug)]
+pub enum ConnectErr<S> {
+
+    /// No object provides the service. The service is given back.
+    NotProvided(S),
+
+    /// Provider has no endpoint with requested name. The service is
+    /// given back.
+    NoEndpoint(S),
+
+    /// Providers of the service exist but none of them supports any
+    /// version the requester does. The service is given back.
+    NoCommonVersion(S),
+
+    /// Peers exchanged compatibility manifests at connect and found
+    /// that they can't talk to each other.
+    Incompatible {
+        theirs  : Box<manifest::Manifest>,
+        ours    : Box<manifest::Manifest>,
+    },
+
+    /// Cancel token was triggered while waiting for the provider. The
+    /// service is given back.
+    Cancelled(S),
+
+    /// Requester lacks the capabilities the provider requires. The
+    /// service is given back.
+    PermissionDenied(S),
+
+    /// Sandbox of the requester does not allow connects to the service.
+    /// The service is given back.
+    Sandboxed(S),
+
+    /// Requester connects too fast or holds as many channels as it may.
+    /// The service is given back.
+    Throttled(S, throttle::ThrottleErr),
+}
+
+/// Error of the connect to a set of services. Channels opened before
+/// the failure are closed again.
+#[derive(Debug)]
+pub struct PartialConnectErr<S> {
+
+    /// Position of the service that failed in the requested set.
+    pub failed  : usize,
+    pub err     : ConnectErr<S>,
+}
+
+/// How the connect chooses among several providers of the service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectPolicy<Id> {
+
+    /// Each next connect goes to the next provider.
+    RoundRobin,
+
+    /// Provider with the fewest open channels to the service.
+    LeastConnections,
+
+    /// Any provider at random.
+    Random,
+
+    /// Given object if it provides the service, otherwise as
+    /// 'RoundRobin'.
+    PreferObject(Id),
+}
+
+/// Errors that appear on attempt to freeze or thaw an object.
+#[derive(Debug)]
+pub enum FreezeErr {
+
+    /// Object is not alive.
+    NotAlive,
+
+    /// Object is already frozen.
+    Frozen,
+
+    /// Object is not frozen and cannot be thawed.
+    NotFrozen,
+}
+
+/// Errors that appear while waiting for object to become quiescent.
+#[derive(Debug)]
+pub enum QuiescenceErr {
+
+    /// Object is not alive.
+    NotAlive,
+
+    /// Deadline was reached while the subtree still had open channels
+    /// or in-flight messages.
+    Timeout,
+}
+
 /// A CCS network.
 pub trait Network<S: Service>: Sized {
 
+    /// Objects that live in the network.
+    type Object: Object<S>;
+
+    /// Wait until some object in the network provides the service
+    /// with given identifier. Returns immediately if service is
+    /// already provided.
+    fn wait_for_service(&self, id: &S::Id);
+
+    /// Wait until the object with given identifier dies. Returns
+    /// immediately if there is no such alive object in the network.
+    fn wait_for_death(&self, id: &ObjectId<S, Self>);
+
+    /// Get the state of the whole registry with a cursor that can
+    /// later be used to request changes.
+    fn snapshot(&self) -> discovery::Snapshot<S::Id>;
+
+    /// Get changes that were made to the registry after given cursor.
+    fn changes_since(&self, cursor: discovery::Cursor)
+        -> Result<discovery::Delta<S::Id>, discovery::CursorErr>;
+
+    /// Get what the network knows about the service with given
+    /// identifier, including the description given by its provider.
+    /// None if no object provides the service.
+    fn service_info(&self, id: &S::Id) -> Option<info::ServiceInfo<S::Id>>;
+
+    /// Optional features of the network and the API version it
+    /// implements. Networks that don't report features are taken to
+    /// have none.
+    fn features(&self) -> features::Features {
+        Default::default()
+    }
 }
 
 /// A CCS network that is open for current object. Current object
 /// can register new services or request them in its open networks.
 pub trait OpenNetwork<S>: Network<S> where S: Service {
 
+    /// Socket of the channels in the network.
+    type Socket: Socket<Self::Object, S>;
+
+    /// Handle of the service registered by current object.
+    type OwnedService: OwnedService<Id = S::Id>;
+
     /// Connect to a service provider. If any object in CCS network can
     /// provide such service, then channel is created.
-    fn connect<O, SC>(&self, service: S) -> Result<SC, S>
-        where O     : Object<S>,
-              SC    : Socket<O, S>;
+    fn connect(&self, service: S) -> Result<Self::Socket, ConnectErr<S>>;
 
     /// Attempt to register new service that current object is ready to
     /// provide.
-    fn register<O, OS, SC>(&self, reg_form: RegistrationForm<O, S, SC>)
-        -> Result<OS, RegistrationErr>
-        where   O   : Object<S>,
-                OS  : OwnedService<Id = S::Id>,
-                SC  : Socket<O, S>;
+    fn register(&self, reg_form: Form<S, Self>)
+        -> Result<Self::OwnedService, RegistrationErr>;
 
     /// Try to register service that the object that called this
     /// function is ready to provide. The difference from 'register'
@@ -99,12 +383,121 @@ pub trait OpenNetwork<S>: Network<S> where S: Service {
     /// starts very early at system initialization, it uniquely registers
     /// its services so no other objects in the system later after
     /// booting couldn't succeed in service interception.
-    fn register_unique<O, OS, SC>
-        (&self, reg_form: RegistrationForm<O, S, SC>)
-        -> Result<OS, RegistrationErr>
-        where   O   : Object<S>,
-                OS  : OwnedService<Id = S::Id>,
-                SC  : Socket<O, S>;
+    fn register_unique(&self, reg_form: Form<S, Self>)
+        -> Result<Self::OwnedService, RegistrationErr>;
+
+    /// Register the service uniquely for as long as the lease is
+    /// renewed with 'OwnedService::renew_lease' within given interval.
+    /// After the lease expires, another object may register the
+    /// service and so take the claim over, e.g. a restarted Memory
+    /// Server whose crashed predecessor was never cleaned up. Until
+    /// then, registrations fail with 'RegistrationErr::LeaseHeld'.
+    fn register_unique_leased<T: Time>(&self, reg_form: Form<S, Self>, lease: T)
+        -> Result<Self::OwnedService, RegistrationErr>;
+
+    /// Hint that the service will be requested soon. Networks that load
+    /// provider programs lazily may start the provider ahead of the
+    /// actual connect to hide its start-up time. This is only a hint
+    /// and networks that have nothing to prepare ignore it.
+    fn prefetch(&self, service: &S) {
+        let _ = service;
+    }
+
+    /// Connect to the service with given path.
+    fn connect_path(&self, path: &path::ServicePath)
+        -> Result<Self::Socket, ConnectErr<S>>
+        where S::Id: From<path::ServicePath>
+    {
+        self.connect(S::by_id(path.clone().into()))
+    }
+
+    /// Register the service under given path. Forms with endpoints or
+    /// other settings are registered with 'register' and the path as
+    /// their identifier.
+    fn register_path(&self, path: &path::ServicePath, entry: fn(Self::Socket) -> !)
+        -> Result<Self::OwnedService, RegistrationErr>
+        where S::Id: From<path::ServicePath>
+    {
+        self.register(RegistrationForm::new(entry, path.clone().into()))
+    }
+
+    /// Connect to a provider that supports some of given versions of
+    /// the service protocol. The highest version both sides support is
+    /// chosen and returned, and the provider gets it from 'version' of
+    /// its socket. Providers that support none of them are skipped.
+    fn connect_versioned(&self, service: S, versions: Versions)
+        -> Result<(Self::Socket, u32), ConnectErr<S>>;
+
+    /// Connect with the channel that buffers given count of messages in
+    /// each direction, instead of the capacity set by the provider.
+    fn connect_bounded(&self, service: S, capacity: usize)
+        -> Result<Self::Socket, ConnectErr<S>>;
+
+    /// Connect to all given services or to none of them. If any connect
+    /// fails, the channels already opened are closed. Sockets are in the
+    /// order of the services.
+    fn connect_set(&self, services: &[S])
+        -> Result<Vec<Self::Socket>, PartialConnectErr<S>>
+    {
+        let mut sockets = Vec::with_capacity(services.len());
+        for service in services {
+            match self.connect(S::by_id(service.id())) {
+                Ok(socket)  => sockets.push(socket),
+                Err(err)    => {
+                    let failed = sockets.len();
+                    for socket in sockets {
+                        socket.close();
+                    }
+                    return Err(PartialConnectErr { failed, err });
+                },
+            }
+        }
+        Ok(sockets)
+    }
+
+    /// Connect to the provider chosen by given policy when several
+    /// objects provide the service.
+    fn connect_with_policy(&self, service: S, policy: ConnectPolicy<ObjectId<S, Self>>)
+        -> Result<Self::Socket, ConnectErr<S>>;
+}
+
+/// Open network that can pre-resolve services into reusable connect
+/// tokens. Clients that open short channels to the same service many
+/// times resolve it once and then connect by the token, skipping
+/// discovery and per-attempt allocations.
+pub trait TokenConnect<S>: OpenNetwork<S> where S: Service {
+
+    /// Resolved service. Token stays valid while the providers it was
+    /// resolved to are alive.
+    type ConnectToken;
+
+    /// Resolve the service. None if no object provides it.
+    fn resolve(&self, service: &S) -> Option<Self::ConnectToken>;
+
+    /// Connect to the resolved service.
+    fn connect_with_token(&self, token: &Self::ConnectToken)
+        -> Result<Self::Socket, ConnectTokenErr>;
+}
+
+/// Open network which services may have several named endpoints.
+pub trait EndpointConnect<S>: OpenNetwork<S> where S: Service {
+
+    /// Connect to the named endpoint of the service. Fails with
+    /// 'ConnectErr::NoEndpoint' if the provider has no such endpoint.
+    fn connect_endpoint(&self, service: S, endpoint: &str)
+        -> Result<Self::Socket, ConnectErr<S>>;
+}
+
+/// Error of connecting with a token.
+#[derive(Debug)]
+pub enum ConnectTokenErr {
+
+    /// Providers the token was resolved to are gone. Service must be
+    /// resolved again.
+    Stale,
+
+    /// Provider exists but declined the connection.
+    Declined,
 }
 
 /// Service is requested by the Object. Service is used to update some
@@ -142,14 +535,92 @@ pub trait Service: Sized {
 /// discontinue service or do other owner-related stuff.
 pub trait OwnedService: Sized + Service {
 
+    /// What 'discontinue' gives back, usually the RegistrationForm.
+    type Form;
+
     /// Notify the system that object doesn't provide selected service
     /// no more. This function returns RegistrationForm so
     /// that discontinued service could be registered again.
-    fn discontinue<O, SC>(self) -> RegistrationForm<O, Self, SC>
-        where   O   : Object<Self>,
-                SC  : Socket<O, Self>;
+    fn discontinue(self) -> Self::Form;
+
+    /// Count of objects that currently provide this service, including
+    /// the owner.
+    fn provider_count(&self) -> usize;
+
+    /// Count of service pointers to this service that are held by
+    /// other objects. Weak handles are not counted.
+    fn reference_count(&self) -> usize;
+
+    /// Extend the lease of the unique registration by its interval
+    /// from now. Does nothing for registrations without the lease.
+    /// Fails with 'RegistrationErr::LeaseHeld' if the lease has expired
+    /// and another provider took the service over.
+    fn renew_lease(&self) -> Result<(), RegistrationErr>;
+}
+
+/// Weak handle to the service. Unlike service pointer, it does not
+/// count as a reference to the service and can be upgraded to service
+/// pointer only while some object provides the service. Clients can
+/// hold such handles for 'maybe available' services without repeating
+/// discovery each time they need to check availability.
+pub trait WeakService<S: Service, N: Network<S>>: Sized {
+
+    /// Make weak handle for the service with given identifier. Same
+    /// as 'Service::by_id' it always succeeds.
+    fn by_id(id: S::Id) -> Self;
+
+    /// Identifier of the service.
+    fn id(&self) -> &S::Id;
+
+    /// Count of objects that currently provide the service in the
+    /// network.
+    fn provider_count(&self, network: &N) -> usize;
+
+    /// Get service pointer if the service is currently provided by
+    /// some object of the network.
+    fn upgrade(&self, network: &N) -> Option<S>;
+}
+
+/// Identifier of the objects in the network.
+pub type ObjectId<S, N> = <<N as Network<S>>::Object as Object<S>>::Id;
+
+/// Registration form of the services in the open network.
+pub type Form<S, N> = RegistrationForm<<N as Network<S>>::Object, S,
+        <N as OpenNetwork<S>>::Socket>;
+
+/// Range of the protocol versions a peer supports, both ends included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Versions {
+    pub min     : u32,
+    pub max     : u32,
+}
+
+impl Versions {
+
+    /// Versions from 'min' to 'max'.
+    pub fn new(min: u32, max: u32) -> Self {
+        Versions { min, max }
+    }
+
+    /// Only the given version.
+    pub fn only(version: u32) -> Self {
+        Versions::new(version, version)
+    }
+
+    /// Highest version in both ranges. None if they don't overlap.
+    pub fn negotiate(&self, theirs: &Versions) -> Option<u32> {
+        let max = self.max.min(theirs.max);
+        if max >= self.min.max(theirs.min) {
+            Some(max)
+        } else {
+            None
+        }
+    }
 }
 
+/// Named entry point of the service.
+pub type Endpoint<SC> = (&'static str, fn(SC) -> !);
+
 pub struct RegistrationForm<O, S, SC>
         where O     : Object<S>,
               S     : Service,
@@ -163,6 +634,39 @@ pub struct RegistrationForm<O, S, SC>
 
     /// Identifier of the service.
     pub id      : S::Id,
+
+    /// Named entry points of the service. Connects that name the
+    /// endpoint start from its function instead of 'entry'.
+    pub endpoints : Vec<Endpoint<SC>>,
+
+    /// Objectives the provider promises to meet. Networks that track
+    /// them raise 'ControlEvent::SloViolated' when they are not met.
+    pub objectives : Vec<slo::Objective>,
+
+    /// Protocol versions the provider supports, only version 0 unless
+    /// set. Plain 'connect' does not check it.
+    pub version : Versions,
+
+    /// Messages the channels to the service buffer in each direction.
+    /// Zero, the default, makes each send wait for the peer to receive.
+    pub capacity : usize,
+
+    /// Limits of the batches in which the receivers of the buffered
+    /// channels are woken. None, the default, wakes them per message.
+    pub coalesce : Option<coalesce::Coalescing>,
+
+    /// Description of the service for requesters and tooling. Empty
+    /// unless set.
+    pub metadata : info::Metadata,
+
+    /// Names of the capabilities the requester must hold, each
+    /// permitting this service, to connect.
+    pub requires : Vec<String>,
+
+    /// Compatibility manifest of the provider. Requesters that connect
+    /// with their own manifest are checked against it. Plain 'connect'
+    /// does not check it.
+    pub manifest : Option<manifest::Manifest>,
 }
 
 impl<O, S, SC> RegistrationForm<O, S, SC>
@@ -185,8 +689,79 @@ impl<O, S, SC> RegistrationForm<O, S, SC>
     ) -> Self {
         RegistrationForm {
             _a      : std::marker::PhantomData,
-            entry   : entry,
-            id      : id
+            entry,
+            id,
+            endpoints : Vec::new(),
+            objectives : Vec::new(),
+            version : Versions::default(),
+            capacity : 0,
+            coalesce : None,
+            metadata : info::Metadata::default(),
+            requires : Vec::new(),
+            manifest : None,
+        }
+    }
+
+    /// Add the named endpoint with its entry function.
+    pub fn endpoint(mut self, name: &'static str, entry: fn(SC) -> !) -> Self {
+        self.endpoints.push((name, entry));
+        self
+    }
+
+    /// Set the protocol versions the provider supports.
+    pub fn version(mut self, version: Versions) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Set count of the messages the channels to the service buffer in
+    /// each direction.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Wake the receivers of the buffered channels once per 'max_batch'
+    /// messages, or once the first of them has waited for 'max_latency'.
+    pub fn coalesce<T: Time>(mut self, max_batch: usize, max_latency: T) -> Self {
+        self.coalesce = Some(coalesce::Coalescing::new(max_batch, max_latency));
+        self
+    }
+
+    /// Require the requesters to hold the capability with given name
+    /// that permits this service.
+    pub fn require(mut self, capability: &str) -> Self {
+        self.requires.push(capability.to_string());
+        self
+    }
+
+    /// Set the compatibility manifest of the provider.
+    pub fn manifest(mut self, manifest: manifest::Manifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    /// Set the description of the service.
+    pub fn metadata(mut self, metadata: info::Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Add the objective of the service.
+    pub fn objective(mut self, objective: slo::Objective) -> Self {
+        self.objectives.push(objective);
+        self
+    }
+
+    /// Entry function for the connect to given endpoint, or to the
+    /// service itself when endpoint is None. None if there is no such
+    /// endpoint.
+    pub fn dispatch(&self, endpoint: Option<&str>) -> Option<fn(SC) -> !> {
+        match endpoint {
+            None        => Some(self.entry),
+            Some(name)  => self.endpoints.iter()
+                .find(|&&(n, _)| n == name)
+                .map(|&(_, entry)| entry),
         }
     }
 }
@@ -197,6 +772,12 @@ impl<O, S, SC> RegistrationForm<O, S, SC>
 pub trait Socket<O, S>: Sized
         where O: Object<S>, S: Service {
 
+    /// Sending half of the split socket.
+    type SendHalf: SendHalf<O, S, Socket = Self>;
+
+    /// Receiving half of the split socket.
+    type ReceiveHalf: ReceiveHalf<O, S, Socket = Self>;
+
     /// Get object which requested the service.
     fn requester(&self) -> &O;
 
@@ -217,13 +798,15 @@ pub trait Socket<O, S>: Sized
             -> Option<Result<D, SocketErr>>;
     
     /// Wait forever until requester receives the data or socket error
-    /// occurs.
+    /// occurs. On the bounded channel, only wait until the buffer has
+    /// room for the data.
     fn send<D: Data>(&self, data: D) -> Result<(), SocketErr>;
 
     /// Try to send the data right now. Same as 'send' but without
     /// waiting. If data was not sent, the consumed data field is
-    /// returned in Result.
-    fn send_now<D: Data>(&self, data: D) -> Result<D, SocketErr>;
+    /// returned in Result. On the bounded channel, data is put to the
+    /// buffer or, if the buffer is full, dropped with 'SocketErr::Full'.
+    fn send_now<D: Data>(&self, data: D) -> Result<Option<D>, SocketErr>;
     
     /// Wait for given amount of time to send a data to the service requester.
     /// Similar to 'send' function. After timeout, None will
@@ -234,7 +817,7 @@ pub trait Socket<O, S>: Sized
     fn close(self);
     
     /// Run some function that can be safely aborted when channel gets closed.
-    fn run_abortable(&self, run_fn: Fn()) -> AbortResult;
+    fn run_abortable(&self, run_fn: &dyn Fn()) -> AbortResult;
     
     /// Check if channel still is opened.
     fn check(self) -> Option<Self>;
@@ -242,13 +825,184 @@ pub trait Socket<O, S>: Sized
     /// Check if channel is opened. Similar to 'check', but does
     /// not consume the socket and returns boolean value instead.
     fn is_opened(&self) -> bool;
+
+    /// Messages the channel buffers in each direction. Zero if each send
+    /// waits for the peer to receive.
+    fn capacity(&self) -> usize;
+
+    /// Messages sent from this end that the peer has not received yet.
+    fn len(&self) -> usize;
+
+    /// Check if the peer has received everything sent from this end.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Protocol version negotiated at connect. 0 for the channels
+    /// opened without the version.
+    fn version(&self) -> u32 {
+        0
+    }
+
+    /// Send all the data in order, waiting until the requester receives
+    /// the last one. Networks may hand the whole batch over at once
+    /// instead of doing a round-trip per message.
+    fn send_vectored<D: Data>(&self, data: Vec<D>) -> Result<(), SocketErr> {
+        for d in data {
+            self.send(d)?;
+        }
+        Ok(())
+    }
+
+    /// Wait until some data is received, then also take what is ready
+    /// right now, up to 'max' in total. Error is returned only if
+    /// nothing was received.
+    fn receive_batch<D: Data>(&self, max: usize) -> Result<Vec<D>, SocketErr> {
+        if max == 0 {
+            return Ok(Vec::new());
+        }
+        let mut batch = vec![self.receive()?];
+        while batch.len() < max {
+            match self.receive_now() {
+                Ok(Some(data))  => batch.push(data),
+                _               => break,
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Send the data by mapping its region to the requester. Returns the
+    /// grant that revokes the mapping and gives the data back, e.g. when
+    /// the channel closes. Networks that span address spaces map the
+    /// pages of the region, in the same address space the region is
+    /// just shared.
+    fn send_shared<D: SharedData>(&self, data: D)
+            -> Result<shared::Grant<D>, SocketErr> {
+        let (grant, mapping) = shared::share(data);
+        self.send(mapping)?;
+        Ok(grant)
+    }
+
+    /// Wait forever until the mapping of the shared region is received
+    /// or socket error occurs.
+    fn receive_shared<D: SharedData>(&self) -> Result<shared::Mapping<D>, SocketErr> {
+        self.receive()
+    }
+
+    /// Split the socket into sending and receiving halves that can be
+    /// owned and moved independently, e.g. to send from one thread and
+    /// receive in another. The channel stays open while either half is
+    /// alive.
+    fn split(self) -> (Self::SendHalf, Self::ReceiveHalf);
+}
+
+/// Sending half of the split socket.
+pub trait SendHalf<O, S>: Sized
+        where O: Object<S>, S: Service {
+
+    /// Socket this half was split from.
+    type Socket: Socket<O, S>;
+
+    /// Same as 'Socket::send'.
+    fn send<D: Data>(&self, data: D) -> Result<(), SocketErr>;
+
+    /// Same as 'Socket::send_now'.
+    fn send_now<D: Data>(&self, data: D) -> Result<Option<D>, SocketErr>;
+
+    /// Same as 'Socket::wait_to_send'.
+    fn wait_to_send<T: Time>(&self, time: T) -> Option<Result<(), SocketErr>>;
+
+    /// Check if channel is opened.
+    fn is_opened(&self) -> bool;
+
+    /// Join the halves back into the full socket. Fails if the halves
+    /// were split from different sockets.
+    fn reunite(self, receive: ReceiveHalfOf<O, S, Self::Socket>)
+        -> Reunited<O, S, Self>;
+}
+
+/// Receiving half of the split socket.
+pub trait ReceiveHalf<O, S>: Sized
+        where O: Object<S>, S: Service {
+
+    /// Socket this half was split from.
+    type Socket: Socket<O, S>;
+
+    /// Same as 'Socket::receive'.
+    fn receive<D: Data>(&self) -> Result<D, SocketErr>;
+
+    /// Same as 'Socket::receive_now'.
+    fn receive_now<D: Data>(&self) -> Result<Option<D>, SocketErr>;
+
+    /// Same as 'Socket::wait_to_receive'.
+    fn wait_to_receive<D: Data, T: Time>(&self, time: T)
+            -> Option<Result<D, SocketErr>>;
+
+    /// Check if channel is opened.
+    fn is_opened(&self) -> bool;
+}
+
+/// Receiving half of the socket 'SC'.
+pub type ReceiveHalfOf<O, S, SC> = <SC as Socket<O, S>>::ReceiveHalf;
+
+/// Result of 'SendHalf::reunite'.
+pub type Reunited<O, S, SH> = Result<<SH as SendHalf<O, S>>::Socket,
+        ReuniteErr<SH, ReceiveHalfOf<O, S, <SH as SendHalf<O, S>>::Socket>>>;
+
+/// Halves of different sockets were given to 'SendHalf::reunite'. The
+/// halves are given back.
+#[derive(Debug)]
+pub struct ReuniteErr<SH, RH>(pub SH, pub RH);
+
+/// Socket that numbers delivered messages. Each message sent over the
+/// channel in one direction gets the next sequence number. On lossy
+/// best-effort channels, receiver can use the numbers to find out that
+/// some messages were lost.
+pub trait SequencedSocket<O, S>: Socket<O, S>
+        where O: Object<S>, S: Service {
+
+    /// Same as 'receive' but also returns sequence number of the
+    /// received message.
+    fn receive_sequenced<D: Data>(&self) -> Result<(u64, D), SocketErr>;
+
+    /// Sequence number of the last received message. None if nothing
+    /// was received yet.
+    fn last_sequence(&self) -> Option<u64>;
+
+    /// Enable or disable gap notification. When enabled, receive
+    /// operations return 'SocketErr::SequenceGap' once when the
+    /// next message has greater number than expected. The message
+    /// itself is returned by the next receive.
+    fn notify_gaps(&self, enabled: bool);
 }
 
-/// Some data that is transfered via channels.
-pub trait Data {
+/// Some data that is transfered via channels. Data is owned and can be
+/// moved to other threads, as the receiver may run on any of them.
+pub trait Data: Send + 'static {
 }
 
-/// The time. Used in timers.
+impl Data for String {
+}
+
+impl Data for Vec<u8> {
+}
+
+/// Data that is passed by mapping its memory region to the receiver
+/// instead of copying, e.g. a framebuffer. The receiver reads it in
+/// place while the sender may still hold it, so it must be shareable
+/// between threads.
+pub trait SharedData: Send + Sync + 'static {
+}
+
+impl SharedData for String {
+}
+
+impl SharedData for Vec<u8> {
+}
+
+/// The time. Used in timers. See 'time::StdTime' for the one backed by
+/// the standard library. Arithmetic saturates at zero and at the longest
+/// span 'seconds' can hold, and works across different 'Time' types.
 pub trait Time {
 
     /// Nanoseconds to wait after all seconds are elapsed
@@ -257,6 +1011,61 @@ pub trait Time {
     
     /// Seconds to wait.
     fn seconds(&self) -> u32;
+
+    /// Time of given seconds and nanoseconds. Whole seconds in 'nanos'
+    /// are carried into the seconds.
+    fn from_parts(seconds: u32, nanos: u32) -> Self where Self: Sized;
+
+    /// Same span as of the other time.
+    fn from_time<T: Time>(other: &T) -> Self where Self: Sized {
+        Self::from_parts(other.seconds(), other.nanos())
+    }
+
+    /// Wall clock time since the Unix epoch. Zero if the clock is set
+    /// before the epoch.
+    fn now() -> Self where Self: Sized {
+        let since = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        from_total(since.as_nanos().min(u64::MAX as u128) as u64)
+    }
+
+    /// Whole span in nanoseconds.
+    fn total_nanos(&self) -> u64 {
+        self.seconds() as u64 * NANOS_PER_SEC + self.nanos() as u64
+    }
+
+    /// Whether the span is empty.
+    fn is_zero(&self) -> bool {
+        self.total_nanos() == 0
+    }
+
+    /// Sum of the spans, or the longest span on overflow.
+    fn saturating_add<T: Time>(&self, other: &T) -> Self where Self: Sized {
+        from_total(self.total_nanos().saturating_add(other.total_nanos()))
+    }
+
+    /// Difference of the spans, or zero if the other is longer.
+    fn saturating_sub<T: Time>(&self, other: &T) -> Self where Self: Sized {
+        from_total(self.total_nanos().saturating_sub(other.total_nanos()))
+    }
+
+    /// Compare the spans.
+    fn cmp_time<T: Time>(&self, other: &T) -> std::cmp::Ordering {
+        self.total_nanos().cmp(&other.total_nanos())
+    }
+}
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Time of given nanoseconds, saturating at the longest span.
+fn from_total<T: Time>(nanos: u64) -> T {
+    let seconds = nanos / NANOS_PER_SEC;
+    if seconds > u32::MAX as u64 {
+        T::from_parts(u32::MAX, (NANOS_PER_SEC - 1) as u32)
+    } else {
+        T::from_parts(seconds as u32, (nanos % NANOS_PER_SEC) as u32)
+    }
 }
 
 /// Error that appears in operation with socket.
@@ -272,6 +1081,59 @@ pub enum SocketErr {
     /// Error is received only by the last socket which tried to perform
     /// the operation.
     Lockup,
+
+    /// Some messages were lost between the last received message and
+    /// the next one. Only returned when gap notification is enabled.
+    SequenceGap {
+        /// Sequence number that was expected.
+        expected    : u64,
+
+        /// Sequence number of the next message.
+        received    : u64,
+    },
+
+    /// Message was damaged on its way and failed integrity check.
+    /// Reported by 'integrity::IntegritySocket'.
+    CorruptData,
+
+    /// Channel was closed because the capability that authorized it
+    /// was revoked.
+    CapabilityRevoked,
+
+    /// Message is not allowed by the protocol of the channel in its
+    /// current state.
+    ProtocolViolation,
+
+    /// Message would exceed the cap of bytes queued in the channel or
+    /// to the receiving object.
+    MemoryLimit,
+
+    /// Provider is overloaded and rejected the request without
+    /// handling it. Request may be retried later or elsewhere.
+    ProviderOverloaded,
+
+    /// Next message in the channel is not of the requested type. The
+    /// message stays in the channel.
+    UnexpectedData,
+
+    /// Object that holds this end was asked to shut down and there are
+    /// no more messages to receive.
+    ShuttingDown,
+
+    /// Buffer of the bounded channel is full. Returned by non-blocking
+    /// sends instead of waiting for room.
+    Full,
+
+    /// Cancel token of the blocking operation was triggered.
+    Cancelled,
+
+    /// Message failed the per-message authorization: its proof is
+    /// wrong, or it was replayed or reordered. Reported by
+    /// 'message_auth::AuthSocket'.
+    Unauthenticated,
+
+    /// Tenant of the sender used up its quota for the current period.
+    QuotaExceeded,
 }
 
 /// Result of running the function that could get aborted if channel closes.
@@ -297,7 +1159,21 @@ pub enum RegistrationErr {
     /// when the same service is already registered in the system,
     /// CCS network can't register this service uniquely. Not until
     /// all the same services are closed.
-    AlreadyRegistered
+    AlreadyRegistered,
+
+    /// Object exceeded its registration limits.
+    Throttled(throttle::ThrottleErr),
+
+    /// Service is uniquely registered with the lease that has not
+    /// expired yet. Also returned on renewal of the lease that has
+    /// been lost.
+    LeaseHeld,
+
+    /// Sandbox of the object does not allow it to register services.
+    Sandboxed(sandbox::SandboxViolation),
+
+    /// Policy of the network denies the registration.
+    PermissionDenied,
 }
 
 #[cfg(test)]
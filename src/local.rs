@@ -0,0 +1,5246 @@
+//! In-process network. Objects are groups of threads of the current
+//! program, each request is handled in a new thread of the provider and
+//! channels are queues behind a mutex. Buffered channels move messages
+//! through the rings of 'spsc' instead. It implements the CCS traits without
+//! the Kobzar kernel underneath, so applications can be prototyped and
+//! tested as plain programs.
+//!
+//! Threads that were not started by the network act as the host object
+//! with identifier 0. The host can register and request services, but it
+//! can't be killed.
+//!
+//! Entry functions of the services must not return. When the handler has
+//! nothing more to do, it calls 'finish', which ends only its own thread.
+
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::future::{self, Future};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, Weak};
+use std::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::{AbortResult, ConnectErr, ConnectPolicy, ConnectTokenErr, Data, EndpointConnect,
+        ExitReason, FreezeErr, Network, Object, ObjectKillErr, OpenNetwork, OwnedObject,
+        OwnedService, QuiescenceErr, ReceiveHalf, RegistrationErr, RegistrationForm,
+        ReuniteErr, SendHalf, SequencedSocket, Service, Socket, SocketErr, Termination, Time,
+        TokenConnect, Versions, WeakService};
+use affinity::{Affinity, AffinityErr, AffinityNetwork, AffinitySocket, Domain};
+use authz::{CachedPolicy, MemoryDecisionCache};
+use aio::{AsyncErr, AsyncNetwork, AsyncOpenNetwork, AsyncSocket};
+use canary::{SplitNetwork, TrafficSplit};
+use checkpoint::{Checkpoint, CheckpointErr, CheckpointStore, Checkpointed,
+    MemoryCheckpointStore};
+use capability::{Capability, CapabilityErr, CapabilityHolder, CapabilityNetwork, GrantErr, Issuer,
+    Revocations, RevokeErr};
+use cancel::{CancelNetwork, CancelSocket, CancelToken, CancelWaker, WakerKey};
+use coalesce::Batcher;
+use data::SmallData;
+use debug::{DebugErr, DebugNetwork, Direction, QueuedMessage, Role, SocketInfo,
+    DEBUG_CAPABILITY};
+use discovery::{Cursor, CursorErr, Delta, RegistryChange, RegistryEntry, Snapshot};
+use features::{Feature, Features};
+use hardened::{Grants, HardenedObject};
+use info::ServiceInfo;
+use manifest::{self, Manifest, ManifestNetwork, ManifestSocket};
+use memory::{MemoryAccount, MemoryCaps};
+use metrics::{MetricFamily, MetricsSource};
+use migration::{MigratingNetwork, MigrationErr, MigrationPlan};
+use mock::{MockNetwork, StubTable};
+use panic::{describe, PanickingObject};
+use partition::{HashRing, PartitionNetwork};
+use policy::{Action, Decision, Pattern, Policy, PolicyNetwork, Request};
+use pubsub::{BufferPolicy, Hub, HubBroadcast, HubSubscription, PubSubErr, PubSubNetwork};
+use registry::ShardedRegistry;
+use reaper::{LivenessSource, ReapableNetwork};
+use rpc::{DirectFn, DirectNetwork};
+use sandbox::{SandboxProfile, Sandboxed};
+use tenant::TenantAccount;
+use usage::{Usage, UsageNetwork, UsageReport};
+use select::{Event, SelectSocket};
+use shed::ShedSocket;
+use spawn::{Placement, Program, SpawnErr, SpawnSpec, Spawner};
+use spsc::{self, Consumer, Producer};
+use supervision::{DeathHook, Supervisor};
+use throttle::{Limits, Operation, Throttle, ThrottledNetwork};
+use rt::{duration, spawn_accept_loop, Incoming, Runtime, ThreadRuntime, ThreadSleep};
+
+/// Count of the last registry changes kept for 'changes_since'.
+const HISTORY: usize = 1024;
+
+/// How often 'await_quiescent' checks the channels.
+const POLL: Duration = Duration::from_millis(5);
+
+/// Largest state of the checkpoint the network keeps, in bytes.
+const CHECKPOINT_LIMIT: usize = 1 << 20;
+
+/// Largest buffer of the channel that goes to the rings. Rings
+/// are allocated whole at connect, larger buffers grow as they fill in
+/// the queues behind the mutex.
+const RING_LIMIT: usize = 4096;
+
+/// Count of the policy decisions the network remembers.
+const DECISIONS: usize = 4096;
+
+/// Ends of the channel.
+const REQUESTER: usize = 0;
+const PROVIDER: usize = 1;
+
+/// Source of the object, registration and channel identifiers.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// Object the current thread belongs to.
+    static CURRENT: RefCell<Option<LocalObject>> = const { RefCell::new(None) };
+}
+
+/// Registration form of the local services.
+pub type LocalForm = RegistrationForm<LocalObject, LocalService, LocalSocket>;
+
+/// Payload of the unwind that ends the thread on purpose.
+struct Finished;
+
+/// End the current thread of the object. The object itself stays alive.
+/// Entry functions call it when they are done with the channel.
+pub fn finish() -> ! {
+    ::std::panic::resume_unwind(Box::new(Finished))
+}
+
+/// Service pointer. Services are identified by their names.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalService {
+    id      : String,
+}
+
+impl Service for LocalService {
+    type Id = String;
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn by_id(id: String) -> Self {
+        LocalService { id }
+    }
+}
+
+/// Handle of the registered service.
+pub struct LocalOwnedService {
+    id              : String,
+
+    /// Network and identifier of the registration. None for handles
+    /// made by 'by_id', which own nothing.
+    registration    : Option<(LocalNetwork, u64)>,
+}
+
+impl Service for LocalOwnedService {
+    type Id = String;
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn by_id(id: String) -> Self {
+        LocalOwnedService {
+            id,
+            registration    : None,
+        }
+    }
+}
+
+impl OwnedService for LocalOwnedService {
+
+    /// None if the registration is already gone, e.g. because its
+    /// object has died.
+    type Form = Option<LocalForm>;
+
+    fn discontinue(self) -> Option<LocalForm> {
+        let (network, registration) = self.registration?;
+        network.discontinue(&self.id, registration)
+    }
+
+    fn provider_count(&self) -> usize {
+        self.registration.as_ref().map_or(0, |(network, _)| {
+            network.inner.registry.providers(&self.id).len()
+        })
+    }
+
+    /// Service pointers are plain names here and can't be counted, so
+    /// the channels that are open to this registration are counted
+    /// instead.
+    fn reference_count(&self) -> usize {
+        self.registration.as_ref().map_or(0, |&(ref network, registration)| {
+            network.open_channels(&self.id, registration)
+        })
+    }
+
+    fn renew_lease(&self) -> Result<(), RegistrationErr> {
+        match self.registration {
+            Some((ref network, registration))   => network.renew(&self.id, registration),
+            None                                => Err(RegistrationErr::LeaseHeld),
+        }
+    }
+}
+
+/// Weak handle to the service, upgraded against the network where the
+/// service is looked up.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalWeakService {
+    id      : String,
+}
+
+impl WeakService<LocalService, LocalNetwork> for LocalWeakService {
+
+    fn by_id(id: String) -> Self {
+        LocalWeakService { id }
+    }
+
+    fn id(&self) -> &String {
+        &self.id
+    }
+
+    fn provider_count(&self, network: &LocalNetwork) -> usize {
+        network.inner.registry.providers(&self.id).len()
+    }
+
+    fn upgrade(&self, network: &LocalNetwork) -> Option<LocalService> {
+        if self.provider_count(network) == 0 {
+            return None;
+        }
+        Some(LocalService::by_id(self.id.clone()))
+    }
+}
+
+/// Waker that runs the function, used to interrupt the blocking waits
+/// when the cancel token is triggered.
+struct CancelWake<F>(F);
+
+impl<F: Fn() + Send + Sync + 'static> Wake for CancelWake<F> {
+
+    fn wake(self: Arc<Self>) {
+        (self.0)()
+    }
+}
+
+/// Waker registration in the cancel token that is removed once the
+/// blocking operation is over.
+struct CancelGuard<'a> {
+    token   : &'a CancelToken,
+    key     : WakerKey,
+}
+
+impl<'a> CancelGuard<'a> {
+
+    /// Register the function to run when the token is triggered. None if
+    /// the token is already cancelled.
+    fn new<F>(token: &'a CancelToken, wake: F) -> Option<Self>
+        where F: Fn() + Send + Sync + 'static
+    {
+        let waker = Waker::from(Arc::new(CancelWake(wake)));
+        token.register(&waker).map(|key| CancelGuard { token, key })
+    }
+}
+
+impl<'a> Drop for CancelGuard<'a> {
+
+    fn drop(&mut self) {
+        self.token.unregister(self.key);
+    }
+}
+
+fn is_cancelled(cancel: Option<&CancelToken>) -> bool {
+    cancel.is_some_and(|c| c.is_cancelled())
+}
+
+/// Message in the queue of the channel.
+struct Message {
+    /// Number of the message in its direction, zero until the message
+    /// is put into the channel.
+    seq     : u64,
+    data    : Payload,
+}
+
+impl Message {
+
+    fn new<D: Data>(data: D) -> Self {
+        Message {
+            seq     : 0,
+            data    : Payload::new(data),
+        }
+    }
+}
+
+/// Data of the message. Bytes sent as 'SmallData' or as 'Vec<u8>' are
+/// kept as 'SmallData', so short payloads need no allocation on the way
+/// and the receiver may take them as either type.
+enum Payload {
+    Bytes(SmallData),
+    Boxed(Box<dyn Any + Send>),
+}
+
+impl Payload {
+
+    fn new<D: Data>(data: D) -> Self {
+        let mut data = Some(data);
+        let any = &mut data as &mut dyn Any;
+        if let Some(bytes) = any.downcast_mut::<Option<SmallData>>() {
+            return Payload::Bytes(bytes.take().expect("data is there"));
+        }
+        if let Some(bytes) = any.downcast_mut::<Option<Vec<u8>>>() {
+            return Payload::Bytes(bytes.take().expect("data is there").into());
+        }
+        Payload::Boxed(Box::new(data.expect("data is there")))
+    }
+
+    /// Whether the data can be taken as given type.
+    fn is<D: Data>(&self) -> bool {
+        match *self {
+            Payload::Bytes(_)       => {
+                let id = TypeId::of::<D>();
+                id == TypeId::of::<SmallData>() || id == TypeId::of::<Vec<u8>>()
+            },
+            Payload::Boxed(ref b)   => b.is::<D>(),
+        }
+    }
+
+    /// Take the data as given type.
+    fn take<D: Data>(self) -> Option<D> {
+        match self {
+            Payload::Bytes(bytes)   => {
+                let mut small = Some(bytes);
+                if let Some(data) = (&mut small as &mut dyn Any).downcast_mut::<Option<D>>() {
+                    return data.take();
+                }
+                let mut vec = small.map(SmallData::into_vec);
+                (&mut vec as &mut dyn Any).downcast_mut::<Option<D>>().and_then(Option::take)
+            },
+            Payload::Boxed(b)       => b.downcast().ok().map(|data| *data),
+        }
+    }
+
+    /// Bytes of the payload, if the data is bytes or a string.
+    fn bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Payload::Bytes(ref bytes)   => Some(bytes),
+            Payload::Boxed(ref b)       => b.downcast_ref::<String>().map(String::as_bytes),
+        }
+    }
+
+    /// Size of the data in memory.
+    fn size(&self) -> usize {
+        match *self {
+            Payload::Bytes(ref bytes)   => bytes.len(),
+            Payload::Boxed(ref b)       => mem::size_of_val(&**b),
+        }
+    }
+}
+
+/// Channel between two objects. Sender waits until the receiver takes
+/// the message, as the 'Socket' contract wants.
+#[derive(Default)]
+struct Channel {
+    id      : u64,
+    state   : Mutex<ChannelState>,
+    cond    : Condvar,
+
+    /// Objects at the requester and provider ends.
+    ends    : [u64; 2],
+
+    /// Service the channel was opened for.
+    service : String,
+
+    /// Protocol version negotiated at connect.
+    version : u32,
+
+    /// Messages buffered in each direction. Zero for the channels where
+    /// send waits for the peer to receive.
+    capacity : usize,
+
+    /// Rings to each end, used instead of the queues in the
+    /// state by the buffered channels up to 'RING_LIMIT'.
+    rings   : Option<[Lane; 2]>,
+
+    /// Mirror of 'ChannelState::closed' that is read without the lock.
+    closed  : AtomicBool,
+
+    /// Somebody is about to wait on the channel. Operations that skip
+    /// the lock take it to wake the waiters only when this is set.
+    waiting : AtomicBool,
+
+    /// Batchers of the receivers at each end, for the buffered channels
+    /// of the services that coalesce the wakeups.
+    batchers : Option<[Batcher; 2]>,
+
+    /// Manifests the ends gave at connect.
+    manifests : [Option<Manifest>; 2],
+
+    /// Numbers of the last messages put to and taken by each end.
+    /// Messages that are withdrawn leave gaps between the numbers.
+    sequence : [AtomicU64; 2],
+    received : [AtomicU64; 2],
+
+    /// Whether each end wants to hear about the gaps, and the number of
+    /// the message which gap was reported to it last.
+    gaps    : [AtomicBool; 2],
+    reported : [AtomicU64; 2],
+
+    /// Network and requester whose throttled connect is held until the
+    /// channel is closed.
+    throttled : Mutex<Option<(LocalNetwork, u64)>>,
+
+    /// Account of the queued bytes, shared with the network.
+    memory  : Option<Arc<MemoryAccount<u64, u64>>>,
+
+    /// Account of the traffic of the tenants, shared with the network,
+    /// and the tenants of the objects at each end.
+    account : Option<Arc<TenantAccount<String>>>,
+    tenants : [Option<String>; 2],
+
+    /// Affinity the ends asked for. The network has no domains, so it
+    /// is only kept to be reported back.
+    affinity : Mutex<Affinity>,
+}
+
+/// Ring to one end of the channel. Sockets may be shared by threads, so
+/// each handle of the ring is taken under its own mutex. The sender and
+/// the receiver still never wait for each other.
+struct Lane {
+    producer    : Mutex<Producer<Message>>,
+    consumer    : Mutex<Consumer<Message>>,
+}
+
+impl Lane {
+
+    fn new(capacity: usize) -> Self {
+        let (producer, consumer) = spsc::ring(capacity);
+        Lane {
+            producer    : Mutex::new(producer),
+            consumer    : Mutex::new(consumer),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.consumer.lock().unwrap().len()
+    }
+
+    fn push(&self, message: Message) -> Result<(), Message> {
+        self.producer.lock().unwrap().push(message)
+    }
+
+    fn pop_if<F: FnOnce(&Message) -> bool>(&self, accept: F) -> Option<Message> {
+        self.consumer.lock().unwrap().pop_if(accept)
+    }
+
+    fn for_each<F: FnMut(&Message)>(&self, f: F) {
+        self.consumer.lock().unwrap().for_each(f)
+    }
+
+    /// Take out all the messages.
+    fn drain<F: FnMut(Message)>(&self, mut f: F) {
+        let mut consumer = self.consumer.lock().unwrap();
+        while let Some(message) = consumer.pop() {
+            f(message);
+        }
+    }
+}
+
+#[derive(Default)]
+struct ChannelState {
+    closed      : bool,
+
+    /// Messages to each end of the channel, unless it has the rings.
+    queues      : [VecDeque<Message>; 2],
+
+    /// Count of the messages ever sent to and taken by each end. Only
+    /// the channels without the buffer rely on them.
+    sent        : [u64; 2],
+    taken       : [u64; 2],
+
+    /// Whether each end waits in send or in receive.
+    sending     : [bool; 2],
+    receiving   : [bool; 2],
+
+    /// Futures to wake on any change.
+    wakers      : Vec<Waker>,
+
+    /// Some end was split. Its halves may send and receive at the same
+    /// time, so waiting in the same operation as the peer is no lockup.
+    split       : bool,
+
+    /// Why the channel was closed.
+    reason      : CloseReason,
+}
+
+/// Why the channel was closed, for the errors of the operations on it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum CloseReason {
+
+    /// Either end closed it.
+    #[default]
+    Closed,
+
+    /// Capability that opened it was revoked.
+    Revoked,
+
+    /// Provider rejected the request as overloaded.
+    Overloaded,
+}
+
+impl ChannelState {
+
+    /// Error of the operations on the closed channel.
+    fn closed_err(&self) -> SocketErr {
+        match self.reason {
+            CloseReason::Closed     => SocketErr::ChannelClosed,
+            CloseReason::Revoked    => SocketErr::CapabilityRevoked,
+            CloseReason::Overloaded => SocketErr::ProviderOverloaded,
+        }
+    }
+}
+
+impl Channel {
+
+    fn lock(&self) -> MutexGuard<'_, ChannelState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Count of the messages queued to given end.
+    fn queued(&self, state: &ChannelState, side: usize) -> usize {
+        match self.rings {
+            Some(ref rings) => rings[side].len(),
+            None            => state.queues[side].len(),
+        }
+    }
+
+    /// Whether the end waits in send and its message is not taken yet.
+    fn waits_to_send(&self, state: &ChannelState, side: usize) -> bool {
+        state.sending[side] && self.queued(state, 1 - side) > 0
+    }
+
+    /// Whether the end waits in receive and has nothing to take.
+    fn waits_to_receive(&self, state: &ChannelState, side: usize) -> bool {
+        state.receiving[side] && self.queued(state, side) == 0
+    }
+
+    /// Whether the message to given end can be put without waiting.
+    fn has_room(&self, state: &ChannelState, side: usize) -> bool {
+        match self.capacity {
+            0           => self.waits_to_receive(state, side),
+            capacity    => self.queued(state, side) < capacity,
+        }
+    }
+
+    /// Put the message to the buffer of given end. The message is given
+    /// back if the buffer is full, which can happen after 'has_room' only
+    /// when other threads send over the same end without the lock.
+    fn enqueue(&self, state: &mut ChannelState, side: usize, mut message: Message)
+        -> Result<(), Message>
+    {
+        self.number(side, &mut message);
+        match self.rings {
+            Some(ref rings) => rings[side].push(message)?,
+            None            => state.queues[side].push_back(message),
+        }
+        state.sent[side] += 1;
+        self.delivered(state, side);
+        Ok(())
+    }
+
+    /// Give the message the next number in the direction to given end,
+    /// unless it already has one.
+    fn number(&self, side: usize, message: &mut Message) {
+        if message.seq == 0 {
+            message.seq = self.sequence[side].fetch_add(1, Ordering::SeqCst) + 1;
+        }
+    }
+
+    /// Check whether given end can take the message as the requested
+    /// type. The gap before the message is reported once, if the end
+    /// wants to hear about it.
+    fn check<D: Data>(&self, side: usize, message: &Message) -> Result<(), SocketErr> {
+        if !message.data.is::<D>() {
+            return Err(SocketErr::UnexpectedData);
+        }
+        let expected = self.received[side].load(Ordering::SeqCst) + 1;
+        if message.seq > expected && self.gaps[side].load(Ordering::SeqCst)
+                && self.reported[side].swap(message.seq, Ordering::SeqCst) != message.seq {
+            return Err(SocketErr::SequenceGap { expected, received: message.seq });
+        }
+        Ok(())
+    }
+
+    /// Account and tenant of the object at given end, if it has one.
+    fn tenant(&self, side: usize) -> Option<(&TenantAccount<String>, &String)> {
+        match (&self.account, &self.tenants[side]) {
+            (Some(account), Some(tenant))   => Some((account, tenant)),
+            _                               => None,
+        }
+    }
+
+    /// Count the message queued to given end against the memory caps
+    /// and the quota of the tenant that sends it.
+    fn charge(&self, side: usize, message: &Message) -> Result<(), SocketErr> {
+        let size = message.data.size() as u64;
+        if let Some(ref memory) = self.memory {
+            memory.enqueue(&self.id, &self.ends[side], size)?;
+        }
+        if let Some((account, tenant)) = self.tenant(1 - side) {
+            if let Err(e) = account.sent(tenant, size) {
+                self.release(side, size);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Release the memory of the bytes queued to given end.
+    fn release(&self, side: usize, size: u64) {
+        if let Some(ref memory) = self.memory {
+            memory.dequeue(&self.id, &self.ends[side], size);
+        }
+    }
+
+    /// Take back what was charged for the message that given end will
+    /// not take.
+    fn refund(&self, side: usize, message: &Message) {
+        let size = message.data.size() as u64;
+        self.release(side, size);
+        if let Some((account, tenant)) = self.tenant(1 - side) {
+            account.unsent(tenant, size);
+        }
+    }
+
+    /// Take out the data of the message that given end has taken.
+    fn taken<D: Data>(&self, side: usize, message: Message) -> Option<D> {
+        let size = message.data.size() as u64;
+        self.release(side, size);
+        if let Some((account, tenant)) = self.tenant(side) {
+            account.received(tenant, size);
+        }
+        self.received[side].store(message.seq, Ordering::SeqCst);
+        message.data.take()
+    }
+
+    /// Take the next message to given end if it is of the requested type.
+    fn dequeue<D: Data>(&self, state: &mut ChannelState, side: usize)
+        -> Result<Option<D>, SocketErr>
+    {
+        let mut refused = None;
+        let mut expected = |m: &Message| match self.check::<D>(side, m) {
+            Ok(())  => true,
+            Err(e)  => {
+                refused = Some(e);
+                false
+            },
+        };
+        let message = match self.rings {
+            Some(ref rings) => rings[side].pop_if(expected),
+            None            => match state.queues[side].front() {
+                Some(m) if expected(m)  => state.queues[side].pop_front(),
+                _                       => None,
+            },
+        };
+        match message {
+            Some(message)   => {
+                state.taken[side] += 1;
+                self.notify(state);
+                Ok(self.taken(side, message))
+            },
+            None            => refused.map_or(Ok(None), Err),
+        }
+    }
+
+    /// Mark that somebody is about to wait. Waiters call it under the
+    /// lock before they check what they wait for, so that the change
+    /// made without the lock is either seen or followed by the wake.
+    fn before_wait(&self) {
+        self.waiting.store(true, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+    }
+
+    /// Wake everybody who waits on the channel, except the receivers
+    /// that wait for the batch.
+    fn notify(&self, state: &mut ChannelState) {
+        self.cond.notify_all();
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Wake everybody, the receivers that wait for the batch too, as the
+    /// change is not the delivery, e.g. the channel got closed.
+    fn wake_all(&self, state: &mut ChannelState) {
+        self.notify(state);
+        self.interrupt_batches();
+    }
+
+    /// Make the receivers that wait for the batch return at once.
+    fn interrupt_batches(&self) {
+        if let Some(ref batchers) = self.batchers {
+            for batcher in batchers {
+                batcher.interrupt();
+            }
+        }
+    }
+
+    /// Tell that the message was put to the buffer of given end. Its
+    /// receiver is woken at once or, if the channel coalesces, once the
+    /// batch is complete. Futures are woken at once either way.
+    fn delivered(&self, state: &mut ChannelState, side: usize) {
+        match self.batchers {
+            Some(ref batchers)  => {
+                batchers[side].notify();
+                for waker in state.wakers.drain(..) {
+                    waker.wake();
+                }
+            },
+            None                => self.notify(state),
+        }
+    }
+
+    /// Tell that the message was put to the ring of given end without the
+    /// lock.
+    fn delivered_unlocked(&self, side: usize) {
+        if let Some(ref batchers) = self.batchers {
+            batchers[side].notify();
+        }
+        self.notify_unlocked();
+    }
+
+    /// Wake the waiters after the change made without the lock, if there
+    /// are any. Waiters that still have to wait mark it again.
+    fn notify_unlocked(&self) {
+        fence(Ordering::SeqCst);
+        if self.waiting.swap(false, Ordering::SeqCst) {
+            let mut state = self.lock();
+            self.notify(&mut state);
+        }
+    }
+
+    fn close(&self) {
+        {
+            let mut state = self.lock();
+            state.closed = true;
+            self.closed.store(true, Ordering::SeqCst);
+            for (side, queue) in state.queues.iter_mut().enumerate() {
+                for message in queue.drain(..) {
+                    self.refund(side, &message);
+                }
+            }
+            if let Some(ref rings) = self.rings {
+                for (side, ring) in rings.iter().enumerate() {
+                    ring.drain(|message| self.refund(side, &message));
+                }
+            }
+            if let Some(ref memory) = self.memory {
+                memory.close_channel(&self.id, &self.ends[REQUESTER]);
+            }
+            self.wake_all(&mut state);
+        }
+        if let Some((network, requester)) = self.throttled.lock().unwrap().take() {
+            network.inner.throttle.release(&requester, Operation::Connect);
+        }
+    }
+
+    /// Close the channel telling the ends why.
+    fn close_for(&self, reason: CloseReason) {
+        self.lock().reason = reason;
+        self.close();
+    }
+
+    fn is_open(&self) -> bool {
+        !self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Wake the waiters of the channel when the token is triggered. Err
+    /// if it is already cancelled.
+    fn on_cancel<'a>(self: &Arc<Self>, token: Option<&'a CancelToken>)
+        -> Result<Option<CancelGuard<'a>>, SocketErr>
+    {
+        let token = match token {
+            Some(token) => token,
+            None        => return Ok(None),
+        };
+        let channel = Arc::downgrade(self);
+        let guard = CancelGuard::new(token, move || {
+            if let Some(channel) = channel.upgrade() {
+                let _state = channel.lock();
+                channel.cond.notify_all();
+                channel.interrupt_batches();
+            }
+        });
+        guard.map(Some).ok_or(SocketErr::Cancelled)
+    }
+}
+
+/// End of the channel. Dropping either end closes the channel.
+pub struct LocalSocket {
+    channel     : Arc<Channel>,
+    side        : usize,
+
+    /// Object that holds this end.
+    owner       : LocalObject,
+    requester   : LocalObject,
+    service     : LocalService,
+}
+
+impl LocalSocket {
+
+    fn peer(&self) -> usize {
+        1 - self.side
+    }
+
+    /// Put the message to the queue of the peer on the channel without
+    /// the buffer. Returns its number.
+    fn push(&self, state: &mut ChannelState, mut message: Message) -> u64 {
+        let peer = self.peer();
+        self.channel.number(peer, &mut message);
+        state.queues[peer].push_back(message);
+        state.sent[peer] += 1;
+        self.channel.notify(state);
+        state.sent[peer]
+    }
+
+    /// Put the message to the ring of the peer without the lock. The
+    /// message is given back when the locked path has to decide, e.g.
+    /// because the ring is full.
+    fn push_unlocked(&self, mut message: Message) -> Result<(), Message> {
+        let rings = match self.channel.rings {
+            Some(ref rings) => rings,
+            None            => return Err(message),
+        };
+        if self.channel.closed.load(Ordering::SeqCst) || self.owner.is_suspended() {
+            return Err(message);
+        }
+        let peer = self.peer();
+        if self.channel.charge(peer, &message).is_err() {
+            return Err(message);
+        }
+        self.channel.number(peer, &mut message);
+        if let Err(back) = rings[peer].push(message) {
+            self.channel.refund(peer, &back);
+            return Err(back);
+        }
+        self.channel.delivered_unlocked(peer);
+        Ok(())
+    }
+
+    /// Take the next message from the ring without the lock. None when
+    /// the locked path has to decide, e.g. because the ring is empty.
+    fn take_unlocked<D: Data>(&self) -> Option<Result<D, SocketErr>> {
+        let ring = &self.channel.rings.as_ref()?[self.side];
+        if self.channel.closed.load(Ordering::SeqCst) || self.owner.is_suspended() {
+            return None;
+        }
+        let mut refused = None;
+        let message = ring.pop_if(|m| match self.channel.check::<D>(self.side, m) {
+            Ok(())  => true,
+            Err(e)  => {
+                refused = Some(e);
+                false
+            },
+        });
+        match message {
+            Some(message)   => {
+                self.channel.notify_unlocked();
+                self.channel.taken(self.side, message).map(Ok)
+            },
+            None            => refused.map(Err),
+        }
+    }
+
+    /// Take back the message with given number if the peer has not
+    /// taken it yet.
+    fn withdraw(&self, state: &mut ChannelState, number: u64) {
+        let peer = self.peer();
+        if state.taken[peer] < number {
+            let index = (number - state.taken[peer] - 1) as usize;
+            if let Some(message) = state.queues[peer].remove(index) {
+                self.channel.refund(peer, &message);
+                state.sent[peer] -= 1;
+            }
+        }
+    }
+
+    /// Take the next message if it is of the requested type. None if
+    /// there is nothing to take now.
+    fn take<D: Data>(&self, state: &mut ChannelState) -> Result<Option<D>, SocketErr> {
+        if state.closed {
+            return Err(state.closed_err());
+        }
+        if self.owner.is_suspended() {
+            return Ok(None);
+        }
+        if self.channel.queued(state, self.side) == 0 && self.owner.life().stopping {
+            return Err(SocketErr::ShuttingDown);
+        }
+        self.channel.dequeue(state, self.side)
+    }
+
+    /// Wait for the message to take until the deadline. Receivers of the
+    /// channels that coalesce wait for the whole batch.
+    fn wait_message<'a>(&'a self, state: MutexGuard<'a, ChannelState>,
+            deadline: Option<Instant>) -> MutexGuard<'a, ChannelState>
+    {
+        match self.channel.batchers {
+            Some(ref batchers)  => {
+                drop(state);
+                batchers[self.side].wait_until(deadline);
+                self.channel.lock()
+            },
+            None                => self.wait(state, deadline),
+        }
+    }
+
+    /// Wait for the notification until the deadline.
+    fn wait<'a>(&self, state: MutexGuard<'a, ChannelState>, deadline: Option<Instant>)
+        -> MutexGuard<'a, ChannelState>
+    {
+        match deadline {
+            Some(d) => self.channel.cond.wait_timeout(state,
+                    d.saturating_duration_since(Instant::now())).unwrap().0,
+            None    => self.channel.cond.wait(state).unwrap(),
+        }
+    }
+
+    /// Send the messages in order. On the channel without the buffer,
+    /// waits until the peer takes the last of them.
+    fn send_all<I>(&self, messages: I, cancel: Option<&CancelToken>) -> Result<(), SocketErr>
+        where I: IntoIterator<Item = Message>
+    {
+        let _guard = self.channel.on_cancel(cancel)?;
+        let mut messages = messages.into_iter();
+        let mut rest = None;
+        if self.channel.rings.is_some() && self.channel.is_open() {
+            for message in messages.by_ref() {
+                if let Err(back) = self.push_unlocked(message) {
+                    rest = Some(back);
+                    break;
+                }
+            }
+            if rest.is_none() {
+                return Ok(());
+            }
+        }
+        let messages = rest.into_iter().chain(messages);
+        let mut state = self.channel.cond.wait_while(self.channel.lock(),
+                |s| !s.closed && self.owner.is_suspended() && !is_cancelled(cancel)).unwrap();
+        if state.closed {
+            return Err(state.closed_err());
+        }
+        if is_cancelled(cancel) {
+            return Err(SocketErr::Cancelled);
+        }
+        if self.channel.capacity > 0 {
+            for message in messages {
+                state = self.send_buffered(state, message, cancel)?;
+            }
+            return Ok(());
+        }
+        let peer = self.peer();
+        if !state.split && self.channel.waits_to_send(&state, peer) {
+            return Err(SocketErr::Lockup);
+        }
+        let first = state.sent[peer] + 1;
+        let mut number = state.taken[peer];
+        for message in messages {
+            if let Err(e) = self.channel.charge(peer, &message) {
+                for n in (first..=number).rev() {
+                    self.withdraw(&mut state, n);
+                }
+                return Err(e);
+            }
+            number = self.push(&mut state, message);
+        }
+        state.sending[self.side] = true;
+        let mut state = self.channel.cond.wait_while(state,
+                |s| !s.closed && s.taken[peer] < number && !is_cancelled(cancel)).unwrap();
+        state.sending[self.side] = false;
+        if state.taken[peer] >= number {
+            Ok(())
+        } else if state.closed {
+            Err(state.closed_err())
+        } else {
+            for n in (first..=number).rev() {
+                self.withdraw(&mut state, n);
+            }
+            Err(SocketErr::Cancelled)
+        }
+    }
+
+    /// Send over the bounded channel, waiting only while its buffer is
+    /// full. Both ends waiting for room in full buffers is a lockup.
+    fn send_buffered<'a>(&self, mut state: MutexGuard<'a, ChannelState>, message: Message,
+            cancel: Option<&CancelToken>) -> Result<MutexGuard<'a, ChannelState>, SocketErr>
+    {
+        let peer = self.peer();
+        let channel = &self.channel;
+        if !channel.has_room(&state, peer) && !state.split && channel.waits_to_send(&state, peer) {
+            return Err(SocketErr::Lockup);
+        }
+        let mut message = message;
+        loop {
+            state.sending[self.side] = true;
+            state = channel.cond.wait_while(state, |s| {
+                channel.before_wait();
+                !s.closed && !channel.has_room(s, peer) && !is_cancelled(cancel)
+            }).unwrap();
+            state.sending[self.side] = false;
+            if state.closed {
+                return Err(state.closed_err());
+            }
+            if !channel.has_room(&state, peer) {
+                return Err(SocketErr::Cancelled);
+            }
+            channel.charge(peer, &message)?;
+            match channel.enqueue(&mut state, peer, message) {
+                Ok(())      => return Ok(state),
+                Err(back)   => {
+                    channel.refund(peer, &back);
+                    message = back;
+                },
+            }
+        }
+    }
+
+    /// Receive waiting until the deadline, or forever if there is none,
+    /// unless the token is triggered first.
+    fn receive_until<D: Data>(&self, deadline: Option<Instant>, cancel: Option<&CancelToken>)
+        -> Option<Result<D, SocketErr>>
+    {
+        let _guard = match self.channel.on_cancel(cancel) {
+            Ok(guard)   => guard,
+            Err(e)      => return Some(Err(e)),
+        };
+        if let Some(result) = self.take_unlocked() {
+            return Some(result);
+        }
+        let mut state = self.channel.lock();
+        self.channel.before_wait();
+        match self.take(&mut state) {
+            Ok(Some(data))  => return Some(Ok(data)),
+            Err(e)          => return Some(Err(e)),
+            Ok(None)        => (),
+        }
+        if !state.split && self.channel.waits_to_receive(&state, self.peer()) {
+            return Some(Err(SocketErr::Lockup));
+        }
+        state.receiving[self.side] = true;
+        self.channel.notify(&mut state);
+        let result = loop {
+            state = self.wait_message(state, deadline);
+            self.channel.before_wait();
+            match self.take(&mut state) {
+                Ok(Some(data))  => break Some(Ok(data)),
+                Err(e)          => break Some(Err(e)),
+                Ok(None)        => (),
+            }
+            if is_cancelled(cancel) {
+                break Some(Err(SocketErr::Cancelled));
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                break None;
+            }
+        };
+        state.receiving[self.side] = false;
+        result
+    }
+}
+
+impl Drop for LocalSocket {
+
+    fn drop(&mut self) {
+        self.channel.close();
+    }
+}
+
+impl Socket<LocalObject, LocalService> for LocalSocket {
+
+    type SendHalf = LocalSendHalf;
+    type ReceiveHalf = LocalReceiveHalf;
+
+    fn requester(&self) -> &LocalObject {
+        &self.requester
+    }
+
+    fn service(&self) -> &LocalService {
+        &self.service
+    }
+
+    fn receive<D: Data>(&self) -> Result<D, SocketErr> {
+        // Without the deadline the wait never times out.
+        self.receive_until(None, None).unwrap()
+    }
+
+    fn receive_now<D: Data>(&self) -> Result<Option<D>, SocketErr> {
+        if let Some(result) = self.take_unlocked() {
+            return result.map(Some);
+        }
+        let mut state = self.channel.lock();
+        self.take(&mut state)
+    }
+
+    fn wait_to_receive<D: Data, T: Time>(&self, time: T)
+            -> Option<Result<D, SocketErr>>
+    {
+        self.receive_until(Some(Instant::now() + duration(&time)), None)
+    }
+
+    fn send<D: Data>(&self, data: D) -> Result<(), SocketErr> {
+        self.send_all(Some(Message::new(data)), None)
+    }
+
+    /// The whole batch is queued at once and the sender waits only once,
+    /// or only while the buffer of the bounded channel is full.
+    fn send_vectored<D: Data>(&self, data: Vec<D>) -> Result<(), SocketErr> {
+        self.send_all(data.into_iter().map(Message::new), None)
+    }
+
+    /// Takes the rest of the batch under the same lock.
+    fn receive_batch<D: Data>(&self, max: usize) -> Result<Vec<D>, SocketErr> {
+        if max == 0 {
+            return Ok(Vec::new());
+        }
+        let mut batch = vec![self.receive()?];
+        let mut state = self.channel.lock();
+        while batch.len() < max {
+            match self.take(&mut state) {
+                Ok(Some(data))  => batch.push(data),
+                _               => break,
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Data is sent only if the peer already waits to receive it, or if
+    /// the buffer of the bounded channel has room.
+    fn send_now<D: Data>(&self, data: D) -> Result<Option<D>, SocketErr> {
+        let mut state = self.channel.lock();
+        if state.closed {
+            return Err(state.closed_err());
+        }
+        let peer = self.peer();
+        if self.owner.is_suspended() {
+            Ok(Some(data))
+        } else if !self.channel.has_room(&state, peer) {
+            if self.channel.capacity > 0 {
+                Err(SocketErr::Full)
+            } else {
+                Ok(Some(data))
+            }
+        } else {
+            let message = Message::new(data);
+            self.channel.charge(peer, &message)?;
+            if self.channel.capacity == 0 {
+                self.push(&mut state, message);
+                return Ok(None);
+            }
+            self.channel.enqueue(&mut state, peer, message).map(|()| None).map_err(|back| {
+                self.channel.refund(peer, &back);
+                SocketErr::Full
+            })
+        }
+    }
+
+    /// Waits until the peer waits to receive or the buffer has room, so
+    /// that following 'send_now' succeeds.
+    fn wait_to_send<T: Time>(&self, time: T) -> Option<Result<(), SocketErr>> {
+        let deadline = Instant::now() + duration(&time);
+        let peer = self.peer();
+        let mut state = self.channel.lock();
+        if !state.split && self.channel.waits_to_send(&state, peer) {
+            return Some(Err(SocketErr::Lockup));
+        }
+        loop {
+            self.channel.before_wait();
+            if state.closed {
+                return Some(Err(state.closed_err()));
+            }
+            if self.channel.has_room(&state, peer) {
+                return Some(Ok(()));
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            state = self.wait(state, Some(deadline));
+        }
+    }
+
+    fn close(self) {
+    }
+
+    /// Threads can't be stopped from outside, so the function always
+    /// runs to the end and is reported aborted if the channel got closed
+    /// meanwhile.
+    fn run_abortable(&self, run_fn: &dyn Fn()) -> AbortResult {
+        run_fn();
+        if self.is_opened() {
+            AbortResult::Finished
+        } else {
+            AbortResult::Aborted
+        }
+    }
+
+    fn check(self) -> Option<Self> {
+        if self.is_opened() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    fn is_opened(&self) -> bool {
+        self.channel.is_open()
+    }
+
+    fn capacity(&self) -> usize {
+        self.channel.capacity
+    }
+
+    fn len(&self) -> usize {
+        self.channel.queued(&self.channel.lock(), self.peer())
+    }
+
+    fn version(&self) -> u32 {
+        self.channel.version
+    }
+
+    fn split(self) -> (LocalSendHalf, LocalReceiveHalf) {
+        self.channel.lock().split = true;
+        let socket = Arc::new(self);
+        (LocalSendHalf(socket.clone()), LocalReceiveHalf(socket))
+    }
+}
+
+impl SelectSocket<LocalObject, LocalService> for LocalSocket {
+
+    fn is_ready(&self, event: Event) -> bool {
+        let state = self.channel.lock();
+        match event {
+            Event::Closed   => state.closed,
+            Event::Readable => self.channel.queued(&state, self.side) > 0
+                    && !self.owner.is_suspended(),
+            Event::Writable => self.channel.has_room(&state, self.peer()),
+        }
+    }
+
+    fn wake_on_change(&self, waker: &Waker) {
+        let mut state = self.channel.lock();
+        self.channel.before_wait();
+        state.wakers.push(waker.clone());
+    }
+}
+
+/// Sending half of 'LocalSocket'.
+pub struct LocalSendHalf(Arc<LocalSocket>);
+
+impl SendHalf<LocalObject, LocalService> for LocalSendHalf {
+
+    type Socket = LocalSocket;
+
+    fn send<D: Data>(&self, data: D) -> Result<(), SocketErr> {
+        self.0.send(data)
+    }
+
+    fn send_now<D: Data>(&self, data: D) -> Result<Option<D>, SocketErr> {
+        self.0.send_now(data)
+    }
+
+    fn wait_to_send<T: Time>(&self, time: T) -> Option<Result<(), SocketErr>> {
+        self.0.wait_to_send(time)
+    }
+
+    fn is_opened(&self) -> bool {
+        self.0.is_opened()
+    }
+
+    fn reunite(self, receive: LocalReceiveHalf)
+        -> Result<LocalSocket, ReuniteErr<Self, LocalReceiveHalf>>
+    {
+        if !Arc::ptr_eq(&self.0, &receive.0) {
+            return Err(ReuniteErr(self, receive));
+        }
+        drop(receive);
+        Ok(Arc::into_inner(self.0).unwrap())
+    }
+}
+
+/// Receiving half of 'LocalSocket'.
+pub struct LocalReceiveHalf(Arc<LocalSocket>);
+
+impl ReceiveHalf<LocalObject, LocalService> for LocalReceiveHalf {
+
+    type Socket = LocalSocket;
+
+    fn receive<D: Data>(&self) -> Result<D, SocketErr> {
+        self.0.receive()
+    }
+
+    fn receive_now<D: Data>(&self) -> Result<Option<D>, SocketErr> {
+        self.0.receive_now()
+    }
+
+    fn wait_to_receive<D: Data, T: Time>(&self, time: T)
+        -> Option<Result<D, SocketErr>>
+    {
+        self.0.wait_to_receive(time)
+    }
+
+    fn is_opened(&self) -> bool {
+        self.0.is_opened()
+    }
+}
+
+/// Timer of the timed asynchronous operations.
+fn timer<T: Time>(time: T) -> Option<Pin<Box<ThreadSleep>>> {
+    Some(Box::pin(ThreadRuntime.sleep(time)))
+}
+
+/// Future that runs the operation on the first poll, unless the token is
+/// cancelled by then.
+fn run_async<T, E, F>(cancel: &CancelToken, operation: F)
+    -> impl Future<Output = Result<T, AsyncErr<E>>>
+    where F: FnOnce() -> Result<T, E>
+{
+    let cancel = cancel.clone();
+    let mut operation = Some(operation);
+    future::poll_fn(move |_| {
+        if cancel.is_cancelled() {
+            return Poll::Ready(Err(AsyncErr::Cancelled));
+        }
+        let operation = operation.take().expect("polled after completion");
+        Poll::Ready(operation().map_err(AsyncErr::Failed))
+    })
+}
+
+/// Pending receive of the local socket. Resolves with None on timeout.
+struct Receiving<'a, D> {
+    socket  : &'a LocalSocket,
+    cancel  : CancelWaker,
+    timer   : Option<Pin<Box<ThreadSleep>>>,
+
+    /// Whether the end is marked as waiting in receive.
+    waiting : bool,
+    _data   : PhantomData<fn() -> D>,
+}
+
+impl<'a, D: Data> Future for Receiving<'a, D> {
+
+    type Output = Option<Result<D, AsyncErr<SocketErr>>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let socket = this.socket;
+        let mut state = socket.channel.lock();
+        socket.channel.before_wait();
+        let ready = match socket.take(&mut state) {
+            Ok(Some(data))  => Some(Some(Ok(data))),
+            Err(e)          => Some(Some(Err(AsyncErr::Failed(e)))),
+            Ok(None) if !this.cancel.register(cx.waker())
+                            => Some(Some(Err(AsyncErr::Cancelled))),
+            Ok(None) if this.timer.as_mut().is_some_and(|t| t.as_mut().poll(cx).is_ready())
+                            => Some(None),
+            Ok(None) if !this.waiting && !state.split
+                    && socket.channel.waits_to_receive(&state, socket.peer())
+                            => Some(Some(Err(AsyncErr::Failed(SocketErr::Lockup)))),
+            Ok(None)        => None,
+        };
+        match ready {
+            Some(out)   => {
+                if this.waiting {
+                    state.receiving[socket.side] = false;
+                    this.waiting = false;
+                }
+                Poll::Ready(out)
+            },
+            None        => {
+                if !this.waiting {
+                    this.waiting = true;
+                    state.receiving[socket.side] = true;
+                    socket.channel.notify(&mut state);
+                }
+                state.wakers.push(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl<'a, D> Drop for Receiving<'a, D> {
+
+    fn drop(&mut self) {
+        if self.waiting {
+            self.socket.channel.lock().receiving[self.socket.side] = false;
+        }
+    }
+}
+
+/// Pending send of the local socket.
+struct Sending<'a> {
+    socket  : &'a LocalSocket,
+    cancel  : CancelWaker,
+    message : Option<Message>,
+
+    /// Number of the message once it is put to the queue.
+    number  : Option<u64>,
+}
+
+impl<'a> Future for Sending<'a> {
+
+    type Output = Result<(), AsyncErr<SocketErr>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let socket = this.socket;
+        let peer = socket.peer();
+        let mut state = socket.channel.lock();
+        socket.channel.before_wait();
+        if state.closed {
+            this.number = None;
+            return Poll::Ready(Err(AsyncErr::Failed(state.closed_err())));
+        }
+        let number = match this.number {
+            Some(number)    => number,
+            None if socket.channel.capacity > 0 => {
+                if socket.channel.has_room(&state, peer) {
+                    let message = this.message.take().expect("polled after completion");
+                    if let Err(e) = socket.channel.charge(peer, &message) {
+                        return Poll::Ready(Err(AsyncErr::Failed(e)));
+                    }
+                    match socket.channel.enqueue(&mut state, peer, message) {
+                        Ok(())      => return Poll::Ready(Ok(())),
+                        Err(back)   => {
+                            socket.channel.refund(peer, &back);
+                            this.message = Some(back);
+                        },
+                    }
+                }
+                if !this.cancel.register(cx.waker()) {
+                    return Poll::Ready(Err(AsyncErr::Cancelled));
+                }
+                state.wakers.push(cx.waker().clone());
+                return Poll::Pending;
+            },
+            None            => {
+                if !state.split && socket.channel.waits_to_send(&state, peer) {
+                    return Poll::Ready(Err(AsyncErr::Failed(SocketErr::Lockup)));
+                }
+                let message = this.message.take().expect("polled after completion");
+                if let Err(e) = socket.channel.charge(peer, &message) {
+                    return Poll::Ready(Err(AsyncErr::Failed(e)));
+                }
+                state.sending[socket.side] = true;
+                *this.number.insert(socket.push(&mut state, message))
+            },
+        };
+        if state.taken[peer] >= number {
+            this.number = None;
+            state.sending[socket.side] = false;
+            return Poll::Ready(Ok(()));
+        }
+        if !this.cancel.register(cx.waker()) {
+            socket.withdraw(&mut state, number);
+            this.number = None;
+            state.sending[socket.side] = false;
+            return Poll::Ready(Err(AsyncErr::Cancelled));
+        }
+        state.wakers.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<'a> Drop for Sending<'a> {
+
+    fn drop(&mut self) {
+        if let Some(number) = self.number {
+            let mut state = self.socket.channel.lock();
+            self.socket.withdraw(&mut state, number);
+            state.sending[self.socket.side] = false;
+        }
+    }
+}
+
+impl AsyncSocket<LocalObject, LocalService> for LocalSocket {
+
+    fn receive_async<D: Data>(&self, cancel: &CancelToken)
+        -> impl Future<Output = Result<D, AsyncErr<SocketErr>>>
+    {
+        let mut receiving = Receiving {
+            socket  : self,
+            cancel  : CancelWaker::new(cancel.clone()),
+            timer   : None,
+            waiting : false,
+            _data   : PhantomData,
+        };
+        // Without the timer the receive never times out.
+        future::poll_fn(move |cx| Pin::new(&mut receiving).poll(cx).map(Option::unwrap))
+    }
+
+    fn send_async<D: Data>(&self, data: D, cancel: &CancelToken)
+        -> impl Future<Output = Result<(), AsyncErr<SocketErr>>>
+    {
+        Sending {
+            socket  : self,
+            cancel  : CancelWaker::new(cancel.clone()),
+            message : Some(Message::new(data)),
+            number  : None,
+        }
+    }
+
+    fn wait_to_receive_async<D: Data, T: Time>(&self, time: T, cancel: &CancelToken)
+        -> impl Future<Output = Option<Result<D, AsyncErr<SocketErr>>>>
+    {
+        Receiving {
+            socket  : self,
+            cancel  : CancelWaker::new(cancel.clone()),
+            timer   : timer(time),
+            waiting : false,
+            _data   : PhantomData,
+        }
+    }
+
+    fn wait_to_send_async<T: Time>(&self, time: T, cancel: &CancelToken)
+        -> impl Future<Output = Option<Result<(), AsyncErr<SocketErr>>>>
+    {
+        let mut cancel = CancelWaker::new(cancel.clone());
+        let mut timer = timer(time);
+        let mut first = true;
+        future::poll_fn(move |cx| {
+            let peer = self.peer();
+            let mut state = self.channel.lock();
+            self.channel.before_wait();
+            let ready = if state.closed {
+                Some(Some(Err(AsyncErr::Failed(state.closed_err()))))
+            } else if first && !state.split && self.channel.waits_to_send(&state, peer) {
+                Some(Some(Err(AsyncErr::Failed(SocketErr::Lockup))))
+            } else if self.channel.has_room(&state, peer) {
+                Some(Some(Ok(())))
+            } else if !cancel.register(cx.waker()) {
+                Some(Some(Err(AsyncErr::Cancelled)))
+            } else if timer.as_mut().is_some_and(|t| t.as_mut().poll(cx).is_ready()) {
+                Some(None)
+            } else {
+                None
+            };
+            first = false;
+            match ready {
+                Some(out)   => Poll::Ready(out),
+                None        => {
+                    state.wakers.push(cx.waker().clone());
+                    Poll::Pending
+                },
+            }
+        })
+    }
+}
+
+impl ManifestSocket<LocalObject, LocalService> for LocalSocket {
+
+    fn peer_manifest(&self) -> &Manifest {
+        self.channel.manifests[self.peer()].as_ref().unwrap_or(&manifest::EMPTY)
+    }
+}
+
+impl CancelSocket<LocalObject, LocalService> for LocalSocket {
+
+    fn receive_cancellable<D: Data>(&self, cancel: &CancelToken) -> Result<D, SocketErr> {
+        self.receive_until(None, Some(cancel)).unwrap()
+    }
+
+    fn send_cancellable<D: Data>(&self, data: D, cancel: &CancelToken)
+        -> Result<(), SocketErr>
+    {
+        self.send_all(Some(Message::new(data)), Some(cancel))
+    }
+}
+
+/// Pinning is accepted and ignored, as the network delivers messages
+/// on the threads of the objects.
+impl AffinitySocket<LocalObject, LocalService> for LocalSocket {
+
+    fn pin(&self, affinity: Affinity) -> Result<(), AffinityErr> {
+        *self.channel.affinity.lock().unwrap() = affinity;
+        Ok(())
+    }
+
+    fn affinity(&self) -> Affinity {
+        *self.channel.affinity.lock().unwrap()
+    }
+}
+
+impl ShedSocket<LocalObject, LocalService> for LocalSocket {
+
+    fn reject_overloaded(self) {
+        self.channel.close_for(CloseReason::Overloaded);
+    }
+}
+
+/// Messages are numbered when they are put into the channel, so the
+/// gaps are left by the sends that are withdrawn after the message is
+/// queued, e.g. cancelled ones.
+impl SequencedSocket<LocalObject, LocalService> for LocalSocket {
+
+    fn receive_sequenced<D: Data>(&self) -> Result<(u64, D), SocketErr> {
+        let data = self.receive()?;
+        Ok((self.channel.received[self.side].load(Ordering::SeqCst), data))
+    }
+
+    fn last_sequence(&self) -> Option<u64> {
+        match self.channel.received[self.side].load(Ordering::SeqCst) {
+            0   => None,
+            seq => Some(seq),
+        }
+    }
+
+    fn notify_gaps(&self, enabled: bool) {
+        self.channel.gaps[self.side].store(enabled, Ordering::SeqCst);
+    }
+}
+
+/// Object of the local network. Handles are cheap to clone and all of
+/// them refer to the same object. Any handle can be used as the owned
+/// one.
+#[derive(Clone)]
+pub struct LocalObject {
+    state   : Arc<ObjectState>,
+}
+
+struct ObjectState {
+    id          : u64,
+    network     : LocalNetwork,
+    internal    : OnceLock<LocalNetwork>,
+    life        : Mutex<Life>,
+
+    /// Whether the object is frozen. Written under the 'life' lock and
+    /// read without it by the operations on the channels.
+    frozen      : AtomicBool,
+
+    /// Key of the checkpoints of the object and the checkpoint it was
+    /// restored from.
+    checkpoint  : Option<String>,
+    restored    : Option<Checkpoint>,
+
+    /// Sandbox of the object. Set once its initial services are
+    /// registered.
+    sandbox     : OnceLock<SandboxProfile>,
+
+    /// What crosses the boundary of the internal network, and whether
+    /// it started with nothing.
+    grants      : Mutex<Grants>,
+    hardened    : AtomicBool,
+}
+
+#[derive(Default)]
+struct Life {
+    /// Whether the main thread is running.
+    running     : bool,
+
+    /// Whether the object was asked to shut down.
+    stopping    : bool,
+
+    /// Why the object died. None while it is alive.
+    exit        : Option<ExitReason>,
+
+    /// Hooks to call on the death.
+    monitors    : Vec<DeathHook>,
+
+    /// Names and registrations of the provided services.
+    services    : Vec<(String, u64)>,
+
+    /// Capabilities the object holds.
+    capabilities: Vec<Capability>,
+    channels    : Vec<Weak<Channel>>,
+
+    /// Handlers of the channels opened while the object was frozen,
+    /// started on thaw.
+    deferred    : Vec<Box<dyn FnOnce() + Send>>,
+}
+
+impl Life {
+
+    fn is_alive(&self) -> bool {
+        self.exit.is_none() && (self.running || !self.services.is_empty())
+    }
+}
+
+impl LocalObject {
+
+    fn new(id: u64, network: LocalNetwork, checkpoint: Option<String>,
+            restored: Option<Checkpoint>) -> Self
+    {
+        LocalObject {
+            state   : Arc::new(ObjectState {
+                id,
+                network,
+                internal    : OnceLock::new(),
+                life        : Mutex::new(Life::default()),
+                frozen      : AtomicBool::new(false),
+                checkpoint,
+                restored,
+                sandbox     : OnceLock::new(),
+                grants      : Mutex::new(Grants::ordinary()),
+                hardened    : AtomicBool::new(false),
+            }),
+        }
+    }
+
+    fn life(&self) -> MutexGuard<'_, Life> {
+        self.state.life.lock().unwrap()
+    }
+
+    fn is_host(&self) -> bool {
+        self.state.id == 0
+    }
+
+    /// Why the object died. None while it is alive.
+    pub fn exit_reason(&self) -> Option<ExitReason> {
+        self.life().exit.clone()
+    }
+
+    /// Whether the object or any of its parents is frozen.
+    fn is_suspended(&self) -> bool {
+        self.state.frozen.load(Ordering::SeqCst)
+                || self.state.network.owner().is_some_and(|o| o.is_suspended())
+    }
+
+    /// Whether the sandbox of the object, if any, allows connects to the
+    /// service.
+    fn may_connect(&self, service: &str) -> bool {
+        self.sandbox().is_none_or(|p| p.check_connect(service).is_ok())
+    }
+
+    /// Start the internal network with nothing crossing its boundary.
+    fn harden(&self) {
+        *self.state.grants.lock().unwrap() = Grants::deny_all();
+        self.state.hardened.store(true, Ordering::SeqCst);
+    }
+
+    /// Put the object into its own sandbox nested in that of the
+    /// parent. Without both, the object is not sandboxed.
+    fn confine(&self, parent: Option<&SandboxProfile>, own: Option<SandboxProfile>) {
+        let profile = match (parent, own) {
+            (Some(parent), Some(own))   => parent.nest(own),
+            (Some(parent), None)        => parent.clone(),
+            (None, Some(own))           => own,
+            (None, None)                => return,
+        };
+        let _ = self.state.sandbox.set(profile);
+    }
+
+    fn track(&self, channel: &Arc<Channel>) {
+        let mut life = self.life();
+        life.channels.retain(|c| c.strong_count() > 0);
+        life.channels.push(Arc::downgrade(channel));
+    }
+
+    /// Wake everybody who waits on the channels of the object and its
+    /// sub-objects.
+    fn wake_channels(&self) {
+        let channels = self.life().channels.clone();
+        for channel in channels.iter().filter_map(Weak::upgrade) {
+            let mut state = channel.lock();
+            channel.wake_all(&mut state);
+        }
+        if let Some(internal) = self.state.internal.get() {
+            for object in internal.objects() {
+                object.wake_channels();
+            }
+        }
+    }
+
+    /// Run the function in new thread of the object. Panic in the thread
+    /// kills the whole object.
+    fn run<F: FnOnce() + Send + 'static>(&self, main: bool, f: F) {
+        let object = self.clone();
+        thread::spawn(move || {
+            CURRENT.with(|c| *c.borrow_mut() = Some(object.clone()));
+            let result = ::std::panic::catch_unwind(AssertUnwindSafe(f));
+            CURRENT.with(|c| c.borrow_mut().take());
+            match result {
+                Err(ref payload) if !payload.is::<Finished>() => {
+                    object.die(ExitReason::Panicked(describe(&**payload, None)));
+                },
+                _ if main   => {
+                    object.life().running = false;
+                    object.retire();
+                },
+                _           => (),
+            }
+        });
+    }
+
+    /// Start the handler thread, or defer it until thaw if the object
+    /// is frozen.
+    fn start<F: FnOnce() + Send + 'static>(&self, f: F) {
+        {
+            let mut life = self.life();
+            if self.state.frozen.load(Ordering::SeqCst) {
+                life.deferred.push(Box::new(f));
+                return;
+            }
+        }
+        self.run(false, f);
+    }
+
+    /// Die with normal exit if the object has nothing more to do.
+    fn retire(&self) {
+        let idle = {
+            let life = self.life();
+            life.exit.is_none() && !life.is_alive()
+        };
+        if idle {
+            self.die(ExitReason::Normal);
+        }
+    }
+
+    /// Discontinue the services, close the channels and kill the
+    /// sub-objects. Returns false if the object is already dead.
+    fn die(&self, reason: ExitReason) -> bool {
+        if self.is_host() {
+            return false;
+        }
+        let (services, channels, monitors) = {
+            let mut life = self.life();
+            if life.exit.is_some() {
+                return false;
+            }
+            life.exit = Some(reason.clone());
+            (mem::take(&mut life.services), mem::take(&mut life.channels),
+                mem::take(&mut life.monitors))
+        };
+        for (service, registration) in services {
+            self.state.network.remove(&service, registration);
+        }
+        for channel in channels.iter().filter_map(Weak::upgrade) {
+            channel.close();
+        }
+        if let Some(internal) = self.state.internal.get() {
+            internal.kill_all();
+        }
+        self.state.network.forget(self.state.id);
+        if let (ExitReason::Normal, Some(key)) = (&reason, &self.state.checkpoint) {
+            self.state.network.inner.checkpoints.clear(key);
+        }
+        for hook in monitors {
+            hook(reason.clone());
+        }
+        true
+    }
+}
+
+impl Object<LocalService> for LocalObject {
+    type Id = u64;
+    type Owned = LocalObject;
+    type Network = LocalNetwork;
+
+    fn id(&self) -> u64 {
+        self.state.id
+    }
+
+    fn service_by_id(&self, id: &String) -> Option<LocalService> {
+        if self.life().services.iter().any(|(s, _)| s == id) {
+            Some(LocalService::by_id(id.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Panics if the current thread was not started by the network.
+    fn myself() -> LocalObject {
+        CURRENT.with(|c| c.borrow().clone())
+            .expect("'myself' called outside of the local objects")
+    }
+
+    fn decease(reason: ExitReason) -> ! {
+        if let Some(object) = CURRENT.with(|c| c.borrow().clone()) {
+            object.die(reason);
+        }
+        finish()
+    }
+
+    fn network(&self) -> &LocalNetwork {
+        &self.state.network
+    }
+}
+
+impl OwnedObject<LocalService> for LocalObject {
+    type OpenNetwork = LocalNetwork;
+
+    fn kill(self) -> Result<(), ObjectKillErr> {
+        if self.die(ExitReason::Killed) {
+            Ok(())
+        } else {
+            Err(ObjectKillErr::NotAlive)
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        self.is_host() || self.life().is_alive()
+    }
+
+    fn internal_network(&self) -> &LocalNetwork {
+        self.state.internal.get_or_init(|| LocalNetwork::inside(Arc::downgrade(&self.state)))
+    }
+
+    fn network(&self) -> &LocalNetwork {
+        &self.state.network
+    }
+
+    fn is_quiescent(&self) -> bool {
+        let open = self.life().channels.iter()
+            .filter_map(Weak::upgrade)
+            .any(|c| c.is_open());
+        !open && self.state.internal.get()
+            .is_none_or(|n| n.objects().iter().all(|o| o.is_quiescent()))
+    }
+
+    fn await_quiescent<T: Time>(&self, deadline: T) -> Result<(), QuiescenceErr> {
+        let deadline = Instant::now() + duration(&deadline);
+        loop {
+            if !self.is_alive() {
+                return Err(QuiescenceErr::NotAlive);
+            }
+            if self.is_quiescent() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(QuiescenceErr::Timeout);
+            }
+            thread::sleep(POLL);
+        }
+    }
+
+    fn freeze(&self) -> Result<(), FreezeErr> {
+        let life = self.life();
+        if life.exit.is_some() {
+            Err(FreezeErr::NotAlive)
+        } else if self.state.frozen.load(Ordering::SeqCst) {
+            Err(FreezeErr::Frozen)
+        } else {
+            self.state.frozen.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn thaw(&self) -> Result<(), FreezeErr> {
+        let deferred = {
+            let mut life = self.life();
+            if life.exit.is_some() {
+                return Err(FreezeErr::NotAlive);
+            } else if !self.state.frozen.load(Ordering::SeqCst) {
+                return Err(FreezeErr::NotFrozen);
+            }
+            self.state.frozen.store(false, Ordering::SeqCst);
+            mem::take(&mut life.deferred)
+        };
+        for handler in deferred {
+            self.run(false, handler);
+        }
+        self.wake_channels();
+        Ok(())
+    }
+
+    fn is_frozen(&self) -> bool {
+        self.state.frozen.load(Ordering::SeqCst)
+    }
+
+    fn request_shutdown<T: Time>(self, deadline: T) -> Result<Termination, ObjectKillErr> {
+        {
+            let mut life = self.life();
+            if self.is_host() || !life.is_alive() {
+                return Err(ObjectKillErr::NotAlive);
+            }
+            life.stopping = true;
+        }
+        self.wake_channels();
+        let network = &self.state.network;
+        let timed_out = network.inner.changed.wait_timeout_while(network.lock(),
+                duration(&deadline), |s| s.objects.contains_key(&self.state.id)).unwrap()
+                .1.timed_out();
+        if !timed_out || !self.die(ExitReason::Killed) {
+            Ok(Termination::Graceful)
+        } else {
+            Ok(Termination::Forced)
+        }
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.life().stopping
+    }
+}
+
+impl CapabilityHolder for LocalObject {
+
+    fn grant(&self, issuer: &Issuer, capability: Capability) -> Result<(), GrantErr> {
+        if !issuer.minted(&capability) {
+            return Err(GrantErr::NotIssuer);
+        }
+        self.life().capabilities.push(capability);
+        Ok(())
+    }
+
+    fn delegate(&self, to: &LocalObject, capability: Capability) -> Result<(), GrantErr> {
+        if !self.life().capabilities.iter().any(|c| capability.descends_from(c.id())) {
+            return Err(GrantErr::NotHeld);
+        }
+        to.life().capabilities.push(capability);
+        Ok(())
+    }
+
+    fn take_away(&self, id: u64) {
+        self.life().capabilities.retain(|c| c.id() != id);
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        self.life().capabilities.clone()
+    }
+}
+
+/// Grants are checked on each connect from the internal network to the
+/// networks outside, and when the external network looks for the
+/// services it does not have itself.
+impl HardenedObject<LocalService> for LocalObject {
+
+    fn is_hardened(&self) -> bool {
+        self.state.hardened.load(Ordering::SeqCst)
+    }
+
+    fn grants(&self) -> Grants {
+        self.state.grants.lock().unwrap().clone()
+    }
+
+    fn grant_connect(&self, pattern: &str) {
+        self.state.grants.lock().unwrap().connect.push(Pattern(pattern.to_string()));
+    }
+
+    fn grant_visible(&self, pattern: &str) {
+        self.state.grants.lock().unwrap().visible.push(Pattern(pattern.to_string()));
+        self.state.network.notify(&mut self.state.network.lock());
+    }
+
+    fn reset_grants(&self) {
+        *self.state.grants.lock().unwrap() = Grants::deny_all();
+    }
+}
+
+/// The catch in the thread of the object would end it as well, but
+/// only once unwound, and never in the programs that abort on panic.
+impl PanickingObject<LocalService> for LocalObject {
+
+    fn panicked(reason: ExitReason) -> bool {
+        match CURRENT.with(|c| c.borrow().clone()) {
+            Some(object) if !object.is_host() => {
+                object.die(reason);
+                true
+            },
+            _   => false,
+        }
+    }
+}
+
+impl Sandboxed<LocalService> for LocalObject {
+
+    fn sandbox(&self) -> Option<&SandboxProfile> {
+        self.state.sandbox.get()
+    }
+}
+
+/// Checkpoints are kept by the network the object was spawned into,
+/// under the key from its 'SpawnSpec'. Normal exit of the object clears
+/// them.
+impl Checkpointed<LocalService> for LocalObject {
+
+    fn save_checkpoint(&self, state: &[u8]) -> Result<u64, CheckpointErr> {
+        let key = self.state.checkpoint.as_ref().ok_or(CheckpointErr::NoStore)?;
+        let store = &self.state.network.inner.checkpoints;
+        let seq = store.last(key).map_or(1, |c| c.seq + 1);
+        store.store(key, Checkpoint { seq, state: state.to_vec() })?;
+        Ok(seq)
+    }
+
+    fn restored_checkpoint(&self) -> Option<&Checkpoint> {
+        self.state.restored.as_ref()
+    }
+}
+
+/// Local network. Handles are cheap to clone and all of them refer to
+/// the same network.
+#[derive(Clone)]
+pub struct LocalNetwork {
+    inner   : Arc<NetworkInner>,
+}
+
+struct NetworkInner {
+    /// Object which internal network this is. Dangling for the top
+    /// network.
+    owner       : Weak<ObjectState>,
+
+    /// Host object while somebody holds it.
+    host        : Mutex<Weak<ObjectState>>,
+
+    /// Registrations of each service.
+    registry    : ShardedRegistry<String, u64>,
+    registrations : Registrations,
+    state       : Mutex<NetworkState>,
+    changed     : Condvar,
+
+    /// Counter to spread connects among the providers.
+    next        : AtomicUsize,
+
+    /// Publish/subscribe topics.
+    topics      : Hub,
+
+    /// Limits of the registrations and connects of the objects.
+    throttle    : Throttle<u64>,
+
+    /// Issuer of the capabilities this network accepts.
+    issuer      : Issuer,
+
+    /// Revoked and spent capabilities, and the channels they opened.
+    revocations : Revocations<Weak<Channel>>,
+
+    /// Rules checked on each register and connect.
+    policy      : CachedPolicy<MemoryDecisionCache>,
+
+    /// Capabilities each object had for the service when the policy was
+    /// last asked about them. Cached decisions about the object are
+    /// dropped when these change.
+    evaluated   : Mutex<HashMap<(u64, String), Vec<String>>>,
+
+    /// Bytes queued in the channels, by channel and by receiving object.
+    memory      : Arc<MemoryAccount<u64, u64>>,
+
+    /// Last checkpoints of the objects by their keys.
+    checkpoints : MemoryCheckpointStore<String>,
+
+    /// Traffic of the tenants and their objects.
+    tenants     : Arc<TenantAccount<String>>,
+
+    /// Registrations of the test doubles by the services they stub.
+    /// The doubles are kept with the registrations, but not in the
+    /// registry.
+    stubs       : StubTable<String, u64>,
+}
+
+#[derive(Default)]
+struct NetworkState {
+    objects         : HashMap<u64, LocalObject>,
+
+    /// Last changes of the registry. The first of them has cursor
+    /// 'first'.
+    history         : VecDeque<RegistryChange<String>>,
+    first           : u64,
+
+    /// Traffic splits of the services among their releases.
+    splits          : HashMap<String, TrafficSplit>,
+
+    /// Futures to wake on any change of the registry or the objects.
+    wakers          : Vec<Waker>,
+
+    /// Tenants of the objects.
+    tenants         : HashMap<u64, String>,
+}
+
+/// Registrations split into shards by the hash of the service
+/// identifier, each behind its own lock. All providers of the service
+/// are in the same shard, so the connect picks among them under one
+/// lock, and registrations and connects of different services rarely
+/// contend. The registry entry of the service is changed only under the
+/// lock of its shard.
+struct Registrations {
+    shards  : Vec<Mutex<HashMap<u64, Registration>>>,
+}
+
+/// Locked shard of the registrations.
+type Shard<'a> = MutexGuard<'a, HashMap<u64, Registration>>;
+
+impl Registrations {
+
+    fn new(shards: usize) -> Self {
+        Registrations {
+            shards  : (0..shards.max(1).next_power_of_two())
+                .map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    /// Lock the shard of the service.
+    fn shard(&self, service: &str) -> Shard<'_> {
+        let mut hasher = DefaultHasher::new();
+        service.hash(&mut hasher);
+        let index = hasher.finish() as usize & (self.shards.len() - 1);
+        self.shards[index].lock().unwrap()
+    }
+
+    /// Collect the items from all the registrations, locking the shards
+    /// one by one.
+    fn collect<T, I, F>(&self, mut f: F) -> Vec<T>
+        where   I   : IntoIterator<Item = T>,
+                F   : FnMut(&Registration) -> I,
+    {
+        let mut all = Vec::new();
+        for shard in &self.shards {
+            all.extend(shard.lock().unwrap().values().flat_map(&mut f));
+        }
+        all
+    }
+}
+
+struct Registration {
+    provider    : LocalObject,
+    release     : Option<String>,
+
+    /// Handler for direct calls, a 'DirectFn' of some types.
+    direct      : Option<Arc<dyn Any + Send + Sync>>,
+
+    /// Interval of the lease on the unique claim and when it expires.
+    lease       : Option<(Duration, Instant)>,
+    form        : LocalForm,
+    channels    : Vec<Weak<Channel>>,
+
+    /// Queue of the accept loop that serves the channels on some
+    /// runtime instead of the entry.
+    served      : Option<Served>,
+}
+
+/// Incoming queue of the served registration, closed when the
+/// registration is dropped so that its accept loop ends.
+struct Served(Arc<Incoming<LocalSocket>>);
+
+impl Drop for Served {
+
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+impl Default for LocalNetwork {
+
+    fn default() -> Self {
+        LocalNetwork::inside(Weak::new())
+    }
+}
+
+impl LocalNetwork {
+
+    /// Create empty network.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn inside(owner: Weak<ObjectState>) -> Self {
+        LocalNetwork {
+            inner   : Arc::new(NetworkInner {
+                owner,
+                host        : Mutex::new(Weak::new()),
+                registry    : ShardedRegistry::new(16),
+                registrations : Registrations::new(16),
+                state       : Mutex::new(NetworkState::default()),
+                changed     : Condvar::new(),
+                next        : AtomicUsize::new(0),
+                topics      : Hub::new(),
+                throttle    : Throttle::new(Limits::unlimited()),
+                issuer      : Issuer::new(),
+                revocations : Revocations::default(),
+                policy      : CachedPolicy::new(Policy::default(),
+                        MemoryDecisionCache::new(DECISIONS)),
+                evaluated   : Mutex::new(HashMap::new()),
+                memory      : Arc::new(MemoryAccount::new(MemoryCaps::default())),
+                checkpoints : MemoryCheckpointStore::new(CHECKPOINT_LIMIT),
+                tenants     : Arc::new(TenantAccount::new()),
+                stubs       : StubTable::default(),
+            }),
+        }
+    }
+
+    /// Start new object in the network. Its main thread runs given
+    /// function. After the function returns, the object stays alive
+    /// while it provides some services. The object is in the sandbox of
+    /// the current one. Panics if that sandbox does not allow spawns;
+    /// sandboxed objects should spawn with 'Spawner' instead.
+    pub fn spawn<F>(&self, main: F) -> LocalObject
+        where F: FnOnce() + Send + 'static
+    {
+        let parent = self.current();
+        if let Some(profile) = parent.sandbox() {
+            profile.check_spawn().expect("sandbox allows the spawn");
+        }
+        let object = self.create(None);
+        object.confine(parent.sandbox(), None);
+        object.run(true, main);
+        object
+    }
+
+    /// Whether the held capabilities include all the required ones that
+    /// may be used for the service.
+    fn permitted(&self, held: &[Capability], requires: &[String], service: &str,
+            now: SystemTime)
+        -> bool
+    {
+        self.authorizing(held, requires, service, now).is_some()
+    }
+
+    /// Held capabilities that satisfy the requirements, one per name.
+    fn authorizing(&self, held: &[Capability], requires: &[String], service: &str,
+            now: SystemTime)
+        -> Option<Vec<Capability>>
+    {
+        self.inner.revocations.authorizing(&self.inner.issuer, held, requires, service, now)
+    }
+
+    /// Whether the policy of the network allows the action of the object
+    /// on the service. Capabilities count if they may be used for the
+    /// service now.
+    fn policy_allows(&self, subject: &LocalObject, action: Action, service: &str) -> bool {
+        let now = SystemTime::now();
+        let capabilities: Vec<_> = subject.capabilities().iter()
+            .filter(|c| self.inner.issuer.minted(c))
+            .filter(|c| self.inner.revocations.check(c, service, now).is_ok())
+            .map(|c| c.name().to_string())
+            .collect();
+        let subject_name = subject.state.id.to_string();
+
+        // Grants, revocations and expiry all show up as the change of the
+        // usable capabilities. The lock is held until the decision is
+        // cached, so it is never made from the capabilities seen before.
+        let mut evaluated = self.inner.evaluated.lock().unwrap();
+        let key = (subject.state.id, service.to_string());
+        if evaluated.get(&key) != Some(&capabilities) {
+            self.inner.policy.capabilities_changed(&subject_name);
+            evaluated.insert(key, capabilities.clone());
+        }
+        let request = Request {
+            action,
+            subject         : &subject_name,
+            service,
+            capabilities    : &capabilities,
+        };
+        self.inner.policy.evaluate(&request) == Decision::Allow
+    }
+
+    /// Cap the bytes queued in the channels. Sends over the caps fail with
+    /// 'SocketErr::MemoryLimit'. Ignored unless called by the host or by
+    /// the owner of the internal network.
+    pub fn set_memory_caps(&self, caps: MemoryCaps) {
+        let current = self.current();
+        let owner = self.owner().map(|o| o.state.id);
+        if current.is_host() || owner == Some(current.state.id) {
+            self.inner.memory.set_caps(caps);
+        }
+    }
+
+    /// Count the traffic of the object against the tenant, in the
+    /// channels the object opens or serves from now on. Ignored unless
+    /// called by the host or by the owner of the internal network.
+    pub fn set_tenant(&self, object: &u64, tenant: String) {
+        let current = self.current();
+        let owner = self.owner().map(|o| o.state.id);
+        if current.is_host() || owner == Some(current.state.id) {
+            let mut state = self.lock();
+            if state.objects.contains_key(object) {
+                state.tenants.insert(*object, tenant);
+            }
+        }
+    }
+
+    /// Account of the traffic of the tenants, to set their quotas and
+    /// flush the usage to the meter. Only the host and the owner of the
+    /// internal network get it.
+    pub fn tenants(&self) -> Option<Arc<TenantAccount<String>>> {
+        let current = self.current();
+        let owner = self.owner().map(|o| o.state.id);
+        if current.is_host() || owner == Some(current.state.id) {
+            Some(self.inner.tenants.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Resources held by the object of this network.
+    fn usage_of(&self, object: &LocalObject) -> Usage {
+        let life = object.life();
+        Usage {
+            channels        : life.channels.iter().filter_map(Weak::upgrade)
+                    .filter(|c| c.is_open()).count() as u32,
+            services        : life.services.len() as u32,
+            queued_bytes    : self.inner.memory.object_bytes(&object.state.id),
+            children        : object.state.internal.get()
+                    .map_or(0, |internal| internal.lock().objects.len() as u32),
+        }
+    }
+
+    /// Issuer of the capabilities this network accepts. Only the host of
+    /// the top network and the owner of the internal one get it.
+    pub fn issuer(&self) -> Option<Issuer> {
+        let current = self.current();
+        let owner = self.owner().map(|o| o.state.id);
+        if (current.is_host() && owner.is_none()) || owner == Some(current.state.id) {
+            Some(self.inner.issuer.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Register the service which channels are served by the tasks of
+    /// the runtime instead of the threads of the current object. The
+    /// accept loop spawns the handler for the socket of each new
+    /// channel; entry and endpoints of the form are not run. The loop
+    /// ends when the service is discontinued.
+    pub fn serve<R, F, Fut>(&self, runtime: &R, reg_form: LocalForm, handler: F)
+        -> Result<LocalOwnedService, RegistrationErr>
+        where   R   : Runtime + Clone + Send + 'static,
+                F   : Fn(LocalSocket) -> Fut + Send + 'static,
+                Fut : Future<Output = ()> + Send + 'static
+    {
+        let incoming = Arc::new(Incoming::new());
+        let owned = self.add_for(self.current(), reg_form, false, None, None,
+                Some(Served(incoming.clone())))?;
+        spawn_accept_loop(runtime, incoming, handler);
+        Ok(owned)
+    }
+
+    /// Add the object whose main thread is about to start, restoring
+    /// the last checkpoint saved under the key.
+    fn create(&self, checkpoint: Option<String>) -> LocalObject {
+        let restored = checkpoint.as_ref().and_then(|k| self.inner.checkpoints.last(k));
+        let object = self.instance(checkpoint, restored);
+        object.life().running = true;
+        object
+    }
+
+    /// Add the object that starts from given checkpoint.
+    fn instance(&self, checkpoint: Option<String>, restored: Option<Checkpoint>) -> LocalObject {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let object = LocalObject::new(id, self.clone(), checkpoint, restored);
+        self.lock().objects.insert(id, object.clone());
+        object
+    }
+
+    fn lock(&self) -> MutexGuard<'_, NetworkState> {
+        self.inner.state.lock().unwrap()
+    }
+
+    fn owner(&self) -> Option<LocalObject> {
+        self.inner.owner.upgrade().map(|state| LocalObject { state })
+    }
+
+    /// Object of the current thread.
+    fn current(&self) -> LocalObject {
+        CURRENT.with(|c| c.borrow().clone()).unwrap_or_else(|| {
+            let mut host = self.inner.host.lock().unwrap();
+            match host.upgrade() {
+                Some(state) => LocalObject { state },
+                None        => {
+                    let object = LocalObject::new(0, self.clone(), None, None);
+                    *host = Arc::downgrade(&object.state);
+                    object
+                },
+            }
+        })
+    }
+
+    fn objects(&self) -> Vec<LocalObject> {
+        self.lock().objects.values().cloned().collect()
+    }
+
+    fn record(&self, state: &mut NetworkState, change: RegistryChange<String>) {
+        state.history.push_back(change);
+        if state.history.len() > HISTORY {
+            state.history.pop_front();
+            state.first += 1;
+        }
+        self.notify(state);
+    }
+
+    /// Wake everybody who waits for the change of the network, and of
+    /// the networks outside, where the services of this one may be
+    /// visible.
+    fn notify(&self, state: &mut NetworkState) {
+        self.inner.changed.notify_all();
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+        if let Some(owner) = self.owner() {
+            let outside = &owner.state.network;
+            outside.notify(&mut outside.lock());
+        }
+    }
+
+    /// Ready once the network is in the wanted state, otherwise wakes
+    /// the task on the next change.
+    fn poll_change<F>(&self, cx: &mut Context, ready: F) -> Poll<()>
+        where F: Fn(&NetworkState) -> bool
+    {
+        let mut state = self.lock();
+        if ready(&state) {
+            return Poll::Ready(());
+        }
+        if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    fn add(&self, form: LocalForm, unique: bool, release: Option<&str>, lease: Option<Duration>)
+        -> Result<LocalOwnedService, RegistrationErr>
+    {
+        self.add_for(self.current(), form, unique, release, lease, None)
+    }
+
+    /// Register the service provided by given object.
+    fn add_for(&self, provider: LocalObject, form: LocalForm, unique: bool,
+            release: Option<&str>, lease: Option<Duration>, served: Option<Served>)
+        -> Result<LocalOwnedService, RegistrationErr>
+    {
+        if let Some(profile) = provider.sandbox() {
+            profile.check_register().map_err(RegistrationErr::Sandboxed)?;
+        }
+        let action = if unique { Action::RegisterUnique } else { Action::Register };
+        if !self.policy_allows(&provider, action, &form.id) {
+            return Err(RegistrationErr::PermissionDenied);
+        }
+        let id = form.id.clone();
+        self.reclaim(&id)?;
+        let registration = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let owner = provider.state.id;
+        self.inner.throttle.admit(&owner, Operation::Register)
+            .map_err(RegistrationErr::Throttled)?;
+        let mut shard = self.inner.registrations.shard(&id);
+        if let Err(e) = self.inner.registry.register(id.clone(), registration, unique) {
+            self.inner.throttle.release(&owner, Operation::Register);
+            return Err(e);
+        }
+        provider.life().services.push((id.clone(), registration));
+        shard.insert(registration, Registration {
+            provider,
+            release     : release.map(str::to_string),
+            direct      : None,
+            lease       : lease.map(|interval| (interval, Instant::now() + interval)),
+            form,
+            channels    : Vec::new(),
+            served,
+        });
+        self.record(&mut self.lock(), RegistryChange::Registered { id: id.clone(), unique });
+        Ok(LocalOwnedService {
+            id,
+            registration    : Some((self.clone(), registration)),
+        })
+    }
+
+    fn remove(&self, service: &str, registration: u64) -> Option<Registration> {
+        let mut shard = self.inner.registrations.shard(service);
+        self.unlink(&mut shard, registration)
+    }
+
+    /// Take the registration out of the network to put it into some
+    /// other one. Gives whether the service was registered uniquely.
+    fn take(&self, service: &str, registration: u64) -> Option<(Registration, bool)> {
+        let mut shard = self.inner.registrations.shard(service);
+        let unique = self.inner.registry.is_unique(&shard.get(&registration)?.form.id);
+        self.unlink(&mut shard, registration).map(|r| (r, unique))
+    }
+
+    /// Put the registration taken out of some network into this one for
+    /// the provider. Gives it back if this network refuses it.
+    fn put(&self, number: u64, mut registration: Registration, unique: bool,
+            provider: &LocalObject) -> Result<(), Box<Registration>>
+    {
+        let id = registration.form.id.clone();
+        let owner = provider.state.id;
+        if self.inner.throttle.admit(&owner, Operation::Register).is_err() {
+            return Err(Box::new(registration));
+        }
+        let mut shard = self.inner.registrations.shard(&id);
+        if self.inner.registry.register(id.clone(), number, unique).is_err() {
+            self.inner.throttle.release(&owner, Operation::Register);
+            return Err(Box::new(registration));
+        }
+        provider.life().services.push((id.clone(), number));
+        registration.provider = provider.clone();
+        registration.channels = Vec::new();
+        shard.insert(number, registration);
+        self.record(&mut self.lock(), RegistryChange::Registered { id, unique });
+        Ok(())
+    }
+
+    /// Move the registrations back from the new instance to the object
+    /// and discard the instance.
+    fn bring_back(&self, object: &LocalObject, moved: Moved) {
+        for (service, number) in moved.registrations {
+            let taken = moved.instance.state.network.take(&service, number);
+            if let Some((registration, unique)) = taken {
+                let _ = self.put(number, registration, unique, object);
+            }
+        }
+        moved.instance.die(ExitReason::Killed);
+    }
+
+    /// Remove the registration from the locked shard of its service.
+    fn unlink(&self, shard: &mut Shard, registration: u64) -> Option<Registration> {
+        let removed = shard.remove(&registration)?;
+        self.inner.throttle.release(&removed.provider.state.id, Operation::Register);
+        let id = removed.form.id.clone();
+        self.inner.registry.unregister(&id, &registration);
+        removed.provider.life().services.retain(|&(_, r)| r != registration);
+        self.record(&mut self.lock(), RegistryChange::Discontinued { id });
+        Some(removed)
+    }
+
+    /// Take the unique claim on the service away from the provider
+    /// whose lease has expired. Fails if the lease is still held.
+    fn reclaim(&self, id: &String) -> Result<(), RegistrationErr> {
+        let expired = {
+            let mut shard = self.inner.registrations.shard(id);
+            let providers = self.inner.registry.providers(id);
+            let leased = providers.first()
+                .and_then(|r| shard[r].lease.map(|l| (*r, l)));
+            match leased {
+                Some((_, (_, expiry))) if expiry > Instant::now() => {
+                    return Err(RegistrationErr::LeaseHeld);
+                },
+                Some((registration, _)) => self.unlink(&mut shard, registration),
+                None                    => None,
+            }
+        };
+        if let Some(expired) = expired {
+            expired.provider.retire();
+        }
+        Ok(())
+    }
+
+    fn renew(&self, service: &str, registration: u64) -> Result<(), RegistrationErr> {
+        match self.inner.registrations.shard(service).get_mut(&registration) {
+            Some(r) => {
+                if let Some((interval, ref mut expiry)) = r.lease {
+                    *expiry = Instant::now() + interval;
+                }
+                Ok(())
+            },
+            None    => Err(RegistrationErr::LeaseHeld),
+        }
+    }
+
+    fn discontinue(&self, service: &str, registration: u64) -> Option<LocalForm> {
+        let removed = self.remove(service, registration)?;
+        removed.provider.retire();
+        Some(removed.form)
+    }
+
+    fn open_channels(&self, service: &str, registration: u64) -> usize {
+        self.inner.registrations.shard(service).get(&registration).map_or(0, |r| {
+            r.channels.iter().filter_map(Weak::upgrade).filter(|c| c.is_open()).count()
+        })
+    }
+
+    /// Open channels of all the registrations.
+    fn open_channels_all(&self) -> Vec<Arc<Channel>> {
+        self.inner.registrations.collect(|r| {
+            r.channels.iter().filter_map(Weak::upgrade).filter(|c| c.is_open()).collect::<Vec<_>>()
+        })
+    }
+
+    /// Forget the dead object.
+    fn forget(&self, id: u64) {
+        self.inner.throttle.forget(&id);
+        let mut state = self.lock();
+        state.objects.remove(&id);
+        state.tenants.remove(&id);
+        self.notify(&mut state);
+        drop(state);
+        self.inner.evaluated.lock().unwrap().retain(|&(object, _), _| object != id);
+    }
+
+    fn kill_all(&self) {
+        for object in self.objects() {
+            object.die(ExitReason::Killed);
+        }
+    }
+
+    /// Open the channel to the service and start its handler.
+    fn open(&self, service: LocalService, pick: Pick)
+        -> Result<LocalSocket, ConnectErr<LocalService>>
+    {
+        self.open_for(self.current(), service, pick)
+    }
+
+    /// Connect given object to the service.
+    fn open_for(&self, requester: LocalObject, service: LocalService, pick: Pick)
+        -> Result<LocalSocket, ConnectErr<LocalService>>
+    {
+        if !requester.may_connect(&service.id) {
+            return Err(ConnectErr::Sandboxed(service));
+        }
+        if !self.admits(&requester, &service.id)
+                || !self.policy_allows(&requester, Action::Connect, &service.id) {
+            return Err(ConnectErr::PermissionDenied(service));
+        }
+        let mut held = requester.capabilities();
+        held.extend(pick.capability.cloned());
+        if let Some(double) = self.inner.stubs.route(&service.id) {
+            let shard = self.inner.registrations.shard(&service.id);
+
+            // Double could be unstubbed since the route.
+            if shard.contains_key(&double) {
+                return self.open_chosen(shard, requester, &held, service, double, pick);
+            }
+        }
+        if !self.inner.registry.contains(&service.id) {
+            if let Some(internal) = self.exporter(&service.id) {
+                return internal.open_for(requester, service, pick);
+            }
+        }
+        let next = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        let release = self.lock().splits.get(&service.id)
+            .and_then(|s| s.choose(&requester.state.id.to_string(), (next % 100) as u32))
+            .map(str::to_string);
+        let shard = self.inner.registrations.shard(&service.id);
+        let chosen = {
+            let mut providers = self.inner.registry.providers(&service.id);
+            if providers.is_empty() {
+                return Err(ConnectErr::NotProvided(service));
+            }
+            let now = SystemTime::now();
+            providers.retain(|r| self.permitted(&held, &shard[r].form.requires, &service.id, now));
+            if providers.is_empty() {
+                return Err(ConnectErr::PermissionDenied(service));
+            }
+            if let Some(release) = release {
+                let released: Vec<_> = providers.iter().cloned()
+                    .filter(|r| shard[r].release.as_ref() == Some(&release))
+                    .collect();
+                if !released.is_empty() {
+                    providers = released;
+                }
+            }
+            if let Some(versions) = pick.versions {
+                providers.retain(|r| shard[r].form.version.negotiate(&versions)
+                    .is_some());
+                if providers.is_empty() {
+                    return Err(ConnectErr::NoCommonVersion(service));
+                }
+            }
+            if let Some(ours) = pick.manifest {
+                let theirs = |r: &u64| shard[r].form.manifest.as_ref();
+                let incompatible = providers.iter()
+                    .filter_map(&theirs)
+                    .find(|m| !ours.is_compatible(m))
+                    .cloned();
+                providers.retain(|r| theirs(r).is_none_or(|m| ours.is_compatible(m)));
+                if let (true, Some(theirs)) = (providers.is_empty(), incompatible) {
+                    return Err(ConnectErr::Incompatible {
+                        theirs  : Box::new(theirs),
+                        ours    : Box::new(ours.clone()),
+                    });
+                }
+            }
+            match pick.key {
+                Some(key)   => *providers.iter().cloned().collect::<HashRing<_>>()
+                    .get(key).expect("providers are not empty"),
+                None        => match pick.policy {
+                    ConnectPolicy::RoundRobin       => providers[next % providers.len()],
+                    ConnectPolicy::LeastConnections => *providers.iter()
+                        .min_by_key(|r| shard[r].channels.iter()
+                            .filter_map(Weak::upgrade).filter(|c| c.is_open()).count())
+                        .expect("providers are not empty"),
+                    ConnectPolicy::Random           => {
+                        let random = RandomState::new().build_hasher().finish() as usize;
+                        providers[random % providers.len()]
+                    },
+                    ConnectPolicy::PreferObject(id) => providers.iter().cloned()
+                        .find(|r| shard[r].provider.state.id == id)
+                        .unwrap_or(providers[next % providers.len()]),
+                },
+            }
+        };
+        self.open_chosen(shard, requester, &held, service, chosen, pick)
+    }
+
+    /// Whether the objects whose internal networks the requester is in
+    /// let its connect out to this network.
+    fn admits(&self, requester: &LocalObject, service: &str) -> bool {
+        let mut network = requester.state.network.clone();
+        while !Arc::ptr_eq(&network.inner, &self.inner) {
+            let owner = match network.owner() {
+                Some(owner) => owner,
+                None        => return true,
+            };
+            if !owner.grants().may_connect(service) {
+                return false;
+            }
+            network = owner.state.network.clone();
+        }
+        true
+    }
+
+    /// Internal network of the object that makes the service visible
+    /// here and provides it.
+    fn exporter(&self, service: &str) -> Option<LocalNetwork> {
+        Self::exporter_among(&self.objects(), service)
+    }
+
+    /// Whether the service is registered here or made visible from some
+    /// internal network, so that connect to it may succeed.
+    fn reachable(&self, state: &NetworkState, service: &String) -> bool {
+        self.inner.registry.contains(service)
+            || Self::exporter_among(state.objects.values(), service).is_some()
+    }
+
+    /// Same as 'exporter' but looks only at given objects, for the
+    /// callers that hold the lock.
+    fn exporter_among<'a, I>(objects: I, service: &str) -> Option<LocalNetwork>
+        where I: IntoIterator<Item = &'a LocalObject>
+    {
+        objects.into_iter()
+            .filter(|o| o.grants().is_visible(service))
+            .filter_map(|o| o.state.internal.get().cloned())
+            .find(|n| n.inner.registry.contains(&service.to_string()))
+    }
+
+    /// Open the channel to the chosen registration of the service. The
+    /// channel is bound to the capabilities that authorized it, which
+    /// are spent if single-use.
+    fn open_chosen(&self, mut shard: Shard, requester: LocalObject, held: &[Capability],
+            service: LocalService, chosen: u64, pick: Pick)
+        -> Result<LocalSocket, ConnectErr<LocalService>>
+    {
+        let (provider, entry, channel, served) = {
+            let registration = shard.get_mut(&chosen)
+                .expect("registry is updated together with registrations");
+            let version = pick.versions
+                .and_then(|v| registration.form.version.negotiate(&v))
+                .unwrap_or(0);
+            let capacity = pick.capacity.unwrap_or(registration.form.capacity);
+            let rings = match capacity {
+                0                       => None,
+                c if c <= RING_LIMIT    => Some([Lane::new(c), Lane::new(c)]),
+                _                       => None,
+            };
+            let batchers = match registration.form.coalesce {
+                Some(limits) if capacity > 0    => Some([limits.batcher(), limits.batcher()]),
+                _                               => None,
+            };
+            let tenants = {
+                let state = self.lock();
+                [state.tenants.get(&requester.state.id).cloned(),
+                    state.tenants.get(&registration.provider.state.id).cloned()]
+            };
+            let channel = Arc::new(Channel {
+                id          : NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                ends        : [requester.state.id, registration.provider.state.id],
+                service     : service.id.clone(),
+                version,
+                capacity,
+                rings,
+                batchers,
+                manifests   : [pick.manifest.cloned(), registration.form.manifest.clone()],
+                memory      : Some(self.inner.memory.clone()),
+                account     : Some(self.inner.tenants.clone()),
+                tenants,
+                ..Default::default()
+            });
+            let entry = match registration.form.dispatch(pick.endpoint) {
+                Some(entry) => entry,
+                None        => return Err(ConnectErr::NoEndpoint(service)),
+            };
+            let now = SystemTime::now();
+            let mut authorizing = self.authorizing(held, &registration.form.requires,
+                    &service.id, now).unwrap_or_default();
+            if let Some(capability) = pick.capability {
+                if authorizing.iter().all(|c| c.id() != capability.id()) {
+                    authorizing.push(capability.clone());
+                }
+            }
+            if !authorizing.is_empty() {
+                let revocations = &self.inner.revocations;
+                revocations.unbind(|c| c.upgrade().is_none_or(|c| !c.is_open()));
+                if revocations.bind_all(&authorizing, &service.id, now, Arc::downgrade(&channel))
+                        .is_err() {
+                    return Err(ConnectErr::PermissionDenied(service));
+                }
+            }
+            let requester_id = requester.state.id;
+            if let Err(e) = self.inner.throttle.admit(&requester_id, Operation::Connect) {
+                return Err(ConnectErr::Throttled(service, e));
+            }
+            *channel.throttled.lock().unwrap() = Some((self.clone(), requester_id));
+            registration.channels.retain(|c| c.strong_count() > 0);
+            registration.channels.push(Arc::downgrade(&channel));
+            let served = registration.served.as_ref().map(|s| s.0.clone());
+            (registration.provider.clone(), entry, channel, served)
+        };
+        drop(shard);
+        requester.track(&channel);
+        provider.track(&channel);
+        let socket = |side, owner: &LocalObject| LocalSocket {
+            channel     : channel.clone(),
+            side,
+            owner       : owner.clone(),
+            requester   : requester.clone(),
+            service     : service.clone(),
+        };
+        let theirs = socket(PROVIDER, &provider);
+        let ours = socket(REQUESTER, &requester);
+        match served {
+            // Closed queue drops the socket, which closes the channel.
+            Some(incoming)  => drop(incoming.push(theirs)),
+            None            => provider.start(move || entry(theirs)),
+        }
+        Ok(ours)
+    }
+}
+
+impl<'a> Default for Pick<'a> {
+
+    fn default() -> Self {
+        Pick {
+            endpoint    : None,
+            key         : None,
+            versions    : None,
+            capacity    : None,
+            policy      : ConnectPolicy::RoundRobin,
+            manifest    : None,
+            capability  : None,
+        }
+    }
+}
+
+/// How the connect picks the provider and its entry.
+struct Pick<'a> {
+    endpoint    : Option<&'a str>,
+
+    /// Partition key of the connect.
+    key         : Option<&'a [u8]>,
+
+    /// Versions of the requester. None skips the negotiation.
+    versions    : Option<Versions>,
+
+    /// Capacity asked by the requester instead of that of the provider.
+    capacity    : Option<usize>,
+
+    /// Choice among the providers when there is no partition key.
+    policy      : ConnectPolicy<u64>,
+
+    /// Manifest of the requester. None skips the comparison.
+    manifest    : Option<&'a Manifest>,
+
+    /// Capability the requester connects with besides those it holds.
+    capability  : Option<&'a Capability>,
+}
+
+impl Network<LocalService> for LocalNetwork {
+    type Object = LocalObject;
+
+    fn wait_for_service(&self, id: &String) {
+        let _state = self.inner.changed.wait_while(self.lock(),
+                |s| !self.reachable(s, id)).unwrap();
+    }
+
+    fn wait_for_death(&self, id: &u64) {
+        let _state = self.inner.changed.wait_while(self.lock(),
+                |s| s.objects.contains_key(id)).unwrap();
+    }
+
+    fn snapshot(&self) -> Snapshot<String> {
+        let state = self.lock();
+        let mut entries = Vec::new();
+        self.inner.registry.for_each(|id, providers, unique| {
+            entries.push(RegistryEntry {
+                id          : id.clone(),
+                providers   : providers.len(),
+                unique,
+            });
+        });
+        Snapshot {
+            entries,
+            cursor  : Cursor(state.first + state.history.len() as u64),
+        }
+    }
+
+    fn changes_since(&self, cursor: Cursor) -> Result<Delta<String>, CursorErr> {
+        let state = self.lock();
+        let end = state.first + state.history.len() as u64;
+        if cursor.0 < state.first {
+            Err(CursorErr::Expired)
+        } else if cursor.0 > end {
+            Err(CursorErr::Invalid)
+        } else {
+            Ok(Delta {
+                changes : state.history.iter()
+                    .skip((cursor.0 - state.first) as usize)
+                    .cloned()
+                    .collect(),
+                cursor  : Cursor(end),
+            })
+        }
+    }
+
+    fn service_info(&self, id: &String) -> Option<ServiceInfo<String>> {
+        let shard = self.inner.registrations.shard(id);
+        let providers = self.inner.registry.providers(id);
+        let form = &shard.get(providers.first()?)?.form;
+        Some(ServiceInfo {
+            id          : id.clone(),
+            providers   : providers.len(),
+            unique      : self.inner.registry.is_unique(id),
+            endpoints   : form.endpoints.iter().map(|e| e.0.to_string()).collect(),
+            version     : form.version,
+            capacity    : form.capacity,
+            metadata    : form.metadata.clone(),
+        })
+    }
+
+    fn features(&self) -> Features {
+        Features::new()
+            .with(Feature::Async)
+            .with(Feature::UniqueRegistration)
+            .with(Feature::Endpoints)
+            .with(Feature::ConnectTokens)
+            .with(Feature::PubSub)
+            .with(Feature::Policy)
+            .with(Feature::Capabilities)
+            .with(Feature::Timeouts)
+            .with(Feature::TrafficSplit)
+            .with(Feature::Partitioning)
+            .with(Feature::Spawning)
+            .with(Feature::Migration)
+            .with(Feature::Usage)
+            .with(Feature::Affinity)
+    }
+}
+
+impl AsyncNetwork<LocalService> for LocalNetwork {
+
+    fn wait_for_service_async(&self, id: &String) -> impl Future<Output = ()> {
+        let id = id.clone();
+        future::poll_fn(move |cx| self.poll_change(cx, |s| self.reachable(s, &id)))
+    }
+
+    fn wait_for_death_async(&self, id: &u64) -> impl Future<Output = ()> {
+        let id = *id;
+        future::poll_fn(move |cx| self.poll_change(cx, |s| !s.objects.contains_key(&id)))
+    }
+}
+
+impl OpenNetwork<LocalService> for LocalNetwork {
+    type Socket = LocalSocket;
+    type OwnedService = LocalOwnedService;
+
+    fn connect(&self, service: LocalService)
+        -> Result<LocalSocket, ConnectErr<LocalService>>
+    {
+        self.open(service, Pick::default())
+    }
+
+    fn register(&self, reg_form: LocalForm)
+        -> Result<LocalOwnedService, RegistrationErr>
+    {
+        self.add(reg_form, false, None, None)
+    }
+
+    fn register_unique(&self, reg_form: LocalForm)
+        -> Result<LocalOwnedService, RegistrationErr>
+    {
+        self.add(reg_form, true, None, None)
+    }
+
+    fn register_unique_leased<T: Time>(&self, reg_form: LocalForm, lease: T)
+        -> Result<LocalOwnedService, RegistrationErr>
+    {
+        self.add(reg_form, true, None, Some(duration(&lease)))
+    }
+
+    fn connect_versioned(&self, service: LocalService, versions: Versions)
+        -> Result<(LocalSocket, u32), ConnectErr<LocalService>>
+    {
+        let socket = self.open(service, Pick { versions: Some(versions), ..Default::default() })?;
+        let version = socket.channel.version;
+        Ok((socket, version))
+    }
+
+    fn connect_bounded(&self, service: LocalService, capacity: usize)
+        -> Result<LocalSocket, ConnectErr<LocalService>>
+    {
+        self.open(service, Pick { capacity: Some(capacity), ..Default::default() })
+    }
+
+    fn connect_with_policy(&self, service: LocalService, policy: ConnectPolicy<u64>)
+        -> Result<LocalSocket, ConnectErr<LocalService>>
+    {
+        self.open(service, Pick { policy, ..Default::default() })
+    }
+}
+
+/// Connects and registrations here never wait, so the futures complete
+/// on the first poll. They act for the object that created them, on
+/// whatever thread they are polled.
+impl AsyncOpenNetwork<LocalService> for LocalNetwork {
+
+    fn connect_async(&self, service: LocalService, cancel: &CancelToken)
+        -> impl Future<Output = Result<LocalSocket, AsyncErr<ConnectErr<LocalService>>>>
+    {
+        let requester = self.current();
+        run_async(cancel, move || self.open_for(requester, service, Pick::default()))
+    }
+
+    fn register_async(&self, reg_form: LocalForm, cancel: &CancelToken)
+        -> impl Future<Output = Result<LocalOwnedService, AsyncErr<RegistrationErr>>>
+    {
+        let provider = self.current();
+        run_async(cancel, move || self.add_for(provider, reg_form, false, None, None, None))
+    }
+
+    fn register_unique_async(&self, reg_form: LocalForm, cancel: &CancelToken)
+        -> impl Future<Output = Result<LocalOwnedService, AsyncErr<RegistrationErr>>>
+    {
+        let provider = self.current();
+        run_async(cancel, move || self.add_for(provider, reg_form, true, None, None, None))
+    }
+}
+
+impl ThrottledNetwork<LocalService> for LocalNetwork {
+
+    type ObjectId = u64;
+
+    /// Ignored unless called by the host or by the owner of the
+    /// internal network.
+    fn set_limits(&self, object: &u64, limits: Limits) {
+        let current = self.current();
+        let owner = self.owner().map(|o| o.state.id);
+        if current.is_host() || owner == Some(current.state.id) {
+            self.inner.throttle.set_limits(object, limits);
+        }
+    }
+
+    fn limits(&self, object: &u64) -> Limits {
+        self.inner.throttle.limits(object)
+    }
+}
+
+/// Bytes queued in the channels, by channel and by receiving object.
+impl MetricsSource for LocalNetwork {
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut families = self.inner.memory.collect();
+        families.extend(self.inner.tenants.collect());
+        families
+    }
+}
+
+/// Subject of the rules is the identifier of the object in decimal form.
+impl PolicyNetwork<LocalService> for LocalNetwork {
+
+    /// Ignored unless called by the host or by the owner of the
+    /// internal network.
+    fn set_policy(&self, policy: Policy) {
+        let current = self.current();
+        let owner = self.owner().map(|o| o.state.id);
+        if current.is_host() || owner == Some(current.state.id) {
+            self.inner.policy.set_policy(policy);
+        }
+    }
+
+    fn policy(&self) -> Policy {
+        self.inner.policy.policy()
+    }
+}
+
+/// Doubles are provided by the object that stubs the services. Connects
+/// with the tokens go to the registrations found at resolve and are not
+/// routed to the doubles.
+impl MockNetwork<LocalService> for LocalNetwork {
+
+    fn stub(&self, double: LocalForm) -> Result<(), RegistrationErr> {
+        let current = self.current();
+        let owner = self.owner().map(|o| o.state.id);
+        if !current.is_host() && owner != Some(current.state.id) {
+            return Err(RegistrationErr::PermissionDenied);
+        }
+        let id = double.id.clone();
+        let number = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let mut shard = self.inner.registrations.shard(&id);
+        shard.insert(number, Registration {
+            provider    : current,
+            release     : None,
+            direct      : None,
+            lease       : None,
+            form        : double,
+            channels    : Vec::new(),
+            served      : None,
+        });
+        if let Some(replaced) = self.inner.stubs.insert(id, number) {
+            shard.remove(&replaced);
+        }
+        Ok(())
+    }
+
+    fn unstub(&self, id: &String) -> bool {
+        let mut shard = self.inner.registrations.shard(id);
+        self.inner.stubs.remove(id).map(|number| shard.remove(&number)).is_some()
+    }
+
+    fn stub_hits(&self, id: &String) -> usize {
+        self.inner.stubs.hits(id)
+    }
+}
+
+impl AffinityNetwork<LocalService> for LocalNetwork {
+
+    fn domains(&self) -> Vec<Domain> {
+        Vec::new()
+    }
+}
+
+/// Children of the object are the objects of its internal network.
+impl UsageNetwork<LocalService> for LocalNetwork {
+    type ObjectId = u64;
+
+    fn usage(&self, object: &u64) -> Option<Usage> {
+        let object = self.lock().objects.get(object).cloned()?;
+        Some(self.usage_of(&object))
+    }
+
+    fn usages(&self) -> Vec<UsageReport<u64>> {
+        let mut reports: Vec<_> = self.objects().iter()
+            .map(|object| UsageReport { object: object.state.id, usage: self.usage_of(object) })
+            .collect();
+        reports.sort_by_key(|r| r.object);
+        reports
+    }
+}
+
+impl ManifestNetwork<LocalService> for LocalNetwork {
+
+    fn connect_with_manifest(&self, service: LocalService, ours: Manifest)
+        -> Result<LocalSocket, ConnectErr<LocalService>>
+    {
+        self.open(service, Pick { manifest: Some(&ours), ..Default::default() })
+    }
+}
+
+impl EndpointConnect<LocalService> for LocalNetwork {
+
+    fn connect_endpoint(&self, service: LocalService, endpoint: &str)
+        -> Result<LocalSocket, ConnectErr<LocalService>>
+    {
+        self.open(service, Pick { endpoint: Some(endpoint), ..Default::default() })
+    }
+}
+
+/// The capability counts as held by the requester for the connect and
+/// has to be minted by the issuer of this network.
+impl CapabilityNetwork<LocalService> for LocalNetwork {
+
+    fn connect_with_capability(&self, capability: &Capability, service: LocalService)
+        -> Result<LocalSocket, CapabilityErr>
+    {
+        if !self.inner.issuer.minted(capability) {
+            return Err(CapabilityErr::NotPermitted);
+        }
+        self.inner.revocations.check(capability, &service.id, SystemTime::now())?;
+        self.open(service, Pick { capability: Some(capability), ..Default::default() })
+            .map_err(|_| CapabilityErr::NotPermitted)
+    }
+
+    /// Only the holders of the issuer may revoke.
+    fn revoke(&self, capability: &Capability) -> Result<usize, RevokeErr> {
+        if !self.issuer().is_some_and(|issuer| issuer.minted(capability)) {
+            return Err(RevokeErr::NotIssuer);
+        }
+        let channels: Vec<_> = self.inner.revocations.revoke(capability.id()).iter()
+            .filter_map(Weak::upgrade)
+            .filter(|channel| channel.is_open())
+            .collect();
+        for channel in &channels {
+            channel.close_for(CloseReason::Revoked);
+        }
+        Ok(channels.len())
+    }
+}
+
+/// Service resolved to the registrations of its providers.
+pub struct LocalConnectToken {
+    service         : LocalService,
+
+    /// Registrations the requester may connect to, taken in turn.
+    registrations   : Vec<u64>,
+    next            : AtomicUsize,
+}
+
+/// Token keeps the registrations the service was resolved to, so
+/// connects by it neither ask the registry nor see providers registered
+/// after the resolve.
+impl TokenConnect<LocalService> for LocalNetwork {
+
+    type ConnectToken = LocalConnectToken;
+
+    fn resolve(&self, service: &LocalService) -> Option<LocalConnectToken> {
+        let current = self.current();
+        if !current.may_connect(&service.id) || !self.admits(&current, &service.id)
+                || !self.policy_allows(&current, Action::Connect, &service.id) {
+            return None;
+        }
+        let held = current.capabilities();
+        let shard = self.inner.registrations.shard(&service.id);
+        let now = SystemTime::now();
+        let registrations: Vec<_> = self.inner.registry.providers(&service.id).into_iter()
+            .filter(|r| self.permitted(&held, &shard[r].form.requires, &service.id, now))
+            .collect();
+        if registrations.is_empty() {
+            return None;
+        }
+        Some(LocalConnectToken {
+            service         : service.clone(),
+            registrations,
+            next            : AtomicUsize::new(0),
+        })
+    }
+
+    fn connect_with_token(&self, token: &LocalConnectToken)
+        -> Result<LocalSocket, ConnectTokenErr>
+    {
+        let requester = self.current();
+        if !requester.may_connect(&token.service.id)
+            || !self.admits(&requester, &token.service.id)
+        {
+            return Err(ConnectTokenErr::Declined);
+        }
+        let held = requester.capabilities();
+        let shard = self.inner.registrations.shard(&token.service.id);
+        let count = token.registrations.len();
+        let first = token.next.fetch_add(1, Ordering::Relaxed);
+        let chosen = (0..count)
+            .map(|i| token.registrations[(first + i) % count])
+            .find(|r| shard.contains_key(r))
+            .ok_or(ConnectTokenErr::Stale)?;
+        // Capabilities could have expired since the resolve.
+        let requires = &shard[&chosen].form.requires;
+        if !self.permitted(&held, requires, &token.service.id, SystemTime::now()) {
+            return Err(ConnectTokenErr::Declined);
+        }
+        self.open_chosen(shard, requester, &held, token.service.clone(), chosen,
+                Pick::default())
+            .map_err(|_| ConnectTokenErr::Declined)
+    }
+}
+
+impl DirectNetwork<LocalService> for LocalNetwork {
+
+    fn register_direct<Q, R, F>(&self, reg_form: LocalForm, handler: F)
+        -> Result<LocalOwnedService, RegistrationErr>
+        where   Q   : Data,
+                R   : Data,
+                F   : Fn(Q) -> R + Send + Sync + 'static
+    {
+        let owned = self.add(reg_form, false, None, None)?;
+        let registration = owned.registration.as_ref().map(|&(_, r)| r)
+            .expect("registered service has registration");
+        let handler: DirectFn<Q, R> = Arc::new(handler);
+        if let Some(r) = self.inner.registrations.shard(&owned.id).get_mut(&registration) {
+            r.direct = Some(Arc::new(handler));
+        }
+        Ok(owned)
+    }
+
+    /// All objects of the local network share the address space, so
+    /// any provider with the handler will do, as long as the current
+    /// object holds the capabilities the provider requires, as for
+    /// 'connect', and the provider is not frozen.
+    fn direct<Q: Data, R: Data>(&self, service: &LocalService) -> Option<DirectFn<Q, R>> {
+        let current = self.current();
+        if !current.may_connect(&service.id) || !self.admits(&current, &service.id)
+                || !self.policy_allows(&current, Action::Direct, &service.id) {
+            return None;
+        }
+        let held = current.capabilities();
+        let now = SystemTime::now();
+        let shard = self.inner.registrations.shard(&service.id);
+        let handlers: Vec<_> = self.inner.registry.providers(&service.id).iter()
+            .map(|r| &shard[r])
+            .filter(|r| !r.provider.is_suspended())
+            .filter_map(|r| {
+                let authorizing = self.authorizing(&held, &r.form.requires, &service.id, now)?;
+                let handler = r.direct.as_ref()?.downcast_ref::<DirectFn<Q, R>>()?;
+                Some((authorizing, handler))
+            })
+            .collect();
+        if handlers.is_empty() {
+            return None;
+        }
+        let next = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        let (authorizing, handler) = &handlers[next % handlers.len()];
+        self.inner.revocations.spend(authorizing, &service.id, now).ok()?;
+        Some((*handler).clone())
+    }
+}
+
+/// Debugging session of the local object.
+pub struct LocalDebugSession {
+    object  : LocalObject,
+}
+
+impl LocalDebugSession {
+
+    /// Open channel of the object with given identifier.
+    fn channel(&self, id: &u64) -> Result<Arc<Channel>, DebugErr> {
+        let channel = self.object.life().channels.iter()
+            .filter_map(Weak::upgrade)
+            .find(|c| c.id == *id)
+            .ok_or(DebugErr::NoChannel)?;
+        if channel.is_open() {
+            Ok(channel)
+        } else {
+            Err(DebugErr::ChannelClosed)
+        }
+    }
+}
+
+/// Describe the message. Only strings and bytes can be shown.
+fn queued(direction: Direction, message: &Message) -> QueuedMessage {
+    let bytes = message.data.bytes().map(<[u8]>::to_vec);
+    QueuedMessage {
+        direction,
+        size    : bytes.as_ref().map_or(message.data.size(), Vec::len),
+        bytes,
+    }
+}
+
+/// Host may debug any object, others need 'DEBUG_CAPABILITY' that
+/// permits the identifier of the object.
+impl DebugNetwork<LocalService> for LocalNetwork {
+    type ObjectId = u64;
+    type ChannelId = u64;
+    type Session = LocalDebugSession;
+
+    fn attach(&self, object: &u64) -> Result<LocalDebugSession, DebugErr> {
+        let caller = self.current();
+        if !caller.is_host() {
+            let (required, now) = ([DEBUG_CAPABILITY.to_string()], SystemTime::now());
+            let target = object.to_string();
+            let authorizing = self.authorizing(&caller.capabilities(), &required, &target, now)
+                .ok_or(DebugErr::Denied)?;
+            self.inner.revocations.spend(&authorizing, &target, now)
+                .map_err(|_| DebugErr::Denied)?;
+        }
+        let object = self.lock().objects.get(object).cloned().ok_or(DebugErr::NoObject)?;
+        if object.exit_reason().is_some() {
+            return Err(DebugErr::NoObject);
+        }
+        Ok(LocalDebugSession { object })
+    }
+
+    fn sockets(&self, session: &LocalDebugSession) -> Vec<SocketInfo<u64, String, u64>> {
+        let id = session.object.state.id;
+        let channels = session.object.life().channels.clone();
+        channels.iter().filter_map(Weak::upgrade).filter_map(|c| {
+            let state = c.lock();
+            if state.closed {
+                return None;
+            }
+            let side = if c.ends[REQUESTER] == id { REQUESTER } else { PROVIDER };
+            Some(SocketInfo {
+                channel     : c.id,
+                service     : c.service.clone(),
+                peer        : c.ends[1 - side],
+                role        : if side == REQUESTER { Role::Requester } else { Role::Provider },
+                queued      : c.queued(&state, REQUESTER) + c.queued(&state, PROVIDER),
+            })
+        }).collect()
+    }
+
+    fn peek(&self, session: &LocalDebugSession, channel: &u64, max: usize)
+        -> Result<Vec<QueuedMessage>, DebugErr>
+    {
+        let channel = session.channel(channel)?;
+        let state = channel.lock();
+        let mut messages = Vec::new();
+        for &(side, direction) in &[(PROVIDER, Direction::ToProvider),
+                (REQUESTER, Direction::ToRequester)] {
+            match channel.rings {
+                Some(ref rings) => rings[side].for_each(|m| messages.push(queued(direction, m))),
+                None            => messages.extend(state.queues[side].iter()
+                        .map(|m| queued(direction, m))),
+            }
+        }
+        messages.truncate(max);
+        Ok(messages)
+    }
+
+    /// The message goes after the ones already queued and does not wait
+    /// for the buffer to have room. Buffers kept in the rings can't hold
+    /// more than their capacity, so the message is refused as 'Full'.
+    fn inject<D: Data>(&self, session: &LocalDebugSession, channel: &u64,
+            direction: Direction, data: D) -> Result<(), DebugErr>
+    {
+        let channel = session.channel(channel)?;
+        let side = match direction {
+            Direction::ToProvider   => PROVIDER,
+            Direction::ToRequester  => REQUESTER,
+        };
+        let mut state = channel.lock();
+        if state.closed {
+            return Err(DebugErr::ChannelClosed);
+        }
+        let message = Message::new(data);
+        channel.charge(side, &message).map_err(|_| DebugErr::Full)?;
+        channel.enqueue(&mut state, side, message).map_err(|back| {
+            channel.refund(side, &back);
+            DebugErr::Full
+        })
+    }
+
+    fn force_close(&self, session: &LocalDebugSession, channel: &u64)
+        -> Result<(), DebugErr>
+    {
+        session.channel(channel)?.close();
+        Ok(())
+    }
+}
+
+/// Migration of the local object that is in progress.
+pub struct LocalMigration {
+    object  : LocalObject,
+
+    /// What the transfer moved. None until the transfer.
+    moved   : Mutex<Option<Moved>>,
+}
+
+/// New instance of the migrated object with its registrations.
+struct Moved {
+    instance        : LocalObject,
+
+    /// Services and numbers of the moved registrations.
+    registrations   : Vec<(String, u64)>,
+}
+
+/// Nodes are local networks, so the object can move e.g. into the
+/// internal network of another object. Channels are served by the
+/// threads of the old instance and can't be moved. They are closed when
+/// the old instance is killed on resume.
+impl MigratingNetwork<LocalService> for LocalNetwork {
+    type Node = LocalNetwork;
+    type Ticket = LocalMigration;
+
+    fn freeze_for_migration(&self, id: &u64) -> Result<LocalMigration, MigrationErr> {
+        let object = self.lock().objects.get(id).cloned().ok_or(MigrationErr::NoObject)?;
+        match object.freeze() {
+            Ok(())                  => Ok(LocalMigration {
+                object,
+                moved   : Mutex::new(None),
+            }),
+            Err(FreezeErr::Frozen)  => Err(MigrationErr::InProgress),
+            Err(_)                  => Err(MigrationErr::NoObject),
+        }
+    }
+
+    /// The new instance has no main thread. It lives while it provides
+    /// the moved services.
+    fn transfer(&self, ticket: &LocalMigration, plan: MigrationPlan<LocalNetwork>)
+        -> Result<(), MigrationErr>
+    {
+        if plan.channels {
+            return Err(MigrationErr::ChannelsNotMovable);
+        }
+        let mut moved = ticket.moved.lock().unwrap();
+        if moved.is_some() {
+            return Err(MigrationErr::InProgress);
+        }
+        let object = &ticket.object;
+        if object.exit_reason().is_some() {
+            return Err(MigrationErr::NoObject);
+        }
+        let instance = plan.target.instance(object.state.checkpoint.clone(), plan.state);
+        instance.state.frozen.store(true, Ordering::SeqCst);
+        instance.confine(object.sandbox(), None);
+        let services = object.life().services.clone();
+        let mut transferred = Moved { instance, registrations: Vec::new() };
+        for (service, number) in services {
+            let (registration, unique) = match self.take(&service, number) {
+                Some(taken) => taken,
+                None        => continue,
+            };
+            if let Err(registration) = plan.target.put(number, registration, unique,
+                    &transferred.instance) {
+                let _ = self.put(number, *registration, unique, object);
+                self.bring_back(object, transferred);
+                return Err(MigrationErr::Refused);
+            }
+            transferred.registrations.push((service, number));
+        }
+        *moved = Some(transferred);
+        Ok(())
+    }
+
+    fn resume(&self, ticket: LocalMigration) -> Result<(), MigrationErr> {
+        let moved = ticket.moved.lock().unwrap().take();
+        let moved = match moved {
+            Some(moved) => moved,
+            None        => {
+                self.abort(ticket);
+                return Err(MigrationErr::Refused);
+            },
+        };
+        let _ = moved.instance.thaw();
+        moved.instance.retire();
+        ticket.object.die(ExitReason::Killed);
+        Ok(())
+    }
+
+    fn abort(&self, ticket: LocalMigration) {
+        let moved = ticket.moved.lock().unwrap().take();
+        if let Some(moved) = moved {
+            self.bring_back(&ticket.object, moved);
+        }
+        let _ = ticket.object.thaw();
+    }
+}
+
+/// Only the channels of the registrations that are still in the
+/// network are listed.
+impl ReapableNetwork<LocalService> for LocalNetwork {
+    type ObjectId = u64;
+    type ChannelId = u64;
+
+    fn channels(&self) -> Vec<(u64, u64, u64)> {
+        self.open_channels_all().iter()
+            .map(|c| (c.id, c.ends[REQUESTER], c.ends[PROVIDER]))
+            .collect()
+    }
+
+    fn registrations(&self) -> Vec<(String, u64)> {
+        self.inner.registrations.collect(|r| Some((r.form.id.clone(), r.provider.state.id)))
+    }
+
+    fn reclaim_channel(&self, channel: &u64) {
+        let found = self.open_channels_all().into_iter().find(|c| c.id == *channel);
+        if let Some(channel) = found {
+            channel.close();
+        }
+    }
+
+    fn reclaim_registration(&self, service: &String, owner: &u64) {
+        let mut shard = self.inner.registrations.shard(service);
+        let found = shard.iter()
+            .find(|(_, r)| r.form.id == *service && r.provider.state.id == *owner)
+            .map(|(&registration, _)| registration);
+        if let Some(registration) = found {
+            self.unlink(&mut shard, registration);
+        }
+    }
+}
+
+/// Host object is always alive.
+impl LivenessSource<u64> for LocalNetwork {
+
+    fn is_alive(&self, id: &u64) -> bool {
+        *id == 0 || self.lock().objects.get(id).is_some_and(|o| o.life().exit.is_none())
+    }
+}
+
+impl Supervisor<LocalService> for LocalNetwork {
+
+    fn on_death(&self, object: &LocalObject, hook: DeathHook) {
+        let reason = {
+            let mut life = object.life();
+            match life.exit.clone() {
+                Some(reason)    => reason,
+                None            => return life.monitors.push(hook),
+            }
+        };
+        hook(reason);
+    }
+}
+
+impl Spawner<LocalService> for LocalNetwork {
+
+    /// Spawn the object that runs the function. Local network has no
+    /// program images.
+    fn spawn(&self, spec: SpawnSpec<LocalService, Self>) -> Result<LocalObject, SpawnErr> {
+        let main = match spec.program {
+            Program::Entry(main)    => main,
+            Program::Image(name)    => return Err(SpawnErr::UnknownImage(name)),
+        };
+        let parent = self.current();
+        if let Some(profile) = parent.sandbox() {
+            profile.check_spawn().map_err(SpawnErr::Sandboxed)?;
+            if !spec.services.is_empty() {
+                profile.check_register().map_err(SpawnErr::Sandboxed)?;
+            }
+        }
+        let network = match spec.placement {
+            Placement::External => self.clone(),
+            Placement::Internal => parent.internal_network().clone(),
+        };
+        let object = network.create(spec.checkpoint);
+        if spec.hardened {
+            object.harden();
+        }
+        for service in spec.services {
+            let added = network.add_for(object.clone(), service.form, service.unique, None, None,
+                    None);
+            if let Err(e) = added {
+                object.die(ExitReason::Killed);
+                return Err(e.into());
+            }
+        }
+        object.confine(parent.sandbox(), spec.sandbox);
+        object.run(true, main);
+        Ok(object)
+    }
+}
+
+impl CancelNetwork<LocalService> for LocalNetwork {
+
+    fn connect_cancellable(&self, service: LocalService, cancel: &CancelToken)
+        -> Result<LocalSocket, ConnectErr<LocalService>>
+    {
+        let inner = Arc::downgrade(&self.inner);
+        let _guard = match CancelGuard::new(cancel, move || {
+            if let Some(inner) = inner.upgrade() {
+                let _state = inner.state.lock();
+                inner.changed.notify_all();
+            }
+        }) {
+            Some(guard) => guard,
+            None        => return Err(ConnectErr::Cancelled(service)),
+        };
+        let provided = {
+            let state = self.inner.changed.wait_while(self.lock(),
+                    |s| !self.reachable(s, &service.id) && !cancel.is_cancelled())
+                    .unwrap();
+            self.reachable(&state, &service.id)
+        };
+        if !provided {
+            return Err(ConnectErr::Cancelled(service));
+        }
+        self.connect(service)
+    }
+}
+
+impl PartitionNetwork<LocalService> for LocalNetwork {
+
+    fn connect_partition(&self, service: LocalService, key: &[u8])
+        -> Result<LocalSocket, ConnectErr<LocalService>>
+    {
+        self.open(service, Pick { key: Some(key), ..Default::default() })
+    }
+}
+
+impl SplitNetwork<LocalService> for LocalNetwork {
+
+    fn register_release(&self, reg_form: LocalForm, release: &str)
+        -> Result<LocalOwnedService, RegistrationErr>
+    {
+        self.add(reg_form, false, Some(release), None)
+    }
+
+    fn set_split(&self, id: &String, split: TrafficSplit) {
+        self.lock().splits.insert(id.clone(), split);
+    }
+
+    fn clear_split(&self, id: &String) {
+        self.lock().splits.remove(id);
+    }
+}
+
+impl PubSubNetwork<LocalService> for LocalNetwork {
+
+    type Broadcast = HubBroadcast;
+    type Subscription = HubSubscription;
+
+    fn broadcast(&self, topic: &str) -> Result<HubBroadcast, PubSubErr> {
+        self.inner.topics.broadcast(topic)
+    }
+
+    fn subscribe(&self, topic: &str, buffer: BufferPolicy) -> HubSubscription {
+        self.inner.topics.subscribe(topic, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capability::Attenuation;
+    use info::Metadata;
+    use path::ServicePath;
+    use migration::MigrationPlan;
+    use reaper::{AuditSink, ReapEvent, ReapReport, Reaper};
+    use rpc::{serve_loop, Caller};
+    use sandbox::SandboxViolation;
+    use tenant::Quota;
+    use throttle::{Limit, ThrottleErr};
+
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    fn service(id: &str) -> LocalService {
+        LocalService::by_id(id.to_string())
+    }
+
+    fn echo(socket: LocalSocket) -> ! {
+        while let Ok(text) = socket.receive::<String>() {
+            if socket.send(text).is_err() {
+                break;
+            }
+        }
+        finish()
+    }
+
+    fn boom(_: LocalSocket) -> ! {
+        panic!("boom")
+    }
+
+    fn idle(_socket: LocalSocket) -> ! {
+        loop {
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn weak_service() {
+        let network = LocalNetwork::new();
+        let weak = LocalWeakService::by_id("echo".to_string());
+        assert_eq!(weak.id(), "echo");
+        assert_eq!(weak.upgrade(&network), None);
+
+        let first = network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let second = network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        assert_eq!(weak.provider_count(&network), 2);
+        assert_eq!(first.provider_count(), 2);
+        let pointer = weak.upgrade(&network).unwrap();
+        assert_eq!(pointer.id(), "echo");
+        // Neither the handle nor the pointer holds the service.
+        assert_eq!(first.reference_count() + second.reference_count(), 0);
+        let socket = network.connect(pointer).unwrap();
+        assert_eq!(first.reference_count() + second.reference_count(), 1);
+        socket.close();
+
+        first.discontinue();
+        assert_eq!(second.provider_count(), 1);
+        second.discontinue();
+        assert_eq!(weak.provider_count(&network), 0);
+        assert_eq!(weak.upgrade(&network), None);
+        // Other networks have their own providers.
+        let other = LocalNetwork::new();
+        other.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        assert!(weak.upgrade(&other).is_some());
+    }
+
+    /// Reply with the number of each message and the gap before it.
+    fn numbered(socket: LocalSocket) -> ! {
+        socket.notify_gaps(true);
+        let mut gap = String::new();
+        loop {
+            match socket.receive_sequenced::<String>() {
+                Ok((seq, s))    => {
+                    socket.send(format!("{}{} {}", gap, seq, s)).unwrap();
+                    gap.clear();
+                    // Give the requester time to cancel the next send.
+                    thread::sleep(Duration::from_millis(50));
+                },
+                Err(SocketErr::SequenceGap { expected, received }) => {
+                    gap = format!("gap {}..{}, ", expected, received);
+                },
+                Err(_)          => finish(),
+            }
+        }
+    }
+
+    #[test]
+    fn sequence_gap() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(numbered, "numbered".to_string())).unwrap();
+        let socket = network.connect(service("numbered")).unwrap();
+        assert_eq!(socket.last_sequence(), None);
+        socket.send("a".to_string()).unwrap();
+        assert_eq!(socket.receive_sequenced::<String>().unwrap(), (1, "1 a".to_string()));
+
+        // Provider sleeps, so the send is cancelled before it is taken.
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            canceller.cancel();
+        });
+        assert!(matches!(socket.send_cancellable("b".to_string(), &cancel),
+                Err(SocketErr::Cancelled)));
+        socket.send("c".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "gap 2..3, 3 c");
+        socket.send("d".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "4 d");
+        assert_eq!(socket.last_sequence(), Some(3));
+    }
+
+    #[test]
+    fn token_connect() {
+        let network = LocalNetwork::new();
+        let first = network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let token = network.resolve(&service("echo")).unwrap();
+        assert!(network.resolve(&service("none")).is_none());
+
+        // Provider registered after the resolve is not looked up.
+        let second = network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let sockets: Vec<_> = (0..4).map(|_| network.connect_with_token(&token).unwrap())
+            .collect();
+        sockets[0].send("hi".to_string()).unwrap();
+        assert_eq!(sockets[0].receive::<String>().unwrap(), "hi");
+        assert_eq!(first.reference_count(), 4);
+        assert_eq!(second.reference_count(), 0);
+
+        first.discontinue();
+        assert!(matches!(network.connect_with_token(&token), Err(ConnectTokenErr::Stale)));
+        assert!(network.connect(service("echo")).is_ok());
+    }
+
+    #[test]
+    fn echo_and_discontinue() {
+        let network = LocalNetwork::new();
+        let owned = network.register(RegistrationForm::new(echo, "echo".to_string()))
+            .unwrap();
+        let socket = network.connect(service("echo")).unwrap();
+        socket.send("hi".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "hi");
+        assert_eq!(owned.reference_count(), 1);
+        socket.close();
+
+        let cursor = network.snapshot().cursor;
+        assert!(owned.discontinue().is_some());
+        assert!(network.connect(service("echo")).is_err());
+        assert_eq!(network.changes_since(cursor).unwrap().changes,
+                vec![RegistryChange::Discontinued { id: "echo".to_string() }]);
+    }
+
+    #[test]
+    fn lockup_and_unexpected_data() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let socket = network.connect(service("echo")).unwrap();
+
+        // Both ends receiving would wait for each other forever.
+        assert!(socket.wait_to_send(Millis(1000)).unwrap().is_ok());
+        assert!(matches!(socket.receive::<String>(), Err(SocketErr::Lockup)));
+
+        assert!(socket.send_now("hi".to_string()).unwrap().is_none());
+        assert!(matches!(socket.receive::<Vec<u8>>(), Err(SocketErr::UnexpectedData)));
+        assert_eq!(socket.receive::<String>().unwrap(), "hi");
+    }
+
+    #[test]
+    fn split_and_reunite() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let (send, receive) = network.connect(service("echo")).unwrap().split();
+        let sender = thread::spawn(move || {
+            send.send("hi".to_string()).unwrap();
+            send
+        });
+        assert_eq!(receive.receive::<String>().unwrap(), "hi");
+        let send = sender.join().unwrap();
+
+        let (other_send, other_receive) = network.connect(service("echo")).unwrap().split();
+        let ReuniteErr(send, other_receive) = send.reunite(other_receive).err().unwrap();
+        assert!(other_send.reunite(other_receive).is_ok());
+        let socket = send.reunite(receive).ok().unwrap();
+        socket.send("again".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "again");
+    }
+
+    fn release_one(socket: LocalSocket) -> ! {
+        let _ = socket.send("1".to_string());
+        finish()
+    }
+
+    fn release_two(socket: LocalSocket) -> ! {
+        let _ = socket.send("2".to_string());
+        finish()
+    }
+
+    #[test]
+    fn traffic_split() {
+        let network = LocalNetwork::new();
+        network.register_release(RegistrationForm::new(release_one, "svc".to_string()), "1")
+            .unwrap();
+        network.register_release(RegistrationForm::new(release_two, "svc".to_string()), "2")
+            .unwrap();
+        let release = || network.connect(service("svc")).unwrap().receive::<String>().unwrap();
+
+        network.set_split(&"svc".to_string(), TrafficSplit::new().weight("2", 100));
+        assert!((0..4).all(|_| release() == "2"));
+
+        // Host object has identifier 0.
+        network.set_split(&"svc".to_string(), TrafficSplit::new().pin("0", "1").weight("2", 100));
+        assert!((0..4).all(|_| release() == "1"));
+    }
+
+    fn report_version(socket: LocalSocket) -> ! {
+        let _ = socket.send(socket.version().to_string());
+        finish()
+    }
+
+    #[test]
+    fn version_negotiation() {
+        let network = LocalNetwork::new();
+        let form = RegistrationForm::new(report_version, "svc".to_string());
+        network.register(form.version(Versions::new(1, 3))).unwrap();
+        let (socket, version) = network.connect_versioned(service("svc"), Versions::new(2, 5))
+            .unwrap();
+        assert_eq!((version, socket.version()), (3, 3));
+        assert_eq!(socket.receive::<String>().unwrap(), "3");
+        assert!(matches!(network.connect_versioned(service("svc"), Versions::only(4)),
+            Err(ConnectErr::NoCommonVersion(_))));
+        assert_eq!(network.connect(service("svc")).unwrap().version(), 0);
+    }
+
+    #[test]
+    fn service_paths() {
+        let network = LocalNetwork::new();
+        let path = ServicePath::parse("kobzar.echo").unwrap();
+        network.register_path(&path, echo).unwrap();
+        let socket = network.connect_path(&path).unwrap();
+        socket.send("hi".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "hi");
+        assert!(network.connect(service("kobzar.echo")).is_ok());
+    }
+
+    fn double(n: String) -> String {
+        n.repeat(2)
+    }
+
+    fn serve_double(socket: LocalSocket) -> ! {
+        let _ = serve_loop(&socket, double);
+        finish()
+    }
+
+    #[test]
+    fn direct_calls() {
+        let network = LocalNetwork::new();
+        network.register_direct(RegistrationForm::new(serve_double, "double".to_string()), double)
+            .unwrap();
+        network.register(RegistrationForm::new(serve_double, "plain".to_string())).unwrap();
+
+        let direct = Caller::<_, _, _, String, String>::connect(&network, service("double"))
+            .unwrap();
+        assert!(direct.is_direct());
+        assert_eq!(direct.call("ab".to_string(), Millis(1000)).unwrap(), "abab");
+        let plain = Caller::<_, _, _, String, String>::connect(&network, service("plain"))
+            .unwrap();
+        assert!(!plain.is_direct());
+        assert_eq!(plain.call("ab".to_string(), Millis(1000)).unwrap(), "abab");
+    }
+
+    #[test]
+    fn unique_lease() {
+        let network = LocalNetwork::new();
+        let form = || RegistrationForm::new(echo, "memory".to_string());
+        let first = network.register_unique_leased(form(), Millis(30)).unwrap();
+        assert!(matches!(network.register_unique(form()), Err(RegistrationErr::LeaseHeld)));
+        assert!(first.renew_lease().is_ok());
+
+        thread::sleep(Duration::from_millis(40));
+        assert!(network.register_unique(form()).is_ok());
+        assert!(matches!(first.renew_lease(), Err(RegistrationErr::LeaseHeld)));
+    }
+
+    #[test]
+    fn required_capabilities() {
+        let network = LocalNetwork::new();
+        let form = RegistrationForm::new(echo, "disk".to_string()).require("drivers");
+        network.register(form).unwrap();
+        assert!(matches!(network.connect(service("disk")), Err(ConnectErr::PermissionDenied(_))));
+
+        let me = network.current();
+        let issuer = network.issuer().unwrap();
+        me.grant(&issuer, issuer.issue("drivers", vec![Pattern("net.*".to_string())])).unwrap();
+        assert!(matches!(network.connect(service("disk")), Err(ConnectErr::PermissionDenied(_))));
+        let drivers = issuer.issue("drivers", vec![Pattern("disk".to_string())]);
+        me.grant(&issuer, drivers.clone()).unwrap();
+        assert!(network.connect(service("disk")).is_ok());
+        me.take_away(drivers.id());
+        assert!(network.connect(service("disk")).is_err());
+
+        // Capabilities made up by the objects are of no use.
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let net = network.clone();
+        let helper = network.spawn(move || {
+            let me = LocalObject::myself();
+            let forger = Issuer::new();
+            me.grant(&forger, forger.issue("drivers", vec![Pattern("*".to_string())]))
+                .unwrap();
+            let issued = net.issuer().is_some();
+            let forged = net.connect(service("disk")).is_ok();
+            tx.send((issued, forged)).unwrap();
+            thread::park();
+        });
+        assert_eq!(rx.recv().unwrap(), (false, false));
+        assert_eq!(me.grant(&Issuer::new(), drivers.clone()), Err(GrantErr::NotIssuer));
+
+        // Only the holders delegate, and only what they hold.
+        assert_eq!(me.delegate(&helper, drivers.clone()), Err(GrantErr::NotHeld));
+        me.grant(&issuer, drivers.clone()).unwrap();
+        let narrow = drivers.attenuate(Default::default()).unwrap();
+        me.delegate(&helper, narrow).unwrap();
+        assert_eq!(helper.capabilities()[1].lineage(), &[drivers.id()]);
+    }
+
+    #[test]
+    fn revoked_capabilities() {
+        let network = LocalNetwork::new();
+        let form = RegistrationForm::new(echo, "disk".to_string()).require("drivers");
+        network.register(form).unwrap();
+        let issuer = network.issuer().unwrap();
+        let drivers = issuer.issue("drivers", vec![Pattern("disk".to_string())]);
+        let derived = drivers.attenuate(Default::default()).unwrap();
+
+        let socket = network.connect_with_capability(&derived, service("disk")).unwrap();
+        socket.send("ping".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "ping");
+        assert_eq!(network.revoke(&drivers), Ok(1));
+        assert!(matches!(socket.receive::<String>(), Err(SocketErr::CapabilityRevoked)));
+        assert_eq!(network.connect_with_capability(&derived, service("disk")).err(),
+            Some(CapabilityErr::Revoked));
+
+        let me = network.current();
+        me.grant(&issuer, derived).unwrap();
+        assert!(matches!(network.connect(service("disk")), Err(ConnectErr::PermissionDenied(_))));
+
+        // Single-use capability opens one channel.
+        let drivers = issuer.issue("drivers", vec![Pattern("disk".to_string())]);
+        let once = drivers.attenuate(Attenuation { single_use: true, ..Default::default() })
+            .unwrap();
+        me.grant(&issuer, once).unwrap();
+        assert!(network.connect(service("disk")).is_ok());
+        assert!(matches!(network.connect(service("disk")), Err(ConnectErr::PermissionDenied(_))));
+        assert_eq!(network.revoke(&Issuer::new().issue("drivers", vec![])),
+            Err(RevokeErr::NotIssuer));
+    }
+
+    #[test]
+    fn policy_enforced() {
+        let network = LocalNetwork::new();
+        network.register_direct(RegistrationForm::new(serve_double, "double".to_string()), double)
+            .unwrap();
+        network.set_policy(Policy::parse("
+            allow connect,direct service=double require=math
+            deny register service=kobzar.*
+        ").unwrap());
+        assert!(matches!(network.register(RegistrationForm::new(echo, "kobzar.disk".to_string())),
+            Err(RegistrationErr::PermissionDenied)));
+        assert!(network.register_unique(RegistrationForm::new(echo, "kobzar.disk".to_string()))
+            .is_ok());
+        assert!(matches!(network.connect(service("double")), Err(ConnectErr::PermissionDenied(_))));
+        assert!(network.direct::<String, String>(&service("double")).is_none());
+
+        let issuer = network.issuer().unwrap();
+        let math = issuer.issue("math", vec![Pattern("double".to_string())]);
+        network.current().grant(&issuer, math.clone()).unwrap();
+        assert!(network.connect(service("double")).is_ok());
+        assert!(network.direct::<String, String>(&service("double")).is_some());
+
+        // Cached decisions follow the capabilities and the rules.
+        network.revoke(&math).unwrap();
+        assert!(matches!(network.connect(service("double")), Err(ConnectErr::PermissionDenied(_))));
+        network.set_policy(Policy::parse("allow connect service=double").unwrap());
+        assert!(network.connect(service("double")).is_ok());
+    }
+
+    /// Answer each message with the same text, as the double of echo.
+    fn fake(socket: LocalSocket) -> ! {
+        while socket.receive::<String>().is_ok() {
+            if socket.send("fake".to_string()).is_err() {
+                break;
+            }
+        }
+        finish()
+    }
+
+    /// Provider that is always too busy to serve.
+    fn busy(socket: LocalSocket) -> ! {
+        let _ = socket.receive::<String>();
+        socket.reject_overloaded();
+        finish()
+    }
+
+    #[test]
+    fn rejected_as_overloaded() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(busy, "busy".to_string())).unwrap();
+        let socket = network.connect(service("busy")).unwrap();
+        socket.send("request".to_string()).unwrap();
+        assert!(matches!(socket.receive::<String>(), Err(SocketErr::ProviderOverloaded)));
+        assert!(matches!(socket.send("again".to_string()), Err(SocketErr::ProviderOverloaded)));
+    }
+
+    #[test]
+    fn tenant_quota() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let tenants = network.tenants().unwrap();
+        tenants.set_quota(&"acme".to_string(), Quota { messages: Some(2), bytes: None });
+        let (go, wait) = ::std::sync::mpsc::channel();
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let inside = network.clone();
+        let object = network.spawn(move || {
+            wait.recv().unwrap();
+            let socket = inside.connect(service("echo")).unwrap();
+            for text in &["one", "two", "three"] {
+                let sent = socket.send(text.to_string());
+                tx.send(matches!(sent, Err(SocketErr::QuotaExceeded))).unwrap();
+                if sent.is_ok() {
+                    socket.receive::<String>().unwrap();
+                }
+            }
+        });
+        network.set_tenant(&object.id(), "acme".to_string());
+        go.send(()).unwrap();
+        assert_eq!(rx.iter().take(3).collect::<Vec<_>>(), [false, false, true]);
+        let traffic = tenants.current(&"acme".to_string());
+        assert_eq!((traffic.messages_sent, traffic.messages_received), (2, 2));
+        assert_eq!(traffic.bytes_sent, 2 * mem::size_of::<String>() as u64);
+    }
+
+    #[test]
+    fn pinning_ignored() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let socket = network.connect(service("echo")).unwrap();
+        assert!(network.domains().is_empty());
+        socket.pin(Affinity::Core(3)).unwrap();
+        assert_eq!(socket.affinity(), Affinity::Core(3));
+        socket.send("hi".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "hi");
+    }
+
+    #[test]
+    fn usage_reported() {
+        let network = LocalNetwork::new();
+        let inside = network.clone();
+        let provider = network.spawn(move || {
+            inside.register(RegistrationForm::new(idle, "idle".to_string()).capacity(8)).unwrap();
+        });
+        network.wait_for_service(&"idle".to_string());
+        let socket = network.connect(service("idle")).unwrap();
+        socket.send(vec![0u8; 5]).unwrap();
+        assert_eq!(network.usage(&provider.id()), Some(Usage {
+            channels        : 1,
+            services        : 1,
+            queued_bytes    : 5,
+            children        : 0,
+        }));
+        assert_eq!(network.usages().len(), 1);
+        assert_eq!(network.usage(&0), None);
+    }
+
+    #[test]
+    fn stubbed_connects() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "clock".to_string())).unwrap();
+        network.stub(RegistrationForm::new(fake, "clock".to_string())).unwrap();
+        network.stub(RegistrationForm::new(fake, "disk".to_string())).unwrap();
+        let ask = |id| {
+            let socket = network.connect(service(id)).unwrap();
+            socket.send("real".to_string()).unwrap();
+            socket.receive::<String>().unwrap()
+        };
+        assert_eq!(ask("clock"), "fake");
+        assert_eq!(ask("disk"), "fake");
+        assert_eq!(network.stub_hits(&"clock".to_string()), 1);
+
+        assert!(network.unstub(&"clock".to_string()));
+        assert!(network.unstub(&"disk".to_string()));
+        assert!(!network.unstub(&"disk".to_string()));
+        assert_eq!(ask("clock"), "real");
+        assert!(matches!(network.connect(service("disk")), Err(ConnectErr::NotProvided(_))));
+
+        // Only the harness stubs the services.
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let inside = network.clone();
+        network.spawn(move || {
+            let stubbed = inside.stub(RegistrationForm::new(fake, "clock".to_string()));
+            tx.send(matches!(stubbed, Err(RegistrationErr::PermissionDenied))).unwrap();
+        });
+        assert!(rx.recv().unwrap());
+    }
+
+    #[test]
+    fn connect_set() {
+        let network = LocalNetwork::new();
+        let disk = network.register(RegistrationForm::new(echo, "disk".to_string())).unwrap();
+        network.register(RegistrationForm::new(echo, "net".to_string())).unwrap();
+        network.register(RegistrationForm::new(echo, "fs".to_string())).unwrap();
+
+        let sockets = network.connect_set(&[service("disk"), service("net")]).unwrap();
+        sockets[1].send("ping".to_string()).unwrap();
+        assert_eq!(sockets[1].receive::<String>().unwrap(), "ping");
+        drop(sockets);
+
+        match network.connect_set(&[service("disk"), service("tape"), service("fs")]) {
+            Err(err)    => {
+                assert_eq!(err.failed, 1);
+                assert!(matches!(err.err, ConnectErr::NotProvided(_)));
+            },
+            Ok(_)       => panic!("tape is not provided"),
+        }
+        // Channel to the disk was rolled back.
+        assert_eq!(disk.reference_count(), 0);
+    }
+
+    /// Replies with the identifier of the provider.
+    fn whoami(socket: LocalSocket) -> ! {
+        let _ = socket.send(socket.owner.id().to_string());
+        idle(socket)
+    }
+
+    #[test]
+    fn connect_policies() {
+        let network = LocalNetwork::new();
+        let providers: Vec<_> = (0..2).map(|_| network.spawn(|| {
+            let me = LocalObject::myself();
+            let form = RegistrationForm::new(whoami, "disk".to_string());
+            let _service = OwnedObject::network(&me).register(form).unwrap();
+            loop {
+                thread::park();
+            }
+        })).collect();
+        while network.service_info(&"disk".to_string()).map_or(0, |i| i.providers) < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let chosen = |policy| {
+            let socket = network.connect_with_policy(service("disk"), policy).unwrap();
+            let id = socket.receive::<String>().unwrap();
+            (socket, id)
+        };
+
+        let second = providers[1].id();
+        for _ in 0..3 {
+            assert_eq!(chosen(ConnectPolicy::PreferObject(second)).1, second.to_string());
+        }
+        // The first one has no channels yet.
+        let (_socket, id) = chosen(ConnectPolicy::LeastConnections);
+        assert_eq!(id, providers[0].id().to_string());
+        let (_socket, id) = chosen(ConnectPolicy::RoundRobin);
+        assert_ne!(id, chosen(ConnectPolicy::RoundRobin).1);
+        assert!(network.connect_with_policy(service("disk"), ConnectPolicy::Random).is_ok());
+    }
+
+    #[test]
+    fn direct_call_requires_capabilities() {
+        let network = LocalNetwork::new();
+        let form = RegistrationForm::new(serve_double, "double".to_string()).require("math");
+        network.register_direct(form, double).unwrap();
+        assert!(network.direct::<String, String>(&service("double")).is_none());
+        assert!(matches!(Caller::<_, _, _, String, String>::connect(&network, service("double")),
+            Err(ConnectErr::PermissionDenied(_))));
+
+        let issuer = network.issuer().unwrap();
+        network.current().grant(&issuer, issuer.issue("math", vec![Pattern("double".to_string())]))
+            .unwrap();
+        assert!(network.direct::<String, String>(&service("double")).is_some());
+    }
+
+    #[test]
+    fn incompatible_manifests() {
+        let network = LocalNetwork::new();
+        let theirs = Manifest::new(1).schema("Alloc", "struct Alloc { size: u32 }");
+        let form = RegistrationForm::new(echo, "memory".to_string()).manifest(theirs.clone());
+        network.register(form).unwrap();
+
+        let ours = Manifest::new(1).schema("Alloc", "struct Alloc { size: u64 }");
+        match network.connect_with_manifest(service("memory"), ours.clone()) {
+            Err(ConnectErr::Incompatible { theirs: t, ours: o }) => {
+                assert_eq!(*t, theirs);
+                assert_eq!(*o, ours);
+            },
+            _ => panic!("manifests must not match"),
+        }
+
+        let ours = Manifest::new(1).schema("Alloc", "struct Alloc { size: u32 }");
+        let socket = network.connect_with_manifest(service("memory"), ours).unwrap();
+        assert_eq!(*socket.peer_manifest(), theirs);
+        let plain = network.connect(service("memory")).unwrap();
+        assert_eq!(*plain.peer_manifest(), theirs);
+    }
+
+    #[test]
+    fn frozen_provider_handles_nothing() {
+        let network = LocalNetwork::new();
+        let provider = network.spawn(|| {
+            let me = LocalObject::myself();
+            let form = RegistrationForm::new(serve_double, "double".to_string());
+            let _service = OwnedObject::network(&me).register_direct(form, double).unwrap();
+            loop {
+                thread::park();
+            }
+        });
+        network.wait_for_service(&"double".to_string());
+        provider.freeze().unwrap();
+
+        assert!(network.direct::<String, String>(&service("double")).is_none());
+        let requester = network.clone();
+        let call = thread::spawn(move || {
+            let caller = Caller::<_, _, _, String, String>::connect(&requester, service("double"))
+                .unwrap();
+            assert!(!caller.is_direct());
+            caller.call("ab".to_string(), Millis(5000)).unwrap()
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(!call.is_finished());
+
+        provider.thaw().unwrap();
+        assert_eq!(call.join().unwrap(), "abab");
+        assert!(network.direct::<String, String>(&service("double")).is_some());
+    }
+
+    #[test]
+    fn features() {
+        let features = LocalNetwork::new().features();
+        assert!(features.is_current());
+        assert!(features.has(Feature::ConnectTokens));
+        assert!(features.has(Feature::Async));
+        assert!(features.has(Feature::Capabilities));
+        assert!(!features.has(Feature::SharedMemory));
+    }
+
+    #[test]
+    fn throttled_object() {
+        let network = LocalNetwork::new();
+        let me = network.current().id();
+        let limits = Limits {
+            register    : Limit::new(1, Duration::from_secs(60)),
+            connect     : Limit::unlimited().max_held(1),
+        };
+        network.set_limits(&me, limits);
+        assert_eq!(network.limits(&me), limits);
+
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        assert!(matches!(network.register(RegistrationForm::new(echo, "other".to_string())),
+            Err(RegistrationErr::Throttled(ThrottleErr::TooFast { .. }))));
+
+        let socket = network.connect(service("echo")).unwrap();
+        assert!(matches!(network.connect(service("echo")),
+            Err(ConnectErr::Throttled(_, ThrottleErr::TooMany))));
+        drop(socket);
+        assert!(network.connect(service("echo")).is_ok());
+    }
+
+    #[test]
+    fn service_info() {
+        let network = LocalNetwork::new();
+        assert!(network.service_info(&"memory".to_string()).is_none());
+        let metadata = Metadata::new()
+            .name("Memory Server")
+            .message_type("Alloc")
+            .limit("max-block", 4096);
+        let form = RegistrationForm::new(echo, "memory".to_string())
+            .endpoint("stats", idle)
+            .capacity(8)
+            .metadata(metadata.clone());
+        network.register_unique(form).unwrap();
+
+        let info = network.service_info(&"memory".to_string()).unwrap();
+        assert_eq!(info.providers, 1);
+        assert!(info.unique);
+        assert_eq!(info.endpoints, vec!["stats"]);
+        assert_eq!(info.capacity, 8);
+        assert_eq!(info.metadata, metadata);
+    }
+
+    /// What the last 'drain' handler received before its receive failed.
+    static DRAINED: Mutex<Option<String>> = Mutex::new(None);
+
+    fn drain(socket: LocalSocket) -> ! {
+        let mut received = 0;
+        let end = loop {
+            match socket.receive::<String>() {
+                Ok(_)   => received += 1,
+                Err(e)  => break e,
+            }
+        };
+        *DRAINED.lock().unwrap() = Some(format!("{} {:?}", received, end));
+        finish()
+    }
+
+    #[test]
+    fn graceful_shutdown() {
+        let network = LocalNetwork::new();
+        let object = network.spawn(|| {
+            let me = LocalObject::myself();
+            let form = RegistrationForm::new(drain, "drain".to_string());
+            let service = OwnedObject::network(&me).register(form).unwrap();
+            while !me.is_shutting_down() || DRAINED.lock().unwrap().is_none() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            service.discontinue();
+        });
+        network.wait_for_service(&"drain".to_string());
+        let socket = network.connect(service("drain")).unwrap();
+        socket.send("a".to_string()).unwrap();
+        socket.send("b".to_string()).unwrap();
+        let termination = object.clone().request_shutdown(Millis(1000));
+        assert_eq!(termination.ok(), Some(Termination::Graceful));
+        assert_eq!(DRAINED.lock().unwrap().as_deref(), Some("2 ShuttingDown"));
+        assert_eq!(object.exit_reason(), Some(ExitReason::Normal));
+
+        let stubborn = network.spawn(|| loop {
+            thread::park();
+        });
+        let termination = stubborn.clone().request_shutdown(Millis(20));
+        assert_eq!(termination.ok(), Some(Termination::Forced));
+        assert_eq!(stubborn.exit_reason(), Some(ExitReason::Killed));
+    }
+
+    #[test]
+    fn cancel_blocking() {
+        let network = LocalNetwork::new();
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        let cancelling = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+        let late = network.connect_cancellable(service("late"), &cancel);
+        assert!(matches!(late, Err(ConnectErr::Cancelled(_))));
+        cancelling.join().unwrap();
+
+        // Service that shows up in the internal network of an object.
+        let vault = Spawner::spawn(&network, SpawnSpec::entry(thread::park).hardened()).unwrap();
+        vault.grant_visible("vault.*");
+        let net = network.clone();
+        let pending = thread::spawn(move || {
+            net.connect_cancellable(service("vault.get"), &CancelToken::new()).map(|_| ())
+        });
+        thread::sleep(Duration::from_millis(20));
+        let _get = vault.internal_network()
+            .register(RegistrationForm::new(echo, "vault.get".to_string())).unwrap();
+        assert!(pending.join().unwrap().is_ok());
+
+        network.register(RegistrationForm::new(idle, "idle".to_string())).unwrap();
+        let socket = network.connect_cancellable(service("idle"), &CancelToken::new()).unwrap();
+        assert!(matches!(socket.receive_cancellable::<String>(&cancel), Err(SocketErr::Cancelled)));
+        assert!(matches!(socket.send_cancellable("hi".to_string(), &cancel),
+                Err(SocketErr::Cancelled)));
+
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        let cancelling = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+        assert!(matches!(socket.send_cancellable("hi".to_string(), &cancel),
+                Err(SocketErr::Cancelled)));
+        assert_eq!(socket.len(), 0);
+        cancelling.join().unwrap();
+
+        // Receive that already waits is woken by the cancel.
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        let cancelling = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+        assert!(matches!(socket.receive_cancellable::<String>(&cancel),
+                Err(SocketErr::Cancelled)));
+        assert!(socket.is_opened());
+        cancelling.join().unwrap();
+    }
+
+    /// Replies with the batches of two, three words in total.
+    fn batches(socket: LocalSocket) -> ! {
+        let mut batches = Vec::new();
+        let mut words = 0;
+        while words < 3 {
+            match socket.receive_batch::<String>(2) {
+                Ok(batch)   => {
+                    words += batch.len();
+                    batches.push(batch.join(" "));
+                },
+                Err(_)      => finish(),
+            }
+        }
+        let _ = socket.send(batches.join("|"));
+        // Closing drops what the bounded channel still buffers.
+        let _ = socket.receive::<String>();
+        finish()
+    }
+
+    #[test]
+    fn vectored_and_batch() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(batches, "batches".to_string())).unwrap();
+        let words = || vec!["a".to_string(), "b".to_string(), "c".to_string()];
+
+        let socket = network.connect(service("batches")).unwrap();
+        socket.send_vectored(words()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "a b|c");
+
+        let bounded = network.connect_bounded(service("batches"), 1).unwrap();
+        bounded.send_vectored(words()).unwrap();
+        assert_eq!(bounded.receive::<String>().unwrap().replace('|', " "), "a b c");
+        assert!(socket.receive_batch::<String>(0).unwrap().is_empty());
+    }
+
+    /// Reports the address of the shared frame and then whether it was
+    /// revoked.
+    fn frames(socket: LocalSocket) -> ! {
+        if let Ok(frame) = socket.receive_shared::<Vec<u8>>() {
+            let address = frame.read(|f| f.as_ptr() as usize).unwrap();
+            let _ = socket.send(address.to_string());
+            let _ = socket.receive::<String>();
+            let _ = socket.send(frame.is_revoked().to_string());
+        }
+        finish()
+    }
+
+    #[test]
+    fn shared_region() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(frames, "frames".to_string())).unwrap();
+        let socket = network.connect(service("frames")).unwrap();
+        let frame = vec![7u8; 4096];
+        let address = frame.as_ptr() as usize;
+
+        let grant = socket.send_shared(frame).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), address.to_string());
+        assert!(grant.is_mapped());
+        let frame = grant.revoke();
+        assert_eq!(frame.as_ptr() as usize, address);
+        socket.send("revoked?".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "true");
+    }
+
+    #[test]
+    fn spawn_with_services() {
+        let network = LocalNetwork::new();
+        network.register_unique(RegistrationForm::new(echo, "taken".to_string())).unwrap();
+        let spec = SpawnSpec::entry(thread::park)
+            .unique_service(RegistrationForm::new(echo, "echo".to_string()))
+            .unique_service(RegistrationForm::new(echo, "taken".to_string()));
+        let failed = Spawner::spawn(&network, spec);
+        assert!(matches!(failed, Err(SpawnErr::Registration(RegistrationErr::UniquelyRegistered))));
+        assert!(network.connect(service("echo")).is_err());
+
+        // Services are there right away, before the main thread runs.
+        let spec = SpawnSpec::entry(|| ())
+            .service(RegistrationForm::new(echo, "echo".to_string()));
+        let object = Spawner::spawn(&network, spec).unwrap();
+        let socket = network.connect(service("echo")).unwrap();
+        socket.send("hi".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "hi");
+        assert!(OwnedObject::is_alive(&object));
+
+        let inner = Spawner::spawn(&network, SpawnSpec::entry(|| ())
+            .service(RegistrationForm::new(echo, "inner".to_string()))
+            .internal()).unwrap();
+        assert!(network.connect(service("inner")).is_err());
+        assert!(OwnedObject::network(&inner).connect(service("inner")).is_ok());
+        assert!(matches!(Spawner::spawn(&network, SpawnSpec::image("memory")),
+            Err(SpawnErr::UnknownImage(_))));
+    }
+
+    #[test]
+    fn sandboxed_spawn() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "fs.read".to_string())).unwrap();
+        network.register(RegistrationForm::new(echo, "net.socket".to_string())).unwrap();
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let net = network.clone();
+        let profile = SandboxProfile::deny_all().allow_connect("fs.*").allow_spawn();
+        Spawner::spawn(&network, SpawnSpec::entry(move || {
+            tx.send(net.connect(service("fs.read")).is_ok()).unwrap();
+            tx.send(matches!(net.connect(service("net.socket")),
+                    Err(ConnectErr::Sandboxed(_)))).unwrap();
+            tx.send(matches!(net.register(RegistrationForm::new(echo, "mine".to_string())),
+                    Err(RegistrationErr::Sandboxed(SandboxViolation::Register)))).unwrap();
+            let with_service = SpawnSpec::entry(|| ())
+                .service(RegistrationForm::new(echo, "mine".to_string()));
+            tx.send(matches!(Spawner::spawn(&net, with_service),
+                    Err(SpawnErr::Sandboxed(SandboxViolation::Register)))).unwrap();
+
+            // Child never gets more than its parent.
+            let (child_net, child_tx) = (net.clone(), tx.clone());
+            let child = SpawnSpec::entry(move || {
+                child_tx.send(matches!(child_net.connect(service("net.socket")),
+                        Err(ConnectErr::Sandboxed(_)))).unwrap();
+                child_tx.send(matches!(Spawner::spawn(&child_net, SpawnSpec::entry(|| ())),
+                        Err(SpawnErr::Sandboxed(SandboxViolation::Spawn)))).unwrap();
+            }).sandbox(SandboxProfile::deny_all().allow_connect("*"));
+            let child = Spawner::spawn(&net, child).unwrap();
+            tx.send(child.sandbox().is_some_and(|p| p.check_connect("fs.read").is_ok()))
+                .unwrap();
+        }).sandbox(profile)).unwrap();
+        let checks: Vec<bool> = rx.iter().take(7).collect();
+        assert_eq!(checks, vec![true; 7]);
+        assert!(network.connect(service("net.socket")).is_ok());
+    }
+
+    #[test]
+    fn exported_service_awaited() {
+        let network = LocalNetwork::new();
+        let vault = Spawner::spawn(&network, SpawnSpec::entry(thread::park).hardened()).unwrap();
+        vault.grant_visible("vault.*");
+        let net = network.clone();
+        let waiting = thread::spawn(move || {
+            net.wait_for_service(&"vault.get".to_string());
+            ThreadRuntime.block_on(net.wait_for_service_async(&"vault.get".to_string()));
+        });
+        thread::sleep(Duration::from_millis(20));
+        let _get = vault.internal_network()
+            .register(RegistrationForm::new(echo, "vault.get".to_string())).unwrap();
+        waiting.join().unwrap();
+        assert!(network.connect(service("vault.get")).is_ok());
+    }
+
+    #[test]
+    fn hardened_object() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "kobzar.memory.alloc".to_string()))
+            .unwrap();
+        network.register(RegistrationForm::new(echo, "net.socket".to_string())).unwrap();
+        let vault = Spawner::spawn(&network, SpawnSpec::entry(thread::park).hardened()).unwrap();
+        assert!(vault.is_hardened());
+        assert_eq!(vault.grants(), Grants::deny_all());
+        let internal = vault.internal_network().clone();
+        let _get = internal.register(RegistrationForm::new(echo, "vault.get".to_string()))
+            .unwrap();
+
+        // Connects out from a sub-object of the vault.
+        let connect_out = |id: &'static str| {
+            let (tx, rx) = ::std::sync::mpsc::channel();
+            let net = network.clone();
+            internal.spawn(move || tx.send(net.connect(service(id)).map(|_| ())).unwrap());
+            rx.recv().unwrap()
+        };
+        assert!(matches!(connect_out("kobzar.memory.alloc"),
+            Err(ConnectErr::PermissionDenied(_))));
+        vault.grant_connect("kobzar.memory.*");
+        assert!(connect_out("kobzar.memory.alloc").is_ok());
+        assert!(matches!(connect_out("net.socket"), Err(ConnectErr::PermissionDenied(_))));
+
+        assert!(matches!(network.connect(service("vault.get")), Err(ConnectErr::NotProvided(_))));
+        vault.grant_visible("vault.*");
+        let socket = network.connect(service("vault.get")).unwrap();
+        socket.send("key".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "key");
+        vault.reset_grants();
+        assert!(matches!(network.connect(service("vault.get")), Err(ConnectErr::NotProvided(_))));
+        assert!(matches!(connect_out("kobzar.memory.alloc"),
+            Err(ConnectErr::PermissionDenied(_))));
+
+        // Ordinary objects let their sub-objects out and show nothing.
+        let plain = Spawner::spawn(&network, SpawnSpec::entry(thread::park)).unwrap();
+        assert!(!plain.is_hardened());
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let net = network.clone();
+        plain.internal_network()
+            .spawn(move || tx.send(net.connect(service("net.socket")).is_ok()).unwrap());
+        assert!(rx.recv().unwrap());
+        let _hidden = plain.internal_network()
+            .register(RegistrationForm::new(echo, "plain.hidden".to_string())).unwrap();
+        assert!(network.connect(service("plain.hidden")).is_err());
+    }
+
+    #[test]
+    fn checkpoint_restored() {
+        let network = LocalNetwork::new();
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let spawn = |crash: bool| {
+            let tx = tx.clone();
+            let spec = SpawnSpec::entry(move || {
+                let me = LocalObject::myself();
+                let restored = me.restored_checkpoint().cloned();
+                let count = restored.as_ref().map_or(0, |c| c.state[0]);
+                me.save_checkpoint(&[count + 1]).unwrap();
+                tx.send(restored).unwrap();
+                if crash {
+                    panic!("crash");
+                }
+            }).checkpoint("counter");
+            let object = Spawner::spawn(&network, spec).unwrap();
+            let restored = rx.recv().unwrap();
+            let (dead, died) = ::std::sync::mpsc::channel();
+            network.on_death(&object, Box::new(move |_| dead.send(()).unwrap()));
+            died.recv().unwrap();
+            restored
+        };
+        assert_eq!(spawn(true), None);
+        assert_eq!(spawn(true), Some(Checkpoint { seq: 1, state: vec![1] }));
+
+        // Normal exit clears the checkpoint.
+        assert_eq!(spawn(false), Some(Checkpoint { seq: 2, state: vec![2] }));
+        assert_eq!(spawn(false), None);
+
+        let plain = network.spawn(|| ());
+        assert!(matches!(plain.save_checkpoint(&[1]), Err(CheckpointErr::NoStore)));
+    }
+
+    #[test]
+    fn await_quiescent() {
+        let network = LocalNetwork::new();
+        let spec = SpawnSpec::entry(|| ())
+            .service(RegistrationForm::new(echo, "echo".to_string()));
+        let object = Spawner::spawn(&network, spec).unwrap();
+        let socket = network.connect(service("echo")).unwrap();
+        assert!(matches!(object.await_quiescent(Millis(30)), Err(QuiescenceErr::Timeout)));
+
+        drop(socket);
+        assert!(object.await_quiescent(Millis(1000)).is_ok());
+        assert!(object.is_quiescent());
+
+        assert!(object.clone().kill().is_ok());
+        assert!(matches!(object.await_quiescent(Millis(30)), Err(QuiescenceErr::NotAlive)));
+    }
+
+    #[test]
+    fn async_network() {
+        let network = LocalNetwork::new();
+        let object = network.spawn(|| thread::sleep(Duration::from_millis(20)));
+        let registering = network.clone();
+        let provider = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            registering.register(RegistrationForm::new(echo, "late".to_string())).unwrap()
+        });
+        ThreadRuntime.block_on(network.wait_for_service_async(&"late".to_string()));
+        ThreadRuntime.block_on(network.wait_for_death_async(&object.id()));
+        assert!(!OwnedObject::is_alive(&object));
+        provider.join().unwrap();
+    }
+
+    /// Liveness source that saw the objects die before the network did.
+    struct Dead(Vec<u64>);
+
+    impl LivenessSource<u64> for Dead {
+
+        fn is_alive(&self, id: &u64) -> bool {
+            !self.0.contains(id)
+        }
+    }
+
+    type LocalReap = ReapEvent<u64, String, u64>;
+
+    #[derive(Clone, Default)]
+    struct Audit(Arc<Mutex<Vec<LocalReap>>>);
+
+    impl AuditSink<LocalReap> for Audit {
+
+        fn record(&self, event: LocalReap) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn reap_dead_objects() {
+        let network = LocalNetwork::new();
+        let spec = SpawnSpec::entry(thread::park)
+            .service(RegistrationForm::new(idle, "idle".to_string()));
+        let provider = Spawner::spawn(&network, spec).unwrap();
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let connecting = network.clone();
+        let requester = network.spawn(move || {
+            let _socket = connecting.connect(service("idle")).unwrap();
+            tx.send(()).unwrap();
+            loop {
+                thread::park();
+            }
+        });
+        rx.recv().unwrap();
+        let alive = network.connect(service("idle")).unwrap();
+
+        let audit = Audit::default();
+        let reaper = Reaper::new(network.clone(), audit.clone());
+        assert_eq!(reaper.run_once(&network), ReapReport::default());
+
+        let reaper = Reaper::new(Dead(vec![provider.id(), requester.id()]), audit.clone());
+        assert_eq!(reaper.run_once(&network), ReapReport { channels: 1, registrations: 1 });
+        assert!(matches!(audit.0.lock().unwrap()[0], ReapEvent::ChannelReclaimed {
+            requester: r, provider: p, .. } if r == requester.id() && p == provider.id()));
+        assert!(network.connect(service("idle")).is_err());
+        assert!(alive.is_opened());
+    }
+
+    #[test]
+    fn debug_channels() {
+        let network = LocalNetwork::new();
+        let spec = SpawnSpec::entry(thread::park)
+            .service(RegistrationForm::new(idle, "idle".to_string()));
+        let provider = Spawner::spawn(&network, spec).unwrap();
+        let id = provider.id();
+        let socket = network.connect_bounded(service("idle"), 4).unwrap();
+        socket.send("hi".to_string()).unwrap();
+
+        let session = network.attach(&id).unwrap();
+        let sockets = network.sockets(&session);
+        assert_eq!(sockets.len(), 1);
+        let info = &sockets[0];
+        assert_eq!((&info.service[..], info.peer, info.role, info.queued), ("idle", 0,
+            Role::Provider, 1));
+        let peeked = network.peek(&session, &info.channel, 10).unwrap();
+        assert_eq!(peeked.len(), 1);
+        assert_eq!(peeked[0].direction, Direction::ToProvider);
+        assert_eq!(peeked[0].bytes.as_deref(), Some(&b"hi"[..]));
+
+        network.inject(&session, &info.channel, Direction::ToRequester, "poke".to_string())
+            .unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "poke");
+        assert!(matches!(network.peek(&session, &0, 1), Err(DebugErr::NoChannel)));
+        network.force_close(&session, &info.channel).unwrap();
+        assert!(!socket.is_opened());
+        assert!(matches!(network.peek(&session, &info.channel, 1),
+            Err(DebugErr::ChannelClosed)));
+        assert!(matches!(network.attach(&0), Err(DebugErr::NoObject)));
+
+        // Other objects need the capability.
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let (granted, wait) = ::std::sync::mpsc::channel();
+        let debugging = network.clone();
+        let debugger = network.spawn(move || {
+            tx.send(matches!(debugging.attach(&id), Err(DebugErr::Denied))).unwrap();
+            wait.recv().unwrap();
+            tx.send(debugging.attach(&id).is_ok()).unwrap();
+        });
+        assert!(rx.recv().unwrap());
+        let issuer = network.issuer().unwrap();
+        debugger.grant(&issuer, issuer.issue(DEBUG_CAPABILITY, vec![Pattern(id.to_string())]))
+            .unwrap();
+        granted.send(()).unwrap();
+        assert!(rx.recv().unwrap());
+    }
+
+    /// Replies with the state the provider was restored from.
+    fn restored(socket: LocalSocket) -> ! {
+        let me = LocalObject::myself();
+        let state = me.restored_checkpoint().map_or(Vec::new(), |c| c.state.clone());
+        let _ = socket.send(state);
+        idle(socket)
+    }
+
+    #[test]
+    fn migrate_round_trip() {
+        let network = LocalNetwork::new();
+        let target = LocalNetwork::new();
+        let spec = SpawnSpec::entry(thread::park)
+            .unique_service(RegistrationForm::new(restored, "state".to_string()));
+        let object = Spawner::spawn(&network, spec).unwrap();
+        let plan = |channels| MigrationPlan {
+            target  : target.clone(),
+            channels,
+            state   : Some(Checkpoint { seq: 1, state: vec![7] }),
+        };
+        assert!(matches!(network.migrate(&0, plan(false)), Err(MigrationErr::NoObject)));
+
+        // Failed transfer leaves the object where it was.
+        let failed = network.migrate(&object.id(), plan(true));
+        assert!(matches!(failed, Err(MigrationErr::ChannelsNotMovable)));
+        let ticket = network.freeze_for_migration(&object.id()).unwrap();
+        assert!(matches!(network.freeze_for_migration(&object.id()),
+            Err(MigrationErr::InProgress)));
+        network.transfer(&ticket, plan(false)).unwrap();
+        assert!(network.connect(service("state")).is_err());
+        network.abort(ticket);
+        let socket = network.connect(service("state")).unwrap();
+        assert_eq!(socket.receive::<Vec<u8>>().unwrap(), Vec::<u8>::new());
+        assert!(target.connect(service("state")).is_err());
+
+        // Old instance is killed and its channels are closed.
+        network.migrate(&object.id(), plan(false)).unwrap();
+        assert_eq!(object.exit_reason(), Some(ExitReason::Killed));
+        assert!(!socket.is_opened());
+        assert!(network.connect(service("state")).is_err());
+        let socket = target.connect(service("state")).unwrap();
+        assert_eq!(socket.receive::<Vec<u8>>().unwrap(), vec![7]);
+        assert!(target.register_unique(RegistrationForm::new(idle, "state".to_string()))
+            .is_err());
+    }
+
+    #[test]
+    fn memory_caps() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(idle, "idle".to_string()).capacity(8)).unwrap();
+        network.set_memory_caps(MemoryCaps { per_channel: Some(64), per_object: None });
+        let socket = network.connect(service("idle")).unwrap();
+        socket.send(vec![0u8; 40]).unwrap();
+        assert!(matches!(socket.send(vec![0u8; 40]), Err(SocketErr::MemoryLimit)));
+        assert!(matches!(socket.send_now(vec![0u8; 40]), Err(SocketErr::MemoryLimit)));
+        assert_eq!(network.collect()[0].samples[0].value, 40.0);
+
+        // Bytes of the closed channel are released.
+        drop(socket);
+        assert!(network.collect()[0].samples.is_empty());
+        assert_eq!(network.collect()[1].samples[0].value, 0.0);
+    }
+
+    #[cfg(feature = "openmetrics")]
+    #[test]
+    fn memory_exported() {
+        use metrics::openmetrics::Exporter;
+
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(idle, "idle".to_string()).capacity(8)).unwrap();
+        let socket = network.connect(service("idle")).unwrap();
+        socket.send(vec![0u8; 5]).unwrap();
+        let mut exporter = Exporter::new();
+        exporter.add_source(network.clone());
+        let text = exporter.render();
+        assert!(text.contains("# TYPE ccs_channel_queued_bytes gauge"));
+        assert!(text.contains(&format!("ccs_channel_queued_bytes{{channel=\"{}\"}} 5",
+            socket.channel.id)));
+    }
+
+    #[test]
+    fn bounded_channel() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(idle, "idle".to_string()).capacity(2)).unwrap();
+        let socket = network.connect(service("idle")).unwrap();
+        assert_eq!(socket.capacity(), 2);
+        socket.send("1".to_string()).unwrap();
+        assert_eq!(socket.send_now("2".to_string()).unwrap(), None);
+        assert_eq!(socket.len(), 2);
+        assert!(matches!(socket.send_now("3".to_string()), Err(SocketErr::Full)));
+        assert!(socket.wait_to_send(Millis(10)).is_none());
+
+        let socket = network.connect_bounded(service("idle"), 0).unwrap();
+        assert_eq!(socket.capacity(), 0);
+        assert_eq!(socket.send_now("1".to_string()).unwrap().as_deref(), Some("1"));
+
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let socket = network.connect_bounded(service("echo"), 1).unwrap();
+        socket.send("a".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "a");
+        assert!(socket.is_empty());
+    }
+
+    #[test]
+    fn buffered_channels_use_rings() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let rings = |capacity| network.connect_bounded(service("echo"), capacity).unwrap()
+            .channel.rings.is_some();
+        assert!(!rings(0));
+        assert!(rings(RING_LIMIT));
+        assert!(!rings(RING_LIMIT + 1));
+
+        let socket = network.connect_bounded(service("echo"), 4).unwrap();
+        socket.send("x".to_string()).unwrap();
+        assert!(matches!(socket.receive::<Vec<u8>>(), Err(SocketErr::UnexpectedData)));
+        assert_eq!(socket.receive::<String>().unwrap(), "x");
+
+        let (send, receive) = socket.split();
+        let sender = thread::spawn(move || {
+            for i in 0..1000 {
+                send.send(i.to_string()).unwrap();
+            }
+        });
+        for i in 0..1000 {
+            assert_eq!(receive.receive::<String>().unwrap(), i.to_string());
+        }
+        sender.join().unwrap();
+    }
+
+    /// Replies with the bytes as they came.
+    fn echo_bytes(socket: LocalSocket) -> ! {
+        while let Ok(bytes) = socket.receive::<SmallData>() {
+            if socket.send(bytes).is_err() {
+                break;
+            }
+        }
+        finish()
+    }
+
+    #[test]
+    fn bytes_taken_as_either_type() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo_bytes, "echo".to_string())).unwrap();
+        let socket = network.connect(service("echo")).unwrap();
+        socket.send(vec![1, 2, 3]).unwrap();
+        assert_eq!(socket.receive::<Vec<u8>>().unwrap(), vec![1, 2, 3]);
+        let bytes: SmallData = SmallData::from_slice(&[4; 64]);
+        socket.send(bytes).unwrap();
+        let bytes = socket.receive::<SmallData>().unwrap();
+        assert!(!bytes.is_inline());
+        assert_eq!(&*bytes, &[4; 64][..]);
+    }
+
+    /// Replies with the count once it has received 64 words.
+    fn count(socket: LocalSocket) -> ! {
+        let mut words = 0;
+        while words < 64 {
+            match socket.receive::<String>() {
+                Ok(_)   => words += 1,
+                Err(_)  => finish(),
+            }
+        }
+        let _ = socket.send(words.to_string());
+        let _ = socket.receive::<String>();
+        finish()
+    }
+
+    #[test]
+    fn coalesced_wakeups() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(count, "count".to_string())
+            .capacity(64).coalesce(16, Millis(20))).unwrap();
+        let socket = network.connect(service("count")).unwrap();
+        // Let the receiver fall asleep before the burst.
+        thread::sleep(Duration::from_millis(20));
+        for i in 0..64 {
+            socket.send(i.to_string()).unwrap();
+        }
+        // Reply is the only message of its batch and comes after the
+        // latency.
+        assert_eq!(socket.receive::<String>().unwrap(), "64");
+        let wakeups = socket.channel.batchers.as_ref().unwrap()[PROVIDER].wakeups();
+        assert!((1..=8).contains(&wakeups), "{} wakeups for 64 messages", wakeups);
+    }
+
+    #[test]
+    fn partition_key() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(release_one, "shard".to_string())).unwrap();
+        network.register(RegistrationForm::new(release_two, "shard".to_string())).unwrap();
+        let owner = |key: &str| network.connect_partition(service("shard"), key.as_bytes())
+            .unwrap().receive::<String>().unwrap();
+        let owners: Vec<_> = (0..16).map(|i| owner(&i.to_string())).collect();
+        assert!(owners.iter().any(|o| o == "1") && owners.iter().any(|o| o == "2"));
+        assert!((0..16).all(|i| owner(&i.to_string()) == owners[i]));
+    }
+
+    #[test]
+    fn async_operations() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        network.register(RegistrationForm::new(idle, "idle".to_string())).unwrap();
+        let rt = ThreadRuntime;
+        let cancel = CancelToken::new();
+
+        let echo = network.connect(service("echo")).unwrap();
+        rt.block_on(echo.send_async("hi".to_string(), &cancel)).unwrap();
+        assert_eq!(rt.block_on(echo.receive_async::<String>(&cancel)).unwrap(), "hi");
+
+        let idle = network.connect(service("idle")).unwrap();
+        assert!(rt.block_on(idle.wait_to_receive_async::<String, _>(Millis(10), &cancel))
+                .is_none());
+        let canceller = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            canceller.cancel();
+        });
+        assert!(matches!(rt.block_on(idle.send_async("hi".to_string(), &cancel)),
+                Err(AsyncErr::Cancelled)));
+        assert!(idle.is_opened());
+    }
+
+    /// Handler that echoes through the asynchronous socket until the
+    /// requester closes the channel.
+    fn echo_async(socket: LocalSocket) -> impl Future<Output = ()> + Send {
+        let cancel = CancelToken::new();
+        let mut received = None;
+        future::poll_fn(move |cx| loop {
+            if received.is_none() {
+                match Box::pin(socket.receive_async::<String>(&cancel)).as_mut().poll(cx) {
+                    Poll::Ready(Ok(message))    => received = Some(message),
+                    Poll::Ready(Err(_))         => return Poll::Ready(()),
+                    Poll::Pending               => return Poll::Pending,
+                }
+            }
+            let message = received.clone().unwrap();
+            match Box::pin(socket.send_async(message, &cancel)).as_mut().poll(cx) {
+                Poll::Ready(_)  => received = None,
+                Poll::Pending   => return Poll::Pending,
+            }
+        })
+    }
+
+    #[test]
+    fn served_on_runtime() {
+        let network = LocalNetwork::new();
+        let form = RegistrationForm::new(idle, "echo".to_string()).capacity(1);
+        let owned = network.serve(&ThreadRuntime, form, echo_async).unwrap();
+        let first = network.connect(service("echo")).unwrap();
+        let second = network.connect(service("echo")).unwrap();
+        second.send("b".to_string()).unwrap();
+        first.send("a".to_string()).unwrap();
+        assert_eq!(second.receive::<String>().unwrap(), "b");
+        assert_eq!(first.receive::<String>().unwrap(), "a");
+
+        owned.discontinue();
+        assert!(matches!(network.connect(service("echo")), Err(ConnectErr::NotProvided(_))));
+    }
+
+    #[test]
+    fn async_open_network() {
+        let network = LocalNetwork::new();
+        let rt = ThreadRuntime;
+        let cancel = CancelToken::new();
+        let form = || RegistrationForm::new(echo, "echo".to_string());
+        let owned = rt.block_on(network.register_async(form(), &cancel)).unwrap();
+        assert_eq!(owned.provider_count(), 1);
+        assert!(matches!(rt.block_on(network.register_unique_async(form(), &cancel)),
+                Err(AsyncErr::Failed(RegistrationErr::AlreadyRegistered))));
+        let socket = rt.block_on(network.connect_async(service("echo"), &cancel)).unwrap();
+        socket.send("hi".to_string()).unwrap();
+        assert_eq!(socket.receive::<String>().unwrap(), "hi");
+        assert!(matches!(rt.block_on(network.connect_async(service("none"), &cancel)),
+                Err(AsyncErr::Failed(ConnectErr::NotProvided(_)))));
+
+        // Connect stays the object's own when another thread polls it.
+        let (sender, receiver) = ::std::sync::mpsc::channel();
+        let object = network.spawn(move || {
+            let myself = LocalObject::myself();
+            let cancel = CancelToken::new();
+            let connect = OwnedObject::network(&myself).connect_async(service("echo"), &cancel);
+            let socket = thread::scope(|s| s.spawn(|| ThreadRuntime.block_on(connect))
+                .join().unwrap()).unwrap();
+            sender.send(socket.requester().id()).unwrap();
+        });
+        assert_eq!(receiver.recv().unwrap(), object.id());
+
+        cancel.cancel();
+        let form = RegistrationForm::new(idle, "idle".to_string());
+        assert!(matches!(rt.block_on(network.register_async(form, &cancel)),
+                Err(AsyncErr::Cancelled)));
+        assert!(network.connect(service("idle")).is_err());
+    }
+
+    #[test]
+    fn objects_die() {
+        let network = LocalNetwork::new();
+        let object = network.spawn(|| {
+            let myself = LocalObject::myself();
+            OwnedObject::network(&myself)
+                .register(RegistrationForm::new(boom, "boom".to_string()))
+                .unwrap();
+        });
+        network.wait_for_service(&"boom".to_string());
+        assert!(object.is_alive());
+
+        // Panic in the handler kills the whole object.
+        let socket = network.connect(service("boom")).unwrap();
+        network.wait_for_death(&object.id());
+        assert!(!socket.is_opened());
+        assert!(matches!(object.exit_reason(), Some(ExitReason::Panicked(_))));
+        assert!(network.snapshot().entries.is_empty());
+
+        let idle = network.spawn(|| ());
+        network.wait_for_death(&idle.id());
+        assert_eq!(idle.exit_reason(), Some(ExitReason::Normal));
+        assert!(idle.kill().is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Compatibility manifests. At connect, the peers exchange compact
+//! manifests with the protocol version, hashes of the message schemas
+//! and feature flags they use. Mismatch fails the connect with
+//! 'ConnectErr::Incompatible' right away, instead of mysterious decode
+//! failures somewhere in the middle of the stream.
+
+use super::{ConnectErr, Data, Object, OpenNetwork, Service, Socket};
+
+/// What the peer speaks on the channel.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Manifest {
+
+    /// Version of the protocol. Peers must have the same.
+    pub protocol    : u32,
+
+    /// Names of the message schemas with their hashes. Schemas known to
+    /// both peers must have the same hashes.
+    pub schemas     : Vec<(String, u64)>,
+
+    /// Features the peer supports.
+    pub features    : Vec<String>,
+
+    /// Features the peer needs from the other side.
+    pub requires    : Vec<String>,
+}
+
+impl Data for Manifest {
+}
+
+impl Manifest {
+
+    /// Create manifest of given protocol version.
+    pub fn new(protocol: u32) -> Self {
+        Manifest {
+            protocol,
+            ..Default::default()
+        }
+    }
+
+    /// Add the schema with the hash of its definition text.
+    pub fn schema(mut self, name: &str, definition: &str) -> Self {
+        self.schemas.push((name.to_string(), schema_hash(definition)));
+        self
+    }
+
+    /// Add the supported feature.
+    pub fn feature(mut self, name: &str) -> Self {
+        self.features.push(name.to_string());
+        self
+    }
+
+    /// Add the feature the other side must support.
+    pub fn require(mut self, name: &str) -> Self {
+        self.requires.push(name.to_string());
+        self
+    }
+
+    /// Check if the peers can talk to each other.
+    pub fn is_compatible(&self, theirs: &Manifest) -> bool {
+        let schemas_match = self.schemas.iter().all(|(name, hash)| {
+            theirs.schemas.iter()
+                .find(|(n, _)| n == name)
+                .is_none_or(|(_, h)| h == hash)
+        });
+        let supports = |m: &Manifest, required: &[String]| {
+            required.iter().all(|f| m.features.contains(f))
+        };
+        self.protocol == theirs.protocol
+            && schemas_match
+            && supports(theirs, &self.requires)
+            && supports(self, &theirs.requires)
+    }
+}
+
+/// Hash of the schema definition, 64-bit FNV-1a.
+pub fn schema_hash(definition: &str) -> u64 {
+    definition.bytes().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Socket of the channel which peers exchanged manifests.
+pub trait ManifestSocket<O, S>: Socket<O, S>
+        where O: Object<S>, S: Service {
+
+    /// Manifest of the other side of the channel. Empty manifest if the
+    /// other side gave none.
+    fn peer_manifest(&self) -> &Manifest;
+}
+
+/// Network that compares the manifests of the peers at connect.
+pub trait ManifestNetwork<S>: OpenNetwork<S> where S: Service {
+
+    /// Connect to a provider whose manifest is compatible with ours.
+    /// Providers without a manifest accept any requester. Fails with
+    /// 'ConnectErr::Incompatible' if no provider is compatible.
+    fn connect_with_manifest(&self, service: S, ours: Manifest)
+        -> Result<Self::Socket, ConnectErr<S>>;
+}
+
+/// Manifest of the peer that gave none.
+pub static EMPTY: Manifest = Manifest {
+    protocol    : 0,
+    schemas     : Vec::new(),
+    features    : Vec::new(),
+    requires    : Vec::new(),
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compatibility() {
+        let ours = Manifest::new(1)
+            .schema("request", "struct Request { id: u32 }")
+            .feature("compression")
+            .require("checksum");
+        let theirs = Manifest::new(1)
+            .schema("request", "struct Request { id: u32 }")
+            .schema("extra", "struct Extra")
+            .feature("checksum");
+        assert!(ours.is_compatible(&theirs));
+        assert!(!ours.is_compatible(&Manifest::new(1)
+            .schema("request", "struct Request { id: u64 }")
+            .feature("checksum")));
+        assert!(!ours.is_compatible(&theirs.clone().require("encryption")));
+        assert!(!ours.is_compatible(&Manifest::new(2).feature("checksum")));
+    }
+}
@@ -0,0 +1,155 @@
+//! Memory accounting of the queues. Backends count the bytes queued in
+//! each channel and in all channels of each receiving object, expose
+//! them as metrics, and refuse sends beyond the configured caps with
+//! 'SocketErr::MemoryLimit'. A slow consumer then shows up and fails
+//! loudly instead of silently eating all memory of the system.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use super::SocketErr;
+use metrics::{MetricFamily, MetricKind, MetricsSource, Sample};
+
+/// Caps of the queued bytes. None means no cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryCaps {
+    pub per_channel : Option<u64>,
+    pub per_object  : Option<u64>,
+}
+
+/// Bytes queued in the channels, grouped by channel 'C' and by the
+/// receiving object 'O'.
+pub struct MemoryAccount<C, O> {
+    caps    : Mutex<MemoryCaps>,
+    state   : Mutex<(HashMap<C, u64>, HashMap<O, u64>)>,
+}
+
+impl<C, O> MemoryAccount<C, O>
+        where   C   : Hash + Eq + Clone + ToString,
+                O   : Hash + Eq + Clone + ToString
+{
+
+    /// Create the account with given caps.
+    pub fn new(caps: MemoryCaps) -> Self {
+        MemoryAccount {
+            caps    : Mutex::new(caps),
+            state   : Mutex::new((HashMap::new(), HashMap::new())),
+        }
+    }
+
+    /// Replace the caps. Bytes queued already are not refused even if
+    /// they exceed the new caps.
+    pub fn set_caps(&self, caps: MemoryCaps) {
+        *self.caps.lock().unwrap() = caps;
+    }
+
+    /// Current caps.
+    pub fn caps(&self) -> MemoryCaps {
+        *self.caps.lock().unwrap()
+    }
+
+    /// Count the message queued to the object over the channel. Fails
+    /// without counting if any cap would be exceeded.
+    pub fn enqueue(&self, channel: &C, receiver: &O, bytes: u64)
+        -> Result<(), SocketErr>
+    {
+        let caps = self.caps();
+        let mut state = self.state.lock().unwrap();
+        let in_channel = state.0.get(channel).cloned().unwrap_or(0) + bytes;
+        let in_object = state.1.get(receiver).cloned().unwrap_or(0) + bytes;
+        if caps.per_channel.is_some_and(|cap| in_channel > cap)
+                || caps.per_object.is_some_and(|cap| in_object > cap) {
+            return Err(SocketErr::MemoryLimit);
+        }
+        state.0.insert(channel.clone(), in_channel);
+        state.1.insert(receiver.clone(), in_object);
+        Ok(())
+    }
+
+    /// Count the message as received.
+    pub fn dequeue(&self, channel: &C, receiver: &O, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(n) = state.0.get_mut(channel) {
+            *n = n.saturating_sub(bytes);
+        }
+        if let Some(n) = state.1.get_mut(receiver) {
+            *n = n.saturating_sub(bytes);
+        }
+    }
+
+    /// Forget the closed channel. Its queued bytes are released.
+    pub fn close_channel(&self, channel: &C, receiver: &O) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(bytes) = state.0.remove(channel) {
+            if let Some(n) = state.1.get_mut(receiver) {
+                *n = n.saturating_sub(bytes);
+            }
+        }
+    }
+
+    /// Bytes queued in the channel.
+    pub fn channel_bytes(&self, channel: &C) -> u64 {
+        self.state.lock().unwrap().0.get(channel).cloned().unwrap_or(0)
+    }
+
+    /// Bytes queued to the object over all its channels.
+    pub fn object_bytes(&self, object: &O) -> u64 {
+        self.state.lock().unwrap().1.get(object).cloned().unwrap_or(0)
+    }
+}
+
+impl<C, O> MetricsSource for MemoryAccount<C, O>
+        where   C   : Hash + Eq + Clone + ToString,
+                O   : Hash + Eq + Clone + ToString
+{
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let state = self.state.lock().unwrap();
+        let samples = |label: &str, map: Vec<(String, u64)>| map.into_iter()
+            .map(|(k, v)| Sample {
+                labels  : vec![(label.to_string(), k)],
+                value   : v as f64,
+            })
+            .collect();
+        vec![
+            MetricFamily {
+                name    : "ccs_channel_queued_bytes".to_string(),
+                help    : "Bytes queued in the channel.".to_string(),
+                kind    : MetricKind::Gauge,
+                samples : samples("channel", state.0.iter()
+                    .map(|(k, &v)| (k.to_string(), v)).collect()),
+            },
+            MetricFamily {
+                name    : "ccs_object_queued_bytes_total".to_string(),
+                help    : "Bytes queued to the object over all channels.".to_string(),
+                kind    : MetricKind::Gauge,
+                samples : samples("object", state.1.iter()
+                    .map(|(k, &v)| (k.to_string(), v)).collect()),
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_turn_into_errors() {
+        let account = MemoryAccount::new(MemoryCaps {
+            per_channel : Some(100),
+            per_object  : Some(150),
+        });
+        account.enqueue(&1, &"slow", 100).unwrap();
+        assert!(account.enqueue(&1, &"slow", 1).is_err());
+        account.enqueue(&2, &"slow", 50).unwrap();
+        assert!(account.enqueue(&3, &"slow", 1).is_err());
+
+        account.dequeue(&1, &"slow", 60);
+        assert_eq!(account.object_bytes(&"slow"), 90);
+        account.close_channel(&2, &"slow");
+        assert_eq!(account.object_bytes(&"slow"), 40);
+        assert_eq!(account.collect()[0].samples.len(), 1);
+    }
+}
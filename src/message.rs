@@ -0,0 +1,345 @@
+//! Typed messages. 'Data' only marks what may go over the channel, so
+//! services that talk to other programs exchange byte payloads and each
+//! of them frames the bytes in its own way. 'Message' gives the types a
+//! common binary encoding and 'TypedSocket' sends and receives them, so
+//! services exchange strongly-typed requests and responses.
+//!
+//! Encoding is little-endian. Sequences are prefixed with their length
+//! as 'u32', options with a byte 0 or 1.
+//!
+//! Typed sockets send the payloads as 'SmallData', so short messages
+//! are encoded into a reused buffer and sent without an allocation.
+//!
+//! With the 'serde' feature, any type that implements 'Serialize' and
+//! 'DeserializeOwned' is a message when wrapped into 'Serde', encoded
+//! the same way as the types here.
+
+use std::cell::RefCell;
+use std::convert::{TryFrom, TryInto};
+use std::marker::PhantomData;
+use std::mem;
+
+use super::{Object, Service, Socket, SocketErr};
+use data::{SmallData, INLINE_LEN};
+
+#[cfg(feature = "serde")]
+pub use serde_message::Serde;
+
+thread_local! {
+    /// Buffer the messages are encoded into before they are sent.
+    static SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Error of the message decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeErr {
+
+    /// Payload ended in the middle of the message.
+    Truncated,
+
+    /// Payload has bytes after the end of the message.
+    Trailing(usize),
+
+    /// Payload holds a value that is not valid for the type.
+    Invalid(&'static str),
+
+    /// Serde implementation of the type rejected the payload.
+    Custom(String),
+}
+
+/// Error of the message encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeErr {
+
+    /// Sequence is longer than its 'u32' length prefix can hold. Holds
+    /// the length.
+    TooLong(usize),
+
+    /// Serde implementation of the type can't express the value in the
+    /// encoding.
+    Custom(String),
+}
+
+/// Value with a binary encoding.
+pub trait Message: Sized {
+
+    /// Append the encoding of the value. On error, 'out' may hold a
+    /// part of the encoding.
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr>;
+
+    /// Decode the value from the start of the input and advance the
+    /// input past it.
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr>;
+
+    /// Encode the value into new payload.
+    fn to_bytes(&self) -> Result<Vec<u8>, EncodeErr> {
+        let mut out = Vec::new();
+        self.encode(&mut out)?;
+        Ok(out)
+    }
+
+    /// Encode the value into new payload that keeps short encodings
+    /// inline.
+    fn to_small_data(&self) -> Result<SmallData, EncodeErr> {
+        SCRATCH.with(|scratch| {
+            let mut scratch = match scratch.try_borrow_mut() {
+                Ok(scratch) => scratch,
+
+                // Encoding of some value sends another one.
+                Err(_)      => return self.to_bytes().map(SmallData::from),
+            };
+            scratch.clear();
+            self.encode(&mut scratch)?;
+            if scratch.len() <= INLINE_LEN {
+                Ok(SmallData::from_slice(&scratch))
+            } else {
+                Ok(SmallData::from(mem::take(&mut *scratch)))
+            }
+        })
+    }
+
+    /// Decode the payload that holds exactly one value.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeErr> {
+        let mut input = bytes;
+        let value = Self::decode(&mut input)?;
+        match input.len() {
+            0       => Ok(value),
+            rest    => Err(DecodeErr::Trailing(rest)),
+        }
+    }
+}
+
+/// Take given count of bytes from the input.
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeErr> {
+    if input.len() < len {
+        return Err(DecodeErr::Truncated);
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Ok(head)
+}
+
+macro_rules! int_message {
+    ($($t:ty),*) => {$(
+        impl Message for $t {
+
+            fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+                out.extend_from_slice(&self.to_le_bytes());
+                Ok(())
+            }
+
+            fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+                let bytes = take(input, ::std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        }
+    )*}
+}
+
+int_message!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl Message for bool {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        out.push(*self as u8);
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        match u8::decode(input)? {
+            0   => Ok(false),
+            1   => Ok(true),
+            _   => Err(DecodeErr::Invalid("bool")),
+        }
+    }
+}
+
+/// Append the length prefix of a sequence.
+pub fn encode_len(len: usize, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+    u32::try_from(len).map_err(|_| EncodeErr::TooLong(len))?.encode(out)
+}
+
+/// Decode the length prefix of a sequence.
+pub fn decode_len(input: &mut &[u8]) -> Result<usize, DecodeErr> {
+    u32::decode(input).map(|len| len as usize)
+}
+
+impl Message for String {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        encode_len(self.len(), out)?;
+        out.extend_from_slice(self.as_bytes());
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        let len = decode_len(input)?;
+        String::from_utf8(take(input, len)?.to_vec())
+            .map_err(|_| DecodeErr::Invalid("string"))
+    }
+}
+
+impl<T: Message> Message for Vec<T> {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        encode_len(self.len(), out)?;
+        for item in self {
+            item.encode(out)?;
+        }
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        let len = decode_len(input)?;
+
+        // Length is not trusted for the allocation: each item takes at
+        // least a byte, except for empty tuples which nobody sends.
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Message> Message for Option<T> {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        match *self {
+            None            => {
+                out.push(0);
+                Ok(())
+            },
+            Some(ref value) => {
+                out.push(1);
+                value.encode(out)
+            },
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        match bool::decode(input).map_err(|_| DecodeErr::Invalid("option"))? {
+            false   => Ok(None),
+            true    => T::decode(input).map(Some),
+        }
+    }
+}
+
+impl<A: Message, B: Message> Message for (A, B) {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        self.0.encode(out)?;
+        self.1.encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+/// Error of the typed socket.
+#[derive(Debug)]
+pub enum TypedErr {
+
+    /// Channel failed.
+    Socket(SocketErr),
+
+    /// Message to send can't be encoded.
+    Encode(EncodeErr),
+
+    /// Received payload is not a valid message.
+    Decode(DecodeErr),
+}
+
+/// Socket that sends and receives messages of type 'T'. Services that
+/// send different messages in different directions or states use an
+/// enum of all of them as 'T'. Messages go as byte payloads, so the
+/// peer may be written in any language that speaks the encoding.
+pub struct TypedSocket<O, S, SC, T> {
+    socket  : SC,
+    _m      : PhantomData<(O, S, T)>,
+}
+
+impl<O, S, SC, T> TypedSocket<O, S, SC, T>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                T   : Message
+{
+
+    /// Wrap the socket.
+    pub fn new(socket: SC) -> Self {
+        TypedSocket {
+            socket,
+            _m      : PhantomData,
+        }
+    }
+
+    /// Underlying socket.
+    pub fn socket(&self) -> &SC {
+        &self.socket
+    }
+
+    /// Unwrap the socket.
+    pub fn into_inner(self) -> SC {
+        self.socket
+    }
+
+    /// Send the message and wait until the peer receives it.
+    pub fn send(&self, message: &T) -> Result<(), TypedErr> {
+        let payload = message.to_small_data().map_err(TypedErr::Encode)?;
+        self.socket.send(payload).map_err(TypedErr::Socket)
+    }
+
+    /// Wait for the next message.
+    pub fn receive(&self) -> Result<T, TypedErr> {
+        let bytes = self.socket.receive::<SmallData>().map_err(TypedErr::Socket)?;
+        T::from_bytes(&bytes).map_err(TypedErr::Decode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use {OpenNetwork, RegistrationForm};
+
+    fn echo(socket: LocalSocket) -> ! {
+        while let Ok(bytes) = socket.receive::<Vec<u8>>() {
+            if socket.send(bytes).is_err() {
+                break;
+            }
+        }
+        finish()
+    }
+
+    #[test]
+    fn encoding() {
+        let value = (7u32, vec![Some("a".to_string()), None]);
+        let bytes = value.to_bytes().unwrap();
+        assert_eq!(bytes, [7, 0, 0, 0, 2, 0, 0, 0, 1, 1, 0, 0, 0, b'a', 0]);
+        assert_eq!(Message::from_bytes(&bytes), Ok(value));
+        assert_eq!(u32::from_bytes(&[1, 0]), Err(DecodeErr::Truncated));
+        assert_eq!(u8::from_bytes(&[1, 0]), Err(DecodeErr::Trailing(1)));
+        assert_eq!(bool::from_bytes(&[2]), Err(DecodeErr::Invalid("bool")));
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn length_over_prefix() {
+        let mut out = Vec::new();
+        let len = u32::MAX as usize + 1;
+        assert_eq!(encode_len(len, &mut out), Err(EncodeErr::TooLong(len)));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn typed_socket() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let socket = network.connect(LocalService::by_id("echo".to_string())).unwrap();
+        let typed = TypedSocket::new(socket);
+        typed.send(&(1u64, "one".to_string())).unwrap();
+        assert_eq!(typed.receive().unwrap(), (1, "one".to_string()));
+    }
+}
@@ -0,0 +1,296 @@
+//! Per-message authorization for especially sensitive services. Normally
+//! the policy is checked once at connect and the channel is trusted
+//! after that. In this mode each message carries a proof as well: a MAC
+//! under the key that both ends derive in the handshake from the secret
+//! they share, e.g. one bound to the capability that authorized the
+//! connect, and from the nonces each end picks. The MAC covers the
+//! sequence number of the message, so a message can't be forged,
+//! replayed or reordered by whoever moves the bytes between the ends.
+//! SipHash-2-4 keeps the check cheap enough to run on every message.
+//!
+//! The check is a layer over any socket, and 'SocketErr::Unauthenticated'
+//! comes only from 'AuthSocket'. Networks don't seal the messages
+//! themselves: the local one moves them within the process, where
+//! nobody stands between the ends.
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::marker::PhantomData;
+
+use super::{Data, Object, Service, Socket, SocketErr};
+
+/// Secret shared by the ends of the channel.
+pub type Secret = [u8; 16];
+
+/// Authorization mode of the channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageAuth {
+
+    /// Only the connect is authorized. Messages are sent as they are.
+    Off,
+
+    /// Each message carries the MAC derived from the handshake.
+    Mac,
+}
+
+/// Nonce each end sends in the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthHello(pub u64);
+
+impl Data for AuthHello {
+}
+
+/// Message with its proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sealed {
+
+    /// Number of the message in its direction, from zero.
+    pub seq     : u64,
+
+    /// MAC of the number and the bytes. Zero when the mode is off.
+    pub tag     : u64,
+
+    /// The message itself.
+    pub bytes   : Vec<u8>,
+}
+
+impl Data for Sealed {
+}
+
+/// Socket wrapper that seals each message.
+pub struct AuthSocket<'a, O, S, SC: 'a> {
+    socket  : &'a SC,
+    mode    : MessageAuth,
+
+    /// Keys of the outgoing and incoming directions.
+    send_key    : Secret,
+    receive_key : Secret,
+
+    sent        : Cell<u64>,
+    received    : Cell<u64>,
+    _os         : PhantomData<(O, S)>,
+}
+
+impl<'a, O, S, SC> AuthSocket<'a, O, S, SC>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+{
+
+    /// Wrap the socket on the side that connected and make the
+    /// handshake. Both sides must use the same mode and secret.
+    pub fn requester(socket: &'a SC, mode: MessageAuth, secret: &Secret)
+        -> Result<Self, SocketErr>
+    {
+        Self::handshake(socket, mode, secret, true)
+    }
+
+    /// Wrap the socket on the side of the provider and make the
+    /// handshake.
+    pub fn provider(socket: &'a SC, mode: MessageAuth, secret: &Secret)
+        -> Result<Self, SocketErr>
+    {
+        Self::handshake(socket, mode, secret, false)
+    }
+
+    fn handshake(socket: &'a SC, mode: MessageAuth, secret: &Secret, requester: bool)
+        -> Result<Self, SocketErr>
+    {
+        let mut auth = AuthSocket {
+            socket,
+            mode,
+            send_key    : [0; 16],
+            receive_key : [0; 16],
+            sent        : Cell::new(0),
+            received    : Cell::new(0),
+            _os         : PhantomData,
+        };
+        if mode == MessageAuth::Off {
+            return Ok(auth);
+        }
+        let ours = nonce();
+        let theirs = if requester {
+            socket.send(AuthHello(ours))?;
+            socket.receive::<AuthHello>()?.0
+        } else {
+            let theirs = socket.receive::<AuthHello>()?.0;
+            socket.send(AuthHello(ours))?;
+            theirs
+        };
+        let (requester_nonce, provider_nonce) = if requester {
+            (ours, theirs)
+        } else {
+            (theirs, ours)
+        };
+        let to_provider = derive(secret, requester_nonce, provider_nonce, 0);
+        let to_requester = derive(secret, requester_nonce, provider_nonce, 1);
+        if requester {
+            auth.send_key = to_provider;
+            auth.receive_key = to_requester;
+        } else {
+            auth.send_key = to_requester;
+            auth.receive_key = to_provider;
+        }
+        Ok(auth)
+    }
+
+    /// Authorization mode of this channel.
+    pub fn mode(&self) -> MessageAuth {
+        self.mode
+    }
+
+    /// Send the bytes with their proof.
+    pub fn send(&self, bytes: Vec<u8>) -> Result<(), SocketErr> {
+        let seq = self.sent.get();
+        let tag = match self.mode {
+            MessageAuth::Off    => 0,
+            MessageAuth::Mac    => mac(&self.send_key, seq, &bytes),
+        };
+        self.socket.send(Sealed { seq, tag, bytes })?;
+        self.sent.set(seq + 1);
+        Ok(())
+    }
+
+    /// Receive the bytes and verify their proof. Fails with
+    /// 'SocketErr::Unauthenticated' if the message is forged, replayed
+    /// or out of order.
+    pub fn receive(&self) -> Result<Vec<u8>, SocketErr> {
+        let message = self.socket.receive::<Sealed>()?;
+        if self.mode == MessageAuth::Mac {
+            let seq = self.received.get();
+            if message.seq != seq || mac(&self.receive_key, seq, &message.bytes) != message.tag {
+                return Err(SocketErr::Unauthenticated);
+            }
+            self.received.set(seq + 1);
+        }
+        Ok(message.bytes)
+    }
+}
+
+/// Random nonce of the handshake.
+fn nonce() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Key of one direction of the channel.
+fn derive(secret: &Secret, requester: u64, provider: u64, direction: u8) -> Secret {
+    let mut input = Vec::with_capacity(18);
+    input.extend_from_slice(&requester.to_le_bytes());
+    input.extend_from_slice(&provider.to_le_bytes());
+    input.push(direction);
+    let mut key = [0; 16];
+    input.push(0);
+    key[..8].copy_from_slice(&siphash(secret, &input).to_le_bytes());
+    *input.last_mut().unwrap() = 1;
+    key[8..].copy_from_slice(&siphash(secret, &input).to_le_bytes());
+    key
+}
+
+fn mac(key: &Secret, seq: u64, bytes: &[u8]) -> u64 {
+    let mut input = Vec::with_capacity(8 + bytes.len());
+    input.extend_from_slice(&seq.to_le_bytes());
+    input.extend_from_slice(bytes);
+    siphash(key, &input)
+}
+
+/// SipHash-2-4 of the bytes under the key.
+pub fn siphash(key: &Secret, bytes: &[u8]) -> u64 {
+    let word = |b: &[u8]| {
+        let mut w = [0u8; 8];
+        w[..b.len()].copy_from_slice(b);
+        u64::from_le_bytes(w)
+    };
+    let k0 = word(&key[..8]);
+    let k1 = word(&key[8..]);
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+    let round = |v: &mut [u64; 4]| {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    };
+    let compress = |v: &mut [u64; 4], m: u64| {
+        v[3] ^= m;
+        round(v);
+        round(v);
+        v[0] ^= m;
+    };
+
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        compress(&mut v, word(chunk));
+    }
+    let last = word(chunks.remainder()) | ((bytes.len() as u64 & 0xff) << 56);
+    compress(&mut v, last);
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use {OpenNetwork, RegistrationForm};
+
+    #[test]
+    fn siphash_vectors() {
+        let mut key = [0; 16];
+        for (i, k) in key.iter_mut().enumerate() {
+            *k = i as u8;
+        }
+        assert_eq!(siphash(&key, b""), 0x726f_db47_dd0e_0e31);
+        let bytes: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash(&key, &bytes), 0xa129_ca61_49be_45e5);
+    }
+
+    const SECRET: Secret = *b"sixteen byte key";
+
+    /// Replies to each authorized message with its length.
+    fn vault(socket: LocalSocket) -> ! {
+        if let Ok(auth) = AuthSocket::provider(&socket, MessageAuth::Mac, &SECRET) {
+            loop {
+                let reply = match auth.receive() {
+                    Ok(bytes)                       => bytes.len().to_string(),
+                    Err(SocketErr::Unauthenticated) => "forged".to_string(),
+                    Err(_)                          => break,
+                };
+                if auth.send(reply.into_bytes()).is_err() {
+                    break;
+                }
+            }
+        }
+        finish()
+    }
+
+    #[test]
+    fn sealed_messages() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(vault, "vault".to_string())).unwrap();
+        let socket = network.connect(LocalService::by_id("vault".to_string())).unwrap();
+        let auth = AuthSocket::requester(&socket, MessageAuth::Mac, &SECRET).unwrap();
+
+        auth.send(b"open".to_vec()).unwrap();
+        assert_eq!(auth.receive().unwrap(), b"4");
+
+        // Replay of the first message.
+        let tag = mac(&auth.send_key, 0, b"open");
+        socket.send(Sealed { seq: 0, tag, bytes: b"open".to_vec() }).unwrap();
+        assert_eq!(socket.receive::<Sealed>().unwrap().bytes, b"forged");
+    }
+}
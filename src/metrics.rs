@@ -0,0 +1,53 @@
+//! Metrics of the network. Backends and objects expose their counters
+//! and gauges through 'MetricsSource' so that exporters can collect
+//! them without knowing where they come from.
+
+#[cfg(feature = "openmetrics")]
+pub mod openmetrics;
+
+/// Kind of the metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+
+    /// Value that only grows, e.g. count of sent messages.
+    Counter,
+
+    /// Value that can go up and down, e.g. count of open channels.
+    Gauge,
+}
+
+/// Single value of the metric with its labels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+
+    /// Label names and values that separate this sample from the other
+    /// samples of the same metric.
+    pub labels  : Vec<(String, String)>,
+
+    /// Value of the sample.
+    pub value   : f64,
+}
+
+/// All samples of one metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFamily {
+
+    /// Name of the metric.
+    pub name    : String,
+
+    /// Human-readable description of the metric.
+    pub help    : String,
+
+    /// Kind of the metric.
+    pub kind    : MetricKind,
+
+    /// Current samples.
+    pub samples : Vec<Sample>,
+}
+
+/// Something that has metrics to report.
+pub trait MetricsSource {
+
+    /// Collect current values of all metrics.
+    fn collect(&self) -> Vec<MetricFamily>;
+}
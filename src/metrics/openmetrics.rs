@@ -0,0 +1,147 @@
+//! Exporter of the metrics in OpenMetrics text format, so that standard
+//! monitoring stacks can scrape a Kobzar node. Metrics are served over
+//! a CCS service and, on hosted targets, over plain HTTP.
+
+use std::fmt::Write;
+use std::io::{self, Read as IoRead, Write as IoWrite};
+use std::net::{TcpListener, ToSocketAddrs};
+
+use super::{MetricKind, MetricsSource};
+use {Data, Object, Service, Socket, SocketErr};
+
+/// Request for the current metrics sent by a scraper over the channel.
+/// The exporter replies with the text of all metrics.
+#[derive(Debug, Clone, Copy)]
+pub struct Scrape;
+
+impl Data for Scrape {
+}
+
+/// Exporter that aggregates metrics of several sources.
+#[derive(Default)]
+pub struct Exporter {
+    sources : Vec<Box<dyn MetricsSource + Send + Sync>>,
+}
+
+impl Exporter {
+
+    /// Create exporter without sources.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add source which metrics will be exported.
+    pub fn add_source<M>(&mut self, source: M)
+        where M: MetricsSource + Send + Sync + 'static
+    {
+        self.sources.push(Box::new(source));
+    }
+
+    /// Render metrics of all sources in OpenMetrics text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for family in self.sources.iter().flat_map(|s| s.collect()) {
+            let (kind, suffix) = match family.kind {
+                MetricKind::Counter => ("counter", "_total"),
+                MetricKind::Gauge   => ("gauge", ""),
+            };
+            let _ = writeln!(out, "# TYPE {} {}", family.name, kind);
+            let _ = writeln!(out, "# HELP {} {}", family.name,
+                    escape(&family.help, false));
+            for sample in &family.samples {
+                out.push_str(&family.name);
+                out.push_str(suffix);
+                if !sample.labels.is_empty() {
+                    let labels: Vec<_> = sample.labels.iter()
+                        .map(|(k, v)| format!("{}=\"{}\"", k, escape(v, true)))
+                        .collect();
+                    let _ = write!(out, "{{{}}}", labels.join(","));
+                }
+                let _ = writeln!(out, " {}", sample.value);
+            }
+        }
+        out.push_str("# EOF\n");
+        out
+    }
+
+    /// Serve the scrapes received over the channel until it gets closed.
+    pub fn serve<O, S, SC>(&self, socket: &SC) -> Result<(), SocketErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+    {
+        loop {
+            socket.receive::<Scrape>()?;
+            socket.send(self.render())?;
+        }
+    }
+
+    /// Serve the metrics over HTTP on given address. Any request gets
+    /// the metrics in response. Blocks current thread forever unless
+    /// the listener fails.
+    pub fn serve_http<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        for stream in listener.incoming() {
+            let mut stream = stream?;
+
+            // Request itself is not interesting, only read its head.
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf)?;
+
+            let body = self.render();
+            let _ = write!(stream,
+                "HTTP/1.0 200 OK\r\n\
+                 Content-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\n\
+                 Content-Length: {}\r\n\r\n{}", body.len(), body);
+        }
+        Ok(())
+    }
+}
+
+/// Escape the text for HELP line or label value.
+fn escape(s: &str, quote: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\'            => out.push_str("\\\\"),
+            '\n'            => out.push_str("\\n"),
+            '"' if quote    => out.push_str("\\\""),
+            c               => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::{MetricFamily, Sample};
+
+    struct Channels;
+
+    impl MetricsSource for Channels {
+
+        fn collect(&self) -> Vec<MetricFamily> {
+            vec![MetricFamily {
+                name    : "ccs_messages".to_string(),
+                help    : "Sent messages.".to_string(),
+                kind    : MetricKind::Counter,
+                samples : vec![Sample {
+                    labels  : vec![("service".to_string(), "a\"b".to_string())],
+                    value   : 3.0,
+                }],
+            }]
+        }
+    }
+
+    #[test]
+    fn render_counter() {
+        let mut exporter = Exporter::new();
+        exporter.add_source(Channels);
+        assert_eq!(exporter.render(),
+            "# TYPE ccs_messages counter\n\
+             # HELP ccs_messages Sent messages.\n\
+             ccs_messages_total{service=\"a\\\"b\"} 3\n\
+             # EOF\n");
+    }
+}
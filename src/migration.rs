@@ -0,0 +1,91 @@
+//! Live migration of objects between nodes of bridged or distributed
+//! networks. Migration goes in three steps: the object is frozen on the
+//! source node, its registrations (and optionally open channels) with
+//! the state are transferred to a new instance on the target node and
+//! then the new instance is resumed. Requesters of the migrated
+//! services do not need to reconnect.
+
+use super::{Network, ObjectId, Service};
+use checkpoint::Checkpoint;
+
+/// Error that can appear during migration.
+#[derive(Debug)]
+pub enum MigrationErr {
+
+    /// Object with given identifier does not exist or is not alive.
+    NoObject,
+
+    /// Target node is not reachable or does not belong to the network.
+    NoNode,
+
+    /// Object is already being migrated.
+    InProgress,
+
+    /// Target node refused to accept the object, e.g. because it lacks
+    /// resources or the program image of the object.
+    Refused,
+
+    /// Open channels were requested to be moved but the network
+    /// cannot move channels between given nodes.
+    ChannelsNotMovable,
+}
+
+/// Options of the migration.
+#[derive(Debug, Clone)]
+pub struct MigrationPlan<N> {
+
+    /// Node that will run new instance of the object.
+    pub target      : N,
+
+    /// Whether open channels must be moved too. When false, open
+    /// channels are closed when object is frozen and only the
+    /// registrations are moved.
+    pub channels    : bool,
+
+    /// State that is given to the new instance.
+    pub state       : Option<Checkpoint>,
+}
+
+/// Network that can move objects between its nodes. This is part of
+/// the administration API and is only available to the objects that
+/// manage the network.
+pub trait MigratingNetwork<S: Service>: Network<S> {
+
+    /// Identifier of the node in the network.
+    type Node;
+
+    /// Handle of the migration that is in progress.
+    type Ticket;
+
+    /// Freeze the object so that it could be migrated. Senders to the
+    /// object's channels wait until migration finishes.
+    fn freeze_for_migration(&self, id: &ObjectId<S, Self>)
+        -> Result<Self::Ticket, MigrationErr>;
+
+    /// Transfer registrations of the frozen object and the state to
+    /// the new instance on the target node.
+    fn transfer(&self, ticket: &Self::Ticket, plan: MigrationPlan<Self::Node>)
+        -> Result<(), MigrationErr>;
+
+    /// Resume the new instance. Channels and registrations are served
+    /// by the new instance from now on and the old one is killed.
+    /// If resume fails, the network aborts the migration itself.
+    fn resume(&self, ticket: Self::Ticket) -> Result<(), MigrationErr>;
+
+    /// Abort the migration. The object continues to run on the source
+    /// node as if migration never started.
+    fn abort(&self, ticket: Self::Ticket);
+
+    /// Run all steps of the migration. If transfer fails, migration is
+    /// aborted.
+    fn migrate(&self, id: &ObjectId<S, Self>, plan: MigrationPlan<Self::Node>)
+        -> Result<(), MigrationErr>
+    {
+        let ticket = self.freeze_for_migration(id)?;
+        if let Err(e) = self.transfer(&ticket, plan) {
+            self.abort(ticket);
+            return Err(e);
+        }
+        self.resume(ticket)
+    }
+}
@@ -0,0 +1,87 @@
+//! Test doubles of services. In test mode the harness stubs selected
+//! services of the network: connects to them go to the doubles it
+//! registered instead of the real providers, while all other services
+//! are left as is. Higher-level objects can then be tested against the
+//! real network with only some dependencies stubbed out.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use super::{Form, OpenNetwork, RegistrationErr, Service};
+
+/// Open network that can route connects to test doubles.
+pub trait MockNetwork<S: Service>: OpenNetwork<S> {
+
+    /// Route all following connects to the service with the identifier
+    /// from the form to its entry function. Replaces previous double
+    /// of the same service. Channels that are already open are not
+    /// affected. Only the harness, i.e. the owner of the network, may
+    /// stub the services.
+    fn stub(&self, double: Form<S, Self>) -> Result<(), RegistrationErr>;
+
+    /// Route connects to the service back to the real providers.
+    /// Returns false if the service was not stubbed.
+    fn unstub(&self, id: &S::Id) -> bool;
+
+    /// Count of connects that went to the double of the service.
+    fn stub_hits(&self, id: &S::Id) -> usize;
+}
+
+/// Table of the doubles for backends to keep. 'H' is whatever the
+/// backend needs to start the double, e.g. its entry function.
+pub struct StubTable<Id, H> {
+    stubs   : Mutex<HashMap<Id, (H, usize)>>,
+}
+
+impl<Id, H> Default for StubTable<Id, H> where Id: Hash + Eq {
+
+    fn default() -> Self {
+        StubTable {
+            stubs   : Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<Id, H> StubTable<Id, H> where Id: Hash + Eq, H: Clone {
+
+    /// Add the double of the service. Gives the double it replaces.
+    pub fn insert(&self, id: Id, double: H) -> Option<H> {
+        self.stubs.lock().unwrap().insert(id, (double, 0)).map(|entry| entry.0)
+    }
+
+    /// Remove the double of the service.
+    pub fn remove(&self, id: &Id) -> Option<H> {
+        self.stubs.lock().unwrap().remove(id).map(|entry| entry.0)
+    }
+
+    /// Find the double for the connect and count the hit. None if the
+    /// connect should go to the real providers.
+    pub fn route(&self, id: &Id) -> Option<H> {
+        self.stubs.lock().unwrap().get_mut(id).map(|entry| {
+            entry.1 += 1;
+            entry.0.clone()
+        })
+    }
+
+    /// Count of connects routed to the double.
+    pub fn hits(&self, id: &Id) -> usize {
+        self.stubs.lock().unwrap().get(id).map_or(0, |entry| entry.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_stubbed_are_routed() {
+        let table = StubTable::default();
+        table.insert("clock", "fake-clock");
+        assert_eq!(table.route(&"clock"), Some("fake-clock"));
+        assert_eq!(table.route(&"disk"), None);
+        assert_eq!(table.hits(&"clock"), 1);
+        assert_eq!(table.remove(&"clock"), Some("fake-clock"));
+        assert_eq!(table.route(&"clock"), None);
+    }
+}
@@ -0,0 +1,87 @@
+//! Panic hook. Unexpected panic in any thread of the object should not
+//! leave the object half-alive with its services still registered.
+//! Once installed, the hook ends the object of the panicking thread with
+//! 'ExitReason::Panicked', so the network discontinues its services and
+//! tells the monitors why the object is gone. The thread itself goes on
+//! unwinding. Panics in threads that belong to no object, like those of
+//! the host, are left alone.
+//!
+//! The hook also runs in programs built with 'panic = "abort"', before
+//! the abort. Aborts that do not come from a panic can't be caught from
+//! within the process and are left to the network to detect.
+
+use std::any::Any;
+use std::panic::{self, Location};
+
+use super::{ExitReason, Object, Service};
+
+/// Object which can be ended from within its panicking thread.
+pub trait PanickingObject<S: Service>: Object<S> {
+
+    /// End the object the current thread belongs to with given reason.
+    /// Unlike 'decease', returns so that the thread can continue to
+    /// unwind. False if the thread belongs to no object.
+    fn panicked(reason: ExitReason) -> bool;
+}
+
+/// Install the hook for the object of the current program. The hook
+/// that was installed before, e.g. the default one that prints the
+/// message, is run first.
+pub fn install<O, S>()
+        where   O   : PanickingObject<S> + 'static,
+                S   : Service + 'static
+{
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        previous(info);
+        O::panicked(ExitReason::Panicked(describe(info.payload(), info.location())));
+    }));
+}
+
+/// Message of the panic with its location.
+pub fn describe(payload: &dyn Any, location: Option<&Location>) -> String {
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "unknown panic"
+    };
+    match location {
+        Some(l) => format!("{} at {}:{}", message, l.file(), l.line()),
+        None    => message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use local::{LocalNetwork, LocalObject, LocalService};
+    use {Network, Object};
+
+    #[test]
+    fn panic_message() {
+        let payload: Box<dyn Any> = Box::new(String::from("boom"));
+        assert_eq!(describe(&*payload, None), "boom");
+        let payload: Box<dyn Any> = Box::new(1);
+        assert_eq!(describe(&*payload, None), "unknown panic");
+    }
+
+    #[test]
+    fn hook_ends_only_objects() {
+        install::<LocalObject, LocalService>();
+
+        // Host thread unwinds as without the hook.
+        let host = thread::spawn(|| panic!("host"));
+        assert!(host.join().is_err());
+
+        let network = LocalNetwork::new();
+        let object = network.spawn(|| panic!("object"));
+        network.wait_for_death(&object.id());
+        match object.exit_reason() {
+            Some(ExitReason::Panicked(message)) => assert!(message.starts_with("object at ")),
+            other   => panic!("unexpected exit {:?}", other),
+        }
+    }
+}
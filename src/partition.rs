@@ -0,0 +1,172 @@
+//! Partitioned services. Stateful services such as caches and storage
+//! shards are provided by several objects, each holding its part of the
+//! data, so the requester must reach the same provider for the same key
+//! every time. Connects with a partition key are routed by consistent
+//! hashing: each provider takes a number of points on the ring and the
+//! key goes to the provider of the first point after its hash. When a
+//! provider comes or goes, only the keys of its arcs move.
+
+use std::hash::{Hash, Hasher};
+
+use super::{ConnectErr, OpenNetwork, Service};
+
+/// Points each node takes on the ring by default.
+pub const REPLICAS: usize = 64;
+
+/// FNV-1a with a final mix. Hash of the key must not change between
+/// processes and builds, which 'DefaultHasher' does not promise.
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+
+    fn default() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        // FNV alone spreads short inputs badly over the high bits.
+        let mut h = self.0;
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        h ^ (h >> 33)
+    }
+}
+
+/// Hash of the partition key on the ring.
+pub fn key_hash(key: &[u8]) -> u64 {
+    let mut hasher = StableHasher::default();
+    hasher.write(key);
+    hasher.finish()
+}
+
+fn point_hash<N: Hash>(node: &N, replica: usize) -> u64 {
+    let mut hasher = StableHasher::default();
+    node.hash(&mut hasher);
+    hasher.write(&(replica as u64).to_le_bytes());
+    hasher.finish()
+}
+
+/// Consistent hash ring of nodes 'N'.
+#[derive(Debug, Clone)]
+pub struct HashRing<N> {
+    replicas    : usize,
+
+    /// Points sorted by hash.
+    points      : Vec<(u64, N)>,
+}
+
+impl<N: Hash + Eq + Clone> Default for HashRing<N> {
+
+    fn default() -> Self {
+        HashRing::new(REPLICAS)
+    }
+}
+
+impl<N: Hash + Eq + Clone> HashRing<N> {
+
+    /// Create empty ring where each node takes given count of points.
+    /// More points spread the keys more evenly.
+    pub fn new(replicas: usize) -> Self {
+        HashRing {
+            replicas    : replicas.max(1),
+            points      : Vec::new(),
+        }
+    }
+
+    /// Add the node. Adding the node twice has no effect.
+    pub fn insert(&mut self, node: N) {
+        if self.contains(&node) {
+            return;
+        }
+        for replica in 0..self.replicas {
+            self.points.push((point_hash(&node, replica), node.clone()));
+        }
+        self.points.sort_by_key(|&(hash, _)| hash);
+    }
+
+    /// Remove the node.
+    pub fn remove(&mut self, node: &N) {
+        self.points.retain(|(_, n)| n != node);
+    }
+
+    /// Check if the ring has the node.
+    pub fn contains(&self, node: &N) -> bool {
+        self.points.iter().any(|(_, n)| n == node)
+    }
+
+    /// Check if the ring has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Node that owns the key. None if the ring is empty.
+    pub fn get(&self, key: &[u8]) -> Option<&N> {
+        self.get_hash(key_hash(key))
+    }
+
+    /// Node that owns the key with given hash.
+    pub fn get_hash(&self, hash: u64) -> Option<&N> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let i = self.points.partition_point(|&(h, _)| h < hash);
+        Some(&self.points[i % self.points.len()].1)
+    }
+}
+
+impl<N: Hash + Eq + Clone> ::std::iter::FromIterator<N> for HashRing<N> {
+
+    fn from_iter<I: IntoIterator<Item = N>>(nodes: I) -> Self {
+        let mut ring = HashRing::default();
+        for node in nodes {
+            ring.insert(node);
+        }
+        ring
+    }
+}
+
+/// Network that routes connects by the partition key.
+pub trait PartitionNetwork<S: Service>: OpenNetwork<S> {
+
+    /// Connect to the provider of the service that owns the key. Same
+    /// key goes to the same provider while the set of the providers
+    /// stays the same. Traffic split of the service, if any, is applied
+    /// first and the key picks among the providers of the release.
+    fn connect_partition(&self, service: S, key: &[u8])
+        -> Result<Self::Socket, ConnectErr<S>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimal_reshuffling() {
+        let keys: Vec<String> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        let mut ring: HashRing<u32> = (0..4).collect();
+        let before: Vec<u32> = keys.iter().map(|k| *ring.get(k.as_bytes()).unwrap()).collect();
+        assert!((0..4).all(|n| before.iter().filter(|&&b| b == n).count() > 150));
+
+        ring.insert(4);
+        let after: Vec<u32> = keys.iter().map(|k| *ring.get(k.as_bytes()).unwrap()).collect();
+        let moved = before.iter().zip(&after).filter(|(b, a)| b != a).count();
+        assert!(before.iter().zip(&after).all(|(b, a)| b == a || *a == 4));
+        assert!(moved > 100 && moved < 300);
+
+        ring.remove(&4);
+        assert!(keys.iter().zip(&before).all(|(k, b)| ring.get(k.as_bytes()) == Some(b)));
+        assert_eq!(HashRing::<u32>::new(8).get(b"key"), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! Hierarchical service identifiers. Flat identifiers collide quickly
+//! in a large system, so services are named by dot-separated paths such
+//! as 'kobzar.memory.alloc', each segment being a namespace of the
+//! next. Patterns select whole namespaces: '*' stands for one segment
+//! and '**' for any number of them, so 'kobzar.*.alloc' and
+//! 'kobzar.memory.**' both match the path above.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Error of the path parsing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathErr {
+
+    /// Path has no segments or an empty one, e.g. 'a..b'.
+    EmptySegment,
+
+    /// Segment has a wildcard, which only patterns may have.
+    Wildcard,
+}
+
+/// Path of the service in the namespace tree.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ServicePath {
+    segments    : Vec<String>,
+}
+
+impl ServicePath {
+
+    /// Parse the dot-separated path.
+    pub fn parse(text: &str) -> Result<Self, PathErr> {
+        let segments: Vec<String> = text.split('.').map(str::to_string).collect();
+        if segments.iter().any(String::is_empty) {
+            Err(PathErr::EmptySegment)
+        } else if segments.iter().any(|s| s.contains('*')) {
+            Err(PathErr::Wildcard)
+        } else {
+            Ok(ServicePath { segments })
+        }
+    }
+
+    /// Segments from the root namespace down.
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    /// Last segment, the name of the service in its namespace.
+    pub fn name(&self) -> &str {
+        self.segments.last().expect("path has at least one segment")
+    }
+
+    /// Namespace of the path. None for the top-level one.
+    pub fn parent(&self) -> Option<ServicePath> {
+        match self.segments.len() {
+            1   => None,
+            n   => Some(ServicePath { segments: self.segments[..n - 1].to_vec() }),
+        }
+    }
+
+    /// Path of the segment inside this namespace.
+    pub fn child(&self, segment: &str) -> Result<ServicePath, PathErr> {
+        let child = ServicePath::parse(segment)?;
+        let mut segments = self.segments.clone();
+        segments.extend(child.segments);
+        Ok(ServicePath { segments })
+    }
+
+    /// Check if the path is somewhere inside this namespace.
+    pub fn is_ancestor_of(&self, other: &ServicePath) -> bool {
+        other.segments.len() > self.segments.len()
+            && other.segments.starts_with(&self.segments)
+    }
+
+    /// Check if the path is right inside this namespace.
+    pub fn is_parent_of(&self, other: &ServicePath) -> bool {
+        other.segments.len() == self.segments.len() + 1 && self.is_ancestor_of(other)
+    }
+
+    /// Check if the path matches the pattern with '*' and '**'
+    /// wildcard segments.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern: Vec<&str> = pattern.split('.').collect();
+        matches(&pattern, &self.segments)
+    }
+}
+
+fn matches(pattern: &[&str], segments: &[String]) -> bool {
+    match pattern.split_first() {
+        None                    => segments.is_empty(),
+        Some((&"**", rest))     => (0..=segments.len()).any(|i| matches(rest, &segments[i..])),
+        Some((first, rest))     => match segments.split_first() {
+            Some((segment, others)) => (*first == "*" || first == segment)
+                && matches(rest, others),
+            None                    => false,
+        },
+    }
+}
+
+impl fmt::Display for ServicePath {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.segments.join("."))
+    }
+}
+
+impl FromStr for ServicePath {
+    type Err = PathErr;
+
+    fn from_str(text: &str) -> Result<Self, PathErr> {
+        ServicePath::parse(text)
+    }
+}
+
+impl From<ServicePath> for String {
+
+    fn from(path: ServicePath) -> String {
+        path.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_and_patterns() {
+        let path = ServicePath::parse("kobzar.memory.alloc").unwrap();
+        let memory = path.parent().unwrap();
+        assert_eq!(memory.to_string(), "kobzar.memory");
+        assert_eq!(memory.child("alloc").unwrap(), path);
+        assert!(memory.is_parent_of(&path));
+        assert!(memory.parent().unwrap().is_ancestor_of(&path));
+        assert!(!path.is_ancestor_of(&path));
+        assert_eq!(path.name(), "alloc");
+
+        assert!(path.matches("kobzar.*.alloc"));
+        assert!(path.matches("kobzar.**"));
+        assert!(path.matches("**.alloc"));
+        assert!(!path.matches("kobzar.*"));
+        assert!(!path.matches("kobzar.memory.alloc.*"));
+
+        assert_eq!(ServicePath::parse("a..b"), Err(PathErr::EmptySegment));
+        assert_eq!(ServicePath::parse("a.*"), Err(PathErr::Wildcard));
+    }
+}
@@ -0,0 +1,217 @@
+//! Pipelines of services. A pipeline is a chain of services where each
+//! item goes through all of them in order, e.g. decode, filter, store.
+//! Pipeline establishes the channels to all stages and moves items
+//! along the chain, so each deployment doesn't need its own glue code.
+
+use super::{ConnectErr, Data, Object, OpenNetwork, Service, Socket, SocketErr};
+use cancel::CancelToken;
+
+/// Error of the pipeline.
+#[derive(Debug)]
+pub enum PipelineErr<S> {
+
+    /// Stage with given index could not be connected.
+    Connect(usize, ConnectErr<S>),
+
+    /// Channel of the stage with given index failed.
+    Stage(usize, SocketErr),
+
+    /// Pipeline was cancelled.
+    Cancelled,
+}
+
+/// Builder of the pipeline.
+pub struct Pipeline<S: Service> {
+    stages  : Vec<S>,
+}
+
+impl<S: Service> Default for Pipeline<S> {
+
+    fn default() -> Self {
+        Pipeline {
+            stages  : Vec::new(),
+        }
+    }
+}
+
+impl<S: Service> Pipeline<S> {
+
+    /// Create pipeline without stages.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the service as the next stage of the pipeline.
+    pub fn stage(mut self, service: S) -> Self {
+        self.stages.push(service);
+        self
+    }
+
+    /// Connect to all stages. If some stage cannot be connected,
+    /// channels to the stages that were already connected are closed.
+    pub fn connect<N>(self, network: &N) -> Result<Chain<N::Socket>, PipelineErr<S>>
+        where   N   : OpenNetwork<S>
+    {
+        let mut sockets = Vec::with_capacity(self.stages.len());
+        for (i, service) in self.stages.into_iter().enumerate() {
+            match network.connect(service) {
+                Ok(socket)      => sockets.push(socket),
+                Err(e)          => {
+                    for socket in sockets {
+                        socket.close();
+                    }
+                    return Err(PipelineErr::Connect(i, e));
+                },
+            }
+        }
+        Ok(Chain { sockets })
+    }
+}
+
+/// Connected pipeline. Each stage receives an item and replies with
+/// the item for the next stage. Sends block while the stage is busy,
+/// so a slow stage holds back the whole chain instead of letting
+/// items pile up in memory.
+pub struct Chain<SC> {
+    sockets : Vec<SC>,
+}
+
+impl<SC> Chain<SC> {
+
+    /// Count of the stages.
+    pub fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    /// Check if pipeline has no stages.
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    /// Pass single item through all stages.
+    pub fn process<O, S, D>(&self, item: D) -> Result<D, PipelineErr<S>>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                D   : Data
+    {
+        let mut item = item;
+        for (i, socket) in self.sockets.iter().enumerate() {
+            socket.send(item).map_err(|e| PipelineErr::Stage(i, e))?;
+            item = socket.receive().map_err(|e| PipelineErr::Stage(i, e))?;
+        }
+        Ok(item)
+    }
+
+    /// Pass all items through the pipeline and give the results to the
+    /// sink. When items end, the token is cancelled or some stage
+    /// fails, channels to all stages are closed in order from the first
+    /// stage to the last. Returns count of processed items.
+    pub fn run<O, S, D, I, F>(self, items: I, mut sink: F, cancel: &CancelToken)
+        -> Result<usize, PipelineErr<S>>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                D   : Data,
+                I   : IntoIterator<Item = D>,
+                F   : FnMut(D)
+    {
+        let mut count = 0;
+        let mut result = Ok(());
+        for item in items {
+            if cancel.is_cancelled() {
+                result = Err(PipelineErr::Cancelled);
+                break;
+            }
+            match self.process(item) {
+                Ok(item)    => sink(item),
+                Err(e)      => {
+                    result = Err(e);
+                    break;
+                },
+            }
+            count += 1;
+        }
+        for socket in self.sockets {
+            socket.close();
+        }
+        result.map(|_| count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use RegistrationForm;
+
+    fn stage(socket: LocalSocket, f: fn(String) -> String) -> ! {
+        while let Ok(item) = socket.receive::<String>() {
+            if socket.send(f(item)).is_err() {
+                break;
+            }
+        }
+        finish()
+    }
+
+    fn reverse(socket: LocalSocket) -> ! {
+        stage(socket, |s| s.chars().rev().collect())
+    }
+
+    fn exclaim(socket: LocalSocket) -> ! {
+        stage(socket, |s| s + "!")
+    }
+
+    /// Takes the item and closes the channel without replying.
+    fn broken(socket: LocalSocket) -> ! {
+        let _ = socket.receive::<String>();
+        finish()
+    }
+
+    fn network() -> LocalNetwork {
+        let network = LocalNetwork::new();
+        for (id, handler) in [("reverse", reverse as fn(LocalSocket) -> !),
+                ("exclaim", exclaim), ("broken", broken)] {
+            network.register(RegistrationForm::new(handler, id.to_string())).unwrap();
+        }
+        network
+    }
+
+    fn pipeline(stages: &[&str]) -> Pipeline<LocalService> {
+        stages.iter().fold(Pipeline::new(), |p, id| p.stage(LocalService::by_id(id.to_string())))
+    }
+
+    #[test]
+    fn stages_in_order() {
+        let network = network();
+        let chain = pipeline(&["reverse", "exclaim"]).connect(&network).unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain.process("ab".to_string()).unwrap(), "ba!");
+
+        let mut out = Vec::new();
+        let items = vec!["ab".to_string(), "cd".to_string()];
+        let count = chain.run(items, |item| out.push(item), &CancelToken::new()).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(out, vec!["ba!", "dc!"]);
+    }
+
+    #[test]
+    fn errors_propagate() {
+        let network = network();
+        let missing = pipeline(&["reverse", "exclaim", "missing"]).connect(&network);
+        assert!(matches!(missing, Err(PipelineErr::Connect(2, ConnectErr::NotProvided(_)))));
+
+        let chain = pipeline(&["reverse", "broken", "exclaim"]).connect(&network).unwrap();
+        let mut out = Vec::new();
+        let items = vec!["ab".to_string(), "cd".to_string()];
+        let failed = chain.run(items, |item| out.push(item), &CancelToken::new());
+        assert!(matches!(failed, Err(PipelineErr::Stage(1, SocketErr::ChannelClosed))));
+        assert!(out.is_empty());
+
+        let chain = pipeline(&["reverse"]).connect(&network).unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let cancelled = chain.run(vec!["ab".to_string()], |_| (), &cancel);
+        assert!(matches!(cancelled, Err(PipelineErr::Cancelled)));
+    }
+}
@@ -0,0 +1,317 @@
+//! Security policy of the network. Network owner loads declarative
+//! rules that say who may register which services, who may connect to
+//! what and which capabilities are required for that. The network
+//! evaluates them on every register and connect, so providers don't
+//! have to repeat the same checks each in its own way.
+//!
+//! Rules are written one per line in the form
+//!
+//! ```text
+//! <allow|deny> <action>[,<action>...] subject=<pattern> service=<pattern> [require=<capability>,...]
+//! default <allow|deny>
+//! ```
+//!
+//! where action is 'register', 'register-unique', 'connect' or 'direct'
+//! and patterns may contain '*' that matches any sequence of
+//! characters. Lines starting with '#' are comments. The first rule
+//! that matches the request decides; if none does, the default applies.
+
+use std::fmt::Write;
+
+use super::{Network, Service};
+
+/// Operation that is checked by the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Register,
+    RegisterUnique,
+    Connect,
+
+    /// Call the provider in-process, bypassing the channel and so any
+    /// checks made on its messages.
+    Direct,
+}
+
+/// What to do with the request that matches the rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+/// Pattern of the names with '*' wildcards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern(pub String);
+
+impl Pattern {
+
+    /// Check if the text matches the pattern.
+    pub fn matches(&self, text: &str) -> bool {
+        let mut parts = self.0.split('*');
+        let first = parts.next().unwrap_or("");
+        let mut rest = match text.strip_prefix(first) {
+            Some(rest)  => rest,
+            None        => return false,
+        };
+        let parts: Vec<_> = parts.collect();
+        let last = match parts.split_last() {
+            Some((last, middle)) => {
+                for part in middle {
+                    match rest.find(part) {
+                        Some(i) => rest = &rest[i + part.len()..],
+                        None    => return false,
+                    }
+                }
+                last
+            },
+
+            // No wildcard at all.
+            None => return rest.is_empty(),
+        };
+        rest.ends_with(last)
+    }
+}
+
+/// Single rule of the policy.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub effect      : Effect,
+    pub actions     : Vec<Action>,
+
+    /// Pattern of the names of the objects the rule applies to.
+    pub subject     : Pattern,
+
+    /// Pattern of the service identifiers the rule applies to.
+    pub service     : Pattern,
+
+    /// Capabilities the subject must hold for the rule to allow the
+    /// request.
+    pub requires    : Vec<String>,
+}
+
+/// Request to check.
+#[derive(Debug, Clone, Copy)]
+pub struct Request<'a> {
+    pub action          : Action,
+
+    /// Name of the object that makes the request.
+    pub subject         : &'a str,
+
+    /// Identifier of the service in text form.
+    pub service         : &'a str,
+
+    /// Capabilities held by the subject.
+    pub capabilities    : &'a [String],
+}
+
+/// Decision of the policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+
+    /// Request is allowed.
+    Allow,
+
+    /// Request is denied by the rule with given index, or by default
+    /// when index is None.
+    Deny(Option<usize>),
+
+    /// Rule with given index would allow the request but the subject
+    /// lacks the capability.
+    MissingCapability(usize, String),
+}
+
+/// Error of the policy parsing.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PolicyErr {
+
+    /// Line number, starting from one.
+    pub line    : usize,
+
+    /// What is wrong.
+    pub reason  : String,
+}
+
+/// Set of rules with the default effect.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub rules   : Vec<Rule>,
+    pub default : Effect,
+}
+
+impl Default for Policy {
+
+    /// Policy without rules that allows everything.
+    fn default() -> Self {
+        Policy {
+            rules   : Vec::new(),
+            default : Effect::Allow,
+        }
+    }
+}
+
+impl Policy {
+
+    /// Parse the policy text.
+    pub fn parse(text: &str) -> Result<Policy, PolicyErr> {
+        let mut policy = Policy::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |reason: &str| PolicyErr {
+                line    : n + 1,
+                reason  : reason.to_string(),
+            };
+            let mut words = line.split_whitespace();
+            let effect = match words.next() {
+                Some("allow")   => Effect::Allow,
+                Some("deny")    => Effect::Deny,
+                Some("default") => {
+                    policy.default = match words.next() {
+                        Some("allow")   => Effect::Allow,
+                        Some("deny")    => Effect::Deny,
+                        _               => return Err(err("expected allow or deny")),
+                    };
+                    continue;
+                },
+                _ => return Err(err("expected allow, deny or default")),
+            };
+            let actions = words.next().ok_or_else(|| err("expected actions"))?
+                .split(',')
+                .map(|a| match a {
+                    "register"          => Ok(Action::Register),
+                    "register-unique"   => Ok(Action::RegisterUnique),
+                    "connect"           => Ok(Action::Connect),
+                    "direct"            => Ok(Action::Direct),
+                    _                   => Err(err("unknown action")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut subject = Pattern("*".to_string());
+            let mut service = Pattern("*".to_string());
+            let mut requires = Vec::new();
+            for word in words {
+                match word.split_once('=') {
+                    Some(("subject", p))    => subject = Pattern(p.to_string()),
+                    Some(("service", p))    => service = Pattern(p.to_string()),
+                    Some(("require", c))    => requires.extend(
+                            c.split(',').map(|c| c.to_string())),
+                    _                       => return Err(err("unknown attribute")),
+                }
+            }
+            policy.rules.push(Rule {
+                effect,
+                actions,
+                subject,
+                service,
+                requires,
+            });
+        }
+        Ok(policy)
+    }
+
+    /// Write the policy back in the text form accepted by 'parse'.
+    pub fn to_text(&self) -> String {
+        let effect = |e| match e {
+            Effect::Allow   => "allow",
+            Effect::Deny    => "deny",
+        };
+        let mut out = String::new();
+        for rule in &self.rules {
+            let actions: Vec<_> = rule.actions.iter().map(|a| match *a {
+                Action::Register        => "register",
+                Action::RegisterUnique  => "register-unique",
+                Action::Connect         => "connect",
+                Action::Direct          => "direct",
+            }).collect();
+            let _ = write!(out, "{} {} subject={} service={}", effect(rule.effect),
+                    actions.join(","), rule.subject.0, rule.service.0);
+            if !rule.requires.is_empty() {
+                let _ = write!(out, " require={}", rule.requires.join(","));
+            }
+            out.push('\n');
+        }
+        let _ = writeln!(out, "default {}", effect(self.default));
+        out
+    }
+
+    /// Evaluate the request.
+    pub fn evaluate(&self, request: &Request) -> Decision {
+        let rule = self.rules.iter().enumerate().find(|&(_, r)| {
+            r.actions.contains(&request.action)
+                && r.subject.matches(request.subject)
+                && r.service.matches(request.service)
+        });
+        match rule {
+            Some((i, rule)) => match rule.effect {
+                Effect::Deny    => Decision::Deny(Some(i)),
+                Effect::Allow   => {
+                    let missing = rule.requires.iter()
+                        .find(|c| !request.capabilities.contains(c));
+                    match missing {
+                        Some(c) => Decision::MissingCapability(i, c.clone()),
+                        None    => Decision::Allow,
+                    }
+                },
+            },
+            None => match self.default {
+                Effect::Allow   => Decision::Allow,
+                Effect::Deny    => Decision::Deny(None),
+            },
+        }
+    }
+}
+
+/// Network that enforces the policy on registers and connects.
+pub trait PolicyNetwork<S: Service>: Network<S> {
+
+    /// Replace the policy of the network. Only the owner of the network
+    /// may do that.
+    fn set_policy(&self, policy: Policy);
+
+    /// Get current policy.
+    fn policy(&self) -> Policy;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern() {
+        assert!(Pattern("kobzar.*".to_string()).matches("kobzar.memory"));
+        assert!(Pattern("*.alloc".to_string()).matches("kobzar.memory.alloc"));
+        assert!(Pattern("a*b*c".to_string()).matches("abbc"));
+        assert!(!Pattern("a*b*c".to_string()).matches("acb"));
+        assert!(!Pattern("memory".to_string()).matches("memory2"));
+    }
+
+    #[test]
+    fn first_rule_decides() {
+        let policy = Policy::parse("
+            # Only the memory server may provide memory services.
+            allow register-unique subject=memory-server service=kobzar.memory.*
+            deny register,register-unique service=kobzar.memory.*
+            allow connect service=driver.* require=drivers
+            default deny
+        ").unwrap();
+
+        let request = |action, subject, service, capabilities| Request {
+            action,
+            subject,
+            service,
+            capabilities,
+        };
+        let none: &[String] = &[];
+        assert_eq!(policy.evaluate(&request(Action::RegisterUnique,
+                "memory-server", "kobzar.memory.alloc", none)), Decision::Allow);
+        assert_eq!(policy.evaluate(&request(Action::Register,
+                "impostor", "kobzar.memory.alloc", none)), Decision::Deny(Some(1)));
+        assert_eq!(policy.evaluate(&request(Action::Connect,
+                "app", "driver.disk", none)),
+                Decision::MissingCapability(2, "drivers".to_string()));
+        assert_eq!(policy.evaluate(&request(Action::Connect,
+                "app", "other", none)), Decision::Deny(None));
+    }
+}
@@ -0,0 +1,246 @@
+//! Worker pool of the service handlers. Provider that serves many
+//! channels hands each request to the pool instead of running it on
+//! the accepting thread. Each worker has its own bounded queue, so
+//! workers don't contend on one lock, and a worker that runs out of
+//! work steals half of the queue of a busy one. Requests of uneven cost
+//! then don't leave some workers idle while another is backlogged.
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+
+use metrics::{MetricFamily, MetricKind, MetricsSource, Sample};
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Counters of one worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WorkerStats {
+
+    /// Tasks the worker has started.
+    pub executed    : usize,
+
+    /// Times the worker stole from another one.
+    pub steals      : usize,
+
+    /// Tasks it took in those steals.
+    pub stolen      : usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    executed    : AtomicUsize,
+    steals      : AtomicUsize,
+    stolen      : AtomicUsize,
+}
+
+#[derive(Default)]
+struct Signal {
+
+    /// Tasks in the queues that no worker has claimed yet.
+    pending     : usize,
+    shutdown    : bool,
+}
+
+struct Shared {
+    queues      : Vec<Mutex<VecDeque<Task>>>,
+    capacity    : usize,
+
+    /// Tasks that did not fit the local queues.
+    overflow    : Mutex<VecDeque<Task>>,
+    signal      : Mutex<Signal>,
+    wake        : Condvar,
+    counters    : Vec<Counters>,
+}
+
+impl Shared {
+
+    /// Take the task for the worker: from its own queue, then from the
+    /// overflow, then by stealing.
+    fn find(&self, me: usize) -> Option<Task> {
+        if let Some(task) = self.queues[me].lock().unwrap().pop_front() {
+            return Some(task);
+        }
+        if let Some(task) = self.overflow.lock().unwrap().pop_front() {
+            return Some(task);
+        }
+        let count = self.queues.len();
+        for victim in (1..count).map(|i| (me + i) % count) {
+            let mut loot = {
+                let mut queue = self.queues[victim].lock().unwrap();
+                let half = queue.len().div_ceil(2);
+                let at = queue.len() - half;
+                queue.split_off(at)
+            };
+            let task = match loot.pop_front() {
+                Some(task)  => task,
+                None        => continue,
+            };
+            let counters = &self.counters[me];
+            counters.steals.fetch_add(1, Ordering::Relaxed);
+            counters.stolen.fetch_add(loot.len() + 1, Ordering::Relaxed);
+            self.queues[me].lock().unwrap().extend(loot);
+            return Some(task);
+        }
+        None
+    }
+
+    fn work(&self, me: usize) {
+        loop {
+            {
+                let mut signal = self.wake.wait_while(self.signal.lock().unwrap(),
+                        |s| s.pending == 0 && !s.shutdown).unwrap();
+                if signal.pending == 0 {
+                    return;
+                }
+                signal.pending -= 1;
+            }
+
+            // Claimed task is somewhere in the queues, though another
+            // worker may be moving it by a steal right now.
+            let task = loop {
+                match self.find(me) {
+                    Some(task)  => break task,
+                    None        => thread::yield_now(),
+                }
+            };
+            self.counters[me].executed.fetch_add(1, Ordering::Relaxed);
+
+            // Panic of the handler must not take the worker down.
+            let _ = panic::catch_unwind(AssertUnwindSafe(task));
+        }
+    }
+}
+
+/// Pool of worker threads with work stealing.
+pub struct WorkerPool {
+    shared      : Arc<Shared>,
+    threads     : Vec<JoinHandle<()>>,
+
+    /// Worker to get the next task.
+    next        : AtomicUsize,
+}
+
+impl WorkerPool {
+
+    /// Start given count of workers, each with a local queue of given
+    /// capacity. Tasks that don't fit go to the shared overflow queue.
+    pub fn new(workers: usize, capacity: usize) -> Self {
+        let workers = workers.max(1);
+        let shared = Arc::new(Shared {
+            queues      : (0..workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+            capacity    : capacity.max(1),
+            overflow    : Mutex::new(VecDeque::new()),
+            signal      : Mutex::new(Signal::default()),
+            wake        : Condvar::new(),
+            counters    : (0..workers).map(|_| Counters::default()).collect(),
+        });
+        let threads = (0..workers).map(|me| {
+            let shared = shared.clone();
+            thread::spawn(move || shared.work(me))
+        }).collect();
+        WorkerPool {
+            shared,
+            threads,
+            next        : AtomicUsize::new(0),
+        }
+    }
+
+    /// Count of the workers.
+    pub fn workers(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Queue the task. Tasks are dealt to the workers in turn.
+    pub fn submit<F>(&self, task: F)
+        where F: FnOnce() + Send + 'static
+    {
+        let task: Task = Box::new(task);
+        let me = self.next.fetch_add(1, Ordering::Relaxed) % self.workers();
+        {
+            let mut queue = self.shared.queues[me].lock().unwrap();
+            if queue.len() < self.shared.capacity {
+                queue.push_back(task);
+            } else {
+                drop(queue);
+                self.shared.overflow.lock().unwrap().push_back(task);
+            }
+        }
+        self.shared.signal.lock().unwrap().pending += 1;
+        self.shared.wake.notify_one();
+    }
+
+    /// Counters of each worker.
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        self.shared.counters.iter().map(|c| WorkerStats {
+            executed    : c.executed.load(Ordering::Relaxed),
+            steals      : c.steals.load(Ordering::Relaxed),
+            stolen      : c.stolen.load(Ordering::Relaxed),
+        }).collect()
+    }
+}
+
+impl Drop for WorkerPool {
+
+    /// Run the queued tasks and stop the workers.
+    fn drop(&mut self) {
+        self.shared.signal.lock().unwrap().shutdown = true;
+        self.shared.wake.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl MetricsSource for WorkerPool {
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let stats = self.stats();
+        let family = |name: &str, help: &str, value: fn(&WorkerStats) -> usize| MetricFamily {
+            name    : name.to_string(),
+            help    : help.to_string(),
+            kind    : MetricKind::Counter,
+            samples : stats.iter().enumerate().map(|(worker, s)| Sample {
+                labels  : vec![("worker".to_string(), worker.to_string())],
+                value   : value(s) as f64,
+            }).collect(),
+        };
+        vec![
+            family("ccs_pool_executed", "Tasks started by the worker.", |s| s.executed),
+            family("ccs_pool_steals", "Steals made by the worker.", |s| s.steals),
+            family("ccs_pool_stolen", "Tasks taken by the worker in steals.", |s| s.stolen),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn idle_worker_steals() {
+        let pool = WorkerPool::new(2, 8);
+        let (done, finished) = mpsc::channel();
+        for i in 0..8 {
+            let done = done.clone();
+            pool.submit(move || {
+                if i == 0 {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                done.send(i).unwrap();
+            });
+        }
+        let mut order: Vec<_> = finished.iter().take(8).collect();
+        assert_eq!(order.pop(), Some(0));
+        order.sort();
+        assert_eq!(order, [1, 2, 3, 4, 5, 6, 7]);
+
+        let stats = pool.stats();
+        assert_eq!(stats.iter().map(|s| s.executed).sum::<usize>(), 8);
+        assert!(stats.iter().any(|s| s.steals > 0));
+    }
+}
@@ -0,0 +1,67 @@
+//! Preemption of contended services. When a high-priority object needs
+//! a unique service or resource that is held by lower-priority objects,
+//! the network first sends them a notice with a deadline to release it.
+//! Only those that do not release in time are force-closed.
+
+use std::time::Instant;
+
+use super::{Network, Object, Service, Socket, Time};
+
+/// Priority of the object. Greater value means higher priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Priority(pub u8);
+
+/// Notice received by the holder of the preempted channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preemption {
+
+    /// Priority of the object that needs the service.
+    pub priority    : Priority,
+
+    /// When the channel will be force-closed if not released.
+    pub deadline    : Instant,
+}
+
+/// Error of the preemption.
+#[derive(Debug)]
+pub enum PreemptErr {
+
+    /// Service is held by objects of the same or higher priority.
+    NotLower,
+
+    /// Caller is not allowed to preempt the service.
+    Denied,
+}
+
+/// Result of the preemption.
+#[derive(Debug)]
+pub struct PreemptReport<OId> {
+
+    /// Objects that released the service before the deadline.
+    pub released    : Vec<OId>,
+
+    /// Objects which channels were force-closed after the deadline.
+    pub forced      : Vec<OId>,
+}
+
+/// Network that can preempt holders of its services.
+pub trait PreemptingNetwork<S: Service>: Network<S> {
+
+    /// Identifier of the object in this network.
+    type ObjectId;
+
+    /// Send preemption notices to all objects with priority lower than
+    /// given that hold channels to the service, wait until they release
+    /// them or until the deadline, then force-close the rest.
+    fn preempt<T: Time>(&self, service: &S::Id, priority: Priority, deadline: T)
+        -> Result<PreemptReport<Self::ObjectId>, PreemptErr>;
+}
+
+/// Socket which holder can be asked to release it.
+pub trait PreemptibleSocket<O, S>: Socket<O, S>
+        where O: Object<S>, S: Service {
+
+    /// Get preemption notice for this channel if there is one. Holder
+    /// should finish its work and close the socket before the deadline.
+    fn preemption(&self) -> Option<Preemption>;
+}
@@ -0,0 +1,377 @@
+//! Channel protocols. Provider declares the legal sequence of messages
+//! on the channel of its service as a state machine: in each state only
+//! some messages may go in some direction, and each message moves the
+//! channel to the next state. 'ProtocolSocket' tracks the state on
+//! either side and rejects out-of-order messages with
+//! 'SocketErr::ProtocolViolation' instead of letting both sides get
+//! confused.
+//!
+//! When the protocol is known at compile time, 'session!' turns it into
+//! session types instead: each state is a type, and 'Session' in that
+//! state has only the sends and receives the protocol allows, each of
+//! which returns the session in the next state. Illegal sequences then
+//! don't compile at all.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+use super::{Data, Object, Service, Socket, SocketErr};
+use debug::{Direction, Role};
+
+/// Message that tells its kind, which is what the protocol checks.
+pub trait Tagged {
+
+    /// Kind of the message, e.g. 'open', 'read' or 'close'.
+    fn tag(&self) -> &'static str;
+}
+
+/// Legal transition of the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition<St> {
+    pub from        : St,
+    pub direction   : Direction,
+    pub tag         : &'static str,
+    pub to          : St,
+}
+
+/// Protocol of the channel as a state machine with states of type
+/// 'St'.
+#[derive(Debug, Clone)]
+pub struct StateMachine<St> {
+    initial     : St,
+    transitions : Vec<Transition<St>>,
+    finals      : Vec<St>,
+}
+
+impl<St: Copy + Eq> StateMachine<St> {
+
+    /// Create protocol that starts in given state.
+    pub fn new(initial: St) -> Self {
+        StateMachine {
+            initial,
+            transitions : Vec::new(),
+            finals      : Vec::new(),
+        }
+    }
+
+    /// Allow the message with given tag to go in given direction in
+    /// state 'from'. The channel then goes to state 'to'.
+    pub fn on(mut self, from: St, direction: Direction, tag: &'static str, to: St)
+        -> Self
+    {
+        self.transitions.push(Transition {
+            from,
+            direction,
+            tag,
+            to,
+        });
+        self
+    }
+
+    /// Mark the state in which the channel may be closed.
+    pub fn final_state(mut self, state: St) -> Self {
+        self.finals.push(state);
+        self
+    }
+
+    /// Initial state of the channel.
+    pub fn initial(&self) -> St {
+        self.initial
+    }
+
+    /// Next state after the message. None if the message is illegal.
+    pub fn next(&self, state: St, direction: Direction, tag: &str) -> Option<St> {
+        self.transitions.iter()
+            .find(|t| t.from == state && t.direction == direction && t.tag == tag)
+            .map(|t| t.to)
+    }
+
+    /// Whether the channel may be closed in the state.
+    pub fn is_final(&self, state: St) -> bool {
+        self.finals.contains(&state)
+    }
+}
+
+/// Socket that enforces the protocol. Violating message is not sent,
+/// and received violating message is dropped. The state is not changed
+/// in both cases.
+pub struct ProtocolSocket<'a, O, S, SC, St: 'a> {
+    socket      : SC,
+    machine     : &'a StateMachine<St>,
+    role        : Role,
+    state       : Cell<St>,
+    _os         : PhantomData<(O, S)>,
+}
+
+impl<'a, O, S, SC, St> ProtocolSocket<'a, O, S, SC, St>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                St  : Copy + Eq
+{
+
+    /// Wrap the socket of the fresh channel. 'role' is the side of the
+    /// channel of the current object.
+    pub fn new(socket: SC, machine: &'a StateMachine<St>, role: Role) -> Self {
+        ProtocolSocket {
+            socket,
+            machine,
+            role,
+            state       : Cell::new(machine.initial()),
+            _os         : PhantomData,
+        }
+    }
+
+    /// Current state of the channel.
+    pub fn state(&self) -> St {
+        self.state.get()
+    }
+
+    fn advance(&self, direction: Direction, tag: &str) -> Result<(), SocketErr> {
+        match self.machine.next(self.state.get(), direction, tag) {
+            Some(next)  => {
+                self.state.set(next);
+                Ok(())
+            },
+            None        => Err(SocketErr::ProtocolViolation),
+        }
+    }
+
+    fn outgoing(&self) -> Direction {
+        match self.role {
+            Role::Requester => Direction::ToProvider,
+            Role::Provider  => Direction::ToRequester,
+        }
+    }
+
+    fn incoming(&self) -> Direction {
+        match self.role {
+            Role::Requester => Direction::ToRequester,
+            Role::Provider  => Direction::ToProvider,
+        }
+    }
+
+    /// Send the message if the protocol allows it in current state.
+    pub fn send<D: Data + Tagged>(&self, data: D) -> Result<(), SocketErr> {
+        let next = self.machine.next(self.state.get(), self.outgoing(), data.tag())
+            .ok_or(SocketErr::ProtocolViolation)?;
+        self.socket.send(data)?;
+        self.state.set(next);
+        Ok(())
+    }
+
+    /// Receive the message and check that the protocol allows it.
+    pub fn receive<D: Data + Tagged>(&self) -> Result<D, SocketErr> {
+        let data = self.socket.receive::<D>()?;
+        self.advance(self.incoming(), data.tag())?;
+        Ok(data)
+    }
+
+    /// Close the channel. Fails with 'SocketErr::ProtocolViolation' if
+    /// the protocol is not in a final state, though the channel is
+    /// closed anyway.
+    pub fn close(self) -> Result<(), SocketErr> {
+        let done = self.machine.is_final(self.state.get());
+        self.socket.close();
+        if done { Ok(()) } else { Err(SocketErr::ProtocolViolation) }
+    }
+
+    /// Get the wrapped socket back.
+    pub fn into_inner(self) -> SC {
+        self.socket
+    }
+}
+
+/// Session state marker of the requester side.
+pub struct RequesterSide;
+
+/// Session state marker of the provider side.
+pub struct ProviderSide;
+
+/// Session state that may send message of type 'D'.
+pub trait SendTo<D> {
+
+    /// State after the message is sent.
+    type Next;
+}
+
+/// Session state that may receive message of type 'D'.
+pub trait ReceiveFrom<D> {
+
+    /// State after the message is received.
+    type Next;
+}
+
+/// Session state in which the channel may be closed.
+pub trait End {
+}
+
+/// Socket in session state 'St'. Only operations that the protocol
+/// allows in this state are available.
+pub struct Session<O, S, SC, St> {
+    socket  : SC,
+    _p      : PhantomData<(O, S, St)>,
+}
+
+/// Received message and the session in the next state.
+pub type Received<D, O, S, SC, St> =
+        (D, Session<O, S, SC, <St as ReceiveFrom<D>>::Next>);
+
+impl<O, S, SC, St> Session<O, S, SC, St>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+{
+
+    /// Start the session on the fresh channel. 'St' must be the initial
+    /// state of the protocol on the side of the current object.
+    pub fn new(socket: SC) -> Self {
+        Session {
+            socket,
+            _p      : PhantomData,
+        }
+    }
+
+    fn next<Next>(self) -> Session<O, S, SC, Next> {
+        Session {
+            socket  : self.socket,
+            _p      : PhantomData,
+        }
+    }
+
+    /// Send the message and go to the next state.
+    pub fn send<D>(self, data: D)
+        -> Result<Session<O, S, SC, St::Next>, SocketErr>
+        where   D   : Data,
+                St  : SendTo<D>
+    {
+        self.socket.send(data)?;
+        Ok(self.next())
+    }
+
+    /// Receive the message and go to the next state.
+    pub fn receive<D>(self)
+        -> Result<Received<D, O, S, SC, St>, SocketErr>
+        where   D   : Data,
+                St  : ReceiveFrom<D>
+    {
+        let data = self.socket.receive::<D>()?;
+        Ok((data, self.next()))
+    }
+
+    /// Close the channel in the final state.
+    pub fn close(self) where St: End {
+        self.socket.close()
+    }
+}
+
+/// Declare session types of the protocol. Each state becomes a type
+/// generic over the side of the channel, 'RequesterSide' or
+/// 'ProviderSide'. Transition 'A >> M => B' means the requester sends
+/// message of type 'M' in state 'A' and both sides go to state 'B';
+/// 'A << M => B' means the provider sends it.
+///
+/// ```
+/// # #[macro_use] extern crate kobzar_ccs;
+/// # use kobzar_ccs::Data;
+/// pub struct Open;
+/// pub struct Bytes(pub Vec<u8>);
+/// pub struct Close;
+/// # impl Data for Open {}
+/// # impl Data for Bytes {}
+/// # impl Data for Close {}
+///
+/// session! {
+///     states Closed, Opened;
+///     end Closed;
+///     Closed  >> Open     => Opened;
+///     Opened  << Bytes    => Opened;
+///     Opened  >> Close    => Closed;
+/// }
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! session {
+    (
+        states $($state:ident),+ ;
+        end $($end:ident),+ ;
+        $($from:ident $dir:tt $msg:ty => $to:ident ;)*
+    ) => {
+        $(
+            pub struct $state<R>(::std::marker::PhantomData<R>);
+        )+
+        $(
+            session!(@transition $from $dir $msg => $to);
+        )*
+        $(
+            impl<R> $crate::protocol::End for $end<R> {}
+        )+
+    };
+    (@transition $from:ident >> $msg:ty => $to:ident) => {
+        session!(@pair $from, $msg, $to,
+                $crate::protocol::RequesterSide, $crate::protocol::ProviderSide);
+    };
+    (@transition $from:ident << $msg:ty => $to:ident) => {
+        session!(@pair $from, $msg, $to,
+                $crate::protocol::ProviderSide, $crate::protocol::RequesterSide);
+    };
+    (@pair $from:ident, $msg:ty, $to:ident, $sender:ty, $receiver:ty) => {
+        impl $crate::protocol::SendTo<$msg> for $from<$sender> {
+            type Next = $to<$sender>;
+        }
+        impl $crate::protocol::ReceiveFrom<$msg> for $from<$receiver> {
+            type Next = $to<$receiver>;
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum File {
+        Closed,
+        Open,
+        Reading,
+    }
+
+    #[test]
+    fn file_protocol() {
+        let machine = StateMachine::new(File::Closed)
+            .on(File::Closed,   Direction::ToProvider,  "open",  File::Open)
+            .on(File::Open,     Direction::ToProvider,  "read",  File::Reading)
+            .on(File::Reading,  Direction::ToRequester, "data",  File::Open)
+            .on(File::Open,     Direction::ToProvider,  "close", File::Closed)
+            .final_state(File::Closed);
+
+        assert_eq!(machine.next(File::Closed, Direction::ToProvider, "open"),
+                Some(File::Open));
+        assert_eq!(machine.next(File::Closed, Direction::ToProvider, "read"), None);
+        assert_eq!(machine.next(File::Open, Direction::ToRequester, "data"), None);
+        assert!(machine.is_final(File::Closed));
+        assert!(!machine.is_final(File::Reading));
+    }
+
+    struct Open;
+    struct Bytes;
+
+    session! {
+        states Closed, Opened;
+        end Closed;
+        Closed  >> Open     => Opened;
+        Opened  << Bytes    => Opened;
+    }
+
+    fn same<T>(_: PhantomData<T>, _: PhantomData<T>) {}
+
+    #[test]
+    fn session_sides_are_dual() {
+        same(PhantomData::<<Closed<RequesterSide> as SendTo<Open>>::Next>,
+                PhantomData::<Opened<RequesterSide>>);
+        same(PhantomData::<<Closed<ProviderSide> as ReceiveFrom<Open>>::Next>,
+                PhantomData::<Opened<ProviderSide>>);
+        same(PhantomData::<<Opened<RequesterSide> as ReceiveFrom<Bytes>>::Next>,
+                PhantomData::<Opened<RequesterSide>>);
+    }
+}
@@ -0,0 +1,563 @@
+//! Publish/subscribe broadcast. Sockets connect one requester to one
+//! provider, but events like configuration changes or sensor readings
+//! are of interest to any number of objects. Publisher opens a
+//! 'Broadcast' to a named topic and every 'Subscription' to the topic
+//! receives a copy of each message published after it was made. Each
+//! subscriber buffers its copies and chooses what happens when it falls
+//! behind: drop the oldest messages, hold the publisher back, or get an
+//! error.
+//!
+//! Owner of the hub may also watch for slow consumers: subscribers
+//! whose backlog stays over the threshold for several publishes in a
+//! row. Each of them is reported to the audit sink and penalized, so
+//! one stalled subscriber can't hold back the whole topic.
+
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{Data, OpenNetwork, Service};
+use reaper::AuditSink;
+
+/// What happens when the buffer of the subscriber is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+
+    /// Oldest buffered message is dropped to make room.
+    DropOldest,
+
+    /// Publisher waits until the subscriber makes room.
+    Block,
+
+    /// New message is dropped and the next receive of the subscriber
+    /// fails with 'PubSubErr::Overrun'.
+    Error,
+}
+
+/// Buffering policy of the subscriber.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPolicy {
+
+    /// Count of messages the buffer holds. At least one.
+    pub capacity    : usize,
+    pub overflow    : Overflow,
+}
+
+impl BufferPolicy {
+
+    /// Create policy with given capacity and overflow behaviour.
+    pub fn new(capacity: usize, overflow: Overflow) -> Self {
+        BufferPolicy {
+            capacity    : capacity.max(1),
+            overflow,
+        }
+    }
+}
+
+/// Errors of publishing and subscribing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PubSubErr {
+
+    /// Topic already has a publisher.
+    Taken,
+
+    /// Publisher of the topic is gone and all published messages were
+    /// received.
+    Closed,
+
+    /// Some messages were lost because the buffer was full. Messages
+    /// received after this error are the ones published later.
+    Overrun,
+
+    /// Next message is not of the requested type. The message stays in
+    /// the buffer.
+    UnexpectedData,
+
+    /// Subscriber fell behind for too long and was disconnected from
+    /// the topic.
+    Disconnected,
+}
+
+/// What happens to the slow consumer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Penalty {
+
+    /// Subscription is cut off the topic. Its receives fail with
+    /// 'PubSubErr::Disconnected' once the buffer is drained.
+    Disconnect,
+
+    /// Subscriber gets only every n-th message from now on. Skipped
+    /// messages count as dropped.
+    Sample(u32),
+
+    /// Subscriber that holds the publisher back with 'Overflow::Block'
+    /// gets 'Overflow::DropOldest' instead.
+    Downgrade,
+}
+
+/// When the subscriber is a slow consumer and what to do with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowConsumerPolicy {
+
+    /// Backlog of the messages that is too long.
+    pub threshold   : usize,
+
+    /// Count of the publishes in a row that find the backlog too long
+    /// before the penalty. At least one.
+    pub strikes     : u32,
+    pub penalty     : Penalty,
+}
+
+/// Report of the penalized slow consumer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowConsumer {
+    pub topic           : String,
+
+    /// Identifier of the subscription, see 'HubSubscription::id'.
+    pub subscription    : u64,
+
+    /// Backlog when the penalty was applied.
+    pub backlog         : usize,
+    pub penalty         : Penalty,
+}
+
+impl Data for SlowConsumer {
+}
+
+/// Publishing end of the topic. Topic is closed when it is dropped.
+pub trait Broadcast {
+
+    /// Send the copy of the data to each subscriber. Returns count of
+    /// the subscribers that got the copy.
+    fn publish<D: Data + Clone>(&self, data: D) -> Result<usize, PubSubErr>;
+
+    /// Count of the current subscribers.
+    fn subscribers(&self) -> usize;
+}
+
+/// Receiving end of the topic.
+pub trait Subscription {
+
+    /// Wait for the next message.
+    fn receive<D: Data>(&self) -> Result<D, PubSubErr>;
+
+    /// Take the next message if there is one.
+    fn try_receive<D: Data>(&self) -> Result<Option<D>, PubSubErr>;
+
+    /// Count of messages this subscriber lost because of the overflow.
+    fn dropped(&self) -> u64;
+}
+
+/// Open network with publish/subscribe topics.
+pub trait PubSubNetwork<S: Service>: OpenNetwork<S> {
+
+    type Broadcast: Broadcast;
+    type Subscription: Subscription;
+
+    /// Start publishing to the topic. Topic may have only one
+    /// publisher at a time.
+    fn broadcast(&self, topic: &str) -> Result<Self::Broadcast, PubSubErr>;
+
+    /// Subscribe to the topic. Topic need not have a publisher yet.
+    fn subscribe(&self, topic: &str, buffer: BufferPolicy) -> Self::Subscription;
+}
+
+type Message = Box<dyn Any + Send>;
+
+type Sink = Arc<dyn AuditSink<SlowConsumer> + Send + Sync>;
+
+/// Slow consumer watch of the hub.
+struct Watch {
+    policy  : SlowConsumerPolicy,
+    sink    : Sink,
+}
+
+/// Buffer of one subscriber.
+struct Buffer {
+    id      : u64,
+    topic   : String,
+    policy  : BufferPolicy,
+    state   : Mutex<BufferState>,
+    changed : Condvar,
+    watch   : Option<Arc<Watch>>,
+}
+
+#[derive(Default)]
+struct BufferState {
+    queue   : VecDeque<Message>,
+    dropped : u64,
+    overrun : bool,
+
+    /// Topic has no publisher.
+    closed  : bool,
+
+    /// Subscription was dropped.
+    gone    : bool,
+
+    /// Publishes in a row that found the backlog too long.
+    strikes : u32,
+
+    /// Penalty applied to the subscriber, if any.
+    penalty : Option<Penalty>,
+
+    /// Messages offered to the sampled subscriber.
+    offered : u64,
+}
+
+impl Buffer {
+
+    /// Put the message into the buffer. False if it was not put.
+    fn push(&self, message: Message) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if let Some(report) = self.judge(&mut state) {
+            let sink = &self.watch.as_ref().unwrap().sink;
+            drop(state);
+            sink.record(report);
+            state = self.state.lock().unwrap();
+        }
+        match state.penalty {
+            Some(Penalty::Disconnect) => return false,
+            Some(Penalty::Sample(n)) => {
+                state.offered += 1;
+                if !(state.offered - 1).is_multiple_of(n.max(1) as u64) {
+                    state.dropped += 1;
+                    return false;
+                }
+            },
+            _ => (),
+        }
+        while state.queue.len() >= self.policy.capacity && !state.gone {
+            let overflow = match (state.penalty, self.policy.overflow) {
+                (Some(Penalty::Downgrade), Overflow::Block) => Overflow::DropOldest,
+                (_, overflow)                               => overflow,
+            };
+            match overflow {
+                Overflow::DropOldest => {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                },
+                Overflow::Error => {
+                    state.overrun = true;
+                    state.dropped += 1;
+                    return false;
+                },
+                Overflow::Block => state = self.changed.wait(state).unwrap(),
+            }
+        }
+        if state.gone {
+            return false;
+        }
+        state.queue.push_back(message);
+        self.changed.notify_all();
+        true
+    }
+
+    /// Count the strike if the backlog is too long and apply the
+    /// penalty after enough of them. Returns the report to raise.
+    fn judge(&self, state: &mut BufferState) -> Option<SlowConsumer> {
+        let policy = self.watch.as_ref()?.policy;
+        if state.penalty.is_some() || state.gone {
+            return None;
+        }
+        let backlog = state.queue.len();
+        if backlog < policy.threshold {
+            state.strikes = 0;
+            return None;
+        }
+        state.strikes += 1;
+        if state.strikes < policy.strikes.max(1) {
+            return None;
+        }
+        state.penalty = Some(policy.penalty);
+        if policy.penalty == Penalty::Disconnect {
+            state.closed = true;
+        }
+        self.changed.notify_all();
+        Some(SlowConsumer {
+            topic           : self.topic.clone(),
+            subscription    : self.id,
+            backlog,
+            penalty         : policy.penalty,
+        })
+    }
+
+    fn set_closed(&self, closed: bool) {
+        self.state.lock().unwrap().closed = closed;
+        self.changed.notify_all();
+    }
+
+    /// Take the next message. None if waiting is needed.
+    fn take<D: Data>(&self, state: &mut BufferState) -> Option<Result<D, PubSubErr>> {
+        if state.overrun {
+            state.overrun = false;
+            return Some(Err(PubSubErr::Overrun));
+        }
+        match state.queue.front() {
+            Some(m) if !m.is::<D>() => return Some(Err(PubSubErr::UnexpectedData)),
+            Some(_)                 => (),
+            None if state.penalty == Some(Penalty::Disconnect)
+                                    => return Some(Err(PubSubErr::Disconnected)),
+            None if state.closed    => return Some(Err(PubSubErr::Closed)),
+            None                    => return None,
+        }
+        let message = state.queue.pop_front().unwrap();
+        self.changed.notify_all();
+        Some(Ok(*message.downcast().unwrap()))
+    }
+}
+
+#[derive(Default)]
+struct Topic {
+    published   : bool,
+    subscribers : Vec<Weak<Buffer>>,
+}
+
+type Topics = Arc<Mutex<HashMap<String, Topic>>>;
+
+/// In-process set of topics for networks that keep all their objects
+/// in one address space.
+#[derive(Clone, Default)]
+pub struct Hub {
+    topics  : Topics,
+    watch   : Option<Arc<Watch>>,
+    next    : Arc<AtomicU64>,
+}
+
+impl Hub {
+
+    /// Create hub without topics.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Watch the subscriptions made from now on for slow consumers.
+    /// Each penalized one is reported to the sink, e.g. the event log.
+    pub fn slow_consumers<A>(mut self, policy: SlowConsumerPolicy, sink: Arc<A>) -> Self
+        where A: AuditSink<SlowConsumer> + Send + Sync + 'static
+    {
+        self.watch = Some(Arc::new(Watch { policy, sink }));
+        self
+    }
+
+    /// Start publishing to the topic.
+    pub fn broadcast(&self, topic: &str) -> Result<HubBroadcast, PubSubErr> {
+        let mut topics = self.topics.lock().unwrap();
+        let entry = topics.entry(topic.to_string()).or_default();
+        if entry.published {
+            return Err(PubSubErr::Taken);
+        }
+        entry.published = true;
+        for buffer in entry.subscribers.iter().filter_map(Weak::upgrade) {
+            buffer.set_closed(false);
+        }
+        Ok(HubBroadcast {
+            topics  : self.topics.clone(),
+            topic   : topic.to_string(),
+        })
+    }
+
+    /// Subscribe to the topic.
+    pub fn subscribe(&self, topic: &str, policy: BufferPolicy) -> HubSubscription {
+        let buffer = Arc::new(Buffer {
+            id      : self.next.fetch_add(1, Ordering::Relaxed),
+            topic   : topic.to_string(),
+            policy,
+            state   : Mutex::new(BufferState::default()),
+            changed : Condvar::new(),
+            watch   : self.watch.clone(),
+        });
+        self.topics.lock().unwrap().entry(topic.to_string()).or_default()
+            .subscribers.push(Arc::downgrade(&buffer));
+        HubSubscription { buffer }
+    }
+}
+
+/// Publisher of the hub topic.
+pub struct HubBroadcast {
+    topics  : Topics,
+    topic   : String,
+}
+
+impl HubBroadcast {
+
+    /// Alive subscribers of the topic. Dropped ones are forgotten.
+    fn buffers(&self) -> Vec<Arc<Buffer>> {
+        let mut topics = self.topics.lock().unwrap();
+        let topic = topics.get_mut(&self.topic).unwrap();
+        topic.subscribers.retain(|b| b.strong_count() > 0);
+        topic.subscribers.iter().filter_map(Weak::upgrade).collect()
+    }
+}
+
+impl Broadcast for HubBroadcast {
+
+    fn publish<D: Data + Clone>(&self, data: D) -> Result<usize, PubSubErr> {
+        // Topics are not locked while pushing as blocking subscribers
+        // may hold the publisher for long.
+        let buffers = self.buffers();
+        Ok(buffers.iter().filter(|b| b.push(Box::new(data.clone()))).count())
+    }
+
+    fn subscribers(&self) -> usize {
+        self.buffers().len()
+    }
+}
+
+impl Drop for HubBroadcast {
+
+    fn drop(&mut self) {
+        let mut topics = self.topics.lock().unwrap();
+        let topic = topics.get_mut(&self.topic).unwrap();
+        topic.published = false;
+        for buffer in topic.subscribers.iter().filter_map(Weak::upgrade) {
+            buffer.set_closed(true);
+        }
+    }
+}
+
+/// Subscriber of the hub topic.
+pub struct HubSubscription {
+    buffer  : Arc<Buffer>,
+}
+
+impl Subscription for HubSubscription {
+
+    fn receive<D: Data>(&self) -> Result<D, PubSubErr> {
+        let mut state = self.buffer.state.lock().unwrap();
+        loop {
+            if let Some(result) = self.buffer.take(&mut state) {
+                return result;
+            }
+            state = self.buffer.changed.wait(state).unwrap();
+        }
+    }
+
+    fn try_receive<D: Data>(&self) -> Result<Option<D>, PubSubErr> {
+        let mut state = self.buffer.state.lock().unwrap();
+        self.buffer.take(&mut state).transpose()
+    }
+
+    fn dropped(&self) -> u64 {
+        self.buffer.state.lock().unwrap().dropped
+    }
+}
+
+impl HubSubscription {
+
+    /// Identifier of the subscription, unique in its hub.
+    pub fn id(&self) -> u64 {
+        self.buffer.id
+    }
+
+    /// Penalty applied to this subscriber as a slow consumer, if any.
+    pub fn penalty(&self) -> Option<Penalty> {
+        self.buffer.state.lock().unwrap().penalty
+    }
+}
+
+impl Drop for HubSubscription {
+
+    fn drop(&mut self) {
+        // Release the publisher that may wait for room in the buffer.
+        self.buffer.state.lock().unwrap().gone = true;
+        self.buffer.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use events::{ControlEvent, EventLog};
+    use std::thread;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Tick(u32);
+
+    impl Data for Tick {
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let hub = Hub::new();
+        let oldest = hub.subscribe("ticks", BufferPolicy::new(2, Overflow::DropOldest));
+        let error = hub.subscribe("ticks", BufferPolicy::new(2, Overflow::Error));
+        let publisher = hub.broadcast("ticks").unwrap();
+        assert_eq!(hub.broadcast("ticks").err(), Some(PubSubErr::Taken));
+        for i in 0..3u32 {
+            publisher.publish(Tick(i)).unwrap();
+        }
+
+        assert_eq!(oldest.receive::<Tick>(), Ok(Tick(1)));
+        assert_eq!(oldest.receive::<Tick>(), Ok(Tick(2)));
+        assert_eq!(oldest.dropped(), 1);
+        assert_eq!(error.receive::<Tick>(), Err(PubSubErr::Overrun));
+        assert_eq!(error.receive::<Tick>(), Ok(Tick(0)));
+        assert_eq!(error.receive::<String>(), Err(PubSubErr::UnexpectedData));
+        assert_eq!(error.receive::<Tick>(), Ok(Tick(1)));
+
+        drop(error);
+        assert_eq!(publisher.subscribers(), 1);
+        drop(publisher);
+        assert_eq!(oldest.try_receive::<Tick>(), Err(PubSubErr::Closed));
+    }
+
+    #[test]
+    fn slow_consumers() {
+        let log = Arc::new(EventLog::<u32, String>::new());
+        let events = log.subscribe(Default::default());
+        let policy = SlowConsumerPolicy { threshold: 2, strikes: 2, penalty: Penalty::Disconnect };
+        let hub = Hub::new().slow_consumers(policy, log.clone());
+        let fast = hub.subscribe("ticks", BufferPolicy::new(8, Overflow::Error));
+        let slow = hub.subscribe("ticks", BufferPolicy::new(8, Overflow::Error));
+        let publisher = hub.broadcast("ticks").unwrap();
+        for i in 0..5u32 {
+            publisher.publish(Tick(i)).unwrap();
+            assert_eq!(fast.receive::<Tick>(), Ok(Tick(i)));
+        }
+
+        assert_eq!(fast.penalty(), None);
+        assert_eq!(slow.penalty(), Some(Penalty::Disconnect));
+        assert_eq!(events.try_recv().unwrap(), ControlEvent::SlowConsumer(SlowConsumer {
+            topic           : "ticks".to_string(),
+            subscription    : slow.id(),
+            backlog         : 3,
+            penalty         : Penalty::Disconnect,
+        }));
+        for i in 0..3u32 {
+            assert_eq!(slow.receive::<Tick>(), Ok(Tick(i)));
+        }
+        assert_eq!(slow.try_receive::<Tick>(), Err(PubSubErr::Disconnected));
+    }
+
+    #[test]
+    fn sampled_consumer() {
+        let policy = SlowConsumerPolicy { threshold: 1, strikes: 1, penalty: Penalty::Sample(2) };
+        let hub = Hub::new().slow_consumers(policy, Arc::new(EventLog::<u32, String>::new()));
+        let slow = hub.subscribe("ticks", BufferPolicy::new(8, Overflow::Error));
+        let publisher = hub.broadcast("ticks").unwrap();
+        for i in 0..6u32 {
+            publisher.publish(Tick(i)).unwrap();
+        }
+        let received: Vec<_> = (0..4).map(|_| slow.receive::<Tick>().unwrap().0).collect();
+        assert_eq!(received, vec![0, 1, 3, 5]);
+        assert_eq!(slow.dropped(), 2);
+    }
+
+    #[test]
+    fn blocking_subscriber_holds_publisher() {
+        let hub = Hub::new();
+        let subscription = hub.subscribe("log", BufferPolicy::new(1, Overflow::Block));
+        let publisher = hub.broadcast("log").unwrap();
+        let thread = thread::spawn(move || {
+            for i in 0..10u32 {
+                assert_eq!(publisher.publish(Tick(i)), Ok(1));
+            }
+        });
+        for i in 0..10u32 {
+            assert_eq!(subscription.receive::<Tick>(), Ok(Tick(i)));
+        }
+        thread.join().unwrap();
+        assert_eq!(subscription.receive::<Tick>(), Err(PubSubErr::Closed));
+    }
+}
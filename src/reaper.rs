@@ -0,0 +1,127 @@
+//! Garbage collection of orphaned channels and registrations. When an
+//! object dies abnormally, the backend may fail to clean up after it.
+//! Reaper periodically walks the network and reclaims channels whose
+//! both endpoints are dead and registrations owned by dead objects.
+
+use super::{Network, Service};
+
+/// Source of the information about which objects are alive. Backends
+/// plug their own sources, e.g. process table of the kernel.
+pub trait LivenessSource<Id> {
+
+    /// Check if object with given identifier is alive.
+    fn is_alive(&self, id: &Id) -> bool;
+}
+
+/// Receiver of the audit events.
+pub trait AuditSink<E> {
+
+    /// Record the event.
+    fn record(&self, event: E);
+}
+
+/// Network that lets reaper inspect and reclaim its resources.
+pub trait ReapableNetwork<S: Service>: Network<S> {
+
+    /// Identifier of the object in this network.
+    type ObjectId;
+
+    /// Identifier of the channel in this network.
+    type ChannelId;
+
+    /// List all channels as (channel, requester, provider).
+    fn channels(&self) -> Vec<(Self::ChannelId, Self::ObjectId, Self::ObjectId)>;
+
+    /// List all registrations as (service, owner).
+    fn registrations(&self) -> Vec<(S::Id, Self::ObjectId)>;
+
+    /// Close the channel and free its resources.
+    fn reclaim_channel(&self, channel: &Self::ChannelId);
+
+    /// Remove the registration of the service owned by given object.
+    fn reclaim_registration(&self, service: &S::Id, owner: &Self::ObjectId);
+}
+
+/// Event emitted for each reclaimed resource.
+#[derive(Debug)]
+pub enum ReapEvent<OId, SId, CId> {
+
+    /// Channel was closed because both its endpoints were dead.
+    ChannelReclaimed {
+        channel     : CId,
+        requester   : OId,
+        provider    : OId,
+    },
+
+    /// Registration was removed because its owner was dead.
+    RegistrationReclaimed {
+        service     : SId,
+        owner       : OId,
+    },
+}
+
+/// Count of resources reclaimed in one pass.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReapReport {
+
+    /// Count of closed channels.
+    pub channels        : usize,
+
+    /// Count of removed registrations.
+    pub registrations   : usize,
+}
+
+/// Reaper that uses given liveness source and reports to given sink.
+pub struct Reaper<L, A> {
+    liveness    : L,
+    audit       : A,
+}
+
+impl<L, A> Reaper<L, A> {
+
+    /// Create new reaper.
+    pub fn new(liveness: L, audit: A) -> Self {
+        Reaper {
+            liveness,
+            audit,
+        }
+    }
+
+    /// Walk the network once and reclaim everything that is orphaned.
+    pub fn run_once<S, N>(&self, network: &N) -> ReapReport
+        where   S   : Service,
+                N   : ReapableNetwork<S>,
+                L   : LivenessSource<N::ObjectId>,
+                A   : AuditSink<ReapEvent<N::ObjectId, S::Id, N::ChannelId>>
+    {
+        let mut report = ReapReport::default();
+
+        for (channel, requester, provider) in network.channels() {
+            if self.liveness.is_alive(&requester)
+                    || self.liveness.is_alive(&provider) {
+                continue;
+            }
+            network.reclaim_channel(&channel);
+            report.channels += 1;
+            self.audit.record(ReapEvent::ChannelReclaimed {
+                channel,
+                requester,
+                provider,
+            });
+        }
+
+        for (service, owner) in network.registrations() {
+            if self.liveness.is_alive(&owner) {
+                continue;
+            }
+            network.reclaim_registration(&service, &owner);
+            report.registrations += 1;
+            self.audit.record(ReapEvent::RegistrationReclaimed {
+                service,
+                owner,
+            });
+        }
+
+        report
+    }
+}
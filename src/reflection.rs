@@ -0,0 +1,457 @@
+//! Reflection of the services. Provider adds the endpoint 'ENDPOINT'
+//! that answers with the description of the service: its endpoints,
+//! their methods with request and reply types, the structs the types
+//! refer to and the protocol versions. Generic tools such as the
+//! console, fuzzers and gateways get the description with 'describe'
+//! and drive the service without being built against it.
+//!
+//! Type names are those of the 'ccs-build' descriptors: 'u8' to 'u64',
+//! 'i8' to 'i64', 'bool', 'string', 'bytes', 'list<T>', 'option<T>' and
+//! struct names.
+
+use std::fmt;
+
+use super::{ConnectErr, EndpointConnect, Object, Service, Socket, SocketErr, Versions};
+use message::{DecodeErr, EncodeErr, Message, TypedErr};
+
+/// Name of the reflection endpoint.
+pub const ENDPOINT: &str = "ccs.reflection";
+
+/// Type of the field, request or reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeDesc {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    Bool,
+    String,
+    Bytes,
+    List(Box<TypeDesc>),
+    Option(Box<TypeDesc>),
+    Struct(String),
+}
+
+const PRIMITIVES: [(&str, TypeDesc); 11] = [
+    ("u8", TypeDesc::U8),
+    ("u16", TypeDesc::U16),
+    ("u32", TypeDesc::U32),
+    ("u64", TypeDesc::U64),
+    ("i8", TypeDesc::I8),
+    ("i16", TypeDesc::I16),
+    ("i32", TypeDesc::I32),
+    ("i64", TypeDesc::I64),
+    ("bool", TypeDesc::Bool),
+    ("string", TypeDesc::String),
+    ("bytes", TypeDesc::Bytes),
+];
+
+impl TypeDesc {
+
+    /// Parse the type name. None if it is not valid.
+    pub fn parse(text: &str) -> Option<TypeDesc> {
+        let text = text.trim();
+        let inner = |prefix: &str| text.strip_prefix(prefix)
+            .and_then(|t| t.strip_suffix('>'))
+            .and_then(TypeDesc::parse)
+            .map(Box::new);
+        if let Some((_, ty)) = PRIMITIVES.iter().find(|&&(name, _)| name == text) {
+            Some(ty.clone())
+        } else if text.starts_with("list<") {
+            inner("list<").map(TypeDesc::List)
+        } else if text.starts_with("option<") {
+            inner("option<").map(TypeDesc::Option)
+        } else if is_ident(text) {
+            Some(TypeDesc::Struct(text.to_string()))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for TypeDesc {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TypeDesc::List(ref t)       => write!(f, "list<{}>", t),
+            TypeDesc::Option(ref t)     => write!(f, "option<{}>", t),
+            TypeDesc::Struct(ref name)  => write!(f, "{}", name),
+            ref primitive               => {
+                let (name, _) = PRIMITIVES.iter().find(|(_, t)| t == primitive)
+                    .expect("all other types are primitive");
+                write!(f, "{}", name)
+            },
+        }
+    }
+}
+
+/// Struct the types refer to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructDesc {
+    pub name    : String,
+    pub fields  : Vec<(String, TypeDesc)>,
+}
+
+/// Method of the endpoint: request it takes and reply it gives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodDesc {
+    pub name    : String,
+    pub request : TypeDesc,
+    pub reply   : TypeDesc,
+}
+
+/// Endpoint of the service with its methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointDesc {
+
+    /// Name of the endpoint. None for the service itself.
+    pub name    : Option<String>,
+    pub methods : Vec<MethodDesc>,
+}
+
+/// What the service provides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceDescription {
+
+    /// Identifier of the service in text form.
+    pub service     : String,
+    pub versions    : Versions,
+    pub endpoints   : Vec<EndpointDesc>,
+    pub structs     : Vec<StructDesc>,
+}
+
+impl StructDesc {
+
+    /// Create struct without fields.
+    pub fn new(name: &str) -> Self {
+        StructDesc {
+            name    : name.to_string(),
+            fields  : Vec::new(),
+        }
+    }
+
+    /// Add the field. Panics if the type name is not valid.
+    pub fn field(mut self, name: &str, ty: &str) -> Self {
+        self.fields.push((name.to_string(), parse(ty)));
+        self
+    }
+}
+
+impl EndpointDesc {
+
+    /// Create description of the service itself.
+    pub fn service() -> Self {
+        EndpointDesc {
+            name    : None,
+            methods : Vec::new(),
+        }
+    }
+
+    /// Create description of the named endpoint.
+    pub fn named(name: &str) -> Self {
+        EndpointDesc {
+            name    : Some(name.to_string()),
+            methods : Vec::new(),
+        }
+    }
+
+    /// Add the method. Panics if the type names are not valid.
+    pub fn method(mut self, name: &str, request: &str, reply: &str) -> Self {
+        self.methods.push(MethodDesc {
+            name    : name.to_string(),
+            request : parse(request),
+            reply   : parse(reply),
+        });
+        self
+    }
+}
+
+impl ServiceDescription {
+
+    /// Create description of the service without endpoints.
+    pub fn new(service: &str, versions: Versions) -> Self {
+        ServiceDescription {
+            service     : service.to_string(),
+            versions,
+            endpoints   : Vec::new(),
+            structs     : Vec::new(),
+        }
+    }
+
+    /// Add the endpoint.
+    pub fn endpoint(mut self, endpoint: EndpointDesc) -> Self {
+        self.endpoints.push(endpoint);
+        self
+    }
+
+    /// Add the struct.
+    pub fn structure(mut self, desc: StructDesc) -> Self {
+        self.structs.push(desc);
+        self
+    }
+
+    /// Find the method of the endpoint, None for the service itself.
+    pub fn method(&self, endpoint: Option<&str>, name: &str) -> Option<&MethodDesc> {
+        self.endpoints.iter()
+            .find(|e| e.name.as_deref() == endpoint)
+            .and_then(|e| e.methods.iter().find(|m| m.name == name))
+    }
+
+    /// Find the struct by its name.
+    pub fn find_struct(&self, name: &str) -> Option<&StructDesc> {
+        self.structs.iter().find(|s| s.name == name)
+    }
+}
+
+fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse(ty: &str) -> TypeDesc {
+    TypeDesc::parse(ty).unwrap_or_else(|| panic!("bad type name '{}'", ty))
+}
+
+/// Request of the reflection protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReflectionRequest {
+
+    /// Ask for the whole description of the service.
+    Describe,
+}
+
+impl Message for ReflectionRequest {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        match *self {
+            ReflectionRequest::Describe => out.push(0),
+        }
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        match u8::decode(input)? {
+            0   => Ok(ReflectionRequest::Describe),
+            _   => Err(DecodeErr::Invalid("ReflectionRequest")),
+        }
+    }
+}
+
+impl Message for TypeDesc {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        match *self {
+            TypeDesc::List(ref t)       => {
+                out.push(11);
+                t.encode(out)
+            },
+            TypeDesc::Option(ref t)     => {
+                out.push(12);
+                t.encode(out)
+            },
+            TypeDesc::Struct(ref name)  => {
+                out.push(13);
+                name.encode(out)
+            },
+            ref primitive               => {
+                let tag = PRIMITIVES.iter().position(|(_, t)| t == primitive)
+                    .expect("all other types are primitive");
+                out.push(tag as u8);
+                Ok(())
+            },
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        match u8::decode(input)? {
+            11      => Ok(TypeDesc::List(Box::new(TypeDesc::decode(input)?))),
+            12      => Ok(TypeDesc::Option(Box::new(TypeDesc::decode(input)?))),
+            13      => Ok(TypeDesc::Struct(String::decode(input)?)),
+            tag     => PRIMITIVES.get(tag as usize)
+                .map(|(_, t)| t.clone())
+                .ok_or(DecodeErr::Invalid("TypeDesc")),
+        }
+    }
+}
+
+impl Message for StructDesc {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        self.name.encode(out)?;
+        self.fields.encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        Ok(StructDesc {
+            name    : Message::decode(input)?,
+            fields  : Message::decode(input)?,
+        })
+    }
+}
+
+impl Message for MethodDesc {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        self.name.encode(out)?;
+        self.request.encode(out)?;
+        self.reply.encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        Ok(MethodDesc {
+            name    : Message::decode(input)?,
+            request : Message::decode(input)?,
+            reply   : Message::decode(input)?,
+        })
+    }
+}
+
+impl Message for EndpointDesc {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        self.name.encode(out)?;
+        self.methods.encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        Ok(EndpointDesc {
+            name    : Message::decode(input)?,
+            methods : Message::decode(input)?,
+        })
+    }
+}
+
+impl Message for ServiceDescription {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        self.service.encode(out)?;
+        (self.versions.min, self.versions.max).encode(out)?;
+        self.endpoints.encode(out)?;
+        self.structs.encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        let service = Message::decode(input)?;
+        let (min, max) = Message::decode(input)?;
+        Ok(ServiceDescription {
+            service,
+            versions    : Versions::new(min, max),
+            endpoints   : Message::decode(input)?,
+            structs     : Message::decode(input)?,
+        })
+    }
+}
+
+/// Error of the reflection query.
+#[derive(Debug)]
+pub enum ReflectionErr<S> {
+
+    /// Service is not provided or has no reflection endpoint.
+    Connect(ConnectErr<S>),
+
+    /// Channel failed or the reply is not a description.
+    Typed(TypedErr),
+}
+
+/// Answer the reflection requests on the channel until it closes.
+/// Entry function of the reflection endpoint calls it.
+pub fn serve<O, S, SC>(socket: &SC, description: &ServiceDescription) -> Result<(), TypedErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+{
+    loop {
+        let bytes = match socket.receive::<Vec<u8>>() {
+            Ok(bytes)                       => bytes,
+            Err(SocketErr::ChannelClosed)   => return Ok(()),
+            Err(e)                          => return Err(TypedErr::Socket(e)),
+        };
+        match ReflectionRequest::from_bytes(&bytes).map_err(TypedErr::Decode)? {
+            ReflectionRequest::Describe => {
+                let payload = description.to_bytes().map_err(TypedErr::Encode)?;
+                socket.send(payload).map_err(TypedErr::Socket)?;
+            },
+        }
+    }
+}
+
+/// Ask for the description on the channel to the reflection endpoint.
+pub fn query<O, S, SC>(socket: &SC) -> Result<ServiceDescription, TypedErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+{
+    let payload = ReflectionRequest::Describe.to_bytes().map_err(TypedErr::Encode)?;
+    socket.send(payload).map_err(TypedErr::Socket)?;
+    let bytes = socket.receive::<Vec<u8>>().map_err(TypedErr::Socket)?;
+    ServiceDescription::from_bytes(&bytes).map_err(TypedErr::Decode)
+}
+
+/// Connect to the reflection endpoint of the service and get its
+/// description.
+pub fn describe<S, N>(network: &N, service: S) -> Result<ServiceDescription, ReflectionErr<S>>
+        where   S   : Service,
+                N   : EndpointConnect<S>
+{
+    let socket = network.connect_endpoint(service, ENDPOINT).map_err(ReflectionErr::Connect)?;
+    query(&socket).map_err(ReflectionErr::Typed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use {OpenNetwork, RegistrationForm};
+
+    fn description() -> ServiceDescription {
+        ServiceDescription::new("memory", Versions::new(1, 2))
+            .endpoint(EndpointDesc::service()
+                .method("alloc", "u64", "Block")
+                .method("free", "Block", "bool"))
+            .endpoint(EndpointDesc::named("stats")
+                .method("blocks", "option<string>", "list<Block>"))
+            .structure(StructDesc::new("Block")
+                .field("addr", "u64")
+                .field("size", "u64"))
+    }
+
+    fn memory(_socket: LocalSocket) -> ! {
+        finish()
+    }
+
+    fn reflect(socket: LocalSocket) -> ! {
+        let _ = serve(&socket, &description());
+        finish()
+    }
+
+    #[test]
+    fn types() {
+        for name in &["u8", "bytes", "list<option<Block>>", "option<i64>"] {
+            assert_eq!(TypeDesc::parse(name).unwrap().to_string(), *name);
+        }
+        assert_eq!(TypeDesc::parse("list<>"), None);
+        assert_eq!(TypeDesc::parse("1x"), None);
+    }
+
+    #[test]
+    fn describe_service() {
+        let network = LocalNetwork::new();
+        let service = || LocalService::by_id("memory".to_string());
+        assert!(matches!(describe(&network, service()), Err(ReflectionErr::Connect(_))));
+
+        let form = RegistrationForm::new(memory, "memory".to_string())
+            .endpoint(ENDPOINT, reflect);
+        network.register(form).unwrap();
+        let found = describe(&network, service()).unwrap();
+        assert_eq!(found, description());
+        let blocks = found.method(Some("stats"), "blocks").unwrap();
+        assert_eq!(blocks.reply.to_string(), "list<Block>");
+        assert!(found.method(None, "blocks").is_none());
+        assert_eq!(found.find_struct("Block").unwrap().fields.len(), 2);
+    }
+}
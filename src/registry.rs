@@ -0,0 +1,142 @@
+//! Service registry for backends. The registry is split into shards
+//! by the hash of the service identifier, each behind its own lock, so
+//! lookups take O(1) and registrations of different services rarely
+//! contend on the same lock even with tens of thousands of services.
+
+use std::collections::HashMap;
+use std::collections::hash_map::{DefaultHasher, Entry as MapEntry};
+use std::hash::{Hash, Hasher};
+use std::sync::RwLock;
+
+use super::RegistrationErr;
+
+/// Providers of a single service.
+#[derive(Debug, Clone)]
+struct Entry<P> {
+    providers   : Vec<P>,
+    unique      : bool,
+}
+
+/// Registry that maps service identifiers to their providers.
+pub struct ShardedRegistry<Id, P> {
+    shards  : Vec<RwLock<HashMap<Id, Entry<P>>>>,
+}
+
+impl<Id: Hash + Eq, P: Clone + PartialEq> ShardedRegistry<Id, P> {
+
+    /// Create registry with given count of shards. Count is rounded up
+    /// to the power of two.
+    pub fn new(shards: usize) -> Self {
+        let count = shards.max(1).next_power_of_two();
+        ShardedRegistry {
+            shards  : (0..count).map(|_| RwLock::new(HashMap::new())).collect(),
+        }
+    }
+
+    fn shard(&self, id: &Id) -> &RwLock<HashMap<Id, Entry<P>>> {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        let index = hasher.finish() as usize & (self.shards.len() - 1);
+        &self.shards[index]
+    }
+
+    /// Add the provider of the service. Unique registration fails if
+    /// the service already has providers, and any registration fails
+    /// if the service is uniquely registered.
+    pub fn register(&self, id: Id, provider: P, unique: bool)
+        -> Result<(), RegistrationErr>
+    {
+        let mut shard = self.shard(&id).write().unwrap();
+        match shard.entry(id) {
+            MapEntry::Vacant(v) => {
+                v.insert(Entry {
+                    providers   : vec![provider],
+                    unique,
+                });
+                Ok(())
+            },
+            MapEntry::Occupied(mut o) => {
+                let entry = o.get_mut();
+                if entry.unique {
+                    Err(RegistrationErr::UniquelyRegistered)
+                } else if unique {
+                    Err(RegistrationErr::AlreadyRegistered)
+                } else {
+                    entry.providers.push(provider);
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    /// Remove the provider of the service. Returns false if it was not
+    /// registered.
+    pub fn unregister(&self, id: &Id, provider: &P) -> bool {
+        let mut shard = self.shard(id).write().unwrap();
+        let (removed, empty) = match shard.get_mut(id) {
+            Some(entry) => {
+                let before = entry.providers.len();
+                entry.providers.retain(|p| p != provider);
+                (entry.providers.len() != before, entry.providers.is_empty())
+            },
+            None        => return false,
+        };
+        if empty {
+            shard.remove(id);
+        }
+        removed
+    }
+
+    /// Get providers of the service.
+    pub fn providers(&self, id: &Id) -> Vec<P> {
+        self.shard(id).read().unwrap().get(id)
+            .map_or_else(Vec::new, |e| e.providers.clone())
+    }
+
+    /// Check if the service is uniquely registered.
+    pub fn is_unique(&self, id: &Id) -> bool {
+        self.shard(id).read().unwrap().get(id).is_some_and(|e| e.unique)
+    }
+
+    /// Check if the service has at least one provider.
+    pub fn contains(&self, id: &Id) -> bool {
+        self.shard(id).read().unwrap().contains_key(id)
+    }
+
+    /// Count of registered services.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.read().unwrap().len()).sum()
+    }
+
+    /// Check if no services are registered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Call the function for each registered service with its providers
+    /// and uniqueness. Shards are visited one by one, so the view is not
+    /// atomic across shards.
+    pub fn for_each<F: FnMut(&Id, &[P], bool)>(&self, mut f: F) {
+        for shard in &self.shards {
+            for (id, entry) in shard.read().unwrap().iter() {
+                f(id, &entry.providers, entry.unique);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_registration() {
+        let registry = ShardedRegistry::new(4);
+        registry.register("memory", 1, true).unwrap();
+        assert!(registry.register("memory", 2, false).is_err());
+        assert!(registry.unregister(&"memory", &1));
+        registry.register("memory", 2, false).unwrap();
+        assert!(registry.register("memory", 3, true).is_err());
+        assert_eq!(registry.providers(&"memory"), vec![2]);
+    }
+}
@@ -0,0 +1,256 @@
+//! Message router. Router receives messages on one channel and forwards
+//! each of them to one of several downstream channels chosen by the
+//! message headers or by a user predicate. Messages no route accepts
+//! go to the dead-letter channel, if there is one, or are dropped.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Data, Object, Service, Socket, SocketErr};
+use metrics::{MetricFamily, MetricKind, MetricsSource, Sample};
+
+/// Message that carries named headers.
+pub trait Headers {
+
+    /// Get value of the header with given name.
+    fn header(&self, name: &str) -> Option<&str>;
+}
+
+type Predicate<D> = Box<dyn Fn(&D) -> bool + Send + Sync>;
+
+struct Route<D, SC> {
+    name        : String,
+    predicate   : Predicate<D>,
+    socket      : SC,
+    routed      : AtomicUsize,
+}
+
+/// Router of the messages of type D to downstream sockets SC.
+pub struct Router<D, SC> {
+    routes          : Vec<Route<D, SC>>,
+    dead_letter     : Option<SC>,
+    dead_lettered   : AtomicUsize,
+}
+
+impl<D: Data, SC> Default for Router<D, SC> {
+
+    fn default() -> Self {
+        Router {
+            routes          : Vec::new(),
+            dead_letter     : None,
+            dead_lettered   : AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<D: Data, SC> Router<D, SC> {
+
+    /// Create router without routes.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add route for messages accepted by the predicate. Routes are
+    /// checked in the order they were added and the first matching one
+    /// is taken.
+    pub fn route_if<F>(mut self, name: &str, predicate: F, socket: SC) -> Self
+        where F: Fn(&D) -> bool + Send + Sync + 'static
+    {
+        self.routes.push(Route {
+            name        : name.to_string(),
+            predicate   : Box::new(predicate),
+            socket,
+            routed      : AtomicUsize::new(0),
+        });
+        self
+    }
+
+    /// Add route for messages which header has given value.
+    pub fn route_header(self, header: &str, value: &str, socket: SC) -> Self
+        where D: Headers
+    {
+        let name = format!("{}={}", header, value);
+        let header = header.to_string();
+        let value = value.to_string();
+        self.route_if(&name, move |d: &D| d.header(&header) == Some(&value[..]),
+                socket)
+    }
+
+    /// Set the channel for messages that match no route.
+    pub fn dead_letter(mut self, socket: SC) -> Self {
+        self.dead_letter = Some(socket);
+        self
+    }
+
+    /// Forward single message. Errors of the downstream channels are
+    /// returned as is.
+    pub fn forward<O, S>(&self, message: D) -> Result<(), SocketErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+    {
+        if let Some(route) = self.routes.iter().find(|r| (r.predicate)(&message)) {
+            route.routed.fetch_add(1, Ordering::Relaxed);
+            return route.socket.send(message);
+        }
+        self.dead_lettered.fetch_add(1, Ordering::Relaxed);
+        match self.dead_letter {
+            Some(ref socket)    => socket.send(message),
+            None                => Ok(()),
+        }
+    }
+
+    /// Forward all messages received from the input channel until it
+    /// gets closed.
+    pub fn run<O, S, IN>(&self, input: &IN) -> Result<(), SocketErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                IN  : Socket<O, S>
+    {
+        loop {
+            match input.receive::<D>() {
+                Ok(message)                     => self.forward(message)?,
+                Err(SocketErr::ChannelClosed)   => return Ok(()),
+                Err(e)                          => return Err(e),
+            }
+        }
+    }
+}
+
+impl<D, SC> MetricsSource for Router<D, SC> {
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut samples: Vec<_> = self.routes.iter()
+            .map(|r| Sample {
+                labels  : vec![("route".to_string(), r.name.clone())],
+                value   : r.routed.load(Ordering::Relaxed) as f64,
+            })
+            .collect();
+        samples.push(Sample {
+            labels  : vec![("route".to_string(), "dead-letter".to_string())],
+            value   : self.dead_lettered.load(Ordering::Relaxed) as f64,
+        });
+        vec![MetricFamily {
+            name    : "ccs_router_messages".to_string(),
+            help    : "Messages forwarded by the router.".to_string(),
+            kind    : MetricKind::Counter,
+            samples,
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use {OpenNetwork, RegistrationForm};
+
+    /// Messages taken by the sinks, as "sink:body".
+    static RECEIVED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Msg {
+        kind    : &'static str,
+        body    : String,
+    }
+
+    impl Data for Msg {
+    }
+
+    impl Headers for Msg {
+
+        fn header(&self, name: &str) -> Option<&str> {
+            if name == "kind" {
+                Some(self.kind)
+            } else {
+                None
+            }
+        }
+    }
+
+    fn msg(kind: &'static str, body: &str) -> Msg {
+        Msg { kind, body: body.to_string() }
+    }
+
+    fn sink(socket: LocalSocket) -> ! {
+        let name = socket.service().id();
+        while let Ok(msg) = socket.receive::<Msg>() {
+            RECEIVED.lock().unwrap().push(format!("{}:{}", name, msg.body));
+        }
+        finish()
+    }
+
+    /// Messages with given suffix that the sinks took, once there are
+    /// 'count' of them.
+    fn received(suffix: &str, count: usize) -> Vec<String> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let mut got: Vec<_> = RECEIVED.lock().unwrap().iter()
+                .filter(|m| m.ends_with(suffix))
+                .cloned()
+                .collect();
+            if got.len() >= count || Instant::now() >= deadline {
+                got.sort();
+                return got;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn network() -> LocalNetwork {
+        let network = LocalNetwork::new();
+        for id in ["a", "b", "dead"] {
+            network.register(RegistrationForm::new(sink, id.to_string())).unwrap();
+        }
+        network
+    }
+
+    fn connect(network: &LocalNetwork, id: &str) -> LocalSocket {
+        network.connect(LocalService::by_id(id.to_string())).unwrap()
+    }
+
+    fn counts(router: &Router<Msg, LocalSocket>) -> Vec<(String, f64)> {
+        router.collect()[0].samples.iter()
+            .map(|s| (s.labels[0].1.clone(), s.value))
+            .collect()
+    }
+
+    #[test]
+    fn routes_and_dead_letter() {
+        let network = network();
+        let router = Router::new()
+            .route_if("long", |m: &Msg| m.body.len() > 10, connect(&network, "b"))
+            .route_header("kind", "a", connect(&network, "a"))
+            .route_header("kind", "b", connect(&network, "b"))
+            .dead_letter(connect(&network, "dead"));
+        router.forward(msg("a", "1.route")).unwrap();
+        router.forward(msg("b", "2.route")).unwrap();
+        router.forward(msg("c", "3.route")).unwrap();
+
+        // First matching route wins over the header route.
+        router.forward(msg("a", "4-long.route")).unwrap();
+        assert_eq!(received(".route", 4),
+            vec!["a:1.route", "b:2.route", "b:4-long.route", "dead:3.route"]);
+        assert_eq!(counts(&router), vec![("long".to_string(), 1.0), ("kind=a".to_string(), 1.0),
+            ("kind=b".to_string(), 1.0), ("dead-letter".to_string(), 1.0)]);
+    }
+
+    #[test]
+    fn no_route_drops() {
+        let network = network();
+        let router = Router::new().route_header("kind", "a", connect(&network, "a"));
+        router.forward(msg("c", "1.drop")).unwrap();
+        router.forward(msg("a", "2.drop")).unwrap();
+        assert_eq!(received(".drop", 1), vec!["a:2.drop"]);
+        assert_eq!(counts(&router), vec![("kind=a".to_string(), 1.0),
+            ("dead-letter".to_string(), 1.0)]);
+
+        // Errors of the downstream channel are returned as is.
+        network.register(RegistrationForm::new(|_: LocalSocket| finish(), "gone".to_string()))
+            .unwrap();
+        let router = Router::new().dead_letter(connect(&network, "gone"));
+        assert!(matches!(router.forward(msg("c", "3.drop")), Err(SocketErr::ChannelClosed)));
+    }
+}
@@ -0,0 +1,417 @@
+//! Request/response calls. Most services are call-style: the requester
+//! sends a request and waits for exactly one reply. 'RpcClient' and
+//! 'serve_loop' do it the same way for all of them: each request is
+//! numbered and the reply carries the number back, so a reply that
+//! comes after its call has timed out is recognized and dropped instead
+//! of being taken for the reply to the next call.
+//!
+//! Providers in the same address space may also be called with the
+//! inline reply slot: the slot for the reply goes to the provider
+//! together with the request and the provider fills it in place, so
+//! the reply takes no second trip through the channel.
+//!
+//! When the provider lives in the same address space and the trust
+//! policy allows, 'Caller' skips the channel altogether and calls the
+//! handler of the provider directly, without queueing and copying.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{ConnectErr, Data, Form, Object, OpenNetwork, RegistrationErr, Service,
+        Socket, SocketErr, Time};
+use rt::duration;
+
+/// Request with its number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call<Q> {
+    pub id      : u64,
+    pub body    : Q,
+}
+
+impl<Q: Data> Data for Call<Q> {
+}
+
+/// Reply to the request with given number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply<R> {
+    pub id      : u64,
+    pub body    : R,
+}
+
+impl<R: Data> Data for Reply<R> {
+}
+
+/// Error of the call.
+#[derive(Debug)]
+pub enum RpcErr {
+
+    /// No reply came in time.
+    Timeout,
+
+    /// Channel was closed before the reply came.
+    Closed,
+
+    /// Channel failed otherwise.
+    Socket(SocketErr),
+}
+
+impl From<SocketErr> for RpcErr {
+
+    fn from(e: SocketErr) -> Self {
+        match e {
+            SocketErr::ChannelClosed    => RpcErr::Closed,
+            e                           => RpcErr::Socket(e),
+        }
+    }
+}
+
+/// Time left until the deadline.
+struct Left(Duration);
+
+impl Left {
+
+    fn until(deadline: Instant) -> Self {
+        Left(deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+impl Time for Left {
+
+    fn nanos(&self) -> u32 {
+        self.0.subsec_nanos()
+    }
+
+    fn seconds(&self) -> u32 {
+        self.0.as_secs() as u32
+    }
+
+    fn from_parts(seconds: u32, nanos: u32) -> Self {
+        Left(Duration::new(seconds as u64, nanos))
+    }
+}
+
+/// Requester side of the call-style channel with requests 'Q' and
+/// replies 'R'.
+pub struct RpcClient<O, S, SC, Q, R> {
+    socket  : SC,
+    next    : Cell<u64>,
+    _m      : PhantomData<(O, S, Q, R)>,
+}
+
+impl<O, S, SC, Q, R> RpcClient<O, S, SC, Q, R>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                Q   : Data,
+                R   : Data
+{
+
+    /// Make calls over the channel.
+    pub fn new(socket: SC) -> Self {
+        RpcClient {
+            socket,
+            next    : Cell::new(0),
+            _m      : PhantomData,
+        }
+    }
+
+    /// Underlying socket.
+    pub fn socket(&self) -> &SC {
+        &self.socket
+    }
+
+    /// Send the request and wait for the reply to it. Timeout covers
+    /// both the send and the wait.
+    pub fn call<T: Time>(&self, request: Q, timeout: T) -> Result<R, RpcErr> {
+        let deadline = Instant::now() + duration(&timeout);
+        let id = self.next.get();
+        self.next.set(id + 1);
+
+        // Provider of the reply to the timed-out call may still wait
+        // to send it.
+        while self.socket.receive_now::<Reply<R>>()?.is_some() {}
+
+        match self.socket.wait_to_send(Left::until(deadline)) {
+            Some(Ok(()))    => (),
+            Some(Err(e))    => return Err(e.into()),
+            None            => return Err(RpcErr::Timeout),
+        }
+        if self.socket.send_now(Call { id, body: request })?.is_some() {
+            return Err(RpcErr::Timeout);
+        }
+        loop {
+            match self.socket.wait_to_receive::<Reply<R>, _>(Left::until(deadline)) {
+                Some(Ok(reply)) if reply.id == id   => return Ok(reply.body),
+                Some(Ok(_))                         => (),
+                Some(Err(e))                        => return Err(e.into()),
+                None                                => return Err(RpcErr::Timeout),
+            }
+        }
+    }
+}
+
+/// Serve the calls on the channel with the handler until the requester
+/// closes it.
+pub fn serve_loop<O, S, SC, Q, R, F>(socket: &SC, mut handler: F) -> Result<(), SocketErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            Q   : Data,
+            R   : Data,
+            F   : FnMut(Q) -> R
+{
+    loop {
+        let call = match socket.receive::<Call<Q>>() {
+            Ok(call)                        => call,
+            Err(SocketErr::ChannelClosed)   => return Ok(()),
+            Err(e)                          => return Err(e),
+        };
+        let reply = Reply { id: call.id, body: handler(call.body) };
+        match socket.send(reply) {
+            Ok(())                          => (),
+            Err(SocketErr::ChannelClosed)   => return Ok(()),
+            Err(e)                          => return Err(e),
+        }
+    }
+}
+
+enum SlotState<R> {
+    Empty,
+    Filled(R),
+
+    /// Provider dropped the slot without the reply.
+    Abandoned,
+}
+
+struct Slot<R> {
+    state   : Mutex<SlotState<R>>,
+    filled  : Condvar,
+}
+
+/// Place for the reply that goes to the provider with the request.
+pub struct ReplySlot<R>(Arc<Slot<R>>);
+
+impl<R> ReplySlot<R> {
+
+    /// Put the reply and wake the requester.
+    pub fn fill(self, reply: R) {
+        *self.0.state.lock().unwrap() = SlotState::Filled(reply);
+        self.0.filled.notify_all();
+    }
+}
+
+impl<R> Drop for ReplySlot<R> {
+
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        if let SlotState::Empty = *state {
+            *state = SlotState::Abandoned;
+            self.0.filled.notify_all();
+        }
+    }
+}
+
+/// Request with the slot for its reply.
+pub struct Inline<Q, R> {
+    pub body    : Q,
+    pub reply   : ReplySlot<R>,
+}
+
+impl<Q: Data, R: Send + 'static> Data for Inline<Q, R> {
+}
+
+/// Send the request with the reply slot and wait until the provider
+/// fills it. Provider must serve the channel with 'serve_inline'.
+/// Timeout covers both the send and the wait.
+pub fn call_inline<O, S, SC, Q, R, T>(socket: &SC, request: Q, timeout: T) -> Result<R, RpcErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            Q   : Data,
+            R   : Send + 'static,
+            T   : Time
+{
+    let deadline = Instant::now() + duration(&timeout);
+    let slot = Arc::new(Slot {
+        state   : Mutex::new(SlotState::Empty),
+        filled  : Condvar::new(),
+    });
+    match socket.wait_to_send(Left::until(deadline)) {
+        Some(Ok(()))    => (),
+        Some(Err(e))    => return Err(e.into()),
+        None            => return Err(RpcErr::Timeout),
+    }
+    let inline = Inline { body: request, reply: ReplySlot(slot.clone()) };
+    if socket.send_now(inline)?.is_some() {
+        return Err(RpcErr::Timeout);
+    }
+    let left = deadline.saturating_duration_since(Instant::now());
+    let (mut state, _) = slot.filled.wait_timeout_while(slot.state.lock().unwrap(), left,
+            |s| matches!(*s, SlotState::Empty)).unwrap();
+    match ::std::mem::replace(&mut *state, SlotState::Abandoned) {
+        SlotState::Filled(reply)    => Ok(reply),
+        SlotState::Abandoned        => Err(RpcErr::Closed),
+        SlotState::Empty            => Err(RpcErr::Timeout),
+    }
+}
+
+/// Serve the calls with the inline reply slots until the requester
+/// closes the channel.
+pub fn serve_inline<O, S, SC, Q, R, F>(socket: &SC, mut handler: F) -> Result<(), SocketErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            Q   : Data,
+            R   : Send + 'static,
+            F   : FnMut(Q) -> R
+{
+    loop {
+        match socket.receive::<Inline<Q, R>>() {
+            Ok(call)                        => call.reply.fill(handler(call.body)),
+            Err(SocketErr::ChannelClosed)   => return Ok(()),
+            Err(e)                          => return Err(e),
+        }
+    }
+}
+
+/// Handler of the provider that is called in-process.
+pub type DirectFn<Q, R> = Arc<dyn Fn(Q) -> R + Send + Sync>;
+
+/// Network that can bypass the channel for the calls to providers in
+/// the same address space.
+pub trait DirectNetwork<S: Service>: OpenNetwork<S> {
+
+    /// Register the service with the handler for direct calls. Requesters
+    /// that can't call it directly connect as usual and are served by
+    /// the entry of the form, which normally runs 'serve_loop' with the
+    /// same handler.
+    fn register_direct<Q, R, F>(&self, reg_form: Form<S, Self>, handler: F)
+        -> Result<Self::OwnedService, RegistrationErr>
+        where   Q   : Data,
+                R   : Data,
+                F   : Fn(Q) -> R + Send + Sync + 'static;
+
+    /// Handler of some provider of the service for direct calls from
+    /// current object. None if no provider has the handler of these
+    /// types, or if it is out of the address space, or if the policy
+    /// does not allow 'Action::Direct'.
+    fn direct<Q: Data, R: Data>(&self, service: &S) -> Option<DirectFn<Q, R>>;
+}
+
+/// Requester side of the call-style service that calls the provider
+/// directly when it can and over the channel otherwise.
+pub enum Caller<O, S, SC, Q, R> {
+    Direct(DirectFn<Q, R>),
+    Channel(RpcClient<O, S, SC, Q, R>),
+}
+
+impl<O, S, SC, Q, R> Caller<O, S, SC, Q, R>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                Q   : Data,
+                R   : Data
+{
+
+    /// Get the direct handler of the service or connect to it.
+    pub fn connect<N>(network: &N, service: S) -> Result<Self, ConnectErr<S>>
+        where N: DirectNetwork<S, Object = O, Socket = SC>
+    {
+        match network.direct(&service) {
+            Some(handler)   => Ok(Caller::Direct(handler)),
+            None            => network.connect(service)
+                .map(|socket| Caller::Channel(RpcClient::new(socket))),
+        }
+    }
+
+    /// Check if the calls bypass the channel.
+    pub fn is_direct(&self) -> bool {
+        matches!(*self, Caller::Direct(_))
+    }
+
+    /// Call the provider. Direct calls run on the current thread to
+    /// completion and don't time out.
+    pub fn call<T: Time>(&self, request: Q, timeout: T) -> Result<R, RpcErr> {
+        match *self {
+            Caller::Direct(ref handler)     => Ok(handler(request)),
+            Caller::Channel(ref client)     => client.call(request, timeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use {OpenNetwork, RegistrationForm};
+
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    /// Replies with the length of the text, taking as many
+    /// milliseconds.
+    fn length(socket: LocalSocket) -> ! {
+        let _ = serve_loop(&socket, |text: String| {
+            thread::sleep(Duration::from_millis(text.len() as u64));
+            text.len().to_string()
+        });
+        finish()
+    }
+
+    fn inline_length(socket: LocalSocket) -> ! {
+        let _ = serve_inline(&socket, |text: String| text.len());
+        finish()
+    }
+
+    /// Takes the request and drops it without the reply.
+    fn drop_inline(socket: LocalSocket) -> ! {
+        let _ = socket.receive::<Inline<String, usize>>();
+        finish()
+    }
+
+    #[test]
+    fn inline_reply() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(inline_length, "length".to_string())).unwrap();
+        network.register(RegistrationForm::new(drop_inline, "drop".to_string())).unwrap();
+        let connect = |id: &str| network.connect(LocalService::by_id(id.to_string())).unwrap();
+
+        let socket = connect("length");
+        assert_eq!(call_inline::<_, _, _, _, usize, _>(&socket, "abc".to_string(), Millis(1000))
+            .unwrap(), 3);
+        assert_eq!(call_inline::<_, _, _, _, usize, _>(&socket, "ab".to_string(), Millis(1000))
+            .unwrap(), 2);
+        let dropped = call_inline::<_, _, _, _, usize, _>(&connect("drop"), String::new(),
+            Millis(1000));
+        assert!(matches!(dropped, Err(RpcErr::Closed)));
+    }
+
+    #[test]
+    fn late_reply_is_dropped() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(length, "length".to_string())).unwrap();
+        let socket = network.connect(LocalService::by_id("length".to_string())).unwrap();
+        let client = RpcClient::<_, _, _, String, String>::new(socket);
+
+        assert!(matches!(client.call("x".repeat(50), Millis(5)), Err(RpcErr::Timeout)));
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(client.call("abc".to_string(), Millis(1000)).unwrap(), "3");
+    }
+}
@@ -0,0 +1,449 @@
+//! Runtime adapters. Asynchronous CCS operations only describe what
+//! should be awaited; the runtime is what actually polls them, spawns
+//! tasks for accepted channels and provides timers. Besides the plain
+//! 'ThreadRuntime', adapters for tokio and smol come with the 'rt-tokio'
+//! and 'rt-smol' features.
+//!
+//! Accept loop of the service takes the sockets of its new channels from
+//! the 'Incoming' queue and spawns a task of the handler for each of
+//! them, so that one executor serves all the channels.
+
+use std::collections::VecDeque;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::Duration;
+
+use super::Time;
+
+/// Executor that drives asynchronous CCS operations. Hosted services
+/// implement this trait for the executor they already use, so that
+/// accept loops and timers of CCS run on the same runtime as the
+/// rest of the program.
+pub trait Runtime {
+
+    /// Future that completes when the timer elapses.
+    type Sleep: Future<Output = ()> + Send;
+
+    /// Spawn a detached task. The task is run to completion by the
+    /// runtime and its output is discarded.
+    fn spawn<F>(&self, task: F)
+        where F: Future<Output = ()> + Send + 'static;
+
+    /// Create a timer that completes after given amount of time.
+    fn sleep<T: Time>(&self, time: T) -> Self::Sleep;
+
+    /// Run the future to completion, blocking current thread.
+    fn block_on<F: Future>(&self, future: F) -> F::Output;
+}
+
+/// Convert CCS time to the standard duration.
+pub fn duration<T: Time>(time: &T) -> Duration {
+    Duration::new(time.seconds() as u64, time.nanos())
+}
+
+/// Runtime that is backed by plain threads of the standard library.
+/// Each spawned task gets its own thread and each timer sleeps in a
+/// separate thread. It is not efficient but needs no executor and so
+/// can be used where no other runtime is available.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ThreadRuntime;
+
+impl Runtime for ThreadRuntime {
+
+    type Sleep = ThreadSleep;
+
+    fn spawn<F>(&self, task: F)
+        where F: Future<Output = ()> + Send + 'static
+    {
+        thread::spawn(move || ThreadRuntime.block_on(task));
+    }
+
+    fn sleep<T: Time>(&self, time: T) -> ThreadSleep {
+        ThreadSleep {
+            duration    : duration(&time),
+            state       : None,
+        }
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(out) = future.as_mut().poll(&mut cx) {
+                return out;
+            }
+            thread::park();
+        }
+    }
+}
+
+/// Waker that unparks the thread which blocks on the future.
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Timer of the ThreadRuntime.
+pub struct ThreadSleep {
+    duration    : Duration,
+
+    /// Shared with the timer thread once the timer is started.
+    /// Holds waker of the last poll and whether time is elapsed.
+    state       : Option<Arc<Mutex<(bool, Waker)>>>,
+}
+
+impl Future for ThreadSleep {
+
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if let Some(ref state) = self.state {
+            let mut state = state.lock().unwrap();
+            if state.0 {
+                return Poll::Ready(());
+            }
+            state.1 = cx.waker().clone();
+            return Poll::Pending;
+        }
+
+        let state = Arc::new(Mutex::new((false, cx.waker().clone())));
+        let timer = state.clone();
+        let duration = self.duration;
+        thread::spawn(move || {
+            thread::sleep(duration);
+            let mut state = timer.lock().unwrap();
+            state.0 = true;
+            state.1.wake_by_ref();
+        });
+        self.state = Some(state);
+        Poll::Pending
+    }
+}
+
+/// Runtime that runs on a tokio runtime, with the 'rt-tokio' feature.
+#[cfg(feature = "rt-tokio")]
+#[derive(Debug, Clone)]
+pub struct TokioRuntime {
+    handle  : tokio::runtime::Handle,
+}
+
+#[cfg(feature = "rt-tokio")]
+impl TokioRuntime {
+
+    /// Run on the tokio runtime of given handle.
+    pub fn new(handle: tokio::runtime::Handle) -> Self {
+        TokioRuntime { handle }
+    }
+
+    /// Run on the tokio runtime of current context. Panics outside of
+    /// it, as 'Handle::current' does.
+    pub fn current() -> Self {
+        TokioRuntime::new(tokio::runtime::Handle::current())
+    }
+}
+
+#[cfg(feature = "rt-tokio")]
+impl Runtime for TokioRuntime {
+
+    type Sleep = tokio::time::Sleep;
+
+    fn spawn<F>(&self, task: F)
+        where F: Future<Output = ()> + Send + 'static
+    {
+        drop(self.handle.spawn(task));
+    }
+
+    fn sleep<T: Time>(&self, time: T) -> tokio::time::Sleep {
+        let _context = self.handle.enter();
+        tokio::time::sleep(duration(&time))
+    }
+
+    /// Must not be called from asynchronous context of the runtime.
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.handle.block_on(future)
+    }
+}
+
+/// Runtime that runs on the global executor of smol, with the
+/// 'rt-smol' feature.
+#[cfg(feature = "rt-smol")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SmolRuntime;
+
+#[cfg(feature = "rt-smol")]
+impl Runtime for SmolRuntime {
+
+    type Sleep = SmolSleep;
+
+    fn spawn<F>(&self, task: F)
+        where F: Future<Output = ()> + Send + 'static
+    {
+        smol::spawn(task).detach();
+    }
+
+    fn sleep<T: Time>(&self, time: T) -> SmolSleep {
+        SmolSleep(smol::Timer::after(duration(&time)))
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        smol::block_on(future)
+    }
+}
+
+/// Timer of the SmolRuntime.
+#[cfg(feature = "rt-smol")]
+pub struct SmolSleep(smol::Timer);
+
+#[cfg(feature = "rt-smol")]
+impl Future for SmolSleep {
+
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        Pin::new(&mut self.0).poll(cx).map(|_| ())
+    }
+}
+
+/// Queue of the sockets of the newly accepted channels. The network
+/// pushes the socket of each new channel of the service and the accept
+/// loop takes them in order.
+pub struct Incoming<SC> {
+    state   : Mutex<IncomingState<SC>>,
+}
+
+struct IncomingState<SC> {
+    sockets : VecDeque<SC>,
+
+    /// Whether no more sockets will come.
+    closed  : bool,
+
+    /// Waker of the loop that waits for the next socket.
+    waker   : Option<Waker>,
+}
+
+impl<SC> Default for Incoming<SC> {
+
+    fn default() -> Self {
+        Incoming {
+            state   : Mutex::new(IncomingState {
+                sockets : VecDeque::new(),
+                closed  : false,
+                waker   : None,
+            }),
+        }
+    }
+}
+
+impl<SC> Incoming<SC> {
+
+    /// Create empty open queue.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Hand the socket to the loop. If the queue is closed, the socket
+    /// is returned back.
+    pub fn push(&self, socket: SC) -> Result<(), SC> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(socket);
+        }
+        state.sockets.push_back(socket);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Tell the loop that no more sockets will come. Sockets that are
+    /// already queued are still taken.
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Whether the queue is closed.
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// Take the next socket, or register the waker to be woken when
+    /// it comes. None once the queue is closed and empty.
+    pub fn poll_next(&self, cx: &mut Context) -> Poll<Option<SC>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(socket) = state.sockets.pop_front() {
+            return Poll::Ready(Some(socket));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Wait for the next socket. Resolves to None once the queue is
+    /// closed and empty.
+    pub fn next(&self) -> impl Future<Output = Option<SC>> + '_ {
+        future::poll_fn(move |cx| self.poll_next(cx))
+    }
+}
+
+/// Spawn the accept loop of the service on the runtime. The loop spawns
+/// a task of the handler for each socket from the queue, so that the
+/// channels are served concurrently, and ends when the queue is closed
+/// and drained.
+pub fn spawn_accept_loop<R, SC, F, Fut>(runtime: &R, incoming: Arc<Incoming<SC>>, handler: F)
+    where   R   : Runtime + Clone + Send + 'static,
+            SC  : Send + 'static,
+            F   : Fn(SC) -> Fut + Send + 'static,
+            Fut : Future<Output = ()> + Send + 'static
+{
+    runtime.spawn(AcceptLoop {
+        runtime     : runtime.clone(),
+        incoming,
+        handler,
+    });
+}
+
+/// Task of the accept loop.
+struct AcceptLoop<R, SC, F> {
+    runtime     : R,
+    incoming    : Arc<Incoming<SC>>,
+    handler     : F,
+}
+
+impl<R, SC, F, Fut> Future for AcceptLoop<R, SC, F>
+    where   R   : Runtime,
+            F   : Fn(SC) -> Fut,
+            Fut : Future<Output = ()> + Send + 'static
+{
+
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &*self;
+        loop {
+            match this.incoming.poll_next(cx) {
+                Poll::Ready(Some(socket))   => this.runtime.spawn((this.handler)(socket)),
+                Poll::Ready(None)           => return Poll::Ready(()),
+                Poll::Pending               => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    #[test]
+    fn sleep_completes() {
+        let rt = ThreadRuntime;
+        rt.block_on(rt.sleep(Millis(5)));
+    }
+
+    /// Future that sends to the channel once the timer elapses.
+    #[cfg(any(feature = "rt-tokio", feature = "rt-smol"))]
+    struct Signal<F> {
+        sleep   : Pin<Box<F>>,
+        tx      : ::std::sync::mpsc::Sender<()>,
+    }
+
+    #[cfg(any(feature = "rt-tokio", feature = "rt-smol"))]
+    impl<F: Future<Output = ()>> Future for Signal<F> {
+
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            let ready = self.sleep.as_mut().poll(cx).is_ready();
+            if ready {
+                let _ = self.tx.send(());
+                return Poll::Ready(());
+            }
+            Poll::Pending
+        }
+    }
+
+    /// Spawned task tells the test its timer elapsed.
+    #[cfg(any(feature = "rt-tokio", feature = "rt-smol"))]
+    fn spawn_and_sleep<R: Runtime>(rt: &R) where R::Sleep: 'static {
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        rt.spawn(Signal { sleep: Box::pin(rt.sleep(Millis(5))), tx });
+        rt.block_on(rt.sleep(Millis(5)));
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    /// Each queued socket gets its own handler task on the runtime.
+    fn accept_each<R: Runtime + Clone + Send + 'static>(rt: &R) {
+        let incoming = Arc::new(Incoming::new());
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        incoming.push(1u32).unwrap();
+        spawn_accept_loop(rt, incoming.clone(), move |n| {
+            let tx = tx.lock().unwrap().clone();
+            future::poll_fn(move |_| {
+                tx.send(n).unwrap();
+                Poll::Ready(())
+            })
+        });
+        incoming.push(2).unwrap();
+        incoming.close();
+        assert_eq!(incoming.push(3), Err(3));
+        let mut served: Vec<u32> = rx.iter().take(2).collect();
+        served.sort();
+        assert_eq!(served, vec![1, 2]);
+    }
+
+    #[test]
+    fn accept_loop() {
+        accept_each(&ThreadRuntime);
+    }
+
+    #[cfg(feature = "rt-tokio")]
+    #[test]
+    fn tokio_runtime() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_time()
+            .build()
+            .unwrap();
+        let rt = TokioRuntime::new(runtime.handle().clone());
+        spawn_and_sleep(&rt);
+        accept_each(&rt);
+    }
+
+    #[cfg(feature = "rt-smol")]
+    #[test]
+    fn smol_runtime() {
+        spawn_and_sleep(&SmolRuntime);
+        accept_each(&SmolRuntime);
+    }
+}
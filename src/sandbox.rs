@@ -0,0 +1,129 @@
+//! Sandbox profiles. Parent object attaches a profile to a child when
+//! spawning it, restricting which services the child may connect to,
+//! whether it may register any services and whether it may spawn
+//! children of its own. Untrusted plugins run as CCS objects this way
+//! with the least privilege they need.
+
+use super::{Object, Service};
+use policy::Pattern;
+
+/// Restrictions of the sandboxed object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxProfile {
+
+    /// Patterns of the services the object may connect to.
+    pub connect         : Vec<Pattern>,
+
+    /// Whether the object may register services.
+    pub may_register    : bool,
+
+    /// Whether the object may spawn sub-objects.
+    pub may_spawn       : bool,
+
+    /// Profile of the sandboxed parent, which also restricts this one.
+    pub parent          : Option<Box<SandboxProfile>>,
+}
+
+/// Operation of the sandboxed object that was denied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SandboxViolation {
+
+    /// Connect to the service with given identifier.
+    Connect(String),
+
+    /// Registration of any service.
+    Register,
+
+    /// Spawn of a sub-object.
+    Spawn,
+}
+
+impl SandboxProfile {
+
+    /// Profile that denies everything.
+    pub fn deny_all() -> Self {
+        SandboxProfile {
+            connect         : Vec::new(),
+            may_register    : false,
+            may_spawn       : false,
+            parent          : None,
+        }
+    }
+
+    /// Allow connects to the services that match the pattern.
+    pub fn allow_connect(mut self, pattern: &str) -> Self {
+        self.connect.push(Pattern(pattern.to_string()));
+        self
+    }
+
+    /// Allow registration of services.
+    pub fn allow_register(mut self) -> Self {
+        self.may_register = true;
+        self
+    }
+
+    /// Allow spawning of sub-objects.
+    pub fn allow_spawn(mut self) -> Self {
+        self.may_spawn = true;
+        self
+    }
+
+    /// Check if the object may connect to the service with given
+    /// identifier in text form.
+    pub fn check_connect(&self, service: &str) -> Result<(), SandboxViolation> {
+        if !self.connect.iter().any(|p| p.matches(service)) {
+            return Err(SandboxViolation::Connect(service.to_string()));
+        }
+        self.parent.as_ref().map_or(Ok(()), |p| p.check_connect(service))
+    }
+
+    /// Check if the object may register services.
+    pub fn check_register(&self) -> Result<(), SandboxViolation> {
+        if !self.may_register {
+            return Err(SandboxViolation::Register);
+        }
+        self.parent.as_ref().map_or(Ok(()), |p| p.check_register())
+    }
+
+    /// Check if the object may spawn sub-objects.
+    pub fn check_spawn(&self) -> Result<(), SandboxViolation> {
+        if !self.may_spawn {
+            return Err(SandboxViolation::Spawn);
+        }
+        self.parent.as_ref().map_or(Ok(()), |p| p.check_spawn())
+    }
+
+    /// Profile for the child of the sandboxed object. Child never gets
+    /// more than its parent: each operation is checked against both the
+    /// child profile and the profile of the parent.
+    pub fn nest(&self, mut child: SandboxProfile) -> SandboxProfile {
+        child.parent = Some(Box::new(self.clone()));
+        child
+    }
+}
+
+/// Object that may run in a sandbox.
+pub trait Sandboxed<S: Service>: Object<S> {
+
+    /// Profile attached to the object at spawn. None if the object is
+    /// not sandboxed.
+    fn sandbox(&self) -> Option<&SandboxProfile>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn child_is_not_wider_than_parent() {
+        let parent = SandboxProfile::deny_all()
+            .allow_connect("fs.*")
+            .allow_spawn();
+        let child = parent.nest(SandboxProfile::deny_all()
+            .allow_connect("*")
+            .allow_register());
+        assert!(child.check_connect("fs.read").is_ok());
+        assert!(child.check_connect("net.socket").is_err());
+        assert_eq!(child.check_register(), Err(SandboxViolation::Register));
+    }
+}
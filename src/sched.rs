@@ -0,0 +1,138 @@
+//! Fair scheduling of the handlers. Single-threaded provider that runs
+//! each accepted channel's handler to completion lets one long request
+//! starve all other clients. Instead, handlers are split into steps and
+//! 'FairScheduler' interleaves them, giving each handler a time slice
+//! before moving to the next one.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::Time;
+use rt::duration;
+
+/// Result of one step of the handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+
+    /// Handler has more work to do.
+    Continue,
+
+    /// Handler has finished.
+    Done,
+}
+
+/// Handler that can be run in steps.
+pub trait Job {
+
+    /// Make a short piece of work and tell if there is more.
+    fn step(&mut self) -> Step;
+}
+
+impl<F: FnMut() -> Step> Job for F {
+
+    fn step(&mut self) -> Step {
+        self()
+    }
+}
+
+/// Round-robin scheduler of the handlers with time slices.
+pub struct FairScheduler<'a> {
+    slice   : Duration,
+    jobs    : VecDeque<Box<dyn Job + 'a>>,
+}
+
+impl<'a> FairScheduler<'a> {
+
+    /// Create scheduler that runs each handler for given time before
+    /// switching to the next one. Handler always makes at least one
+    /// step, so slices are exceeded by steps that take longer.
+    pub fn new<T: Time>(slice: T) -> Self {
+        FairScheduler {
+            slice   : duration(&slice),
+            jobs    : VecDeque::new(),
+        }
+    }
+
+    /// Add handler of the newly accepted channel. It is run after the
+    /// handlers that are already waiting.
+    pub fn add<J: Job + 'a>(&mut self, job: J) {
+        self.jobs.push_back(Box::new(job));
+    }
+
+    /// Count of unfinished handlers.
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Whether all handlers have finished.
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Run the next handler for one time slice. Returns false if there
+    /// are no handlers to run.
+    pub fn run_slice(&mut self) -> bool {
+        let mut job = match self.jobs.pop_front() {
+            Some(job)   => job,
+            None        => return false,
+        };
+        let start = Instant::now();
+        loop {
+            if job.step() == Step::Done {
+                return true;
+            }
+            if start.elapsed() >= self.slice {
+                self.jobs.push_back(job);
+                return true;
+            }
+        }
+    }
+
+    /// Run handlers until all of them are finished.
+    pub fn run(&mut self) {
+        while self.run_slice() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct Nanos(u32);
+
+    impl Time for Nanos {
+
+        fn nanos(&self) -> u32 {
+            self.0
+        }
+
+        fn seconds(&self) -> u32 {
+            0
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Nanos(seconds.saturating_mul(1_000_000_000).saturating_add(nanos))
+        }
+    }
+
+    #[test]
+    fn short_job_is_not_starved() {
+        let order = RefCell::new(Vec::new());
+        let mut sched = FairScheduler::new(Nanos(0));
+        let order = &order;
+        let mut long = 3;
+        sched.add(move || {
+            order.borrow_mut().push("long");
+            long -= 1;
+            if long == 0 { Step::Done } else { Step::Continue }
+        });
+        sched.add(move || {
+            order.borrow_mut().push("short");
+            Step::Done
+        });
+        sched.run();
+        assert!(sched.is_empty());
+        assert_eq!(*order.borrow(), ["long", "short", "long", "long"]);
+    }
+}
@@ -0,0 +1,195 @@
+//! Evolution of message schemas. Provider registers transforms between
+//! consecutive versions of its message encoding. When two peers on a
+//! channel use different versions, the newer peer, which knows all the
+//! transforms, upgrades what it receives and downgrades what it sends,
+//! so objects can be upgraded one by one without breaking channels.
+
+use std::marker::PhantomData;
+
+use super::{Data, Object, Service, Socket, SocketErr};
+
+/// Function that converts encoded message between two versions.
+pub type Transform = dyn Fn(&[u8]) -> Result<Vec<u8>, String>;
+
+/// Versions of the message schema, starting from version 1.
+#[derive(Default)]
+pub struct Schema {
+
+    /// Upgrade and downgrade between version 'i + 1' and 'i + 2'.
+    steps   : Vec<(Box<Transform>, Box<Transform>)>,
+}
+
+/// Error of the schema conversion.
+#[derive(Debug)]
+pub enum SchemaErr {
+
+    /// Version is not known to this schema.
+    UnknownVersion(u32),
+
+    /// Transform from given version failed.
+    Transform {
+        from    : u32,
+        reason  : String,
+    },
+
+    /// Channel failed.
+    Socket(SocketErr),
+}
+
+impl From<SocketErr> for SchemaErr {
+
+    fn from(e: SocketErr) -> Self {
+        SchemaErr::Socket(e)
+    }
+}
+
+/// Encoded message with the version of its schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Versioned {
+    pub version : u32,
+    pub payload : Vec<u8>,
+}
+
+impl Data for Versioned {
+}
+
+impl Schema {
+
+    /// Add the next version with transforms from the current one and
+    /// back to it.
+    pub fn evolve<U, D>(mut self, upgrade: U, downgrade: D) -> Self
+        where   U   : Fn(&[u8]) -> Result<Vec<u8>, String> + 'static,
+                D   : Fn(&[u8]) -> Result<Vec<u8>, String> + 'static
+    {
+        self.steps.push((Box::new(upgrade), Box::new(downgrade)));
+        self
+    }
+
+    /// Latest version of the schema.
+    pub fn current(&self) -> u32 {
+        self.steps.len() as u32 + 1
+    }
+
+    /// Convert the message between any two known versions.
+    pub fn convert(&self, payload: &[u8], from: u32, to: u32)
+        -> Result<Vec<u8>, SchemaErr>
+    {
+        for &v in &[from, to] {
+            if v == 0 || v > self.current() {
+                return Err(SchemaErr::UnknownVersion(v));
+            }
+        }
+        let mut payload = payload.to_vec();
+        let mut version = from;
+        while version != to {
+            let (next, result) = if version < to {
+                (version + 1, (self.steps[version as usize - 1].0)(&payload))
+            } else {
+                (version - 1, (self.steps[version as usize - 2].1)(&payload))
+            };
+            payload = result.map_err(|reason| SchemaErr::Transform {
+                from    : version,
+                reason,
+            })?;
+            version = next;
+        }
+        Ok(payload)
+    }
+}
+
+/// Socket that converts messages between the schema versions of the
+/// peers.
+pub struct SchemaSocket<'a, O, S, SC> {
+    socket  : SC,
+    schema  : &'a Schema,
+
+    /// Version this object speaks.
+    local   : u32,
+
+    /// Version the peer speaks.
+    peer    : u32,
+    _os     : PhantomData<(O, S)>,
+}
+
+impl<'a, O, S, SC> SchemaSocket<'a, O, S, SC>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+{
+
+    /// Exchange versions with the peer over the fresh channel. Object
+    /// speaks the current version of the schema.
+    pub fn handshake(socket: SC, schema: &'a Schema) -> Result<Self, SchemaErr> {
+        let local = schema.current();
+        socket.send(Versioned {
+            version : local,
+            payload : Vec::new(),
+        })?;
+        let peer = socket.receive::<Versioned>()?.version;
+        Ok(SchemaSocket {
+            socket,
+            schema,
+            local,
+            peer,
+            _os     : PhantomData,
+        })
+    }
+
+    /// Version of the peer.
+    pub fn peer_version(&self) -> u32 {
+        self.peer
+    }
+
+    /// Send the message encoded in the local version. It is downgraded
+    /// if the peer is older.
+    pub fn send(&self, payload: Vec<u8>) -> Result<(), SchemaErr> {
+        let (version, payload) = if self.peer < self.local {
+            (self.peer, self.schema.convert(&payload, self.local, self.peer)?)
+        } else {
+            (self.local, payload)
+        };
+        self.socket.send(Versioned {
+            version,
+            payload,
+        })?;
+        Ok(())
+    }
+
+    /// Receive the message in the local version. It is upgraded if the
+    /// peer is older.
+    pub fn receive(&self) -> Result<Vec<u8>, SchemaErr> {
+        let msg = self.socket.receive::<Versioned>()?;
+        if msg.version == self.local {
+            Ok(msg.payload)
+        } else {
+            self.schema.convert(&msg.payload, msg.version, self.local)
+        }
+    }
+
+    /// Get the wrapped socket back.
+    pub fn into_inner(self) -> SC {
+        self.socket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_across_versions() {
+        // Version 2 appends a flags byte, version 3 doubles every byte.
+        let schema = Schema::default()
+            .evolve(|p| {
+                let mut p = p.to_vec();
+                p.push(0);
+                Ok(p)
+            }, |p| Ok(p[..p.len() - 1].to_vec()))
+            .evolve(|p| Ok(p.iter().map(|b| b * 2).collect()),
+                    |p| Ok(p.iter().map(|b| b / 2).collect()));
+        assert_eq!(schema.current(), 3);
+        assert_eq!(schema.convert(&[1, 2], 1, 3).unwrap(), vec![2, 4, 0]);
+        assert_eq!(schema.convert(&[2, 4, 0], 3, 1).unwrap(), vec![1, 2]);
+        assert!(schema.convert(&[], 1, 4).is_err());
+    }
+}
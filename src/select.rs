@@ -0,0 +1,212 @@
+//! Waiting on many sockets at once. Provider that serves several
+//! channels would otherwise need a thread per socket, each blocked in
+//! its own receive. 'Selector' holds any number of sockets and blocks
+//! until one of them gets ready, telling which socket and what for.
+
+use std::cell::Cell;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::task::{Poll, Waker};
+
+use super::{Object, Service, Socket, Time};
+use rt::{Runtime, ThreadRuntime};
+
+/// What the socket got ready for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+
+    /// Data can be received without waiting.
+    Readable,
+
+    /// Peer waits to receive, so 'send_now' succeeds.
+    Writable,
+
+    /// Channel is closed.
+    Closed,
+}
+
+/// Events the selector waits for on the socket. Closing is always
+/// reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interest {
+    pub readable    : bool,
+    pub writable    : bool,
+}
+
+impl Interest {
+    pub const READABLE: Interest = Interest { readable: true, writable: false };
+    pub const WRITABLE: Interest = Interest { readable: false, writable: true };
+    pub const BOTH: Interest = Interest { readable: true, writable: true };
+}
+
+/// Socket that tells its readiness without waiting.
+pub trait SelectSocket<O, S>: Socket<O, S>
+        where O: Object<S>, S: Service {
+
+    /// Check if the socket is ready for the event now.
+    fn is_ready(&self, event: Event) -> bool;
+
+    /// Wake the waker on the next change of the channel.
+    fn wake_on_change(&self, waker: &Waker);
+}
+
+/// Set of sockets to wait on. Sockets are identified by the keys
+/// returned when they are added.
+pub struct Selector<'a, SC: 'a> {
+    sockets : Vec<Option<(&'a SC, Interest)>>,
+
+    /// Where the next scan starts, so that a busy socket does not
+    /// starve the others.
+    start   : Cell<usize>,
+}
+
+impl<'a, SC> Default for Selector<'a, SC> {
+
+    fn default() -> Self {
+        Selector {
+            sockets : Vec::new(),
+            start   : Cell::new(0),
+        }
+    }
+}
+
+impl<'a, SC> Selector<'a, SC> {
+
+    /// Create empty selector.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the socket and get its key.
+    pub fn add(&mut self, socket: &'a SC, interest: Interest) -> usize {
+        self.sockets.push(Some((socket, interest)));
+        self.sockets.len() - 1
+    }
+
+    /// Remove the socket with given key.
+    pub fn remove(&mut self, key: usize) {
+        if let Some(slot) = self.sockets.get_mut(key) {
+            *slot = None;
+        }
+    }
+
+    /// First socket that is ready, scanning from the rotating start.
+    fn ready<O, S>(&self, waker: &Waker) -> Option<(usize, Event)>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : SelectSocket<O, S>
+    {
+        let count = self.sockets.len();
+        let start = self.start.get();
+        for key in (0..count).map(|i| (start + i) % count) {
+            let (socket, interest) = match self.sockets[key] {
+                Some(entry) => entry,
+                None        => continue,
+            };
+
+            // Waker goes first so that a change right after the check
+            // is not missed.
+            socket.wake_on_change(waker);
+            let event = if socket.is_ready(Event::Closed) {
+                Event::Closed
+            } else if interest.readable && socket.is_ready(Event::Readable) {
+                Event::Readable
+            } else if interest.writable && socket.is_ready(Event::Writable) {
+                Event::Writable
+            } else {
+                continue;
+            };
+            self.start.set(key + 1);
+            return Some((key, event));
+        }
+        None
+    }
+
+    /// Wait until some socket is ready.
+    pub fn select<O, S>(&self) -> (usize, Event)
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : SelectSocket<O, S>
+    {
+        ThreadRuntime.block_on(future::poll_fn(|cx| match self.ready(cx.waker()) {
+            Some(ready) => Poll::Ready(ready),
+            None        => Poll::Pending,
+        }))
+    }
+
+    /// Wait until some socket is ready or the time passes. None on
+    /// timeout.
+    pub fn select_timeout<O, S, T>(&self, time: T) -> Option<(usize, Event)>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : SelectSocket<O, S>,
+                T   : Time
+    {
+        let mut timer = Box::pin(ThreadRuntime.sleep(time));
+        ThreadRuntime.block_on(future::poll_fn(|cx| {
+            if let Some(ready) = self.ready(cx.waker()) {
+                return Poll::Ready(Some(ready));
+            }
+            Pin::new(&mut timer).poll(cx).map(|()| None)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use {OpenNetwork, RegistrationForm};
+
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    fn greet(socket: LocalSocket) -> ! {
+        let _ = socket.send("hello".to_string());
+        finish()
+    }
+
+    fn listen(socket: LocalSocket) -> ! {
+        let _ = socket.receive::<String>();
+        finish()
+    }
+
+    #[test]
+    fn reports_ready_sockets() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(greet, "greet".to_string())).unwrap();
+        network.register(RegistrationForm::new(listen, "listen".to_string())).unwrap();
+        let connect = |id: &str| network.connect(LocalService::by_id(id.to_string())).unwrap();
+        let (greet, listen) = (connect("greet"), connect("listen"));
+
+        let mut selector = Selector::new();
+        let g = selector.add(&greet, Interest::READABLE);
+        let l = selector.add(&listen, Interest::WRITABLE);
+        let first = selector.select();
+        selector.remove(first.0);
+        let mut events = vec![first, selector.select()];
+        events.sort_by_key(|e| e.0);
+        assert_eq!(events, [(g, Event::Readable), (l, Event::Writable)]);
+
+        assert_eq!(greet.receive::<String>().unwrap(), "hello");
+        let mut selector = Selector::new();
+        let g = selector.add(&greet, Interest::BOTH);
+        assert_eq!(selector.select(), (g, Event::Closed));
+        selector.remove(g);
+        assert_eq!(selector.select_timeout(Millis(5)), None);
+    }
+}
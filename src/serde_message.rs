@@ -0,0 +1,670 @@
+//! Serde integration of the messages. Types that implement 'Serialize'
+//! and 'DeserializeOwned' become messages when wrapped into 'Serde', so
+//! services don't write 'Message' impls by hand. A blanket impl right on
+//! the types would collide with the impls of 'message' for the standard
+//! types, so the wrapper carries it instead.
+//!
+//! Encoding is the one of 'message': the value of a serde type and of a
+//! hand-written type with the same shape have the same bytes, so peers
+//! don't need to use the same side. Structs and tuples are their fields
+//! in order, enums are the variant index as a byte and then the fields,
+//! maps are sequences of the pairs. The encoding doesn't describe itself,
+//! so types that deserialize with 'deserialize_any' are not supported.
+
+use std::error::Error;
+use std::fmt;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Visitor};
+use serde::ser::{self, Serialize};
+
+use message::{decode_len, encode_len, DecodeErr, EncodeErr, Message};
+
+/// Message of the serde type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Serde<T>(pub T);
+
+impl<T: Serialize + DeserializeOwned> Message for Serde<T> {
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeErr> {
+        self.0.serialize(&mut Encoder { out })
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeErr> {
+        T::deserialize(&mut Decoder { input }).map(Serde)
+    }
+}
+
+// Serde needs its errors to be the standard ones.
+
+impl fmt::Display for EncodeErr {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncodeErr::TooLong(len)     => write!(f, "sequence of {} items is too long", len),
+            EncodeErr::Custom(ref msg)  => f.write_str(msg),
+        }
+    }
+}
+
+impl Error for EncodeErr {}
+
+impl ser::Error for EncodeErr {
+
+    fn custom<M: fmt::Display>(msg: M) -> Self {
+        EncodeErr::Custom(msg.to_string())
+    }
+}
+
+impl fmt::Display for DecodeErr {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeErr::Truncated        => f.write_str("payload is truncated"),
+            DecodeErr::Trailing(len)    => write!(f, "{} bytes after the message", len),
+            DecodeErr::Invalid(ty)      => write!(f, "invalid {}", ty),
+            DecodeErr::Custom(ref msg)  => f.write_str(msg),
+        }
+    }
+}
+
+impl Error for DecodeErr {}
+
+impl de::Error for DecodeErr {
+
+    fn custom<M: fmt::Display>(msg: M) -> Self {
+        DecodeErr::Custom(msg.to_string())
+    }
+}
+
+fn unsupported(what: &str) -> EncodeErr {
+    EncodeErr::Custom(format!("{} is not supported", what))
+}
+
+struct Encoder<'a> {
+    out     : &'a mut Vec<u8>,
+}
+
+impl<'a> Encoder<'a> {
+
+    fn variant(&mut self, index: u32) -> Result<(), EncodeErr> {
+        if index > u8::MAX as u32 {
+            return Err(unsupported("enum with more than 256 variants"));
+        }
+        self.out.push(index as u8);
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::Serializer for &'b mut Encoder<'a> {
+    type Ok = ();
+    type Error = EncodeErr;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<(), EncodeErr> {
+        v.encode(self.out)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), EncodeErr> {
+        v.encode(self.out)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), EncodeErr> {
+        v.encode(self.out)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), EncodeErr> {
+        v.encode(self.out)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), EncodeErr> {
+        v.encode(self.out)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), EncodeErr> {
+        v.encode(self.out)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), EncodeErr> {
+        v.encode(self.out)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), EncodeErr> {
+        v.encode(self.out)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), EncodeErr> {
+        v.encode(self.out)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), EncodeErr> {
+        v.to_bits().encode(self.out)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), EncodeErr> {
+        v.to_bits().encode(self.out)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), EncodeErr> {
+        (v as u32).encode(self.out)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), EncodeErr> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), EncodeErr> {
+        encode_len(v.len(), self.out)?;
+        self.out.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), EncodeErr> {
+        self.out.push(0);
+        Ok(())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), EncodeErr> {
+        self.out.push(1);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), EncodeErr> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), EncodeErr> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, index: u32, _variant: &'static str)
+        -> Result<(), EncodeErr>
+    {
+        self.variant(index)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T)
+        -> Result<(), EncodeErr>
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str, index: u32,
+            _variant: &'static str, value: &T) -> Result<(), EncodeErr>
+    {
+        self.variant(index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self, EncodeErr> {
+        let len = len.ok_or_else(|| unsupported("sequence of unknown length"))?;
+        encode_len(len, self.out)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, EncodeErr> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self, EncodeErr> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, index: u32, _variant: &'static str,
+            _len: usize) -> Result<Self, EncodeErr>
+    {
+        self.variant(index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self, EncodeErr> {
+        let len = len.ok_or_else(|| unsupported("map of unknown length"))?;
+        encode_len(len, self.out)?;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, EncodeErr> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, index: u32, _variant: &'static str,
+            _len: usize) -> Result<Self, EncodeErr>
+    {
+        self.variant(index)?;
+        Ok(self)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl<'a, 'b> ser::SerializeSeq for &'b mut Encoder<'a> {
+    type Ok = ();
+    type Error = EncodeErr;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), EncodeErr> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), EncodeErr> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeTuple for &'b mut Encoder<'a> {
+    type Ok = ();
+    type Error = EncodeErr;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), EncodeErr> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), EncodeErr> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeTupleStruct for &'b mut Encoder<'a> {
+    type Ok = ();
+    type Error = EncodeErr;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), EncodeErr> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), EncodeErr> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeTupleVariant for &'b mut Encoder<'a> {
+    type Ok = ();
+    type Error = EncodeErr;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), EncodeErr> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), EncodeErr> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeMap for &'b mut Encoder<'a> {
+    type Ok = ();
+    type Error = EncodeErr;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), EncodeErr> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), EncodeErr> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), EncodeErr> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeStruct for &'b mut Encoder<'a> {
+    type Ok = ();
+    type Error = EncodeErr;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, _key: &'static str, value: &T)
+        -> Result<(), EncodeErr>
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), EncodeErr> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeStructVariant for &'b mut Encoder<'a> {
+    type Ok = ();
+    type Error = EncodeErr;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, _key: &'static str, value: &T)
+        -> Result<(), EncodeErr>
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), EncodeErr> {
+        Ok(())
+    }
+}
+
+struct Decoder<'a, 'de: 'a> {
+    input   : &'a mut &'de [u8],
+}
+
+impl<'a, 'de> Decoder<'a, 'de> {
+
+    fn bytes(&mut self) -> Result<&'de [u8], DecodeErr> {
+        let len = decode_len(self.input)?;
+        if self.input.len() < len {
+            return Err(DecodeErr::Truncated);
+        }
+        let (head, rest) = self.input.split_at(len);
+        *self.input = rest;
+        Ok(head)
+    }
+
+    fn str(&mut self) -> Result<&'de str, DecodeErr> {
+        ::std::str::from_utf8(self.bytes()?).map_err(|_| DecodeErr::Invalid("string"))
+    }
+}
+
+/// Fixed count of the values in a row: items of a sequence, fields of a
+/// struct or keys and values of a map.
+struct Items<'b, 'a: 'b, 'de: 'a> {
+    decoder : &'b mut Decoder<'a, 'de>,
+    left    : usize,
+}
+
+impl<'b, 'a, 'de> de::SeqAccess<'de> for Items<'b, 'a, 'de> {
+    type Error = DecodeErr;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T)
+        -> Result<Option<T::Value>, DecodeErr>
+    {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        self.left -= 1;
+        seed.deserialize(&mut *self.decoder).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        // Length is not trusted for the allocation, like in 'message'.
+        Some(self.left.min(self.decoder.input.len()))
+    }
+}
+
+impl<'b, 'a, 'de> de::MapAccess<'de> for Items<'b, 'a, 'de> {
+    type Error = DecodeErr;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+        -> Result<Option<K::Value>, DecodeErr>
+    {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        self.left -= 1;
+        seed.deserialize(&mut *self.decoder).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V)
+        -> Result<V::Value, DecodeErr>
+    {
+        seed.deserialize(&mut *self.decoder)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.left.min(self.decoder.input.len()))
+    }
+}
+
+impl<'b, 'a, 'de> de::EnumAccess<'de> for &'b mut Decoder<'a, 'de> {
+    type Error = DecodeErr;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V)
+        -> Result<(V::Value, Self), DecodeErr>
+    {
+        let index = u8::decode(self.input)? as u32;
+        let value = seed.deserialize(de::value::U32Deserializer::new(index))?;
+        Ok((value, self))
+    }
+}
+
+impl<'b, 'a, 'de> de::VariantAccess<'de> for &'b mut Decoder<'a, 'de> {
+    type Error = DecodeErr;
+
+    fn unit_variant(self) -> Result<(), DecodeErr> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T)
+        -> Result<T::Value, DecodeErr>
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V)
+        -> Result<V::Value, DecodeErr>
+    {
+        visitor.visit_seq(Items { decoder: self, left: len })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V)
+        -> Result<V::Value, DecodeErr>
+    {
+        visitor.visit_seq(Items { decoder: self, left: fields.len() })
+    }
+}
+
+impl<'b, 'a, 'de> de::Deserializer<'de> for &'b mut Decoder<'a, 'de> {
+    type Error = DecodeErr;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DecodeErr> {
+        Err(DecodeErr::Invalid("self-described value"))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_bool(bool::decode(self.input)?)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_i8(i8::decode(self.input)?)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_i16(i16::decode(self.input)?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_i32(i32::decode(self.input)?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_i64(i64::decode(self.input)?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_u8(u8::decode(self.input)?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_u16(u16::decode(self.input)?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_u32(u32::decode(self.input)?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_u64(u64::decode(self.input)?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_f32(f32::from_bits(u32::decode(self.input)?))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_f64(f64::from_bits(u64::decode(self.input)?))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        let c = ::std::char::from_u32(u32::decode(self.input)?)
+            .ok_or(DecodeErr::Invalid("char"))?;
+        visitor.visit_char(c)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_borrowed_str(self.str()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_borrowed_bytes(self.bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        match u8::decode(self.input)? {
+            0   => visitor.visit_none(),
+            1   => visitor.visit_some(self),
+            _   => Err(DecodeErr::Invalid("option")),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, DecodeErr>
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, DecodeErr>
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        let left = decode_len(self.input)?;
+        visitor.visit_seq(Items { decoder: self, left })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V)
+        -> Result<V::Value, DecodeErr>
+    {
+        visitor.visit_seq(Items { decoder: self, left: len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize,
+            visitor: V) -> Result<V::Value, DecodeErr>
+    {
+        visitor.visit_seq(Items { decoder: self, left: len })
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeErr> {
+        let left = decode_len(self.input)?;
+        visitor.visit_map(Items { decoder: self, left })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str,
+            fields: &'static [&'static str], visitor: V) -> Result<V::Value, DecodeErr>
+    {
+        visitor.visit_seq(Items { decoder: self, left: fields.len() })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+            _variants: &'static [&'static str], visitor: V) -> Result<V::Value, DecodeErr>
+    {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V)
+        -> Result<V::Value, DecodeErr>
+    {
+        Err(DecodeErr::Invalid("identifier"))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V)
+        -> Result<V::Value, DecodeErr>
+    {
+        Err(DecodeErr::Invalid("ignored value"))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use message::TypedSocket;
+    use serde::{Deserialize, Serialize};
+    use {OpenNetwork, RegistrationForm, Service, Socket};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Block {
+        addr    : u64,
+        size    : u32,
+        owner   : Option<String>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Request {
+        Alloc(u32),
+        Free(Block),
+        Move { from: u64, to: u64 },
+        Usage,
+    }
+
+    fn echo(socket: LocalSocket) -> ! {
+        while let Ok(bytes) = socket.receive::<Vec<u8>>() {
+            if socket.send(bytes).is_err() {
+                break;
+            }
+        }
+        finish()
+    }
+
+    #[test]
+    fn same_bytes_as_hand_written() {
+        let block = Block { addr: 7, size: 2, owner: Some("a".to_string()) };
+        let hand = (7u64, (2u32, Some("a".to_string())));
+        assert_eq!(Serde(block).to_bytes(), hand.to_bytes());
+
+        let request = Serde(Request::Move { from: 1, to: 2 });
+        assert_eq!(request.to_bytes().unwrap(), [2, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Serde::from_bytes(&request.to_bytes().unwrap()), Ok(request));
+
+        let map: BTreeMap<_, _> = vec![(1u8, "x".to_string())].into_iter().collect();
+        assert_eq!(Serde(map.clone()).to_bytes(), vec![(1u8, "x".to_string())].to_bytes());
+        assert_eq!(Serde::from_bytes(&Serde(map.clone()).to_bytes().unwrap()), Ok(Serde(map)));
+    }
+
+    #[test]
+    fn invalid_payloads() {
+        assert_eq!(Serde::<Request>::from_bytes(&[9]).map(|_| ()),
+                Err(DecodeErr::Custom(
+                    "invalid value: integer `9`, expected variant index 0 <= i < 4".to_string())));
+        assert_eq!(Serde::<Request>::from_bytes(&[0, 1]), Err(DecodeErr::Truncated));
+        assert_eq!(Serde::<Request>::from_bytes(&[3, 0]), Err(DecodeErr::Trailing(1)));
+    }
+
+    #[test]
+    fn typed_socket() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(echo, "echo".to_string())).unwrap();
+        let socket = network.connect(LocalService::by_id("echo".to_string())).unwrap();
+        let typed = TypedSocket::new(socket);
+        typed.send(&Serde(Request::Alloc(16))).unwrap();
+        assert_eq!(typed.receive().unwrap(), Serde(Request::Alloc(16)));
+    }
+}
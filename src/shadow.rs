@@ -0,0 +1,171 @@
+//! Shadow traffic. Before a new provider implementation is promoted,
+//! real requests to the service are mirrored to it as a dry run: the
+//! requester gets the reply of the primary provider only, while the
+//! reply of the shadow is compared with it and discarded. Mismatches
+//! are counted and reported to a hook, so the candidate is validated
+//! against real traffic without affecting anybody.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Data, Object, Service, Socket, Time};
+use metrics::{MetricFamily, MetricKind, MetricsSource, Sample};
+use rpc::{RpcClient, RpcErr};
+
+/// Outcome of the mirrored call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+
+    /// Shadow replied the same as the primary.
+    Match,
+
+    /// Shadow replied differently.
+    Mismatch,
+
+    /// Shadow failed or did not reply in time.
+    Failed,
+}
+
+type Hook<Q, R> = Box<dyn Fn(&Q, &R, &Result<R, RpcErr>) + Send + Sync>;
+
+/// Client that mirrors each call to the shadow provider.
+pub struct ShadowClient<O, S, SC, Q, R, T> {
+    primary     : RpcClient<O, S, SC, Q, R>,
+    shadow      : RpcClient<O, S, SC, Q, R>,
+
+    /// How long to wait for the shadow after the primary replied.
+    timeout     : T,
+    hook        : Option<Hook<Q, R>>,
+    verdicts    : [AtomicUsize; 3],
+}
+
+impl<O, S, SC, Q, R, T> ShadowClient<O, S, SC, Q, R, T>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                Q   : Data + Clone,
+                R   : Data + PartialEq,
+                T   : Time + Clone
+{
+
+    /// Mirror the calls on the primary channel to the shadow one. The
+    /// shadow is only called after the primary replied and is given
+    /// 'timeout' to reply, so mirroring delays the calls by at most
+    /// that.
+    pub fn new(primary: SC, shadow: SC, timeout: T) -> Self {
+        ShadowClient {
+            primary     : RpcClient::new(primary),
+            shadow      : RpcClient::new(shadow),
+            timeout,
+            hook        : None,
+            verdicts    : Default::default(),
+        }
+    }
+
+    /// Call the hook with the request, the primary reply and the shadow
+    /// outcome of each call that did not match.
+    pub fn on_mismatch<F>(mut self, hook: F) -> Self
+        where F: Fn(&Q, &R, &Result<R, RpcErr>) + Send + Sync + 'static
+    {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Call the primary provider and mirror the call to the shadow.
+    /// Only the primary result is returned. Calls that fail on the
+    /// primary are not mirrored.
+    pub fn call<TT: Time>(&self, request: Q, timeout: TT) -> Result<R, RpcErr> {
+        let reply = self.primary.call(request.clone(), timeout)?;
+        let shadow = self.shadow.call(request.clone(), self.timeout.clone());
+        let verdict = match shadow {
+            Ok(ref r) if *r == reply    => Verdict::Match,
+            Ok(_)                       => Verdict::Mismatch,
+            Err(_)                      => Verdict::Failed,
+        };
+        self.verdicts[verdict as usize].fetch_add(1, Ordering::Relaxed);
+        if verdict != Verdict::Match {
+            if let Some(ref hook) = self.hook {
+                hook(&request, &reply, &shadow);
+            }
+        }
+        Ok(reply)
+    }
+
+    /// Count of the mirrored calls with given verdict.
+    pub fn count(&self, verdict: Verdict) -> usize {
+        self.verdicts[verdict as usize].load(Ordering::Relaxed)
+    }
+}
+
+impl<O, S, SC, Q, R, T> MetricsSource for ShadowClient<O, S, SC, Q, R, T> {
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let names = ["match", "mismatch", "failed"];
+        vec![MetricFamily {
+            name    : "ccs_shadow_calls".to_string(),
+            help    : "Calls mirrored to the shadow provider by verdict.".to_string(),
+            kind    : MetricKind::Counter,
+            samples : names.iter().zip(self.verdicts.iter()).map(|(name, count)| Sample {
+                labels  : vec![("verdict".to_string(), name.to_string())],
+                value   : count.load(Ordering::Relaxed) as f64,
+            }).collect(),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use local::{finish, LocalNetwork, LocalService, LocalSocket};
+    use rpc::serve_loop;
+    use {OpenNetwork, RegistrationForm};
+
+    #[derive(Clone)]
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    fn upper(socket: LocalSocket) -> ! {
+        let _ = serve_loop(&socket, |text: String| text.to_uppercase());
+        finish()
+    }
+
+    /// Candidate that gets non-ASCII text wrong.
+    fn ascii_upper(socket: LocalSocket) -> ! {
+        let _ = serve_loop(&socket, |text: String| text.to_ascii_uppercase());
+        finish()
+    }
+
+    #[test]
+    fn mismatches_are_reported() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(upper, "upper".to_string())).unwrap();
+        network.register(RegistrationForm::new(ascii_upper, "candidate".to_string())).unwrap();
+        let connect = |id: &str| network.connect(LocalService::by_id(id.to_string())).unwrap();
+        let mismatched = Arc::new(Mutex::new(Vec::new()));
+        let log = mismatched.clone();
+        let client = ShadowClient::new(connect("upper"), connect("candidate"), Millis(1000))
+            .on_mismatch(move |q: &String, _: &String, _: &Result<String, RpcErr>| {
+                log.lock().unwrap().push(q.clone())
+            });
+
+        assert_eq!(client.call("abc".to_string(), Millis(1000)).unwrap(), "ABC");
+        assert_eq!(client.call("ї".to_string(), Millis(1000)).unwrap(), "Ї");
+        assert_eq!(client.count(Verdict::Match), 1);
+        assert_eq!(client.count(Verdict::Mismatch), 1);
+        assert_eq!(*mismatched.lock().unwrap(), ["ї"]);
+    }
+}
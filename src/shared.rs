@@ -0,0 +1,66 @@
+//! Shared regions. Large data like framebuffers or file caches is passed
+//! to the peer by mapping its region rather than copying it. The sender
+//! keeps the grant of the region and the receiver gets the mapping. The
+//! data stays readable through the mapping until the sender revokes the
+//! grant, e.g. when the channel closes, and takes the data back.
+
+use std::sync::{Arc, RwLock};
+
+use super::{Data, SharedData};
+
+type Region<D> = Arc<RwLock<Option<D>>>;
+
+/// Sender side of the shared region. Dropping the grant without the
+/// revoke leaves the data to the peer.
+pub struct Grant<D: SharedData> {
+    region  : Region<D>,
+}
+
+/// Receiver side of the shared region.
+pub struct Mapping<D: SharedData> {
+    region  : Region<D>,
+}
+
+impl<D: SharedData> Data for Mapping<D> {
+}
+
+/// Region was revoked by the sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Revoked;
+
+/// Put the data to the shared region. Returns the grant to keep and the
+/// mapping to pass to the peer.
+pub fn share<D: SharedData>(data: D) -> (Grant<D>, Mapping<D>) {
+    let region = Arc::new(RwLock::new(Some(data)));
+    (Grant { region: region.clone() }, Mapping { region })
+}
+
+impl<D: SharedData> Grant<D> {
+
+    /// Check if the peer still holds the mapping.
+    pub fn is_mapped(&self) -> bool {
+        Arc::strong_count(&self.region) > 1
+    }
+
+    /// Revoke the mapping and take the data back. Waits for the peer to
+    /// finish reading the region if it currently does.
+    pub fn revoke(self) -> D {
+        self.region.write().unwrap().take().expect("region is revoked only once")
+    }
+}
+
+impl<D: SharedData> Mapping<D> {
+
+    /// Read the data of the region unless it was revoked. The sender
+    /// can't revoke the region while the function runs.
+    pub fn read<R, F>(&self, f: F) -> Result<R, Revoked>
+        where F: FnOnce(&D) -> R
+    {
+        self.region.read().unwrap().as_ref().map(f).ok_or(Revoked)
+    }
+
+    /// Check if the sender revoked the region.
+    pub fn is_revoked(&self) -> bool {
+        self.region.read().unwrap().is_none()
+    }
+}
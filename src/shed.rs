@@ -0,0 +1,164 @@
+//! Load shedding. Overloaded provider that accepts every request makes
+//! every client wait until its deadline. Instead, the provider consults
+//! 'LoadShedder' before dispatching each request and rejects some early,
+//! so the requesters get 'SocketErr::ProviderOverloaded' right away and
+//! may retry elsewhere. 'CoDel' is the default policy.
+
+use std::time::{Duration, Instant};
+
+use super::{Object, Service, Socket, Time};
+use rt::duration;
+
+/// What the shedder knows about the request and the provider.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadStats {
+
+    /// Count of requests waiting to be dispatched.
+    pub queue_depth : usize,
+
+    /// How long this request has waited in the queue.
+    pub sojourn     : Duration,
+
+    /// Time left until the deadline of the request, if it has one.
+    pub budget      : Option<Duration>,
+
+    /// When the request is being dispatched.
+    pub now         : Instant,
+}
+
+/// Policy that decides which requests to reject.
+pub trait LoadShedder {
+
+    /// Whether to reject the request instead of dispatching it.
+    fn shed(&mut self, stats: &LoadStats) -> bool;
+}
+
+/// Socket of the provider that can reject the request.
+pub trait ShedSocket<O, S>: Socket<O, S>
+        where O: Object<S>, S: Service {
+
+    /// Close the channel telling the requester that the provider is
+    /// overloaded. Requester gets 'SocketErr::ProviderOverloaded'.
+    fn reject_overloaded(self);
+}
+
+/// Controlled delay policy. While requests keep waiting longer than
+/// the target for the whole interval, the provider is overloaded and
+/// starts rejecting them, more often the longer it lasts. Requests that
+/// already have no budget left are always rejected.
+pub struct CoDel {
+    target      : Duration,
+    interval    : Duration,
+
+    /// When the sojourn time first exceeded the target, while it stays
+    /// above.
+    above_since : Option<Instant>,
+
+    /// When to reject the next request in the rejecting state.
+    next_reject : Option<Instant>,
+
+    /// Count of rejects in the current rejecting state.
+    count       : u32,
+}
+
+impl CoDel {
+
+    /// Create policy with given target sojourn time and interval. Usual
+    /// values are 5 and 100 milliseconds.
+    pub fn new<T: Time, I: Time>(target: T, interval: I) -> Self {
+        CoDel {
+            target      : duration(&target),
+            interval    : duration(&interval),
+            above_since : None,
+            next_reject : None,
+            count       : 0,
+        }
+    }
+
+    /// Whether the policy is rejecting requests now.
+    pub fn is_rejecting(&self) -> bool {
+        self.next_reject.is_some()
+    }
+
+    fn reject_interval(&self) -> Duration {
+        self.interval.div_f64((self.count.max(1) as f64).sqrt())
+    }
+}
+
+impl LoadShedder for CoDel {
+
+    fn shed(&mut self, stats: &LoadStats) -> bool {
+        if stats.budget.is_some_and(|b| b.is_zero()) {
+            return true;
+        }
+        if stats.sojourn < self.target || stats.queue_depth == 0 {
+            self.above_since = None;
+            self.next_reject = None;
+            self.count = 0;
+            return false;
+        }
+        let since = *self.above_since.get_or_insert(stats.now);
+        match self.next_reject {
+            None if stats.now.duration_since(since) >= self.interval => {
+                self.count = 1;
+                self.next_reject = Some(stats.now + self.reject_interval());
+                true
+            },
+            Some(next) if stats.now >= next => {
+                self.count += 1;
+                self.next_reject = Some(next + self.reject_interval());
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    #[test]
+    fn sheds_only_standing_queue() {
+        let mut codel = CoDel::new(Millis(5), Millis(100));
+        let start = Instant::now();
+        let stats = |ms: u64, sojourn: u64| LoadStats {
+            queue_depth : 10,
+            sojourn     : Duration::from_millis(sojourn),
+            budget      : None,
+            now         : start + Duration::from_millis(ms),
+        };
+
+        // Short burst is absorbed.
+        assert!(!codel.shed(&stats(0, 20)));
+        assert!(!codel.shed(&stats(50, 1)));
+        assert!(!codel.shed(&stats(60, 20)));
+
+        // Standing queue for the whole interval.
+        assert!(!codel.shed(&stats(150, 20)));
+        assert!(codel.shed(&stats(160, 20)));
+        assert!(codel.is_rejecting());
+        assert!(!codel.shed(&stats(170, 20)));
+        assert!(codel.shed(&stats(260, 20)));
+
+        assert!(!codel.shed(&stats(270, 1)));
+        assert!(!codel.is_rejecting());
+    }
+}
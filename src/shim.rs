@@ -0,0 +1,364 @@
+//! Emulation of the optional features on backends that lack them.
+//! Application code uses the adapters of this module and they pick the
+//! native implementation when the network reports the feature and the
+//! emulation otherwise, so the same code runs on any backend:
+//!
+//! * timed receive and send are emulated by polling 'receive_now' and
+//!   'send_now';
+//! * publish/subscribe is emulated by fan-out of each message over
+//!   point-to-point channels to the subscribers.
+
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Data, Object, Service, Socket, SocketErr, Time};
+use features::{Feature, Features};
+use pubsub::{Broadcast, BufferPolicy, Overflow, PubSubErr, PubSubNetwork, Subscription};
+use rt::duration;
+
+/// Interval of polling in the emulated timed operations.
+const POLL: Duration = Duration::from_millis(1);
+
+/// Socket with timed operations that work on any backend.
+pub struct Timed<'a, SC: 'a> {
+    socket  : &'a SC,
+    native  : bool,
+}
+
+impl<'a, SC> Timed<'a, SC> {
+
+    /// Wrap the socket of the network with given features.
+    pub fn new(socket: &'a SC, features: &Features) -> Self {
+        Timed {
+            socket,
+            native  : features.has(Feature::Timeouts),
+        }
+    }
+
+    /// Same as 'Socket::wait_to_receive'.
+    pub fn receive<O, S, D, T>(&self, time: T) -> Option<Result<D, SocketErr>>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                D   : Data,
+                T   : Time
+    {
+        if self.native {
+            return self.socket.wait_to_receive(time);
+        }
+        let deadline = Instant::now() + duration(&time);
+        loop {
+            match self.socket.receive_now() {
+                Ok(Some(data))  => return Some(Ok(data)),
+                Err(e)          => return Some(Err(e)),
+                Ok(None)        => (),
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            thread::sleep(POLL);
+        }
+    }
+
+    /// Send the data if the peer takes it before the timeout. The data
+    /// is given back if it was not sent.
+    pub fn send<O, S, D, T>(&self, data: D, time: T) -> Result<Option<D>, SocketErr>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>,
+                D   : Data,
+                T   : Time
+    {
+        if self.native {
+            return match self.socket.wait_to_send(time) {
+                Some(Ok(()))    => self.socket.send_now(data),
+                Some(Err(e))    => Err(e),
+                None            => Ok(Some(data)),
+            };
+        }
+        let deadline = Instant::now() + duration(&time);
+        let mut data = data;
+        loop {
+            data = match self.socket.send_now(data)? {
+                Some(data)  => data,
+                None        => return Ok(None),
+            };
+            if Instant::now() >= deadline {
+                return Ok(Some(data));
+            }
+            thread::sleep(POLL);
+        }
+    }
+}
+
+/// Publisher that sends each message over point-to-point channels to
+/// the subscribers. Channels are added as the subscribers connect. There
+/// are no buffers in between: subscriber with 'Overflow::Block' holds
+/// the publisher until it receives, and the others lose the messages
+/// they are not ready to receive.
+pub struct FanOut<SC> {
+    subscribers : Mutex<Vec<(SC, BufferPolicy)>>,
+}
+
+impl<SC> Default for FanOut<SC> {
+
+    fn default() -> Self {
+        FanOut {
+            subscribers : Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<SC> FanOut<SC> {
+
+    /// Create publisher without subscribers.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the channel to the subscriber.
+    pub fn add(&self, socket: SC, policy: BufferPolicy) {
+        self.subscribers.lock().unwrap().push((socket, policy));
+    }
+}
+
+/// Broadcast of the network, either native or emulated.
+pub enum Publisher<B, SC> {
+    Native(B),
+    FanOut(FanOut<SC>),
+}
+
+impl<B, SC> Publisher<B, SC> {
+
+    /// Publish to the topic natively if the network supports it, or
+    /// start an empty fan-out otherwise.
+    pub fn new<N, S>(network: &N, topic: &str) -> Result<Self, PubSubErr>
+        where   N   : PubSubNetwork<S, Broadcast = B>,
+                S   : Service
+    {
+        if network.features().has(Feature::PubSub) {
+            network.broadcast(topic).map(Publisher::Native)
+        } else {
+            Ok(Publisher::FanOut(FanOut::new()))
+        }
+    }
+
+    /// Emulating publisher to add the channels of the subscribers to.
+    /// None if the publishing is native.
+    pub fn fan_out(&self) -> Option<&FanOut<SC>> {
+        match *self {
+            Publisher::Native(_)        => None,
+            Publisher::FanOut(ref f)    => Some(f),
+        }
+    }
+}
+
+/// Sockets of the fan-out publisher.
+pub trait FanOutSocket {
+
+    /// Send the data as the policy says. False if it was not sent.
+    fn deliver<D: Data>(&self, data: D, policy: &BufferPolicy) -> bool;
+
+    /// Check if the channel is still open.
+    fn is_open(&self) -> bool;
+}
+
+/// Adapter of any socket to 'FanOutSocket'.
+pub struct Channel<O, S, SC> {
+    socket  : SC,
+    _os     : PhantomData<(O, S)>,
+}
+
+impl<O, S, SC> Channel<O, S, SC>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+{
+
+    /// Wrap the socket.
+    pub fn new(socket: SC) -> Self {
+        Channel {
+            socket,
+            _os     : PhantomData,
+        }
+    }
+}
+
+impl<O, S, SC> FanOutSocket for Channel<O, S, SC>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+{
+
+    fn deliver<D: Data>(&self, data: D, policy: &BufferPolicy) -> bool {
+        match policy.overflow {
+            Overflow::Block => self.socket.send(data).is_ok(),
+            Overflow::DropOldest | Overflow::Error
+                            => matches!(self.socket.send_now(data), Ok(None)),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.socket.is_opened()
+    }
+}
+
+impl<SC: FanOutSocket> Broadcast for FanOut<SC> {
+
+    fn publish<D: Data + Clone>(&self, data: D) -> Result<usize, PubSubErr> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|(socket, _)| socket.is_open());
+        Ok(subscribers.iter()
+            .filter(|(socket, policy)| socket.deliver(data.clone(), policy))
+            .count())
+    }
+
+    fn subscribers(&self) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|(socket, _)| socket.is_open());
+        subscribers.len()
+    }
+}
+
+impl<B: Broadcast, SC: FanOutSocket> Broadcast for Publisher<B, SC> {
+
+    fn publish<D: Data + Clone>(&self, data: D) -> Result<usize, PubSubErr> {
+        match *self {
+            Publisher::Native(ref b)    => b.publish(data),
+            Publisher::FanOut(ref f)    => f.publish(data),
+        }
+    }
+
+    fn subscribers(&self) -> usize {
+        match *self {
+            Publisher::Native(ref b)    => b.subscribers(),
+            Publisher::FanOut(ref f)    => f.subscribers(),
+        }
+    }
+}
+
+/// Subscription over the channel from the fan-out publisher.
+pub struct ChannelSubscription<O, S, SC> {
+    socket  : SC,
+    _os     : PhantomData<(O, S)>,
+}
+
+impl<O, S, SC> ChannelSubscription<O, S, SC>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+{
+
+    /// Subscribe over the channel.
+    pub fn new(socket: SC) -> Self {
+        ChannelSubscription {
+            socket,
+            _os     : PhantomData,
+        }
+    }
+
+    /// Socket of the channel, e.g. to reply to the publisher.
+    pub fn socket(&self) -> &SC {
+        &self.socket
+    }
+}
+
+fn pubsub_err(e: SocketErr) -> PubSubErr {
+    match e {
+        SocketErr::UnexpectedData   => PubSubErr::UnexpectedData,
+        SocketErr::SequenceGap {..} => PubSubErr::Overrun,
+        _                           => PubSubErr::Closed,
+    }
+}
+
+impl<O, S, SC> Subscription for ChannelSubscription<O, S, SC>
+        where   O   : Object<S>,
+                S   : Service,
+                SC  : Socket<O, S>
+{
+
+    fn receive<D: Data>(&self) -> Result<D, PubSubErr> {
+        self.socket.receive().map_err(pubsub_err)
+    }
+
+    fn try_receive<D: Data>(&self) -> Result<Option<D>, PubSubErr> {
+        self.socket.receive_now().map_err(pubsub_err)
+    }
+
+    /// Publisher drops the messages without telling, so the count is
+    /// not known.
+    fn dropped(&self) -> u64 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use local::{LocalNetwork, LocalService, LocalSocket, finish};
+    use {OpenNetwork, RegistrationForm};
+
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    /// Subscriber that replies with each received message.
+    fn subscriber(socket: LocalSocket) -> ! {
+        let subscription = ChannelSubscription::new(socket);
+        while let Ok(text) = subscription.receive::<String>() {
+            if subscription.socket().send(text).is_err() {
+                break;
+            }
+        }
+        drop(subscription);
+        finish()
+    }
+
+    fn idle(_socket: LocalSocket) -> ! {
+        loop {
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn emulated_timeouts() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(subscriber, "sub".to_string())).unwrap();
+        let socket = network.connect(LocalService::by_id("sub".to_string())).unwrap();
+        let timed = Timed::new(&socket, &Features::new());
+        assert!(timed.receive::<_, _, String, _>(Millis(5)).is_none());
+        assert!(timed.send("hi".to_string(), Millis(1000)).unwrap().is_none());
+        assert_eq!(timed.receive::<_, _, String, _>(Millis(1000)).unwrap().unwrap(), "hi");
+    }
+
+    #[test]
+    fn fan_out() {
+        let network = LocalNetwork::new();
+        network.register(RegistrationForm::new(subscriber, "sub".to_string())).unwrap();
+        network.register(RegistrationForm::new(idle, "idle".to_string())).unwrap();
+        let connect = |id: &str| network.connect(LocalService::by_id(id.to_string())).unwrap();
+        let fan_out = FanOut::new();
+        fan_out.add(Channel::new(connect("sub")), BufferPolicy::new(1, Overflow::Block));
+        fan_out.add(Channel::new(connect("sub")), BufferPolicy::new(1, Overflow::Block));
+        fan_out.add(Channel::new(connect("idle")), BufferPolicy::new(1, Overflow::DropOldest));
+
+        // Idle subscriber is not ready to receive and loses the message.
+        assert_eq!(fan_out.publish("news".to_string()), Ok(2));
+        assert_eq!(fan_out.subscribers(), 3);
+    }
+}
@@ -0,0 +1,178 @@
+//! Shutdown ordering. This is the mirror image of the bootstrap: a
+//! service is stopped only after all services that depend on it were
+//! stopped, stage by stage, each stage with its own timeout. The result
+//! is a report of what failed to stop in time.
+
+use std::time::Duration;
+
+use super::Time;
+use rt::duration;
+
+/// Services with their dependencies.
+pub struct ShutdownPlan<Id> {
+    services    : Vec<Id>,
+
+    /// Pairs of indices: the first service depends on the second.
+    edges       : Vec<(usize, usize)>,
+    timeout     : Duration,
+}
+
+/// Error of the plan.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShutdownErr<Id> {
+
+    /// Services depend on each other and there is no order to stop
+    /// them.
+    Cycle(Vec<Id>),
+}
+
+/// Result of the shutdown.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ShutdownReport<Id> {
+
+    /// Services that stopped in time.
+    pub stopped : Vec<Id>,
+
+    /// Services that did not stop in time of their stage.
+    pub failed  : Vec<Id>,
+}
+
+impl<Id: PartialEq + Clone> ShutdownPlan<Id> {
+
+    /// Create plan where each stage is given the timeout to stop.
+    pub fn new<T: Time>(stage_timeout: T) -> Self {
+        ShutdownPlan {
+            services    : Vec::new(),
+            edges       : Vec::new(),
+            timeout     : duration(&stage_timeout),
+        }
+    }
+
+    fn index(&mut self, id: &Id) -> usize {
+        match self.services.iter().position(|s| s == id) {
+            Some(i) => i,
+            None    => {
+                self.services.push(id.clone());
+                self.services.len() - 1
+            },
+        }
+    }
+
+    /// Add the service without dependencies.
+    pub fn service(mut self, id: Id) -> Self {
+        self.index(&id);
+        self
+    }
+
+    /// Declare that the service uses the other one, so it must be
+    /// stopped first.
+    pub fn depends(mut self, service: Id, on: Id) -> Self {
+        let a = self.index(&service);
+        let b = self.index(&on);
+        self.edges.push((a, b));
+        self
+    }
+
+    /// Stages of the shutdown. Services of one stage do not depend on
+    /// each other and are stopped together.
+    pub fn stages(&self) -> Result<Vec<Vec<Id>>, ShutdownErr<Id>> {
+        let n = self.services.len();
+
+        // Count of not yet stopped dependents of each service.
+        let mut dependents = vec![0; n];
+        for &(_, b) in &self.edges {
+            dependents[b] += 1;
+        }
+        let mut done = vec![false; n];
+        let mut stages = Vec::new();
+        while done.iter().any(|d| !d) {
+            let stage: Vec<usize> = (0..n)
+                .filter(|&i| !done[i] && dependents[i] == 0)
+                .collect();
+            if stage.is_empty() {
+                return Err(ShutdownErr::Cycle((0..n)
+                    .filter(|&i| !done[i])
+                    .map(|i| self.services[i].clone())
+                    .collect()));
+            }
+            for &i in &stage {
+                done[i] = true;
+                for &(a, b) in &self.edges {
+                    if a == i {
+                        dependents[b] -= 1;
+                    }
+                }
+            }
+            stages.push(stage.into_iter().map(|i| self.services[i].clone()).collect());
+        }
+        Ok(stages)
+    }
+
+    /// Stop the services stage by stage. 'stop' is given services of
+    /// the stage and the timeout and returns those that did not stop in
+    /// time. Shutdown goes on after failures so that as much as possible
+    /// is stopped.
+    pub fn run<F>(&self, mut stop: F) -> Result<ShutdownReport<Id>, ShutdownErr<Id>>
+        where F: FnMut(&[Id], Duration) -> Vec<Id>
+    {
+        let mut report = ShutdownReport {
+            stopped : Vec::new(),
+            failed  : Vec::new(),
+        };
+        for stage in self.stages()? {
+            let failed = stop(&stage, self.timeout);
+            for id in stage {
+                if failed.contains(&id) {
+                    report.failed.push(id);
+                } else {
+                    report.stopped.push(id);
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Secs(u32);
+
+    impl Time for Secs {
+
+        fn nanos(&self) -> u32 {
+            0
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Secs(seconds.saturating_add(nanos / 1_000_000_000))
+        }
+    }
+
+    #[test]
+    fn dependents_stop_first() {
+        let plan = ShutdownPlan::new(Secs(1))
+            .depends("fs", "disk")
+            .depends("app", "fs")
+            .depends("app", "net")
+            .service("log");
+        assert_eq!(plan.stages().unwrap(),
+                vec![vec!["app", "log"], vec!["fs", "net"], vec!["disk"]]);
+
+        let report = plan.run(|stage, _| {
+            stage.iter().filter(|&&s| s == "net").cloned().collect()
+        }).unwrap();
+        assert_eq!(report.failed, vec!["net"]);
+        assert_eq!(report.stopped.len(), 4);
+
+        let cycle = ShutdownPlan::new(Secs(1))
+            .depends("a", "b")
+            .depends("b", "a");
+        assert_eq!(cycle.stages(), Err(ShutdownErr::Cycle(vec!["a", "b"])));
+    }
+}
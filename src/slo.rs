@@ -0,0 +1,203 @@
+//! Service-level objectives. Providers declare in their registration
+//! form how fast and how reliably they promise to serve, the network
+//! feeds the outcome of each request to 'SloTracker', and the tracker
+//! evaluates the objectives over a sliding window. When an objective
+//! stops being met, the tracker raises 'ControlEvent::SloViolated' on
+//! the control-plane stream, so operators get early warning without
+//! any external tooling.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use events::ControlEvent;
+use metrics::{MetricFamily, MetricKind, MetricsSource, Sample};
+use reaper::AuditSink;
+
+/// Objective of the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Objective {
+
+    /// Given share of the requests, in per mille, completes within the
+    /// threshold. E.g. 990 and 50 milliseconds for p99 of 50 ms.
+    Latency {
+        permille    : u32,
+        threshold   : Duration,
+    },
+
+    /// Share of the failed requests, in parts per million, does not
+    /// exceed the limit.
+    ErrorRate {
+        ppm         : u32,
+    },
+}
+
+impl Objective {
+
+    /// Observed value in the units of the objective and whether it meets
+    /// the objective. None if there are no requests.
+    fn evaluate(&self, requests: &VecDeque<Request>) -> Option<(u32, bool)> {
+        if requests.is_empty() {
+            return None;
+        }
+        let total = requests.len() as u64;
+        Some(match *self {
+            Objective::Latency { permille, threshold } => {
+                let fast = requests.iter().filter(|r| r.latency <= threshold).count() as u64;
+                let observed = (fast * 1000 / total) as u32;
+                (observed, observed >= permille)
+            },
+            Objective::ErrorRate { ppm } => {
+                let failed = requests.iter().filter(|r| !r.ok).count() as u64;
+                let observed = (failed * 1_000_000 / total) as u32;
+                (observed, observed <= ppm)
+            },
+        })
+    }
+}
+
+/// Outcome of single request.
+struct Request {
+    at      : Instant,
+    latency : Duration,
+    ok      : bool,
+}
+
+struct Tracked {
+    objectives  : Vec<Objective>,
+    requests    : VecDeque<Request>,
+
+    /// Objectives that are violated now, with their observed values.
+    violated    : Vec<(Objective, u32)>,
+}
+
+/// Tracker of the objectives of the services 'SId'.
+pub struct SloTracker<SId> {
+    window  : Duration,
+    state   : Mutex<HashMap<SId, Tracked>>,
+}
+
+impl<SId: Hash + Eq + Clone + ToString> SloTracker<SId> {
+
+    /// Create tracker that evaluates the objectives over the requests of
+    /// given last period.
+    pub fn new(window: Duration) -> Self {
+        SloTracker {
+            window,
+            state   : Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start tracking the objectives of the service, usually from its
+    /// registration form. Replaces previous objectives of the service.
+    pub fn declare(&self, service: SId, objectives: Vec<Objective>) {
+        self.state.lock().unwrap().insert(service, Tracked {
+            objectives,
+            requests    : VecDeque::new(),
+            violated    : Vec::new(),
+        });
+    }
+
+    /// Stop tracking the service.
+    pub fn forget(&self, service: &SId) {
+        self.state.lock().unwrap().remove(service);
+    }
+
+    /// Record the outcome of the request to the service. Ignored if the
+    /// service declared no objectives.
+    pub fn record(&self, service: &SId, latency: Duration, ok: bool, now: Instant) {
+        if let Some(tracked) = self.state.lock().unwrap().get_mut(service) {
+            tracked.requests.push_back(Request { at: now, latency, ok });
+        }
+    }
+
+    /// Evaluate all objectives over the window ending now and raise an
+    /// event into the sink for each objective that became violated since
+    /// the last evaluation. Objective that is met again is raised again
+    /// on its next violation.
+    pub fn evaluate<OId, A>(&self, now: Instant, sink: &A)
+        where A: AuditSink<ControlEvent<OId, SId>>
+    {
+        let mut events = Vec::new();
+        for (service, tracked) in self.state.lock().unwrap().iter_mut() {
+            while tracked.requests.front()
+                    .is_some_and(|r| now.saturating_duration_since(r.at) > self.window) {
+                tracked.requests.pop_front();
+            }
+            let mut violated = Vec::new();
+            for objective in &tracked.objectives {
+                if let Some((observed, false)) = objective.evaluate(&tracked.requests) {
+                    if !tracked.violated.iter().any(|&(o, _)| o == *objective) {
+                        events.push(ControlEvent::SloViolated {
+                            service     : service.clone(),
+                            objective   : *objective,
+                            observed,
+                        });
+                    }
+                    violated.push((*objective, observed));
+                }
+            }
+            tracked.violated = violated;
+        }
+        for event in events {
+            sink.record(event);
+        }
+    }
+}
+
+impl<SId: Hash + Eq + Clone + ToString> MetricsSource for SloTracker<SId> {
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let state = self.state.lock().unwrap();
+        let samples = state.iter().map(|(service, tracked)| Sample {
+            labels  : vec![("service".to_string(), service.to_string())],
+            value   : tracked.violated.len() as f64,
+        }).collect();
+        vec![MetricFamily {
+            name    : "ccs_slo_violated_objectives".to_string(),
+            help    : "Objectives of the service violated at the last evaluation.".to_string(),
+            kind    : MetricKind::Gauge,
+            samples,
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use events::EventLog;
+
+    #[test]
+    fn raises_once_per_violation() {
+        let tracker = SloTracker::new(Duration::from_secs(10));
+        let p90 = Objective::Latency {
+            permille    : 900,
+            threshold   : Duration::from_millis(50),
+        };
+        tracker.declare("search", vec![p90, Objective::ErrorRate { ppm: 1000 }]);
+        let log = EventLog::<u32, &str>::new();
+        let events = log.subscribe(Default::default());
+
+        let start = Instant::now();
+        for i in 0..10 {
+            let latency = Duration::from_millis(if i < 8 { 10 } else { 80 });
+            tracker.record(&"search", latency, true, start);
+        }
+        tracker.evaluate(start, &log);
+        tracker.evaluate(start, &log);
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![ControlEvent::SloViolated {
+            service     : "search",
+            objective   : p90,
+            observed    : 800,
+        }]);
+        assert_eq!(tracker.collect()[0].samples[0].value, 1.0);
+
+        // Slow requests leave the window.
+        tracker.record(&"search", Duration::from_millis(10), true,
+                start + Duration::from_secs(11));
+        tracker.evaluate(start + Duration::from_secs(11), &log);
+        assert_eq!(events.try_iter().count(), 0);
+        assert_eq!(tracker.collect()[0].samples[0].value, 0.0);
+    }
+}
@@ -0,0 +1,151 @@
+//! Consistent snapshots across services. Coordinator puts a barrier on
+//! the channels to all participating services: each service stops
+//! applying changes once it gets the barrier and reports that it is
+//! ready. Only when all are ready the states are captured, so they all
+//! reflect the same logical point, and then the services are released.
+//! Useful for diagnostics and backups of state spread over many
+//! services.
+
+use super::{Data, Object, Service, Socket, SocketErr};
+
+/// Message of the snapshot protocol. Each carries the identifier of
+/// the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotMsg {
+
+    /// Coordinator asks to stop applying changes.
+    Barrier(u64),
+
+    /// Participant stopped applying changes.
+    Ready(u64),
+
+    /// Coordinator asks for the state.
+    Capture(u64),
+
+    /// State of the participant.
+    State(u64, Vec<u8>),
+
+    /// Coordinator lets the participant continue. Also sent when the
+    /// snapshot is aborted.
+    Release(u64),
+}
+
+impl Data for SnapshotMsg {
+}
+
+/// Error of the snapshot.
+#[derive(Debug)]
+pub enum SnapshotErr {
+
+    /// Channel to the participant with given index failed.
+    Socket(usize, SocketErr),
+
+    /// Participant with given index replied out of protocol.
+    Unexpected(usize, SnapshotMsg),
+}
+
+fn expect<O, S, SC>(i: usize, socket: &SC, want: fn(&SnapshotMsg) -> bool)
+    -> Result<SnapshotMsg, SnapshotErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>
+{
+    let msg = socket.receive::<SnapshotMsg>()
+        .map_err(|e| SnapshotErr::Socket(i, e))?;
+    if want(&msg) { Ok(msg) } else { Err(SnapshotErr::Unexpected(i, msg)) }
+}
+
+/// Take the snapshot with given identifier over the channels to the
+/// participants. Returns states in the order of the channels. The
+/// participants are released whether the snapshot succeeds or not.
+pub fn take<O, S, SC>(id: u64, participants: &[SC])
+    -> Result<Vec<Vec<u8>>, SnapshotErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>
+{
+    let result = barrier_and_capture(id, participants);
+    for socket in participants {
+        let _ = socket.send(SnapshotMsg::Release(id));
+    }
+    result
+}
+
+fn barrier_and_capture<O, S, SC>(id: u64, participants: &[SC])
+    -> Result<Vec<Vec<u8>>, SnapshotErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>
+{
+    for (i, socket) in participants.iter().enumerate() {
+        socket.send(SnapshotMsg::Barrier(id)).map_err(|e| SnapshotErr::Socket(i, e))?;
+    }
+    for (i, socket) in participants.iter().enumerate() {
+        expect(i, socket, |m| matches!(*m, SnapshotMsg::Ready(_)))?;
+    }
+
+    let mut states = Vec::with_capacity(participants.len());
+    for (i, socket) in participants.iter().enumerate() {
+        socket.send(SnapshotMsg::Capture(id)).map_err(|e| SnapshotErr::Socket(i, e))?;
+    }
+    for (i, socket) in participants.iter().enumerate() {
+        match expect(i, socket, |m| matches!(*m, SnapshotMsg::State(..)))? {
+            SnapshotMsg::State(_, state) => states.push(state),
+            _ => unreachable!(),
+        }
+    }
+    Ok(states)
+}
+
+/// Service side of the snapshot.
+pub trait Participant {
+
+    /// Stop applying changes until 'resume'. Requests that change the
+    /// state should be queued meanwhile.
+    fn pause(&mut self);
+
+    /// Capture the state as of the pause.
+    fn capture(&mut self) -> Vec<u8>;
+
+    /// Continue applying changes.
+    fn resume(&mut self);
+}
+
+/// Take part in one snapshot on the channel from the coordinator. The
+/// participant is resumed even if the channel fails midway.
+pub fn participate<O, S, SC, P>(socket: &SC, participant: &mut P)
+    -> Result<(), SocketErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            P   : Participant
+{
+    let id = match socket.receive::<SnapshotMsg>()? {
+        SnapshotMsg::Barrier(id)    => id,
+        _                           => return Ok(()),
+    };
+    participant.pause();
+    let result = participate_paused(socket, participant, id);
+    participant.resume();
+    result
+}
+
+fn participate_paused<O, S, SC, P>(socket: &SC, participant: &mut P, id: u64)
+    -> Result<(), SocketErr>
+    where   O   : Object<S>,
+            S   : Service,
+            SC  : Socket<O, S>,
+            P   : Participant
+{
+    socket.send(SnapshotMsg::Ready(id))?;
+    loop {
+        match socket.receive::<SnapshotMsg>()? {
+            SnapshotMsg::Capture(_) => {
+                let state = participant.capture();
+                socket.send(SnapshotMsg::State(id, state))?;
+            },
+            SnapshotMsg::Release(_) => return Ok(()),
+            _                       => (),
+        }
+    }
+}
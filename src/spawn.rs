@@ -0,0 +1,167 @@
+//! Spawning of objects. Objects can be killed or decease on their own,
+//! and a network that implements 'Spawner' also creates them. The spec
+//! of the new object tells what program it runs, which services it
+//! provides from the start and whether it is born next to the spawning
+//! object or inside it. Initial services are registered before the
+//! program starts, so clients may connect right after the spawn without
+//! waiting for the object to register them itself.
+
+use super::{Form, OpenNetwork, RegistrationErr, Service};
+use sandbox::{SandboxProfile, SandboxViolation};
+use supervision::OwnedOf;
+
+/// Main function of the object.
+pub type EntryFn = Box<dyn FnOnce() + Send>;
+
+/// What the new object runs.
+pub enum Program {
+
+    /// Program image the network loads by its name, e.g. a file of the
+    /// system.
+    Image(String),
+
+    /// Function that runs as the main thread of the object. Only the
+    /// networks that live in the same address space can run it.
+    Entry(EntryFn),
+}
+
+/// Network the new object is born into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Placement {
+
+    /// Network the spawn is called on. The object is a sibling of the
+    /// spawning one.
+    #[default]
+    External,
+
+    /// Internal network of the spawning object. The object is its
+    /// sub-object and is not visible from the outside.
+    Internal,
+}
+
+/// Service the object provides from the start.
+pub struct InitialService<S: Service, N: OpenNetwork<S>> {
+    pub form    : Form<S, N>,
+    pub unique  : bool,
+}
+
+/// Description of the object to spawn.
+pub struct SpawnSpec<S: Service, N: OpenNetwork<S>> {
+    pub program     : Program,
+    pub services    : Vec<InitialService<S, N>>,
+    pub placement   : Placement,
+
+    /// Whether the internal network of the object starts with nothing
+    /// crossing its boundary, see 'hardened' module.
+    pub hardened    : bool,
+
+    /// Key under which the object saves its checkpoints. The object
+    /// receives the last checkpoint saved under it at spawn.
+    pub checkpoint  : Option<String>,
+
+    /// Sandbox of the object. Children of the sandboxed objects are
+    /// also restricted by the sandbox of the parent.
+    pub sandbox     : Option<SandboxProfile>,
+}
+
+impl<S: Service, N: OpenNetwork<S>> SpawnSpec<S, N> {
+
+    /// Spec of the object that runs given program in the external
+    /// network and provides nothing initially.
+    pub fn new(program: Program) -> Self {
+        SpawnSpec {
+            program,
+            services    : Vec::new(),
+            placement   : Placement::External,
+            hardened    : false,
+            checkpoint  : None,
+            sandbox     : None,
+        }
+    }
+
+    /// Spec of the object that loads the program image by its name.
+    pub fn image(name: &str) -> Self {
+        SpawnSpec::new(Program::Image(name.to_string()))
+    }
+
+    /// Spec of the object that runs given function as its main thread.
+    pub fn entry<F>(main: F) -> Self
+        where F: FnOnce() + Send + 'static
+    {
+        SpawnSpec::new(Program::Entry(Box::new(main)))
+    }
+
+    /// Register the service for the object before it starts.
+    pub fn service(mut self, form: Form<S, N>) -> Self {
+        self.services.push(InitialService { form, unique: false });
+        self
+    }
+
+    /// Register the service uniquely for the object before it starts.
+    pub fn unique_service(mut self, form: Form<S, N>) -> Self {
+        self.services.push(InitialService { form, unique: true });
+        self
+    }
+
+    /// Spawn the object in the internal network of the spawning one.
+    pub fn internal(mut self) -> Self {
+        self.placement = Placement::Internal;
+        self
+    }
+
+    /// Start the internal network of the object hardened.
+    pub fn hardened(mut self) -> Self {
+        self.hardened = true;
+        self
+    }
+
+    /// Save checkpoints of the object under given key and restore the
+    /// last of them at spawn, so the restarted object continues where
+    /// the previous one stopped.
+    pub fn checkpoint(mut self, key: &str) -> Self {
+        self.checkpoint = Some(key.to_string());
+        self
+    }
+
+    /// Run the object in the sandbox with given profile.
+    pub fn sandbox(mut self, profile: SandboxProfile) -> Self {
+        self.sandbox = Some(profile);
+        self
+    }
+}
+
+/// Errors that appear on attempt to spawn an object.
+#[derive(Debug)]
+pub enum SpawnErr {
+
+    /// Network has no program image with given name.
+    UnknownImage(String),
+
+    /// Network can't run this kind of program, e.g. a function in the
+    /// network that spans address spaces.
+    UnsupportedProgram,
+
+    /// Initial service could not be registered. The object is not
+    /// started.
+    Registration(RegistrationErr),
+
+    /// Sandbox of the spawning object does not allow the spawn, or
+    /// giving the object initial services.
+    Sandboxed(SandboxViolation),
+}
+
+impl From<RegistrationErr> for SpawnErr {
+
+    fn from(e: RegistrationErr) -> Self {
+        SpawnErr::Registration(e)
+    }
+}
+
+/// Network that creates new objects.
+pub trait Spawner<S: Service>: OpenNetwork<S> {
+
+    /// Create the object by the spec, register its initial services and
+    /// start its program. Either all initial services are registered
+    /// or the object is not created.
+    fn spawn(&self, spec: SpawnSpec<S, Self>) -> Result<OwnedOf<S, Self>, SpawnErr>;
+}
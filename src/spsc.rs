@@ -0,0 +1,231 @@
+//! Bounded lock-free ring for the channels with one sender and one
+//! receiver in each direction. The ring is split into the producer and
+//! the consumer handles. Each handle is the only one of its kind and
+//! needs exclusive access to push or to pop, so neither side ever spins
+//! or waits: the producer publishes the items by the release store of
+//! the tail, the consumer gives the slots back by the release store of
+//! the head.
+//!
+//! Users that can't keep each handle in a single thread put it behind a
+//! mutex. The producer and the consumer still never wait for each other.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Slots shared by the producer and the consumer.
+struct Shared<T> {
+    slots       : Box<[UnsafeCell<MaybeUninit<T>>]>,
+
+    /// Count of the items the ring holds at most.
+    capacity    : usize,
+
+    /// Position of the next item to pop. Written only by the consumer.
+    head        : AtomicUsize,
+
+    /// Position of the next item to push. Written only by the producer.
+    tail        : AtomicUsize,
+}
+
+// The producer writes only free slots and the consumer reads only filled
+// ones, with ownership passed through release/acquire on 'head' and
+// 'tail'. Handles take '&mut self' for anything that touches the slots,
+// so there is one writer and one reader at a time.
+unsafe impl<T: Send> Sync for Shared<T> {}
+unsafe impl<T: Send> Send for Shared<T> {}
+
+impl<T> Shared<T> {
+
+    fn slot(&self, position: usize) -> *mut MaybeUninit<T> {
+        self.slots[position & (self.slots.len() - 1)].get()
+    }
+
+    fn len(&self) -> usize {
+        // Head never passes the tail read after it.
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head).min(self.capacity)
+    }
+}
+
+impl<T> Drop for Shared<T> {
+
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            // SAFETY: slots between head and tail are filled and both
+            // handles are gone.
+            unsafe { (*self.slot(head)).assume_init_drop(); }
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// Sending side of the ring.
+pub struct Producer<T> {
+    shared  : Arc<Shared<T>>,
+}
+
+/// Receiving side of the ring.
+pub struct Consumer<T> {
+    shared  : Arc<Shared<T>>,
+}
+
+/// Create the ring that holds up to 'capacity' items, at least one.
+pub fn ring<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1);
+    let shared = Arc::new(Shared {
+        slots       : (0..capacity.next_power_of_two())
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+        capacity,
+        head        : AtomicUsize::new(0),
+        tail        : AtomicUsize::new(0),
+    });
+    (Producer { shared: shared.clone() }, Consumer { shared })
+}
+
+impl<T> Producer<T> {
+
+    /// Count of the items the ring holds at most.
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    /// Count of the items in the ring.
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    /// Check if the ring is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Push the item. If the ring is full, the item is returned back.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let head = shared.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) >= shared.capacity {
+            return Err(item);
+        }
+        // SAFETY: the slot is free and only the producer writes free slots.
+        unsafe { (*shared.slot(tail)).write(item); }
+        shared.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T> Consumer<T> {
+
+    /// Count of the items the ring holds at most.
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    /// Count of the items in the ring.
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    /// Check if the ring is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pop the next item if there is one.
+    pub fn pop(&mut self) -> Option<T> {
+        self.pop_if(|_| true)
+    }
+
+    /// Pop the next item if there is one and the function accepts it.
+    /// Rejected item stays first in the ring.
+    pub fn pop_if<F: FnOnce(&T) -> bool>(&mut self, accept: F) -> Option<T> {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        // SAFETY: the slot is filled and only the consumer reads filled
+        // slots.
+        let slot = unsafe { &mut *shared.slot(head) };
+        if !accept(unsafe { slot.assume_init_ref() }) {
+            return None;
+        }
+        let item = unsafe { slot.assume_init_read() };
+        shared.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+
+    /// Look at the items in order, from the next one to pop.
+    pub fn for_each<F: FnMut(&T)>(&mut self, mut f: F) {
+        let shared = &*self.shared;
+        let mut position = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+        while position != tail {
+            // SAFETY: slots between head and tail are filled and stay so
+            // while the consumer is borrowed.
+            f(unsafe { (*shared.slot(position)).assume_init_ref() });
+            position = position.wrapping_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn transfer_across_threads() {
+        let (mut tx, mut rx) = ring(4);
+        let sender = thread::spawn(move || {
+            for i in 0..1000u32 {
+                let mut item = i;
+                while let Err(back) = tx.push(item) {
+                    item = back;
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < 1000 {
+            match rx.pop() {
+                Some(i) => {
+                    assert_eq!(i, expected);
+                    expected += 1;
+                },
+                None    => thread::yield_now(),
+            }
+        }
+        sender.join().unwrap();
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn exact_capacity_and_rejected_items() {
+        let (mut tx, mut rx) = ring(3);
+        for i in 0..3 {
+            tx.push(i).unwrap();
+        }
+        assert_eq!(tx.push(3), Err(3));
+        assert_eq!(rx.pop_if(|&i| i == 1), None);
+        let mut seen = Vec::new();
+        rx.for_each(|&i| seen.push(i));
+        assert_eq!(seen, vec![0, 1, 2]);
+        assert_eq!(rx.pop(), Some(0));
+        assert_eq!(tx.len(), 2);
+    }
+
+    #[test]
+    fn items_left_are_dropped() {
+        let item = Arc::new(());
+        let (mut tx, rx) = ring(2);
+        tx.push(item.clone()).unwrap();
+        drop((tx, rx));
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+}
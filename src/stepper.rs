@@ -0,0 +1,147 @@
+//! Pause and single-step delivery. A debugging tool can stop all message
+//! delivery in the network, let messages through one at a time and peek
+//! the queues through 'DebugNetwork' between the steps, replaying races
+//! between messages slowly enough to see them.
+
+use std::sync::{Condvar, Mutex};
+
+use super::{Service, Time};
+use debug::DebugNetwork;
+use rt::duration;
+
+/// Gate that each delivery of the network passes through.
+pub struct DeliveryGate {
+    state   : Mutex<GateState>,
+    cond    : Condvar,
+}
+
+struct GateState {
+    paused      : bool,
+
+    /// Deliveries allowed while paused.
+    steps       : u64,
+
+    /// Count of deliveries so far.
+    delivered   : u64,
+}
+
+impl Default for DeliveryGate {
+
+    fn default() -> Self {
+        DeliveryGate {
+            state   : Mutex::new(GateState {
+                paused      : false,
+                steps       : 0,
+                delivered   : 0,
+            }),
+            cond    : Condvar::new(),
+        }
+    }
+}
+
+impl DeliveryGate {
+
+    /// Called by the backend before each delivery. Blocks while the
+    /// gate is paused and has no steps allowed.
+    pub fn admit(&self) {
+        let mut state = self.state.lock().unwrap();
+        while state.paused && state.steps == 0 {
+            state = self.cond.wait(state).unwrap();
+        }
+        if state.paused {
+            state.steps -= 1;
+        }
+        state.delivered += 1;
+        self.cond.notify_all();
+    }
+
+    /// Stop all deliveries. Deliveries that already passed the gate are
+    /// completed.
+    pub fn pause(&self) {
+        self.state.lock().unwrap().paused = true;
+    }
+
+    /// Continue all deliveries.
+    pub fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.paused = false;
+        state.steps = 0;
+        self.cond.notify_all();
+    }
+
+    /// Whether the deliveries are paused.
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    /// Count of deliveries so far.
+    pub fn delivered(&self) -> u64 {
+        self.state.lock().unwrap().delivered
+    }
+
+    /// Let exactly one delivery through and wait until it happens or
+    /// until timeout. Returns false on timeout, in which case the step
+    /// stays allowed and is taken by the next delivery.
+    pub fn step<T: Time>(&self, timeout: T) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.paused {
+            return true;
+        }
+        let target = state.delivered + 1;
+        state.steps += 1;
+        self.cond.notify_all();
+        let (state, _) = self.cond.wait_timeout_while(state, duration(&timeout),
+                |s| s.delivered < target).unwrap();
+        state.delivered >= target
+    }
+}
+
+/// Network which delivery can be paused and stepped.
+pub trait SteppingNetwork<S: Service>: DebugNetwork<S> {
+
+    /// Gate of all deliveries in the network.
+    fn delivery_gate(&self) -> &DeliveryGate;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    struct Millis(u32);
+
+    impl Time for Millis {
+
+        fn nanos(&self) -> u32 {
+            (self.0 % 1000) * 1_000_000
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0 / 1000
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Millis(seconds.saturating_mul(1000).saturating_add(nanos / 1_000_000))
+        }
+    }
+
+    #[test]
+    fn step_one_by_one() {
+        let gate = Arc::new(DeliveryGate::default());
+        gate.pause();
+        let sender = gate.clone();
+        let handle = thread::spawn(move || {
+            for _ in 0..3 {
+                sender.admit();
+            }
+        });
+        assert!(gate.step(Millis(1000)));
+        assert_eq!(gate.delivered(), 1);
+        assert!(gate.step(Millis(1000)));
+        assert_eq!(gate.delivered(), 2);
+        gate.resume();
+        handle.join().unwrap();
+        assert_eq!(gate.delivered(), 3);
+    }
+}
@@ -0,0 +1,337 @@
+//! Supervision trees. Master object that starts sub-objects can kill
+//! them, but nothing tells it when one of them dies on its own. Network
+//! that implements 'Supervisor' calls hooks on the death of its
+//! objects, and 'SupervisionTree' uses them to restart the children
+//! that panicked by the restart policy of the master: only the dead
+//! child or all of them, and at most so many times in a time window,
+//! after which the tree gives up and stops the rest.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{ExitReason, Network, Object, ObjectId, OwnedObject, Service, Time};
+use rt::duration;
+
+/// Hook that is called once with the exit reason of the object.
+pub type DeathHook = Box<dyn FnOnce(ExitReason) + Send>;
+
+/// Owned handle of the object of the network.
+pub type OwnedOf<S, N> = <<N as Network<S>>::Object as Object<S>>::Owned;
+
+/// Network that tells about the death of its objects.
+pub trait Supervisor<S: Service>: Network<S> {
+
+    /// Call the hook when the object dies, or right away if it is
+    /// already dead.
+    fn on_death(&self, object: &OwnedOf<S, Self>, hook: DeathHook);
+}
+
+/// Which children are restarted when one of them panics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+
+    /// Only the child that panicked.
+    OneForOne,
+
+    /// All children, for those that depend on each other.
+    OneForAll,
+}
+
+/// How the tree restarts its children.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    pub strategy        : Strategy,
+
+    /// Restarts allowed within the window. One more makes the tree give
+    /// up.
+    pub max_restarts    : usize,
+    pub window          : Duration,
+}
+
+impl RestartPolicy {
+
+    /// Create policy that allows 'max_restarts' within the time window.
+    pub fn new<T: Time>(strategy: Strategy, max_restarts: usize, window: T) -> Self {
+        RestartPolicy {
+            strategy,
+            max_restarts,
+            window          : duration(&window),
+        }
+    }
+}
+
+/// What happened in the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChildEvent<Id> {
+
+    /// Child with given number died.
+    Died {
+        child   : usize,
+        id      : Id,
+        reason  : ExitReason,
+    },
+
+    /// Child with given number was started again as a new object.
+    Restarted {
+        child   : usize,
+        id      : Id,
+    },
+
+    /// Restarts exceeded the policy. All children are stopped and none
+    /// is restarted anymore.
+    GaveUp,
+}
+
+type StartFn<S, N> = Arc<dyn Fn(&N) -> OwnedOf<S, N> + Send + Sync>;
+type EventHook<S, N> = Box<dyn Fn(ChildEvent<ObjectId<S, N>>) + Send + Sync>;
+
+struct Child<S: Service, N: Network<S>> {
+    start   : StartFn<S, N>,
+
+    /// Identifier of the current object of the child. None while the
+    /// child is dead.
+    id      : Option<ObjectId<S, N>>,
+    current : Option<OwnedOf<S, N>>,
+}
+
+struct TreeState<S: Service, N: Network<S>> {
+    children    : Vec<Child<S, N>>,
+
+    /// Times of the recent restarts.
+    restarts    : VecDeque<Instant>,
+    stopped     : bool,
+}
+
+struct Tree<S: Service, N: Network<S>> {
+    network     : N,
+    policy      : RestartPolicy,
+    hook        : Option<EventHook<S, N>>,
+    state       : Mutex<TreeState<S, N>>,
+}
+
+/// Children of the master object with their restart policy.
+pub struct SupervisionTree<S: Service, N: Network<S>> {
+    tree    : Arc<Tree<S, N>>,
+}
+
+impl<S: Service, N: Network<S>> TreeState<S, N> {
+
+    /// Forget all children and give their objects to kill.
+    fn take_all(&mut self) -> Vec<OwnedOf<S, N>> {
+        self.children.iter_mut().filter_map(|c| {
+            c.id = None;
+            c.current.take()
+        }).collect()
+    }
+}
+
+impl<S, N> Tree<S, N>
+        where   S               : Service + 'static,
+                N               : Supervisor<S> + Send + Sync + 'static,
+                OwnedOf<S, N>   : Send,
+                ObjectId<S, N>  : PartialEq + Clone + Send
+{
+
+    fn emit(&self, event: ChildEvent<ObjectId<S, N>>) {
+        if let Some(ref hook) = self.hook {
+            hook(event);
+        }
+    }
+
+    /// Start the child and watch it. Death hook may be called right
+    /// inside 'on_death', so no lock is held over it.
+    fn start(self: &Arc<Self>, child: usize, restart: bool) {
+        let start = self.state.lock().unwrap().children[child].start.clone();
+        let owned = start(&self.network);
+        let id = owned.id();
+        self.state.lock().unwrap().children[child].id = Some(id.clone());
+        if restart {
+            self.emit(ChildEvent::Restarted { child, id: id.clone() });
+        }
+        let tree = self.clone();
+        let watched = id.clone();
+        self.network.on_death(&owned, Box::new(move |reason| tree.died(child, watched, reason)));
+
+        // Keep the handle unless the object has died already.
+        let mut state = self.state.lock().unwrap();
+        if state.children[child].id.as_ref() == Some(&id) {
+            state.children[child].current = Some(owned);
+        }
+    }
+
+    fn died(self: &Arc<Self>, child: usize, id: ObjectId<S, N>, reason: ExitReason) {
+        let (restart, kill) = {
+            let mut state = self.state.lock().unwrap();
+
+            // Children killed by the tree itself are already forgotten.
+            if state.stopped || state.children[child].id.as_ref() != Some(&id) {
+                return;
+            }
+            state.children[child].id = None;
+            state.children[child].current = None;
+            self.emit(ChildEvent::Died { child, id, reason: reason.clone() });
+            if !matches!(reason, ExitReason::Panicked(_)) {
+                return;
+            }
+
+            let now = Instant::now();
+            while state.restarts.front().is_some_and(|&t| now - t > self.policy.window) {
+                state.restarts.pop_front();
+            }
+            state.restarts.push_back(now);
+            if state.restarts.len() > self.policy.max_restarts {
+                state.stopped = true;
+                (Vec::new(), state.take_all())
+            } else {
+                match self.policy.strategy {
+                    Strategy::OneForOne => (vec![child], Vec::new()),
+                    Strategy::OneForAll => ((0..state.children.len()).collect(), state.take_all()),
+                }
+            }
+        };
+        for owned in kill {
+            let _ = owned.kill();
+        }
+        if restart.is_empty() {
+            self.emit(ChildEvent::GaveUp);
+        }
+        for child in restart {
+            self.start(child, true);
+        }
+    }
+}
+
+impl<S, N> SupervisionTree<S, N>
+        where   S               : Service + 'static,
+                N               : Supervisor<S> + Send + Sync + 'static,
+                OwnedOf<S, N>   : Send,
+                ObjectId<S, N>  : PartialEq + Clone + Send
+{
+
+    /// Create tree without children. Children are started in the given
+    /// network, usually the internal network of the master.
+    pub fn new(network: N, policy: RestartPolicy) -> Self {
+        SupervisionTree::with_hook(network, policy, None)
+    }
+
+    /// Create tree that reports its events to the hook. Hook must not
+    /// call back into the tree.
+    pub fn with_events<F>(network: N, policy: RestartPolicy, hook: F) -> Self
+        where F: Fn(ChildEvent<ObjectId<S, N>>) + Send + Sync + 'static
+    {
+        SupervisionTree::with_hook(network, policy, Some(Box::new(hook)))
+    }
+
+    fn with_hook(network: N, policy: RestartPolicy, hook: Option<EventHook<S, N>>) -> Self {
+        SupervisionTree {
+            tree    : Arc::new(Tree {
+                network,
+                policy,
+                hook,
+                state   : Mutex::new(TreeState {
+                    children    : Vec::new(),
+                    restarts    : VecDeque::new(),
+                    stopped     : false,
+                }),
+            }),
+        }
+    }
+
+    /// Add the child and start it with the function, which is called
+    /// again on each restart. Returns number of the child.
+    pub fn add_child<F>(&self, start: F) -> usize
+        where F: Fn(&N) -> OwnedOf<S, N> + Send + Sync + 'static
+    {
+        let child = {
+            let mut state = self.tree.state.lock().unwrap();
+            state.children.push(Child {
+                start   : Arc::new(start),
+                id      : None,
+                current : None,
+            });
+            state.children.len() - 1
+        };
+        self.tree.start(child, false);
+        child
+    }
+
+    /// Identifier of the current object of the child. None while it
+    /// is dead.
+    pub fn child(&self, child: usize) -> Option<ObjectId<S, N>> {
+        let state = self.tree.state.lock().unwrap();
+        state.children.get(child).and_then(|c| c.id.clone())
+    }
+
+    /// Check if the tree gave up restarting.
+    pub fn is_stopped(&self) -> bool {
+        self.tree.state.lock().unwrap().stopped
+    }
+
+    /// Kill all children without restarting them.
+    pub fn stop(&self) {
+        let kill: Vec<_> = {
+            let mut state = self.tree.state.lock().unwrap();
+            state.stopped = true;
+            state.take_all()
+        };
+        for owned in kill {
+            let _ = owned.kill();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use local::LocalNetwork;
+
+    struct Secs(u32);
+
+    impl Time for Secs {
+
+        fn nanos(&self) -> u32 {
+            0
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Secs(seconds.saturating_add(nanos / 1_000_000_000))
+        }
+    }
+
+    static STARTS: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn restarts_then_gives_up() {
+        let (events, received) = mpsc::channel();
+        let events = Mutex::new(events);
+        let policy = RestartPolicy::new(Strategy::OneForOne, 2, Secs(60));
+        let tree = SupervisionTree::with_events(LocalNetwork::new(), policy, move |event| {
+            let _ = events.lock().unwrap().send(event);
+        });
+
+        // First child panics on its first start only.
+        tree.add_child(|network: &LocalNetwork| network.spawn(|| {
+            if STARTS.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first start");
+            }
+        }));
+        assert!(matches!(received.recv().unwrap(), ChildEvent::Died { child: 0, .. }));
+        assert!(matches!(received.recv().unwrap(), ChildEvent::Restarted { child: 0, .. }));
+        assert!(matches!(received.recv().unwrap(),
+            ChildEvent::Died { child: 0, reason: ExitReason::Normal, .. }));
+        assert!(!tree.is_stopped());
+
+        // Second one always panics, and the third restart is too many.
+        tree.add_child(|network: &LocalNetwork| network.spawn(|| panic!("always")));
+        let gave_up = received.iter().position(|e| e == ChildEvent::GaveUp).unwrap();
+        assert_eq!(gave_up, 3);
+        assert!(tree.is_stopped());
+    }
+}
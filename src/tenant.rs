@@ -0,0 +1,249 @@
+//! Accounting of the traffic per tenant. Backends count messages and
+//! bytes that each tenant sends and receives over its channels. Every
+//! period the owner of the network flushes the counters to the meter,
+//! e.g. a metering object that bills the tenants, and the account
+//! refuses sends beyond the per-period quota with
+//! 'SocketErr::QuotaExceeded'.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use super::{Data, SocketErr};
+use metrics::{MetricFamily, MetricKind, MetricsSource, Sample};
+
+/// Traffic of the tenant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Traffic {
+    pub messages_sent       : u64,
+    pub messages_received   : u64,
+    pub bytes_sent          : u64,
+    pub bytes_received      : u64,
+}
+
+impl Traffic {
+
+    fn add(&mut self, other: &Traffic) {
+        self.messages_sent += other.messages_sent;
+        self.messages_received += other.messages_received;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+    }
+}
+
+/// Limits of what the tenant may send in one period. None means no
+/// limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quota {
+    pub messages    : Option<u64>,
+    pub bytes       : Option<u64>,
+}
+
+/// Traffic of the tenant in one period.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageRecord<T> {
+    pub tenant  : T,
+
+    /// Number of the period, from zero.
+    pub period  : u64,
+    pub traffic : Traffic,
+}
+
+impl<T: Send + 'static> Data for UsageRecord<T> {
+}
+
+/// Receiver of the usage records.
+pub trait Meter<T> {
+
+    /// Take the record of the finished period.
+    fn record(&self, record: UsageRecord<T>);
+}
+
+impl<T, F: Fn(UsageRecord<T>)> Meter<T> for F {
+
+    fn record(&self, record: UsageRecord<T>) {
+        self(record)
+    }
+}
+
+struct State<T> {
+    period  : u64,
+
+    /// Traffic of the current period.
+    current : HashMap<T, Traffic>,
+
+    /// Traffic of the finished periods.
+    total   : HashMap<T, Traffic>,
+    quotas  : HashMap<T, Quota>,
+}
+
+/// Traffic of the tenants 'T'.
+pub struct TenantAccount<T> {
+    state   : Mutex<State<T>>,
+}
+
+impl<T: Hash + Eq + Clone> Default for TenantAccount<T> {
+
+    fn default() -> Self {
+        TenantAccount::new()
+    }
+}
+
+impl<T: Hash + Eq + Clone> TenantAccount<T> {
+
+    /// Create the account without quotas.
+    pub fn new() -> Self {
+        TenantAccount {
+            state   : Mutex::new(State {
+                period  : 0,
+                current : HashMap::new(),
+                total   : HashMap::new(),
+                quotas  : HashMap::new(),
+            }),
+        }
+    }
+
+    /// Set the quota of the tenant. Applies from the current period.
+    pub fn set_quota(&self, tenant: &T, quota: Quota) {
+        self.state.lock().unwrap().quotas.insert(tenant.clone(), quota);
+    }
+
+    /// Count the message sent by the tenant. Fails without counting if
+    /// the quota would be exceeded.
+    pub fn sent(&self, tenant: &T, bytes: u64) -> Result<(), SocketErr> {
+        let mut state = self.state.lock().unwrap();
+        let quota = state.quotas.get(tenant).cloned().unwrap_or_default();
+        let traffic = state.current.entry(tenant.clone()).or_default();
+        if quota.messages.is_some_and(|q| traffic.messages_sent + 1 > q)
+                || quota.bytes.is_some_and(|q| traffic.bytes_sent + bytes > q) {
+            return Err(SocketErr::QuotaExceeded);
+        }
+        traffic.messages_sent += 1;
+        traffic.bytes_sent += bytes;
+        Ok(())
+    }
+
+    /// Take back the message counted by 'sent' that was not delivered
+    /// after all, e.g. because the channel closed before it.
+    pub fn unsent(&self, tenant: &T, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(traffic) = state.current.get_mut(tenant) {
+            traffic.messages_sent = traffic.messages_sent.saturating_sub(1);
+            traffic.bytes_sent = traffic.bytes_sent.saturating_sub(bytes);
+        }
+    }
+
+    /// Count the message received by the tenant.
+    pub fn received(&self, tenant: &T, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        let traffic = state.current.entry(tenant.clone()).or_default();
+        traffic.messages_received += 1;
+        traffic.bytes_received += bytes;
+    }
+
+    /// Traffic of the tenant in the current period.
+    pub fn current(&self, tenant: &T) -> Traffic {
+        self.state.lock().unwrap().current.get(tenant).cloned().unwrap_or_default()
+    }
+
+    /// Traffic of the tenant over all periods, the current one included.
+    pub fn total(&self, tenant: &T) -> Traffic {
+        let state = self.state.lock().unwrap();
+        let mut traffic = state.total.get(tenant).cloned().unwrap_or_default();
+        if let Some(current) = state.current.get(tenant) {
+            traffic.add(current);
+        }
+        traffic
+    }
+
+    /// Finish the period: give the meter a record of each tenant that
+    /// had traffic in it and start the next one with fresh quotas.
+    /// Returns the number of the finished period.
+    pub fn flush<M: Meter<T>>(&self, meter: &M) -> u64 {
+        let (period, records) = {
+            let mut state = self.state.lock().unwrap();
+            let period = state.period;
+            state.period += 1;
+            let current: Vec<_> = state.current.drain().collect();
+            for (tenant, traffic) in &current {
+                state.total.entry(tenant.clone()).or_default().add(traffic);
+            }
+            (period, current)
+        };
+        for (tenant, traffic) in records {
+            meter.record(UsageRecord { tenant, period, traffic });
+        }
+        period
+    }
+}
+
+impl<T: Hash + Eq + Clone + ToString> MetricsSource for TenantAccount<T> {
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let state = self.state.lock().unwrap();
+        let mut tenants: Vec<_> = state.total.keys().chain(state.current.keys()).collect();
+        tenants.sort_by_key(|t| t.to_string());
+        tenants.dedup();
+        let totals: Vec<_> = tenants.into_iter().map(|t| {
+            let mut traffic = state.total.get(t).cloned().unwrap_or_default();
+            if let Some(current) = state.current.get(t) {
+                traffic.add(current);
+            }
+            (t.to_string(), traffic)
+        }).collect();
+        let family = |name: &str, help: &str, sent: fn(&Traffic) -> u64,
+                received: fn(&Traffic) -> u64| MetricFamily {
+            name    : name.to_string(),
+            help    : help.to_string(),
+            kind    : MetricKind::Counter,
+            samples : totals.iter().flat_map(|(tenant, traffic)| {
+                vec![("sent", sent(traffic)), ("received", received(traffic))].into_iter()
+                    .map(move |(direction, value)| Sample {
+                        labels  : vec![
+                            ("tenant".to_string(), tenant.clone()),
+                            ("direction".to_string(), direction.to_string()),
+                        ],
+                        value   : value as f64,
+                    })
+            }).collect(),
+        };
+        vec![
+            family("ccs_tenant_messages", "Messages of the tenant.",
+                    |t| t.messages_sent, |t| t.messages_received),
+            family("ccs_tenant_bytes", "Bytes of the messages of the tenant.",
+                    |t| t.bytes_sent, |t| t.bytes_received),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn periods_and_quota() {
+        let account = TenantAccount::new();
+        account.set_quota(&"acme", Quota { messages: Some(2), bytes: None });
+        account.sent(&"acme", 10).unwrap();
+        account.sent(&"acme", 20).unwrap();
+        assert!(matches!(account.sent(&"acme", 5), Err(SocketErr::QuotaExceeded)));
+        account.received(&"globex", 7);
+
+        let records = RefCell::new(Vec::new());
+        assert_eq!(account.flush(&|r| records.borrow_mut().push(r)), 0);
+        let mut records = records.into_inner();
+        records.sort_by_key(|r| r.tenant);
+        assert_eq!(records[0].traffic, Traffic {
+            messages_sent   : 2,
+            bytes_sent      : 30,
+            ..Default::default()
+        });
+        assert_eq!(records[1].traffic.bytes_received, 7);
+
+        // Quota is per period.
+        account.sent(&"acme", 5).unwrap();
+        assert_eq!(account.total(&"acme").messages_sent, 3);
+        assert_eq!(account.collect()[1].samples[0].value, 35.0);
+    }
+}
@@ -0,0 +1,307 @@
+//! Throttling of the control plane. Each object gets limits on how fast
+//! it may register services and connect to them and on how many
+//! registrations and channels it may hold, so a buggy or malicious
+//! object can't flood the registry and stall the whole network.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{Network, Service, Time};
+use rt::duration;
+
+/// Limit of one kind of operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+
+    /// Count of operations that may be made at once after a pause.
+    pub burst       : u32,
+
+    /// Time to regain one operation after the burst is spent.
+    pub interval    : Duration,
+
+    /// Count of registrations or channels the object may hold at the
+    /// same time.
+    pub max_held    : Option<u32>,
+}
+
+/// Limits of the object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub register    : Limit,
+    pub connect     : Limit,
+}
+
+/// Operation was throttled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleErr {
+
+    /// Operations are made too fast. Next one will be admitted after
+    /// given time.
+    TooFast {
+        retry_after : Duration,
+    },
+
+    /// Object holds as many registrations or channels as it may.
+    TooMany,
+}
+
+impl Limit {
+
+    /// Allow 'count' operations per given time.
+    pub fn new<T: Time>(count: u32, per: T) -> Self {
+        let count = count.max(1);
+        Limit {
+            burst       : count,
+            interval    : duration(&per) / count,
+            max_held    : None,
+        }
+    }
+
+    /// No limit at all.
+    pub fn unlimited() -> Self {
+        Limit {
+            burst       : u32::MAX,
+            interval    : Duration::ZERO,
+            max_held    : None,
+        }
+    }
+
+    /// Limit the count of registrations or channels held at once.
+    pub fn max_held(mut self, max: u32) -> Self {
+        self.max_held = Some(max);
+        self
+    }
+}
+
+impl Limits {
+
+    /// No limits on either kind of operations.
+    pub fn unlimited() -> Self {
+        Limits {
+            register    : Limit::unlimited(),
+            connect     : Limit::unlimited(),
+        }
+    }
+}
+
+/// Kind of the throttled operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Register,
+    Connect,
+}
+
+/// Per-object accounting of the limits for backends to keep.
+pub struct Throttle<OId> {
+    defaults    : Limits,
+    objects     : Mutex<HashMap<OId, Usage>>,
+}
+
+struct Usage {
+    limits      : Limits,
+    register    : Bucket,
+    connect     : Bucket,
+}
+
+struct Bucket {
+    tokens      : u32,
+    last        : Instant,
+    held        : u32,
+}
+
+impl Bucket {
+
+    fn new(limit: &Limit, now: Instant) -> Self {
+        Bucket {
+            tokens      : limit.burst,
+            last        : now,
+            held        : 0,
+        }
+    }
+
+    fn admit(&mut self, limit: &Limit, now: Instant) -> Result<(), ThrottleErr> {
+        if limit.max_held.is_some_and(|max| self.held >= max) {
+            return Err(ThrottleErr::TooMany);
+        }
+
+        // Regain tokens for the whole intervals passed since the last
+        // refill.
+        if !limit.interval.is_zero() {
+            let elapsed = now.saturating_duration_since(self.last);
+            let gained = (elapsed.as_nanos() / limit.interval.as_nanos())
+                .min(limit.burst as u128) as u32;
+            if gained > 0 {
+                self.tokens = self.tokens.saturating_add(gained).min(limit.burst);
+                self.last += limit.interval * gained;
+            }
+        } else {
+            self.tokens = limit.burst;
+        }
+
+        if self.tokens == 0 {
+            let next = self.last + limit.interval;
+            return Err(ThrottleErr::TooFast {
+                retry_after : next.saturating_duration_since(now),
+            });
+        }
+        if self.tokens == limit.burst {
+            self.last = now;
+        }
+        self.tokens -= 1;
+        self.held += 1;
+        Ok(())
+    }
+}
+
+impl<OId: Hash + Eq + Clone> Throttle<OId> {
+
+    /// Create throttle that applies given limits to the objects that
+    /// have no limits of their own.
+    pub fn new(defaults: Limits) -> Self {
+        Throttle {
+            defaults,
+            objects     : Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set limits of the object.
+    pub fn set_limits(&self, object: &OId, limits: Limits) {
+        let mut objects = self.objects.lock().unwrap();
+        match objects.get_mut(object) {
+            Some(usage) => usage.limits = limits,
+            None        => {
+                objects.insert(object.clone(), Usage::new(limits, Instant::now()));
+            },
+        }
+    }
+
+    /// Get limits of the object.
+    pub fn limits(&self, object: &OId) -> Limits {
+        self.objects.lock().unwrap().get(object)
+            .map_or(self.defaults, |u| u.limits)
+    }
+
+    /// Check that the object may perform the operation now. If it may,
+    /// the operation is counted as held until 'release'.
+    pub fn admit(&self, object: &OId, op: Operation) -> Result<(), ThrottleErr> {
+        self.admit_at(object, op, Instant::now())
+    }
+
+    fn admit_at(&self, object: &OId, op: Operation, now: Instant)
+        -> Result<(), ThrottleErr>
+    {
+        let mut objects = self.objects.lock().unwrap();
+        let usage = objects.entry(object.clone())
+            .or_insert_with(|| Usage::new(self.defaults, now));
+        match op {
+            Operation::Register => usage.register.admit(&usage.limits.register, now),
+            Operation::Connect  => usage.connect.admit(&usage.limits.connect, now),
+        }
+    }
+
+    /// Tell that the registration was discontinued or the channel was
+    /// closed.
+    pub fn release(&self, object: &OId, op: Operation) {
+        if let Some(usage) = self.objects.lock().unwrap().get_mut(object) {
+            let bucket = match op {
+                Operation::Register => &mut usage.register,
+                Operation::Connect  => &mut usage.connect,
+            };
+            bucket.held = bucket.held.saturating_sub(1);
+        }
+    }
+
+    /// Forget the object after its death.
+    pub fn forget(&self, object: &OId) {
+        self.objects.lock().unwrap().remove(object);
+    }
+}
+
+impl Usage {
+
+    fn new(limits: Limits, now: Instant) -> Self {
+        Usage {
+            limits,
+            register    : Bucket::new(&limits.register, now),
+            connect     : Bucket::new(&limits.connect, now),
+        }
+    }
+}
+
+/// Network that throttles registrations and connects of its objects.
+/// Throttled registration fails with 'RegistrationErr::Throttled' and
+/// throttled connect with 'ConnectErr::Throttled'.
+pub trait ThrottledNetwork<S: Service>: Network<S> {
+
+    /// Identifier of the object in this network.
+    type ObjectId;
+
+    /// Set limits of the object. Only the owner of the network may do
+    /// that.
+    fn set_limits(&self, object: &Self::ObjectId, limits: Limits);
+
+    /// Get limits of the object.
+    fn limits(&self, object: &Self::ObjectId) -> Limits;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Secs(u32);
+
+    impl Time for Secs {
+
+        fn nanos(&self) -> u32 {
+            0
+        }
+
+        fn seconds(&self) -> u32 {
+            self.0
+        }
+
+        fn from_parts(seconds: u32, nanos: u32) -> Self {
+            Secs(seconds.saturating_add(nanos / 1_000_000_000))
+        }
+    }
+
+    #[test]
+    fn burst_then_rate() {
+        let limit = Limit::new(2, Secs(2));
+        let throttle = Throttle::new(Limits {
+            register    : limit.max_held(3),
+            connect     : limit,
+        });
+        let now = Instant::now();
+        let op = Operation::Register;
+        assert!(throttle.admit_at(&1, op, now).is_ok());
+        assert!(throttle.admit_at(&1, op, now).is_ok());
+        assert_eq!(throttle.admit_at(&1, op, now), Err(ThrottleErr::TooFast {
+            retry_after : Duration::from_secs(1),
+        }));
+        assert!(throttle.admit_at(&2, op, now).is_ok());
+
+        let later = now + Duration::from_secs(1);
+        assert!(throttle.admit_at(&1, op, later).is_ok());
+        let later = later + Duration::from_secs(10);
+        assert_eq!(throttle.admit_at(&1, op, later), Err(ThrottleErr::TooMany));
+        throttle.release(&1, op);
+        assert!(throttle.admit_at(&1, op, later).is_ok());
+    }
+
+    #[test]
+    fn huge_burst() {
+        let limit = Limit {
+            burst       : u32::MAX,
+            interval    : Duration::from_nanos(1),
+            max_held    : None,
+        };
+        let throttle = Throttle::new(Limits { register: limit, connect: limit });
+        let now = Instant::now();
+        let op = Operation::Connect;
+        assert!(throttle.admit_at(&1, op, now).is_ok());
+        assert!(throttle.admit_at(&1, op, now + Duration::from_secs(10)).is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+//! Time backed by the standard library. 'StdTime' is a span of time
+//! with millisecond constructors, so timeouts of the timed operations
+//! can be computed and compared without each program declaring its own
+//! 'Time' type. Construction, 'now' and the saturating arithmetic come
+//! from the 'Time' trait. 'Duration' is a 'Time' too.
+
+use std::ops::{Add, Sub};
+use std::time::Duration;
+
+use super::Time;
+
+/// Span of time. When taken from 'now', the span since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StdTime(Duration);
+
+impl StdTime {
+
+    /// Zero span.
+    pub const ZERO: StdTime = StdTime(Duration::ZERO);
+
+    /// Span of given milliseconds.
+    pub fn from_millis(millis: u64) -> Self {
+        StdTime(Duration::from_millis(millis))
+    }
+
+    /// Span of given seconds.
+    pub fn from_secs(secs: u64) -> Self {
+        StdTime(Duration::from_secs(secs))
+    }
+
+    /// Whole milliseconds of the span.
+    pub fn as_millis(&self) -> u64 {
+        self.0.as_millis() as u64
+    }
+
+    /// Same span as the standard duration.
+    pub fn as_duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl Time for StdTime {
+
+    fn nanos(&self) -> u32 {
+        self.0.subsec_nanos()
+    }
+
+    /// Saturates at the longest span 'Time' can hold.
+    fn seconds(&self) -> u32 {
+        self.0.as_secs().min(u32::MAX as u64) as u32
+    }
+
+    fn from_parts(seconds: u32, nanos: u32) -> Self {
+        StdTime(Duration::from_parts(seconds, nanos))
+    }
+}
+
+impl Time for Duration {
+
+    fn nanos(&self) -> u32 {
+        self.subsec_nanos()
+    }
+
+    /// Saturates at the longest span 'Time' can hold.
+    fn seconds(&self) -> u32 {
+        self.as_secs().min(u32::MAX as u64) as u32
+    }
+
+    fn from_parts(seconds: u32, nanos: u32) -> Self {
+        Duration::new(seconds as u64, nanos)
+    }
+}
+
+impl From<Duration> for StdTime {
+
+    fn from(d: Duration) -> Self {
+        StdTime(d)
+    }
+}
+
+impl From<StdTime> for Duration {
+
+    fn from(t: StdTime) -> Self {
+        t.0
+    }
+}
+
+/// Saturating, like 'Time::saturating_add'.
+impl Add for StdTime {
+
+    type Output = StdTime;
+
+    fn add(self, other: StdTime) -> StdTime {
+        self.saturating_add(&other)
+    }
+}
+
+/// Saturating, like 'Time::saturating_sub'.
+impl Sub for StdTime {
+
+    type Output = StdTime;
+
+    fn sub(self, other: StdTime) -> StdTime {
+        self.saturating_sub(&other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Ordering;
+
+    #[test]
+    fn arithmetic() {
+        let second = StdTime::from_secs(1);
+        let half = StdTime::from_millis(500);
+        assert_eq!(half + half, second);
+        assert_eq!(half - second, StdTime::ZERO);
+        assert!(half < second);
+        assert_eq!((second + half).nanos(), 500_000_000);
+        assert_eq!((second + half).seconds(), 1);
+        assert_eq!(StdTime::from_time(&Duration::from_millis(1500)).as_millis(), 1500);
+        assert_eq!(StdTime::from_secs(u64::MAX).seconds(), u32::MAX);
+        assert!(StdTime::now() > StdTime::ZERO);
+    }
+
+    /// Arithmetic of any 'Time', as generic code sees it.
+    fn later<T: Time, U: Time>(start: &T, by: &U) -> T {
+        start.saturating_add(by)
+    }
+
+    #[test]
+    fn generic() {
+        let start = StdTime::from_parts(1, 1_500_000_000);
+        assert_eq!(start, StdTime::from_millis(2500));
+        assert_eq!(later(&start, &Duration::from_millis(500)), StdTime::from_secs(3));
+        let longest = Duration::from_parts(u32::MAX, 999_999_999);
+        assert_eq!(later(&longest, &start), longest);
+        assert_eq!(start.saturating_sub(&longest), StdTime::ZERO);
+        assert!(StdTime::ZERO.is_zero());
+        assert_eq!(start.cmp_time(&Duration::from_secs(3)), Ordering::Less);
+        assert!(<Duration as Time>::now().cmp_time(&start).is_gt());
+    }
+}
@@ -0,0 +1,172 @@
+//! Execution traces. Backends and objects record when handlers run and
+//! when messages are sent and received, and the trace is exported in
+//! the Chrome trace event format, which Perfetto and 'chrome://tracing'
+//! show with one track per object and arrows for the messages.
+
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Record of the trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceRecord {
+
+    /// Object was busy with some work, e.g. ran a handler.
+    Span {
+        object  : u64,
+        name    : String,
+        start   : Duration,
+        end     : Duration,
+    },
+
+    /// Message went from one object to another.
+    Message {
+        id      : u64,
+        from    : u64,
+        to      : u64,
+        sent    : Duration,
+        received: Duration,
+    },
+}
+
+/// Collector of the trace records. Times are measured from the moment
+/// the tracer was created.
+pub struct Tracer {
+    origin  : Instant,
+    names   : Mutex<Vec<(u64, String)>>,
+    records : Mutex<Vec<TraceRecord>>,
+}
+
+impl Default for Tracer {
+
+    fn default() -> Self {
+        Tracer {
+            origin  : Instant::now(),
+            names   : Mutex::new(Vec::new()),
+            records : Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Tracer {
+
+    /// Time since the tracer was created.
+    pub fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    /// Name the track of the object.
+    pub fn name_object(&self, object: u64, name: &str) {
+        self.names.lock().unwrap().push((object, name.to_string()));
+    }
+
+    /// Add the record.
+    pub fn record(&self, record: TraceRecord) {
+        self.records.lock().unwrap().push(record);
+    }
+
+    /// Run the function and record it as the span of the object.
+    pub fn span<F: FnOnce() -> R, R>(&self, object: u64, name: &str, f: F) -> R {
+        let start = self.now();
+        let r = f();
+        self.record(TraceRecord::Span {
+            object,
+            name    : name.to_string(),
+            start,
+            end     : self.now(),
+        });
+        r
+    }
+
+    /// Export the trace in the Chrome trace event format.
+    pub fn to_chrome_json(&self) -> String {
+        let mut events = Vec::new();
+        for (object, name) in self.names.lock().unwrap().iter() {
+            events.push(format!("{{\"ph\":\"M\",\"name\":\"thread_name\",\
+                    \"pid\":1,\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+                    object, escape(name)));
+        }
+        for record in self.records.lock().unwrap().iter() {
+            match *record {
+                TraceRecord::Span { object, ref name, start, end } => {
+                    events.push(format!("{{\"ph\":\"X\",\"name\":\"{}\",\
+                            \"pid\":1,\"tid\":{},\"ts\":{},\"dur\":{}}}",
+                            escape(name), object, micros(start),
+                            micros(end.saturating_sub(start))));
+                },
+                TraceRecord::Message { id, from, to, sent, received } => {
+                    // Flow arrows must be bound to slices, so each end
+                    // gets a zero-length slice.
+                    for &(ph, tid, ts) in &[("s", from, sent), ("f", to, received)] {
+                        events.push(format!("{{\"ph\":\"X\",\"name\":\"message\",\
+                                \"pid\":1,\"tid\":{},\"ts\":{},\"dur\":0}}",
+                                tid, micros(ts)));
+                        events.push(format!("{{\"ph\":\"{}\",\"name\":\"message\",\
+                                \"cat\":\"ccs\",\"id\":{},\"pid\":1,\"tid\":{},\
+                                \"ts\":{},\"bp\":\"e\"}}", ph, id, tid, micros(ts)));
+                    }
+                },
+            }
+        }
+        let mut out = String::from("{\"traceEvents\":[");
+        for (i, e) in events.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str(e);
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+/// Microseconds with fraction, as the format wants.
+fn micros(d: Duration) -> String {
+    format!("{}.{:03}", d.as_micros(), d.subsec_nanos() % 1000)
+}
+
+/// Escape the text for JSON string.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"'     => out.push_str("\\\""),
+            '\\'    => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c       => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chrome_format() {
+        let tracer = Tracer::default();
+        tracer.name_object(1, "client \"a\"");
+        tracer.record(TraceRecord::Span {
+            object  : 1,
+            name    : "call".to_string(),
+            start   : Duration::from_micros(10),
+            end     : Duration::from_nanos(12_500),
+        });
+        tracer.record(TraceRecord::Message {
+            id      : 7,
+            from    : 1,
+            to      : 2,
+            sent    : Duration::from_micros(11),
+            received: Duration::from_micros(12),
+        });
+        let json = tracer.to_chrome_json();
+        assert!(json.starts_with("{\"traceEvents\":[{\"ph\":\"M\""));
+        assert!(json.contains("\"args\":{\"name\":\"client \\\"a\\\"\"}"));
+        assert!(json.contains("\"tid\":1,\"ts\":10.000,\"dur\":2.500"));
+        assert!(json.contains("{\"ph\":\"f\",\"name\":\"message\",\"cat\":\"ccs\",\
+                \"id\":7,\"pid\":1,\"tid\":2,\"ts\":12.000"));
+    }
+}
@@ -0,0 +1,138 @@
+//! Resource usage of the objects. Networks report how many channels,
+//! services, queued bytes and children each object holds, so that
+//! administrators and supervisors can see who consumes CCS resources
+//! and scale or restrict them.
+
+use std::marker::PhantomData;
+
+use super::{Data, Network, Object, Service, Socket, SocketErr};
+use metrics::{MetricFamily, MetricKind, MetricsSource, Sample};
+
+/// Resources held by the object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Usage {
+
+    /// Count of open channels, both connected and accepted.
+    pub channels        : u32,
+
+    /// Count of registered services.
+    pub services        : u32,
+
+    /// Bytes of the messages sent to the object and not yet received.
+    pub queued_bytes    : u64,
+
+    /// Count of alive sub-objects.
+    pub children        : u32,
+}
+
+/// Usage of one object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageReport<OId> {
+    pub object  : OId,
+    pub usage   : Usage,
+}
+
+impl<OId: Send + 'static> Data for UsageReport<OId> {
+}
+
+/// Request of the usage reporting service. None asks for all objects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageQuery<OId>(pub Option<OId>);
+
+impl<OId: Send + 'static> Data for UsageQuery<OId> {
+}
+
+/// Network that reports resource usage of its objects.
+pub trait UsageNetwork<S: Service>: Network<S> {
+
+    /// Identifier of the object in this network.
+    type ObjectId: Clone + ToString + Send + 'static;
+
+    /// Usage of the object. None if there is no such object.
+    fn usage(&self, object: &Self::ObjectId) -> Option<Usage>;
+
+    /// Usage of all alive objects.
+    fn usages(&self) -> Vec<UsageReport<Self::ObjectId>>;
+
+    /// Serve one client of the usage reporting service. Client sends
+    /// queries and receives reports of the matching objects in reply to
+    /// each of them until it closes the channel.
+    fn serve_usage<O, SC>(&self, socket: &SC) -> Result<(), SocketErr>
+        where   O   : Object<S>,
+                SC  : Socket<O, S>
+    {
+        loop {
+            let query = match socket.receive::<UsageQuery<Self::ObjectId>>() {
+                Ok(query)                       => query,
+                Err(SocketErr::ChannelClosed)   => return Ok(()),
+                Err(e)                          => return Err(e),
+            };
+            let reports: Vec<_> = match query.0 {
+                Some(object) => self.usage(&object).into_iter()
+                    .map(|usage| UsageReport { object: object.clone(), usage })
+                    .collect(),
+                None => self.usages(),
+            };
+            socket.send(Reports(reports))?;
+        }
+    }
+}
+
+/// Reports sent in reply to one query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reports<OId>(pub Vec<UsageReport<OId>>);
+
+impl<OId: Send + 'static> Data for Reports<OId> {
+}
+
+/// Adapter that exposes usage of the network as metrics.
+pub struct UsageMetrics<'a, N: 'a, S> {
+    network : &'a N,
+    _s      : PhantomData<S>,
+}
+
+impl<'a, N, S> UsageMetrics<'a, N, S>
+        where   N   : UsageNetwork<S>,
+                S   : Service
+{
+
+    /// Create metrics source for the network.
+    pub fn new(network: &'a N) -> Self {
+        UsageMetrics {
+            network,
+            _s      : PhantomData,
+        }
+    }
+}
+
+impl<'a, N, S> MetricsSource for UsageMetrics<'a, N, S>
+        where   N   : UsageNetwork<S>,
+                S   : Service
+{
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let usages = self.network.usages();
+        let family = |name: &str, help: &str, value: &dyn Fn(&Usage) -> f64| {
+            MetricFamily {
+                name    : name.to_string(),
+                help    : help.to_string(),
+                kind    : MetricKind::Gauge,
+                samples : usages.iter().map(|r| Sample {
+                    labels  : vec![("object".to_string(), r.object.to_string())],
+                    value   : value(&r.usage),
+                }).collect(),
+            }
+        };
+        vec![
+            family("ccs_object_channels", "Open channels of the object.",
+                    &|u| u.channels as f64),
+            family("ccs_object_services", "Services registered by the object.",
+                    &|u| u.services as f64),
+            family("ccs_object_queued_bytes",
+                    "Bytes queued to the object and not yet received.",
+                    &|u| u.queued_bytes as f64),
+            family("ccs_object_children", "Alive sub-objects of the object.",
+                    &|u| u.children as f64),
+        ]
+    }
+}